@@ -0,0 +1,429 @@
+//! `.usrdc` artifacts: the parsed program in a compact binary form, so running
+//! (or packing) a compiled script needs neither the source nor the parser.
+//!
+//! Layout: `USRDC` magic, format version (u16), metadata lines (`os:`/`its:`
+//! headers and a `compiler:` line with the userd version), then the encoded program followed by its FNV-1a checksum.
+//! All integers are little endian; strings are a u32 length plus UTF-8 bytes.
+use crate::ast::{BinOp, Expr, Intrinsic, Program, Stmt};
+use crate::error::Error;
+
+const MAGIC: &[u8] = b"USRDC";
+/// 2: statements carry their source line
+const VERSION: u16 = 2;
+/// Oldest version `load` still reads; its statements load with line 0
+const MIN_VERSION: u16 = 1;
+/// Deepest nesting of statements, expressions and snapshot values a `Reader`
+/// accepts: decoding recurses, so crafted input could overflow the stack
+pub(crate) const MAX_NESTING: usize = 256;
+
+/// Markers of the old source-embedding format, still accepted by `load`
+const LEGACY_META: &[u8] = b"__USRDMETA__\n";
+const LEGACY_SRC: &[u8] = b"__USRDSRC__\n";
+
+/// Separates a packed exe from the gzipped artifact appended to it
+pub const PACK_MARKER: &[u8] = b"\n__USRDSCRIPT__\n";
+/// Header naming the userd version that compiled an artifact
+const COMPILER_META: &str = "compiler:";
+
+pub struct Artifact {
+    /// Header lines such as `os: windows`
+    pub meta: Vec<String>,
+    pub program: Program,
+}
+
+fn is_meta(line: &str) -> bool {
+    let l = line.trim().to_lowercase();
+    l.starts_with("os:") || l.starts_with("its:")
+}
+
+/// `os:` / `its:` headers from the first lines of a script
+pub fn meta_lines(src: &str) -> Vec<String> {
+    src.lines().take(16).filter(|l| is_meta(l)).map(|l| l.trim().to_string()).collect()
+}
+
+/// Parse `src` and encode it as an artifact. Header lines are blanked before
+/// parsing so they do not turn into statements and line numbers stay intact.
+pub fn compile(src: &str) -> Vec<u8> {
+    compile_with_meta(src, &[])
+}
+
+/// `compile` with extra header lines, e.g. `policy: sandbox` from `userd pack --sandbox`
+pub fn compile_with_meta(src: &str, extra: &[&str]) -> Vec<u8> {
+    let (program, _) = parse_code(src);
+    let mut meta = compiled_meta(src);
+    meta.extend(extra.iter().map(|m| m.to_string()));
+    encode(&meta, &program)
+}
+
+/// The script's headers and the compiler's own
+fn compiled_meta(src: &str) -> Vec<String> {
+    let mut meta = meta_lines(src);
+    meta.push(format!("{} userd {}", COMPILER_META, env!("CARGO_PKG_VERSION")));
+    meta
+}
+
+/// `compile`, refusing sources with parse errors: the parser skips what it
+/// cannot read, so the artifact of a broken script would silently lack it
+pub fn compile_checked(src: &str) -> Result<Vec<u8>, Vec<Error>> {
+    let (program, errors) = parse_code(src);
+    if !errors.is_empty() { return Err(errors); }
+    Ok(encode(&compiled_meta(src), &program))
+}
+
+/// The program of `src` with header lines blanked, and the parse errors
+fn parse_code(src: &str) -> (Program, Vec<Error>) {
+    let code: Vec<&str> = src.lines().enumerate()
+        .map(|(i, l)| if i < 16 && is_meta(l) { "" } else { l })
+        .collect();
+    let mut parser = crate::parser::Parser::new(&code.join("\n"));
+    let program = parser.parse_program();
+    (program, parser.take_errors())
+}
+
+pub fn encode(meta: &[String], program: &[Stmt]) -> Vec<u8> {
+    let mut payload = Writer::default();
+    payload.u32(program.len() as u32);
+    for s in program { payload.stmt(s); }
+
+    let mut w = Writer::default();
+    w.buf.extend_from_slice(MAGIC);
+    w.buf.extend_from_slice(&VERSION.to_le_bytes());
+    w.u32(meta.len() as u32);
+    for m in meta { w.str(m); }
+    w.u64(payload.buf.len() as u64);
+    w.buf.extend_from_slice(&payload.buf);
+    w.u64(fnv1a(&payload.buf));
+    w.buf
+}
+
+pub fn is_artifact(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Decode an artifact; files from older `userd compile` (embedded source) are parsed instead
+pub fn load(bytes: &[u8]) -> Result<Artifact, String> {
+    if !is_artifact(bytes) {
+        return load_legacy(bytes);
+    }
+    let mut r = Reader::new(bytes);
+    r.pos = MAGIC.len();
+    let version = u16::from_le_bytes(r.take(2)?.try_into().unwrap());
+    if !(MIN_VERSION..=VERSION).contains(&version) {
+        return Err(format!("unsupported artifact version {} (expected {}..={})", version, MIN_VERSION, VERSION));
+    }
+    let n = r.u32()?;
+    let mut meta = Vec::new();
+    for _ in 0..n { meta.push(r.str()?); }
+    let len = r.u64()? as usize;
+    let payload = r.take(len)?;
+    if r.u64()? != fnv1a(payload) {
+        return Err("artifact checksum mismatch".to_string());
+    }
+    let mut r = Reader::new(payload);
+    r.version = version;
+    let program = r.stmts()?;
+    if r.pos != payload.len() {
+        return Err("trailing data in artifact".to_string());
+    }
+    Ok(Artifact { meta, program })
+}
+
+/// What `userd info` reports about an artifact or a packed exe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Info {
+    /// Bytes before the artifact when it is appended to an exe
+    pub exe_size: Option<usize>,
+    /// Size of the artifact as stored; gzipped inside an exe
+    pub stored_size: usize,
+    /// `None` for the old source-embedding format
+    pub version: Option<u16>,
+    pub meta: Vec<String>,
+    /// Encoded program (or embedded source) bytes
+    pub payload_size: usize,
+    /// Top-level statements, when the format records them
+    pub statements: Option<u32>,
+    /// `None` when the format has no checksum
+    pub checksum_ok: Option<bool>,
+}
+
+impl Info {
+    /// Value of header `key` (e.g. `"os:"`), trimmed
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.meta.iter().find_map(|m| {
+            let head = m.get(..key.len())?;
+            head.eq_ignore_ascii_case(key).then(|| m[key.len()..].trim())
+        })
+    }
+}
+
+/// The gzipped or plain artifact appended to a packed exe
+pub fn embedded(exe: &[u8]) -> Option<&[u8]> {
+    let start = exe.windows(PACK_MARKER.len()).rposition(|w| w == PACK_MARKER)? + PACK_MARKER.len();
+    Some(&exe[start..]).filter(|s| !s.is_empty())
+}
+
+/// Read the headers of an artifact or packed exe without decoding the
+/// program, so a damaged file still shows what it claims to be
+pub fn info(bytes: &[u8]) -> Result<Info, String> {
+    let Some(stored) = embedded(bytes).filter(|_| !is_artifact(bytes)) else {
+        if is_artifact(bytes) || bytes.windows(LEGACY_SRC.len()).any(|w| w == LEGACY_SRC) {
+            return info_of(bytes, None, bytes.len());
+        }
+        return Err("neither a userd artifact nor a packed exe".to_string());
+    };
+    let exe_size = bytes.len() - stored.len() - PACK_MARKER.len();
+    if crate::gzip::is_gzip(stored) {
+        let unpacked = crate::gzip::decompress(stored).map_err(|e| format!("packed artifact does not decompress: {}", e))?;
+        info_of(&unpacked, Some(exe_size), stored.len())
+    } else if is_artifact(stored) || std::str::from_utf8(stored).is_ok() {
+        info_of(stored, Some(exe_size), stored.len())
+    } else {
+        // the marker itself is in every userd binary, packed or not
+        Err("no script is packed into this exe".to_string())
+    }
+}
+
+fn info_of(bytes: &[u8], exe_size: Option<usize>, stored_size: usize) -> Result<Info, String> {
+    if !is_artifact(bytes) {
+        // a legacy artifact or an old pack with plain source
+        let meta = load_legacy(bytes).map(|a| a.meta).unwrap_or_default();
+        let payload_size = bytes.windows(LEGACY_SRC.len()).position(|w| w == LEGACY_SRC).map_or(bytes.len(), |p| bytes.len() - p - LEGACY_SRC.len());
+        return Ok(Info { exe_size, stored_size, version: None, meta, payload_size, statements: None, checksum_ok: None });
+    }
+    let mut r = Reader::new(bytes);
+    r.pos = MAGIC.len();
+    let version = u16::from_le_bytes(r.take(2)?.try_into().unwrap());
+    let n = r.u32()?;
+    let mut meta = Vec::new();
+    for _ in 0..n { meta.push(r.str()?); }
+    let len = r.u64()? as usize;
+    let payload = r.take(len)?;
+    let checksum_ok = r.u64().ok().map(|sum| sum == fnv1a(payload));
+    let statements = payload.get(..4).map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+    Ok(Info { exe_size, stored_size, version: Some(version), meta, payload_size: len, statements, checksum_ok: Some(checksum_ok.unwrap_or(false)) })
+}
+
+fn load_legacy(bytes: &[u8]) -> Result<Artifact, String> {
+    let find = |needle: &[u8]| bytes.windows(needle.len()).position(|w| w == needle);
+    let src_pos = find(LEGACY_SRC).ok_or("compiled artifact missing marker")?;
+    let meta = match find(LEGACY_META) {
+        Some(p) if p < src_pos => String::from_utf8_lossy(&bytes[p + LEGACY_META.len()..src_pos])
+            .lines().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect(),
+        _ => Vec::new(),
+    };
+    let src = std::str::from_utf8(&bytes[src_pos + LEGACY_SRC.len()..])
+        .map_err(|_| "compiled artifact contains invalid utf8".to_string())?;
+    let mut parser = crate::parser::Parser::new(src);
+    Ok(Artifact { meta, program: parser.parse_program() })
+}
+
+pub(crate) fn fnv1a(data: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in data {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+/// Encoder shared with `snapshot`
+#[derive(Default)]
+pub(crate) struct Writer {
+    pub(crate) buf: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn u8(&mut self, v: u8) { self.buf.push(v); }
+    pub(crate) fn u32(&mut self, v: u32) { self.buf.extend_from_slice(&v.to_le_bytes()); }
+    pub(crate) fn u64(&mut self, v: u64) { self.buf.extend_from_slice(&v.to_le_bytes()); }
+    pub(crate) fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+    pub(crate) fn strs(&mut self, v: &[String]) {
+        self.u32(v.len() as u32);
+        for s in v { self.str(s); }
+    }
+    pub(crate) fn opt_str(&mut self, s: &Option<String>) {
+        match s {
+            Some(s) => { self.u8(1); self.str(s); }
+            None => self.u8(0),
+        }
+    }
+    pub(crate) fn stmts(&mut self, v: &[Stmt]) {
+        self.u32(v.len() as u32);
+        for s in v { self.stmt(s); }
+    }
+    fn exprs(&mut self, v: &[Expr]) {
+        self.u32(v.len() as u32);
+        for e in v { self.expr(e); }
+    }
+
+    fn stmt(&mut self, s: &Stmt) {
+        match s {
+            Stmt::VarDecl { type_name, name, value, line } => {
+                self.u8(0); self.str(type_name); self.str(name); self.expr(value); self.u32(*line as u32);
+            }
+            Stmt::ExprStmt { expr, line } => { self.u8(1); self.expr(expr); self.u32(*line as u32); }
+            Stmt::FunctionDecl { name, params, param_types, ret_type, body } => {
+                self.u8(2); self.str(name); self.strs(params);
+                self.u32(param_types.len() as u32);
+                for t in param_types { self.opt_str(t); }
+                self.opt_str(ret_type);
+                self.stmts(body);
+            }
+            Stmt::ClassDecl { name, fields, body, is_data } => {
+                self.u8(3); self.str(name); self.strs(fields); self.stmts(body); self.u8(*is_data as u8);
+            }
+            Stmt::MemberAssign { receiver, name, value, line } => {
+                self.u8(4); self.expr(receiver); self.str(name); self.expr(value); self.u32(*line as u32);
+            }
+            Stmt::Block(stmts) => { self.u8(5); self.stmts(stmts); }
+        }
+    }
+
+    fn expr(&mut self, e: &Expr) {
+        match e {
+            Expr::Int(n) => { self.u8(0); self.u64(*n as u64); }
+            Expr::Float(f) => { self.u8(1); self.u64(f.to_bits()); }
+            Expr::Str(s) => { self.u8(2); self.str(s); }
+            Expr::Ident(n) => { self.u8(3); self.str(n); }
+            Expr::Local { slot, name } => { self.u8(4); self.u32(*slot as u32); self.str(name); }
+            Expr::BinaryOp { left, op, right } => {
+                let op = match op { BinOp::Add => 0, BinOp::Sub => 1, BinOp::Mul => 2, BinOp::Div => 3 };
+                self.u8(5); self.expr(left); self.u8(op); self.expr(right);
+            }
+            Expr::Call { func, args, line } => {
+                self.u8(6); self.expr(func); self.exprs(args); self.u32(*line as u32);
+            }
+            Expr::MemberCall { receiver, method, args, line, .. } => {
+                self.u8(7); self.expr(receiver); self.str(method); self.exprs(args); self.u32(*line as u32);
+            }
+            Expr::MemberAccess { receiver, field } => { self.u8(8); self.expr(receiver); self.str(field); }
+            Expr::Intrinsic { op, args, line } => {
+                let op = Intrinsic::ALL.iter().position(|i| i == op).unwrap_or(0);
+                self.u8(9); self.u8(op as u8); self.exprs(args); self.u32(*line as u32);
+            }
+        }
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+    pub(crate) pos: usize,
+    /// member call sites are renumbered on load
+    sites: usize,
+    /// format version of the program being read
+    version: u16,
+    /// levels of nesting being read, up to `MAX_NESTING`
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0, sites: 0, version: VERSION, depth: 0 }
+    }
+    pub(crate) fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(n).filter(|e| *e <= self.buf.len()).ok_or("truncated artifact")?;
+        let s = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(s)
+    }
+    pub(crate) fn u8(&mut self) -> Result<u8, String> { Ok(self.take(1)?[0]) }
+    pub(crate) fn u32(&mut self) -> Result<u32, String> { Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap())) }
+    pub(crate) fn u64(&mut self) -> Result<u64, String> { Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap())) }
+    pub(crate) fn str(&mut self) -> Result<String, String> {
+        let n = self.u32()? as usize;
+        String::from_utf8(self.take(n)?.to_vec()).map_err(|_| "invalid utf8 in artifact".to_string())
+    }
+    pub(crate) fn strs(&mut self) -> Result<Vec<String>, String> {
+        (0..self.u32()?).map(|_| self.str()).collect()
+    }
+    pub(crate) fn opt_str(&mut self) -> Result<Option<String>, String> {
+        Ok(if self.u8()? == 1 { Some(self.str()?) } else { None })
+    }
+    pub(crate) fn stmts(&mut self) -> Result<Vec<Stmt>, String> {
+        (0..self.u32()?).map(|_| self.stmt()).collect()
+    }
+    fn exprs(&mut self) -> Result<Vec<Expr>, String> {
+        (0..self.u32()?).map(|_| self.expr()).collect()
+    }
+    fn next_site(&mut self) -> usize {
+        self.sites += 1;
+        self.sites - 1
+    }
+    fn boxed(&mut self) -> Result<Box<Expr>, String> { Ok(Box::new(self.expr()?)) }
+    /// Go one level deeper, or fail past `MAX_NESTING`; paired with `leave`
+    pub(crate) fn enter(&mut self) -> Result<(), String> {
+        if self.depth >= MAX_NESTING { return Err(format!("nested more than {} levels deep", MAX_NESTING)); }
+        self.depth += 1;
+        Ok(())
+    }
+    pub(crate) fn leave(&mut self) { self.depth -= 1; }
+    /// Source line of a statement; version 1 did not store it
+    fn line(&mut self) -> Result<usize, String> {
+        if self.version < 2 { return Ok(0); }
+        Ok(self.u32()? as usize)
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, String> {
+        self.enter()?;
+        let stmt = self.stmt_tagged();
+        self.leave();
+        stmt
+    }
+
+    fn stmt_tagged(&mut self) -> Result<Stmt, String> {
+        Ok(match self.u8()? {
+            0 => Stmt::VarDecl { type_name: self.str()?, name: self.str()?, value: self.expr()?, line: self.line()? },
+            1 => Stmt::ExprStmt { expr: self.expr()?, line: self.line()? },
+            2 => {
+                let name = self.str()?;
+                let params = self.strs()?;
+                let param_types = (0..self.u32()?).map(|_| self.opt_str()).collect::<Result<_, _>>()?;
+                Stmt::FunctionDecl { name, params, param_types, ret_type: self.opt_str()?, body: self.stmts()? }
+            }
+            3 => Stmt::ClassDecl { name: self.str()?, fields: self.strs()?, body: self.stmts()?, is_data: self.u8()? != 0 },
+            4 => Stmt::MemberAssign { receiver: self.expr()?, name: self.str()?, value: self.expr()?, line: self.line()? },
+            5 => Stmt::Block(self.stmts()?),
+            t => return Err(format!("bad statement tag {} in artifact", t)),
+        })
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        self.enter()?;
+        let expr = self.expr_tagged();
+        self.leave();
+        expr
+    }
+
+    fn expr_tagged(&mut self) -> Result<Expr, String> {
+        Ok(match self.u8()? {
+            0 => Expr::Int(self.u64()? as i64),
+            1 => Expr::Float(f64::from_bits(self.u64()?)),
+            2 => Expr::Str(self.str()?),
+            3 => Expr::Ident(self.str()?),
+            4 => Expr::Local { slot: self.u32()? as usize, name: self.str()? },
+            5 => {
+                let left = self.boxed()?;
+                let op = match self.u8()? {
+                    0 => BinOp::Add, 1 => BinOp::Sub, 2 => BinOp::Mul, 3 => BinOp::Div,
+                    t => return Err(format!("bad operator {} in artifact", t)),
+                };
+                Expr::BinaryOp { left, op, right: self.boxed()? }
+            }
+            6 => Expr::Call { func: self.boxed()?, args: self.exprs()?, line: self.u32()? as usize },
+            7 => Expr::MemberCall {
+                receiver: self.boxed()?, method: self.str()?, args: self.exprs()?, line: self.u32()? as usize,
+                site: self.next_site(),
+            },
+            8 => Expr::MemberAccess { receiver: self.boxed()?, field: self.str()? },
+            9 => {
+                let t = self.u8()?;
+                let op = *Intrinsic::ALL.get(t as usize).ok_or_else(|| format!("bad intrinsic {} in artifact", t))?;
+                Expr::Intrinsic { op, args: self.exprs()?, line: self.u32()? as usize }
+            }
+            t => return Err(format!("bad expression tag {} in artifact", t)),
+        })
+    }
+}
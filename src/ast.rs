@@ -4,40 +4,267 @@ pub enum Expr {
     Float(f64),
     Str(String),
     Ident(String),
+    /// Parameter reference resolved to a frame slot (see `resolver`)
+    Local { slot: usize, name: String },
     BinaryOp {
         left: Box<Expr>,
         op: BinOp,
         right: Box<Expr>,
     },
+    /// `line` is the call site, reported in stack traces
     Call {
         func: Box<Expr>,
         args: Vec<Expr>,
+        line: usize,
     },
-    /// receiver.method(args)
+    /// receiver.method(args); `site` numbers the call site within its program and
+    /// indexes the VM's method cache
     MemberCall {
         receiver: Box<Expr>,
         method: String,
         args: Vec<Expr>,
+        line: usize,
+        site: usize,
     },
     /// receiver.field access
     MemberAccess {
         receiver: Box<Expr>,
         field: String,
     },
+    /// Call of a hot builtin resolved by the parser, evaluated without the
+    /// builtin registry (see `Intrinsic`)
+    Intrinsic {
+        op: Intrinsic,
+        args: Vec<Expr>,
+        line: usize,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BinOp { Add, Sub, Mul, Div }
 
+/// Builtins common enough in loops and data code to get their own node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intrinsic { List, Len, Push, At, Abs, Min, Max, Sqrt }
+
+impl Intrinsic {
+    pub const ALL: [Intrinsic; 8] = [Intrinsic::List, Intrinsic::Len, Intrinsic::Push, Intrinsic::At,
+        Intrinsic::Abs, Intrinsic::Min, Intrinsic::Max, Intrinsic::Sqrt];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|i| i.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Intrinsic::List => "list",
+            Intrinsic::Len => "len",
+            Intrinsic::Push => "push",
+            Intrinsic::At => "at",
+            Intrinsic::Abs => "abs",
+            Intrinsic::Min => "min",
+            Intrinsic::Max => "max",
+            Intrinsic::Sqrt => "sqrt",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Stmt {
-    VarDecl { type_name: String, name: String, value: Expr },
-    ExprStmt(Expr),
-    FunctionDecl { name: String, params: Vec<String>, body: Vec<Stmt> },
-    ClassDecl { name: String, body: Vec<Stmt> },
+    /// `line` of these three is where the statement starts (0 = generated);
+    /// breakpoints are matched against it
+    VarDecl { type_name: String, name: String, value: Expr, line: usize },
+    ExprStmt { expr: Expr, line: usize },
+    /// `rtd add(int-a, int-b) -> int { ... }`; annotations are optional per parameter
+    FunctionDecl { name: String, params: Vec<String>, param_types: Vec<Option<String>>, ret_type: Option<String>, body: Vec<Stmt> },
+    /// `class Name(f1, f2) { ... }` — header fields are captured by a generated `__init__`;
+    /// `data class` additionally generates `__eq__`, `__str__` and `copy_with`
+    ClassDecl { name: String, fields: Vec<String>, body: Vec<Stmt>, is_data: bool },
     /// receiver.field = expr;
-    MemberAssign { receiver: Expr, name: String, value: Expr },
+    MemberAssign { receiver: Expr, name: String, value: Expr, line: usize },
     Block(Vec<Stmt>),
 }
 
+impl Stmt {
+    /// Source line a debugger can stop at; declarations and blocks have none
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Stmt::VarDecl { line, .. } | Stmt::ExprStmt { line, .. } | Stmt::MemberAssign { line, .. } => (*line > 0).then_some(*line),
+            _ => None,
+        }
+    }
+}
+
 pub type Program = Vec<Stmt>;
+
+/// Read-only walk over a tree, for passes that look at some node kinds and
+/// only need to get past the rest. Both hooks default to visiting the
+/// node's children; an override calls `walk_stmt` / `walk_expr` itself to
+/// keep descending.
+pub trait Visitor {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+}
+
+/// Visit the statements and expressions directly inside `stmt`
+pub fn walk_stmt<V: Visitor + ?Sized>(v: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::VarDecl { value, .. } => v.visit_expr(value),
+        Stmt::ExprStmt { expr, .. } => v.visit_expr(expr),
+        Stmt::FunctionDecl { body, .. } | Stmt::ClassDecl { body, .. } | Stmt::Block(body) => {
+            for s in body { v.visit_stmt(s); }
+        }
+        Stmt::MemberAssign { receiver, value, .. } => { v.visit_expr(receiver); v.visit_expr(value); }
+    }
+}
+
+/// Visit the expressions directly inside `expr`
+pub fn walk_expr<V: Visitor + ?Sized>(v: &mut V, expr: &Expr) {
+    match expr {
+        Expr::BinaryOp { left, right, .. } => { v.visit_expr(left); v.visit_expr(right); }
+        Expr::Call { func, args, .. } => { v.visit_expr(func); for a in args { v.visit_expr(a); } }
+        Expr::MemberCall { receiver, args, .. } => { v.visit_expr(receiver); for a in args { v.visit_expr(a); } }
+        Expr::MemberAccess { receiver, .. } => v.visit_expr(receiver),
+        Expr::Intrinsic { args, .. } => for a in args { v.visit_expr(a); },
+        Expr::Int(_) | Expr::Float(_) | Expr::Str(_) | Expr::Ident(_) | Expr::Local { .. } => {}
+    }
+}
+
+/// Source text for `prog`, one statement per line, four-space indents.
+/// Parsing it gives the same tree, except that nothing is left to expand
+/// and member calls on non-names (possible after macro expansion) do not
+/// parse again.
+pub fn to_source(prog: &[Stmt]) -> String {
+    let mut out = String::new();
+    for s in prog { write_stmt(&mut out, s, 0); }
+    out
+}
+
+fn write_body(out: &mut String, body: &[Stmt], indent: usize) {
+    out.push_str("{\n");
+    for s in body { write_stmt(out, s, indent + 1); }
+    out.push_str(&"    ".repeat(indent));
+    out.push_str("}\n");
+}
+
+fn write_stmt(out: &mut String, stmt: &Stmt, indent: usize) {
+    let pad = "    ".repeat(indent);
+    match stmt {
+        Stmt::VarDecl { type_name, name, value, .. } => out.push_str(&format!("{}{}-{} = {};\n", pad, type_name, name, value)),
+        Stmt::ExprStmt { expr, .. } => out.push_str(&format!("{}{};\n", pad, expr)),
+        Stmt::MemberAssign { receiver, name, value, .. } => out.push_str(&format!("{}{}.{} = {};\n", pad, receiver, name, value)),
+        Stmt::FunctionDecl { name, params, param_types, ret_type, body } => {
+            let params: Vec<String> = params.iter().zip(param_types)
+                .map(|(p, t)| t.as_ref().map_or(p.clone(), |t| format!("{}-{}", t, p)))
+                .collect();
+            let ret = ret_type.as_ref().map_or(String::new(), |t| format!(" -> {}", t));
+            out.push_str(&format!("{}rtd {}({}){} ", pad, name, params.join(", "), ret));
+            write_body(out, body, indent);
+        }
+        Stmt::ClassDecl { name, fields, body, is_data } => {
+            let header = if fields.is_empty() { String::new() } else { format!("({})", fields.join(", ")) };
+            out.push_str(&format!("{}{}class {}{}", pad, if *is_data { "data " } else { "" }, name, header));
+            if body.is_empty() && !fields.is_empty() { out.push_str(";\n"); return; }
+            out.push(' ');
+            write_body(out, body, indent);
+        }
+        Stmt::Block(b) => for s in b { write_stmt(out, s, indent); },
+    }
+}
+
+/// Source form; operators are left-associative without precedence, so only
+/// a compound right operand needs parentheses
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |args: &[Expr]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ");
+        match self {
+            Expr::Int(n) => write!(f, "{}", n),
+            Expr::Float(x) => write!(f, "{:?}", x),
+            Expr::Str(s) => write!(f, "\"{}\"", s),
+            Expr::Ident(name) | Expr::Local { name, .. } => f.write_str(name),
+            Expr::BinaryOp { left, op, right } => {
+                let op = match op { BinOp::Add => "+", BinOp::Sub => "-", BinOp::Mul => "*", BinOp::Div => "/" };
+                match right.as_ref() {
+                    Expr::BinaryOp { .. } => write!(f, "{} {} ({})", left, op, right),
+                    _ => write!(f, "{} {} {}", left, op, right),
+                }
+            }
+            Expr::Call { func, args, .. } => write!(f, "{}({})", func, list(args)),
+            Expr::MemberCall { receiver, method, args, .. } => write!(f, "{}.{}({})", receiver, method, list(args)),
+            Expr::MemberAccess { receiver, field } => write!(f, "{}.{}", receiver, field),
+            Expr::Intrinsic { op, args, .. } => write!(f, "{}({})", op.name(), list(args)),
+        }
+    }
+}
+
+/// The tree as JSON for external tools: an array of statements, each node an
+/// object with a `kind` (the variant name) and its fields under their names
+pub fn to_json(prog: &[Stmt]) -> String {
+    format!("[{}]", prog.iter().map(stmt_json).collect::<Vec<_>>().join(","))
+}
+
+fn json_str(s: &str) -> String {
+    format!("\"{}\"", crate::json::escape(s))
+}
+
+fn json_list<T>(items: &[T], f: impl Fn(&T) -> String) -> String {
+    format!("[{}]", items.iter().map(f).collect::<Vec<_>>().join(","))
+}
+
+fn stmt_json(stmt: &Stmt) -> String {
+    match stmt {
+        Stmt::VarDecl { type_name, name, value, line } => format!(
+            "{{\"kind\":\"VarDecl\",\"line\":{},\"type_name\":{},\"name\":{},\"value\":{}}}",
+            line, json_str(type_name), json_str(name), expr_json(value),
+        ),
+        Stmt::ExprStmt { expr, line } => format!("{{\"kind\":\"ExprStmt\",\"line\":{},\"expr\":{}}}", line, expr_json(expr)),
+        Stmt::FunctionDecl { name, params, param_types, ret_type, body } => format!(
+            "{{\"kind\":\"FunctionDecl\",\"name\":{},\"params\":{},\"param_types\":{},\"ret_type\":{},\"body\":{}}}",
+            json_str(name), json_list(params, |p| json_str(p)),
+            json_list(param_types, |t| t.as_deref().map_or("null".to_string(), json_str)),
+            ret_type.as_deref().map_or("null".to_string(), json_str), to_json(body),
+        ),
+        Stmt::ClassDecl { name, fields, body, is_data } => format!(
+            "{{\"kind\":\"ClassDecl\",\"name\":{},\"fields\":{},\"is_data\":{},\"body\":{}}}",
+            json_str(name), json_list(fields, |f| json_str(f)), is_data, to_json(body),
+        ),
+        Stmt::MemberAssign { receiver, name, value, line } => format!(
+            "{{\"kind\":\"MemberAssign\",\"line\":{},\"receiver\":{},\"name\":{},\"value\":{}}}",
+            line, expr_json(receiver), json_str(name), expr_json(value),
+        ),
+        Stmt::Block(b) => format!("{{\"kind\":\"Block\",\"body\":{}}}", to_json(b)),
+    }
+}
+
+fn expr_json(expr: &Expr) -> String {
+    let args = |args: &[Expr]| json_list(args, expr_json);
+    match expr {
+        Expr::Int(n) => format!("{{\"kind\":\"Int\",\"value\":{}}}", n),
+        // JSON has no infinity; a literal too large for f64 is the only way to get one
+        Expr::Float(x) if x.is_finite() => format!("{{\"kind\":\"Float\",\"value\":{:?}}}", x),
+        Expr::Float(_) => "{\"kind\":\"Float\",\"value\":null}".to_string(),
+        Expr::Str(s) => format!("{{\"kind\":\"Str\",\"value\":{}}}", json_str(s)),
+        Expr::Ident(name) => format!("{{\"kind\":\"Ident\",\"name\":{}}}", json_str(name)),
+        Expr::Local { slot, name } => format!("{{\"kind\":\"Local\",\"slot\":{},\"name\":{}}}", slot, json_str(name)),
+        Expr::BinaryOp { left, op, right } => {
+            let op = match op { BinOp::Add => "+", BinOp::Sub => "-", BinOp::Mul => "*", BinOp::Div => "/" };
+            format!("{{\"kind\":\"BinaryOp\",\"op\":\"{}\",\"left\":{},\"right\":{}}}", op, expr_json(left), expr_json(right))
+        }
+        Expr::Call { func, args: a, line } => format!("{{\"kind\":\"Call\",\"line\":{},\"func\":{},\"args\":{}}}", line, expr_json(func), args(a)),
+        Expr::MemberCall { receiver, method, args: a, line, site } => format!(
+            "{{\"kind\":\"MemberCall\",\"line\":{},\"site\":{},\"receiver\":{},\"method\":{},\"args\":{}}}",
+            line, site, expr_json(receiver), json_str(method), args(a),
+        ),
+        Expr::MemberAccess { receiver, field } => {
+            format!("{{\"kind\":\"MemberAccess\",\"receiver\":{},\"field\":{}}}", expr_json(receiver), json_str(field))
+        }
+        Expr::Intrinsic { op, args: a, line } => {
+            format!("{{\"kind\":\"Intrinsic\",\"line\":{},\"op\":\"{}\",\"args\":{}}}", line, op.name(), args(a))
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Base64 (RFC 4648, standard alphabet with `=` padding) for the `b64_*`
+//! builtins, `gui_blit_b64` and the WebSocket handshake.
+
+const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn value(c: char) -> Option<u32> {
+    Some(match c {
+        'A'..='Z' => c as u32 - 'A' as u32,
+        'a'..='z' => c as u32 - 'a' as u32 + 26,
+        '0'..='9' => c as u32 - '0' as u32 + 52,
+        '+' => 62,
+        '/' => 63,
+        _ => return None,
+    })
+}
+
+pub fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() { out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char); } else { out.push('='); }
+        }
+    }
+    out
+}
+
+/// Whitespace is ignored and padding is optional; anything else that is not
+/// base64 is an error
+pub fn decode(s: &str) -> Result<Vec<u8>, String> {
+    let chars: Vec<char> = s.chars().filter(|c| !c.is_whitespace()).collect();
+    let body = chars.iter().position(|c| *c == '=').map_or(&chars[..], |i| &chars[..i]);
+    let padding = chars.len() - body.len();
+    if padding > 2 || chars[body.len()..].iter().any(|c| *c != '=') || (padding > 0 && !chars.len().is_multiple_of(4)) {
+        return Err("bad base64 padding".to_string());
+    }
+    if body.len() % 4 == 1 { return Err("truncated base64".to_string()); }
+    let mut out = Vec::with_capacity(body.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0u32);
+    for c in body {
+        let v = value(*c).ok_or_else(|| format!("bad base64 character {:?}", c))?;
+        bits = (bits << 6 | v) & 0xFFFFFF;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Ok(out)
+}
+
+/// Skips anything that is not base64 and stops at padding; for pixel data,
+/// where a best effort beats failing
+pub fn decode_lenient(s: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    let (mut bits, mut count) = (0u32, 0u32);
+    for c in s.chars() {
+        if c == '=' { break; }
+        let Some(v) = value(c) else { continue };
+        bits = (bits << 6 | v) & 0xFFFFFF;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    out
+}
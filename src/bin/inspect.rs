@@ -1,21 +1,80 @@
+//! Dump what the front end makes of a script: the source, its tokens with
+//! positions, the `os:`/`its:` header lines and the parsed program.
+//! `--json` prints one JSON object instead, for editor plugins and other
+//! tools; `--only tokens|ast|meta` limits the output to one part.
+use userd::json::escape;
+
+const USAGE: &str = "usage: inspect [--json] [--only tokens|ast|meta] <file>";
+
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 { println!("usage: inspect <file>"); return; }
-    let path = &args[1];
-    let s = std::fs::read_to_string(path).expect("read file");
-    println!("--- FILE: {} ---", path);
-    for (i, line) in s.lines().enumerate() {
-        println!("{:04}: {}", i+1, line);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let json = args.iter().any(|a| a == "--json");
+    let mut only = None;
+    let mut path = None;
+    let mut it = args.iter();
+    while let Some(a) = it.next() {
+        match a.as_str() {
+            "--json" => {}
+            "--only" => only = it.next().map(String::as_str),
+            a if a.starts_with("--only=") => only = Some(&a["--only=".len()..]),
+            a if a.starts_with("--") => { eprintln!("unknown option {}\n{}", a, USAGE); std::process::exit(2); }
+            a => path = Some(a),
+        }
     }
-    println!("--- LEXER TOKENS ---");
-    let mut l = userd::lexer::Lexer::new(&s);
-    loop {
-        let t = l.next_token();
-        println!("{:?}", t);
-        if t.is_eof() { break; }
+    if !matches!(only, None | Some("tokens" | "ast" | "meta")) {
+        eprintln!("--only takes tokens, ast or meta\n{}", USAGE);
+        std::process::exit(2);
     }
-    println!("--- PARSED PROGRAM ---");
+    let Some(path) = path else { eprintln!("{}", USAGE); std::process::exit(2) };
+    let s = match std::fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => { eprintln!("failed to read {}: {}", path, e); std::process::exit(1); }
+    };
+    let part = |name: &str| only.is_none_or(|o| o == name);
+
+    let tokens = userd::lexer::Lexer::tokens(&s);
+    let meta = userd::artifact::meta_lines(&s);
     let mut p = userd::parser::Parser::new(&s);
     let prog = p.parse_program();
-    println!("{:?}", prog);
+
+    if json {
+        let mut fields = vec![format!("\"file\":\"{}\"", escape(path))];
+        if part("meta") {
+            fields.push(format!("\"meta\":[{}]", meta.iter().map(|m| format!("\"{}\"", escape(m))).collect::<Vec<_>>().join(",")));
+        }
+        if part("tokens") {
+            fields.push(format!("\"tokens\":[{}]", tokens.iter().map(|(t, span)| t.to_json(span)).collect::<Vec<_>>().join(",")));
+        }
+        if part("ast") {
+            fields.push(format!("\"ast\":{}", userd::ast::to_json(&prog)));
+            let errors: Vec<String> = p.errors().iter()
+                .map(|e| format!("{{\"line\":{},\"message\":\"{}\"}}", e.line().unwrap_or(0), escape(&e.to_string())))
+                .collect();
+            fields.push(format!("\"errors\":[{}]", errors.join(",")));
+        }
+        println!("{{{}}}", fields.join(","));
+        return;
+    }
+
+    if only.is_none() {
+        println!("--- FILE: {} ---", path);
+        for (i, line) in s.lines().enumerate() {
+            println!("{:04}: {}", i+1, line);
+        }
+    }
+    if part("meta") {
+        println!("--- META ---");
+        for m in meta.iter() { println!("{}", m); }
+    }
+    if part("tokens") {
+        println!("--- LEXER TOKENS ---");
+        for (t, span) in tokens.iter() {
+            println!("{}:{} {:?}", span.line, span.col, t);
+        }
+    }
+    if part("ast") {
+        println!("--- PARSED PROGRAM ---");
+        println!("{:?}", prog);
+        for e in p.errors() { println!("error: {}", e); }
+    }
 }
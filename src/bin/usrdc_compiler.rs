@@ -1,14 +1,17 @@
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let all: Vec<String> = std::env::args().collect();
+    // --force writes the output even when the script has parse errors
+    let force = all.iter().any(|a| a == "--force");
+    let args: Vec<String> = all.into_iter().filter(|a| a != "--force").collect();
     if args.len() < 3 {
-        eprintln!("usage: usrdc_compiler <in.usrd> <out.usrdc>\nor: usrdc_compiler pack <template.exe> <in.usrd> <out.exe>");
+        eprintln!("usage: usrdc_compiler [--force] <in.usrd> <out.usrdc>\nor: usrdc_compiler pack [--force] <template.exe> <in.usrd> <out.exe>");
         std::process::exit(2);
     }
 
     // Support a pack mode to create a self-contained EXE by embedding the script into a template exe.
     if args[1] == "pack" {
         if args.len() < 5 {
-            eprintln!("usage: usrdc_compiler pack <template.exe> <in.usrd> <out.exe>");
+            eprintln!("usage: usrdc_compiler pack [--force] <template.exe> <in.usrd> <out.exe>");
             std::process::exit(2);
         }
         let template = &args[2];
@@ -18,13 +21,14 @@ fn main() {
             Ok(s) => s,
             Err(e) => { eprintln!("failed to read {}: {}", input, e); std::process::exit(1); }
         };
+        let compiled = compile(input, &src, force);
         let mut tpl = match std::fs::read(template) {
             Ok(b) => b,
             Err(e) => { eprintln!("failed to read template {}: {}", template, e); std::process::exit(1); }
         };
-        // append marker and script
-        tpl.extend_from_slice(b"\n__USRDSCRIPT__\n");
-        tpl.extend_from_slice(src.as_bytes());
+        // append marker and the compiled program, gzipped like `userd pack`
+        tpl.extend_from_slice(userd::artifact::PACK_MARKER);
+        tpl.extend_from_slice(&userd::gzip::compress(&compiled));
         match std::fs::write(outexe, &tpl) {
             Ok(_) => println!("packed {} + {} -> {}", template, input, outexe),
             Err(e) => { eprintln!("failed to write {}: {}", outexe, e); std::process::exit(1); }
@@ -38,27 +42,22 @@ fn main() {
         Ok(s) => s,
         Err(e) => { eprintln!("failed to read {}: {}", input, e); std::process::exit(1); }
     };
-    // Basic validation: parse
-    let mut parser = userd::parser::Parser::new(&src);
-    let _prog = parser.parse_program();
-    // extract simple metadata headers
-    let mut meta_lines: Vec<String> = Vec::new();
-    for line in src.lines().take(16) {
-        if line.trim().is_empty() { continue; }
-        let l = line.trim();
-        if l.to_lowercase().starts_with("os:") || l.to_lowercase().starts_with("its:") {
-            meta_lines.push(l.to_string());
-        }
-    }
-    let meta_marker = b"__USRDMETA__\n";
-    let src_marker = b"__USRDSRC__\n";
-    let mut out_bytes: Vec<u8> = Vec::new();
-    out_bytes.extend_from_slice(meta_marker);
-    for m in meta_lines.iter() { out_bytes.extend_from_slice(m.as_bytes()); out_bytes.push(b'\n'); }
-    out_bytes.extend_from_slice(src_marker);
-    out_bytes.extend_from_slice(src.as_bytes());
+    let out_bytes = compile(input, &src, force);
     match std::fs::write(out, out_bytes) {
         Ok(_) => println!("compiled {} -> {}", input, out),
         Err(e) => { eprintln!("failed to write {}: {}", out, e); std::process::exit(1); }
     }
 }
+
+/// The artifact of `src`; parse errors are printed as `path: line N: message`
+/// and end the program unless `force` is set
+fn compile(path: &str, src: &str, force: bool) -> Vec<u8> {
+    match userd::artifact::compile_checked(src) {
+        Ok(bytes) => bytes,
+        Err(errors) => {
+            for e in errors.iter() { eprintln!("{}: {}", path, e); }
+            if !force { std::process::exit(1); }
+            userd::artifact::compile(src)
+        }
+    }
+}
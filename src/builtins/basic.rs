@@ -0,0 +1,152 @@
+//! Conversions (including base64 and gzip), arithmetic helpers, randomness, sleeping, yielding and console input.
+//! The last three go through the VM so `VM::deterministic` can replace them.
+use super::{bytes_arg, from_args, int_arg, str_arg, Registry, VARIADIC};
+use crate::ast::Intrinsic;
+use crate::vm::{intrinsic, Capability, Value};
+
+pub(super) fn register(r: &mut Registry) {
+    // the parser turns calls of these into `Expr::Intrinsic`, which skips the
+    // registry; these entries document them and are what a host replaces
+    r.register("list", VARIADIC, None, |_vm, args| intrinsic(Intrinsic::List, args))
+        .describe("list(values...) -> list", "A new list holding the arguments");
+    r.register("len", 1, None, |_vm, args| intrinsic(Intrinsic::Len, args))
+        .describe("len(x) -> int", "Number of items in a list or characters in a string");
+    r.register("push", 2, None, |_vm, args| intrinsic(Intrinsic::Push, args))
+        .describe("push(list-items, value) -> int", "Append value to a list in place; returns the new length");
+    r.register("at", 2, None, |_vm, args| intrinsic(Intrinsic::At, args))
+        .describe("at(x, int-index) -> value", "Item of a list or character of a string at a 0-based index");
+    r.register("abs", 1, None, |_vm, args| intrinsic(Intrinsic::Abs, args))
+        .describe("abs(x) -> number", "Absolute value; fails for the smallest int, whose absolute value does not fit");
+    r.register("min", 2, None, |_vm, args| intrinsic(Intrinsic::Min, args))
+        .describe("min(a, b) -> number", "The smaller of two numbers; an int if both are ints, else a float");
+    r.register("max", 2, None, |_vm, args| intrinsic(Intrinsic::Max, args))
+        .describe("max(a, b) -> number", "The larger of two numbers; an int if both are ints, else a float");
+    r.register("sqrt", 1, None, |_vm, args| intrinsic(Intrinsic::Sqrt, args))
+        .describe("sqrt(x) -> float", "Square root of a number");
+    r.register("get", 1, Some(Capability::Stdin), |vm, args| {
+        let prompt = match &args[0] {
+            Value::Str(s) => s.clone(),
+            Value::Int(n) => n.to_string(),
+            _ => return Err("get: prompt must be string or int".to_string()),
+        };
+        vm.prompt(&prompt);
+        Ok(Value::Str(vm.read_line()?))
+    }).describe("get(prompt) -> str", "Print the prompt and read a line from stdin");
+    r.register("to_int", 1, None, |_vm, args| match &args[0] {
+        Value::Int(n) => Ok(Value::Int(*n)),
+        Value::Str(s) => s.trim().parse::<i64>().map(Value::Int).map_err(|_| "to_int: parse error".to_string()),
+        _ => Err("to_int: unsupported argument type".to_string()),
+    }).describe("to_int(x) -> int", "Parse a string as an integer; ints are returned as they are");
+    r.register("to_float", 1, None, |_vm, args| match &args[0] {
+        Value::Float(n) => Ok(Value::Float(*n)),
+        Value::Int(n) => Ok(Value::Float(*n as f64)),
+        Value::Str(s) => s.trim().parse::<f64>().map(Value::Float).map_err(|_| "to_float: parse error".to_string()),
+        _ => Err("to_float: unsupported argument type".to_string()),
+    }).describe("to_float(x) -> float", "Parse a string or widen an int to a float");
+    r.register("json_stringify", 2, None, |_vm, args| {
+        let canonical = int_arg("json_stringify", args, 1, "canonical")? != 0;
+        crate::json::stringify(&args[0], canonical).map(Value::Str)
+    }).describe("json_stringify(value, int-canonical) -> str", "JSON text of a value; canonical != 0 sorts keys and fixes the float format");
+    r.register("b64_encode", 1, None, |_vm, args| {
+        Ok(Value::Str(crate::base64::encode(&bytes_arg("b64_encode", args, 0, "data")?)))
+    }).describe("b64_encode(data) -> str", "Base64 of a string's UTF-8 bytes or of a list of byte ints");
+    r.register("b64_decode", 1, None, |_vm, args| {
+        let bytes = crate::base64::decode(&str_arg("b64_decode", args, 0, "text")?).map_err(|e| format!("b64_decode: {}", e))?;
+        String::from_utf8(bytes).map(Value::Str).map_err(|_| "b64_decode: not UTF-8 text; use b64_decode_bytes".to_string())
+    }).describe("b64_decode(str-text) -> str", "Decode base64 to text; fails if the bytes are not UTF-8");
+    r.register("b64_decode_bytes", 1, None, |_vm, args| {
+        let bytes = crate::base64::decode(&str_arg("b64_decode_bytes", args, 0, "text")?).map_err(|e| format!("b64_decode_bytes: {}", e))?;
+        Ok(Value::from(bytes.into_iter().map(|b| Value::Int(b as i64)).collect::<Vec<_>>()))
+    }).describe("b64_decode_bytes(str-text) -> list", "Decode base64 to a list of byte ints");
+    r.register("gzip_compress", 1, None, |_vm, args| {
+        let data = bytes_arg("gzip_compress", args, 0, "data")?;
+        Ok(Value::from(crate::gzip::compress(&data).into_iter().map(|b| Value::Int(b as i64)).collect::<Vec<_>>()))
+    }).describe("gzip_compress(data) -> list", "gzip a str or list of byte ints; returns byte ints");
+    r.register("gzip_decompress", 1, None, |_vm, args| {
+        let bytes = crate::gzip::decompress(&bytes_arg("gzip_decompress", args, 0, "bytes")?).map_err(|e| format!("gzip_decompress: {}", e))?;
+        Ok(Value::from(bytes.into_iter().map(|b| Value::Int(b as i64)).collect::<Vec<_>>()))
+    }).describe("gzip_decompress(bytes) -> list", "Contents of gzip data as byte ints");
+    r.register("gzip_decompress_text", 1, None, |_vm, args| {
+        let bytes = crate::gzip::decompress(&bytes_arg("gzip_decompress_text", args, 0, "bytes")?).map_err(|e| format!("gzip_decompress_text: {}", e))?;
+        String::from_utf8(bytes).map(Value::Str).map_err(|_| "gzip_decompress_text: not UTF-8 text; use gzip_decompress".to_string())
+    }).describe("gzip_decompress_text(bytes) -> str", "Contents of gzip data as text; fails if it is not UTF-8");
+    r.register("apply_op", 3, None, |_vm, args| {
+        let (a, b, op): (i64, i64, i64) = from_args("apply_op", args)?;
+        match op {
+            1 => Ok(Value::Int(a + b)),
+            2 => Ok(Value::Int(a - b)),
+            3 => Ok(Value::Int(a * b)),
+            4 => Ok(Value::Int(a / b)),
+            _ => Err("apply_op: unknown op code".to_string()),
+        }
+    }).describe("apply_op(int-a, int-b, int-op) -> int", "Apply + - * / to a and b for op 1..4");
+    r.register("secure_random", 1, None, |vm, args| {
+        let max = int_arg("secure_random", args, 0, "max")?;
+        if max <= 0 { return Err("secure_random: max must be >0".to_string()); }
+        Ok(Value::Int(vm.random_below(max as u64)? as i64))
+    }).describe("secure_random(int-max) -> int", "Random integer in [0, max); seeded in deterministic mode");
+    r.register("random_float", 0, None, |vm, _args| {
+        // 53 random bits fill the mantissa exactly
+        Ok(Value::Float(vm.random_below(1 << 53)? as f64 / (1u64 << 53) as f64))
+    }).describe("random_float() -> float", "Random float in [0, 1)");
+    r.register("random_range", 2, None, |vm, args| {
+        let (lo, hi): (i64, i64) = from_args("random_range", args)?;
+        if hi <= lo { return Err("random_range: hi must be greater than lo".to_string()); }
+        Ok(Value::Int(lo.wrapping_add(vm.random_below(hi.wrapping_sub(lo) as u64)? as i64)))
+    }).describe("random_range(int-lo, int-hi) -> int", "Random integer in [lo, hi)");
+    r.register("shuffle", 1, None, |vm, args| {
+        let Value::List(items) = &args[0] else { return Err(format!("shuffle: expected a list, got {}", args[0].type_name())) };
+        let len = items.borrow().len();
+        // Fisher-Yates, in place
+        for i in (1..len).rev() {
+            let j = vm.random_below(i as u64 + 1)? as usize;
+            items.borrow_mut().swap(i, j);
+        }
+        Ok(args[0].clone())
+    }).describe("shuffle(list) -> list", "Shuffle a list in place and return it");
+    r.register("choice", 1, None, |vm, args| {
+        let Value::List(items) = &args[0] else { return Err(format!("choice: expected a list, got {}", args[0].type_name())) };
+        let len = items.borrow().len();
+        if len == 0 { return Err("choice: the list is empty".to_string()); }
+        let i = vm.random_below(len as u64)? as usize;
+        Ok(items.borrow()[i].clone())
+    }).describe("choice(list) -> value", "Random element of a non-empty list");
+    r.register("random_normal", 2, None, |vm, args| {
+        let (mean, std): (f64, f64) = from_args("random_normal", args)?;
+        crate::rand::normal_with(mean, std, |max| vm.random_below(max)).map(Value::Float).map_err(|e| format!("random_normal: {}", e))
+    }).describe("random_normal(float-mean, float-std) -> float", "Sample of the normal (Gaussian) distribution; seeded in deterministic mode");
+    r.register("rand_seed", 1, None, |vm, args| {
+        let seed = int_arg("rand_seed", args, 0, "n")?;
+        vm.seed_prng(seed as u64);
+        Ok(Value::Int(1))
+    }).describe("rand_seed(int-n) -> int", "Restart the rand_next sequence from seed n; the same seed gives the same numbers");
+    r.register("rand_next", 0, None, |vm, _args| {
+        // 63 bits, so the result is never negative
+        Ok(Value::Int((vm.prng()?.next_u64() >> 1) as i64))
+    }).describe("rand_next() -> int", "Next number in [0, 2^63) from the fast seedable generator (not for secrets)");
+    r.register("rand_below", 1, None, |vm, args| {
+        let max = int_arg("rand_below", args, 0, "max")?;
+        if max <= 0 { return Err("rand_below: max must be > 0".to_string()); }
+        Ok(Value::Int(vm.prng()?.below(max as u64)? as i64))
+    }).describe("rand_below(int-max) -> int", "Next number in [0, max) from the seedable generator");
+    r.register("sleep_ms", 1, None, |vm, args| {
+        let ms = int_arg("sleep_ms", args, 0, "arg")?;
+        vm.sleep_ms(ms as u64)?;
+        Ok(Value::Int(1))
+    }).describe("sleep_ms(int-ms) -> int", "Pause the program for ms milliseconds");
+    r.register("time_now", 0, None, |vm, _args| Ok(Value::Int(vm.time_now_ms() as i64)))
+        .describe("time_now() -> int", "Current Unix time in milliseconds");
+    r.register("perf_now_us", 0, None, |vm, _args| Ok(Value::Int(vm.perf_now_us() as i64)))
+        .describe("perf_now_us() -> int", "Microseconds on a high-resolution monotonic clock, for measuring intervals");
+    r.register("frame_wait", 1, None, |vm, args| {
+        let fps = int_arg("frame_wait", args, 0, "fps")?;
+        if fps <= 0 { return Err("frame_wait: fps must be positive".to_string()); }
+        let missed = vm.frame_wait(fps as u64)?;
+        super::tween::pump(vm)?;
+        Ok(Value::Int(missed as i64))
+    }).describe("frame_wait(int-fps) -> int", "Wait for the next frame at fps frames per second; returns the number of frames skipped");
+    r.register("yield", 0, None, |vm, _args| {
+        vm.request_yield();
+        Ok(Value::Int(1))
+    }).describe("yield() -> int", "Let the host run other scripts after this statement (see VM::run_steps)");
+}
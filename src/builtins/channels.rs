@@ -0,0 +1,339 @@
+//! Threads and string channels shared between them. Channel ids are global to
+//! the process, so a function started with `spawn` can use ids created by its parent.
+//! The worker VM starts with a copy of the parent's globals, taken when
+//! `spawn` is called; changes on either side stay on that side. `join` waits
+//! for a worker and hands back its result or its error; `detach` forgets a
+//! worker that will never be joined, so its handle does not stay in the table.
+//! Shared cells (`shared_create`) hold one value each for every thread; like
+//! channels they store it in snapshot form, so `shared_get` returns a copy.
+//! A cell lives until `shared_delete` frees it.
+//! `group_send` / `group_recv` carry whole values between the VMs of a `VmGroup`.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex, OnceLock};
+use std::thread;
+
+use super::{from_args, int_arg, str_arg, Registry, VmError};
+use crate::vm::{Capability, Fields, Object, Value, VM};
+
+static CH_SENDERS: OnceLock<Mutex<HashMap<u64, mpsc::Sender<String>>>> = OnceLock::new();
+static CH_RECEIVERS: OnceLock<Mutex<HashMap<u64, mpsc::Receiver<String>>>> = OnceLock::new();
+/// Broadcast subscribers of a channel: (subscriber id, sender)
+type Subscribers = Vec<(u64, mpsc::Sender<String>)>;
+
+static CH_NEXT_ID: AtomicU64 = AtomicU64::new(1);
+/// Running or finished workers by handle; a worker's result arrives in snapshot form
+type Worker = thread::JoinHandle<Result<Vec<u8>, VmError>>;
+static WORKERS: OnceLock<Mutex<HashMap<u64, Worker>>> = OnceLock::new();
+static NEXT_WORKER: AtomicU64 = AtomicU64::new(1);
+/// Shared cells by id, each value in snapshot form
+static SHARED: OnceLock<Mutex<HashMap<u64, Vec<u8>>>> = OnceLock::new();
+static NEXT_SHARED: AtomicU64 = AtomicU64::new(1);
+static CH_BCAST: OnceLock<Mutex<HashMap<u64, Subscribers>>> = OnceLock::new();
+static SUB_TO_CHANNEL: OnceLock<Mutex<HashMap<u64, u64>>> = OnceLock::new();
+
+fn ch_senders() -> &'static Mutex<HashMap<u64, mpsc::Sender<String>>> {
+    CH_SENDERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn ch_receivers() -> &'static Mutex<HashMap<u64, mpsc::Receiver<String>>> {
+    CH_RECEIVERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn ch_bcast() -> &'static Mutex<HashMap<u64, Subscribers>> {
+    CH_BCAST.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn sub_to_channel() -> &'static Mutex<HashMap<u64, u64>> {
+    SUB_TO_CHANNEL.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn workers() -> &'static Mutex<HashMap<u64, Worker>> {
+    WORKERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn shared() -> &'static Mutex<HashMap<u64, Vec<u8>>> {
+    SHARED.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub(super) fn register(r: &mut Registry) {
+    r.register("spawn", 1, Some(Capability::Process), |vm, args| {
+        let fname = str_arg("spawn", args, 0, "function")?;
+        spawn(vm, "spawn", fname, Vec::new())
+    }).describe("spawn(str-function) -> int", "Call a function without arguments on a new thread, in a VM holding a copy of the globals; returns a handle for join");
+    r.register("spawn_with", 2, Some(Capability::Process), |vm, args| {
+        let fname = str_arg("spawn_with", args, 0, "function")?;
+        let Value::List(list) = &args[1] else {
+            return Err(format!("spawn_with: args must be a list, got {}", args[1].type_name()));
+        };
+        let list = list.borrow().clone();
+        spawn(vm, "spawn_with", fname, list)
+    }).describe("spawn_with(str-function, list-args) -> int", "Like spawn, passing copies of args as the function's arguments");
+    r.register("join", 1, None, join).describe("join(int-handle) -> value", "Wait for a spawned worker and return a copy of its result; fails with the worker's error");
+    r.register("detach", 1, None, |_vm, args| {
+        let handle = int_arg("detach", args, 0, "handle")? as u64;
+        // dropping the JoinHandle lets the thread run on; its result is discarded
+        let worker = workers().lock().map_err(|_| "detach: worker table poisoned".to_string())?.remove(&handle);
+        Ok(Value::Int(worker.is_some() as i64))
+    }).describe("detach(int-handle) -> int", "Forget a spawned worker without waiting for it; its result is dropped. 0 if the handle was already joined or detached");
+    r.register("channel_create", 0, None, channel_create).describe("channel_create() -> int", "Create a string channel and return its id");
+    r.register("channel_send", 2, None, channel_send).describe("channel_send(int-id, str-text) -> int", "Send text to a channel and its subscribers");
+    r.register("channel_try_recv", 1, None, channel_try_recv).describe("channel_try_recv(int-id) -> Result", "Receive without blocking: Result { ok: 1, msg } or { ok: 0 }");
+    r.register("channel_recv", 1, None, channel_recv).describe("channel_recv(int-id) -> str", "Wait for the next message on a channel");
+    r.register("channel_recv_timeout", 2, None, channel_recv_timeout)
+        .describe("channel_recv_timeout(int-id, int-ms) -> Result", "Wait up to ms for a message: Result { ok: 1, msg } or { ok: 0 } on timeout");
+    r.register("channel_subscribe", 1, None, channel_subscribe).describe("channel_subscribe(int-id) -> int", "Subscribe to every message sent to a channel; returns the subscriber id");
+    r.register("channel_close", 1, None, channel_close).describe("channel_close(int-id) -> int", "Close a channel or a subscriber");
+    r.register("shared_create", 1, None, |_vm, args| {
+        let id = NEXT_SHARED.fetch_add(1, Ordering::SeqCst);
+        shared().lock().map_err(|_| "shared_create: table poisoned".to_string())?.insert(id, crate::snapshot::encode_value(&args[0]));
+        Ok(Value::Int(id as i64))
+    }).describe("shared_create(value) -> int", "Create a cell holding a copy of value that every thread can read and write; returns its id");
+    r.register("shared_get", 1, None, |_vm, args| {
+        let id = int_arg("shared_get", args, 0, "id")? as u64;
+        let cells = shared().lock().map_err(|_| "shared_get: table poisoned".to_string())?;
+        let bytes = cells.get(&id).ok_or_else(|| format!("shared_get: no shared cell {}", id))?;
+        crate::snapshot::decode_value(bytes)
+    }).describe("shared_get(int-id) -> value", "A copy of the value in a shared cell");
+    r.register("shared_set", 2, None, |_vm, args| {
+        let id = int_arg("shared_set", args, 0, "id")? as u64;
+        let mut cells = shared().lock().map_err(|_| "shared_set: table poisoned".to_string())?;
+        let cell = cells.get_mut(&id).ok_or_else(|| format!("shared_set: no shared cell {}", id))?;
+        *cell = crate::snapshot::encode_value(&args[1]);
+        Ok(Value::Int(1))
+    }).describe("shared_set(int-id, value) -> int", "Replace the value in a shared cell with a copy of value");
+    r.register("shared_cas", 3, None, |_vm, args| {
+        let id = int_arg("shared_cas", args, 0, "id")? as u64;
+        let mut cells = shared().lock().map_err(|_| "shared_cas: table poisoned".to_string())?;
+        let cell = cells.get_mut(&id).ok_or_else(|| format!("shared_cas: no shared cell {}", id))?;
+        // compared in encoded form: the same value gives the same bytes
+        if *cell != crate::snapshot::encode_value(&args[1]) { return Ok(Value::Int(0)); }
+        *cell = crate::snapshot::encode_value(&args[2]);
+        Ok(Value::Int(1))
+    }).describe("shared_cas(int-id, expected, new) -> int", "Set a shared cell to new only if it still holds expected, in one step; 1 if it was set");
+    r.register("shared_delete", 1, None, |_vm, args| {
+        let id = int_arg("shared_delete", args, 0, "id")? as u64;
+        let cell = shared().lock().map_err(|_| "shared_delete: table poisoned".to_string())?.remove(&id);
+        Ok(Value::Int(cell.is_some() as i64))
+    }).describe("shared_delete(int-id) -> int", "Free a shared cell; later uses of its id fail. 0 if there was no such cell");
+    r.register("group_send", 2, None, group_send).describe("group_send(str-to, value) -> int", "Send a copy of a value to another VM of the same VmGroup");
+    r.register("group_recv", 0, None, group_recv).describe("group_recv() -> Result", "Next value sent to this VM without blocking: Result { ok: 1, value } or { ok: 0 }");
+}
+
+/// Call `fname(args)` on a new thread and return its handle. Values are not
+/// Send, so the globals, the arguments and the result cross over in
+/// snapshot form and arrive as deep copies
+fn spawn(vm: &mut VM, who: &str, fname: String, args: Vec<Value>) -> Result<Value, VmError> {
+    match vm.get_global(&fname) {
+        Some(Value::Function(fobj)) if fobj.params.len() != args.len() => {
+            return Err(format!("{}: {} takes {} argument(s), got {}", who, fname, fobj.params.len(), args.len()));
+        }
+        Some(set @ Value::Overloaded(_)) if set.overload(args.len()).is_none() => {
+            return Err(format!("{}: no overload of {} takes {} argument(s)", who, fname, args.len()));
+        }
+        Some(Value::Function(_) | Value::Overloaded(_)) => {}
+        _ => return Err(format!("{}: function {} not found", who, fname)),
+    }
+    let globals = vm.snapshot();
+    let args = crate::snapshot::encode_value(&Value::from(args));
+    let policy = vm.policy();
+    let worker = thread::spawn(move || {
+        let mut vm2 = VM::new();
+        vm2.set_policy(policy);
+        vm2.restore(&globals)?;
+        let args = match crate::snapshot::decode_value(&args)? {
+            Value::List(list) => list.borrow().clone(),
+            _ => Vec::new(),
+        };
+        vm2.call(&fname, args).map(|v| crate::snapshot::encode_value(&v)).map_err(|e| format!("{}: {}", fname, e))
+    });
+    let handle = NEXT_WORKER.fetch_add(1, Ordering::SeqCst);
+    workers().lock().map_err(|_| format!("{}: worker table poisoned", who))?.insert(handle, worker);
+    Ok(Value::Int(handle as i64))
+}
+
+/// join(handle) -> the worker's result; each handle can be joined once.
+/// Waits in short slices so a cancel of this VM (the `/run` watchdog, Ctrl-C)
+/// is not held up by a worker that never ends; the handle then goes back to
+/// the table, to be joined again or detached
+fn join(vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let handle = int_arg("join", args, 0, "handle")? as u64;
+    let worker = workers().lock().map_err(|_| "join: worker table poisoned".to_string())?.remove(&handle)
+        .ok_or_else(|| format!("join: no worker with handle {} (already joined?)", handle))?;
+    while !worker.is_finished() {
+        if let Err(e) = vm.check_cancelled() {
+            workers().lock().map_err(|_| "join: worker table poisoned".to_string())?.insert(handle, worker);
+            return Err(e);
+        }
+        thread::sleep(std::time::Duration::from_millis(1));
+    }
+    match worker.join() {
+        Ok(Ok(bytes)) => crate::snapshot::decode_value(&bytes),
+        Ok(Err(e)) => Err(format!("join: worker failed: {}", e)),
+        Err(_) => Err("join: worker panicked".to_string()),
+    }
+}
+
+/// Create a primary channel with one receiver; returns its id
+pub(crate) fn create() -> u64 {
+    let id = CH_NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let (tx, rx) = mpsc::channel::<String>();
+    if let Ok(mut s) = ch_senders().lock() { s.insert(id, tx); }
+    if let Ok(mut r) = ch_receivers().lock() { r.insert(id, rx); }
+    id
+}
+
+/// channel_create() -> id (creates primary channel with one receiver)
+fn channel_create(_vm: &mut VM, _args: &[Value]) -> Result<Value, VmError> {
+    Ok(Value::Int(create() as i64))
+}
+
+/// channel_send(id, text) -> 1 on success
+fn channel_send(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let (id, s): (i64, String) = from_args("channel_send", args)?;
+    if send(id as u64, s) { Ok(Value::Int(1)) } else { Err("channel_send: channel not found".to_string()) }
+}
+
+/// Send to a channel and its subscribers; false if there is no such channel
+pub(crate) fn send(id: u64, s: String) -> bool {
+    let mut sent = false;
+    if let Ok(map) = ch_senders().lock()
+        && let Some(tx) = map.get(&id) {
+            let _ = tx.send(s.clone());
+            sent = true;
+    }
+    // send to broadcast subscribers if any
+    if let Ok(bmap) = ch_bcast().lock()
+        && let Some(list) = bmap.get(&id) {
+            for (_subid, tx) in list.iter() {
+                let _ = tx.send(s.clone());
+                sent = true;
+            }
+    }
+    sent
+}
+
+/// `Result` object returned by `channel_try_recv` and `channel_recv_timeout`: { ok: 1, msg } or { ok: 0 }
+fn recv_result(msg: Option<String>) -> Value {
+    let mut fields = Fields::new();
+    fields.insert("ok".to_string(), Value::Int(msg.is_some() as i64));
+    if let Some(s) = msg { fields.insert("msg".to_string(), Value::Str(s)); }
+    Value::Object(Rc::new(RefCell::new(Object::plain("Result", fields))))
+}
+
+fn group_link<'a>(who: &str, vm: &'a VM) -> Result<&'a crate::group::GroupLink, VmError> {
+    vm.group().ok_or_else(|| format!("{}: this VM is not in a group", who))
+}
+
+/// group_send(to, value) -> 1; the receiver gets a deep copy
+fn group_send(vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let to = str_arg("group_send", args, 0, "to")?;
+    group_link("group_send", vm)?.send(&to, &args[1])?;
+    Ok(Value::Int(1))
+}
+
+/// group_recv() -> Result { ok: 1, value } or { ok: 0 } without blocking
+fn group_recv(vm: &mut VM, _args: &[Value]) -> Result<Value, VmError> {
+    let value = group_link("group_recv", vm)?.recv()?;
+    let mut fields = Fields::new();
+    fields.insert("ok".to_string(), Value::Int(value.is_some() as i64));
+    if let Some(v) = value { fields.insert("value".to_string(), v); }
+    Ok(Value::Object(Rc::new(RefCell::new(Object::plain("Result", fields)))))
+}
+
+/// channel_try_recv(id) -> Result without blocking
+fn channel_try_recv(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let id = int_arg("channel_try_recv", args, 0, "id")? as u64;
+    try_recv("channel_try_recv", id).map(recv_result)
+}
+
+/// The next message on a channel or subscriber, if one is waiting
+pub(crate) fn try_recv(who: &str, id: u64) -> Result<Option<String>, VmError> {
+    if let Ok(mut map) = ch_receivers().lock()
+        && let Some(rx) = map.get_mut(&id) {
+            return match rx.try_recv() {
+                Ok(s) => Ok(Some(s)),
+                Err(mpsc::TryRecvError::Empty) => Ok(None),
+                Err(_) => Err(format!("{}: receive error", who)),
+            };
+    }
+    Err(format!("{}: channel not found", who))
+}
+
+/// channel_recv(id) -> blocks until message (returns string)
+fn channel_recv(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let id = int_arg("channel_recv", args, 0, "id")? as u64;
+    if let Ok(mut map) = ch_receivers().lock()
+        && let Some(rx) = map.get_mut(&id) {
+            return rx.recv().map(Value::Str).map_err(|_| "channel_recv: receive error".to_string());
+    }
+    Err("channel_recv: channel not found".to_string())
+}
+
+/// channel_recv_timeout(id, ms) -> Result, { ok: 0 } once ms pass without a message.
+/// Polls in short slices instead of blocking on the receiver, so the shared
+/// receiver table is never held locked for the whole wait
+fn channel_recv_timeout(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let (id, ms): (i64, i64) = from_args("channel_recv_timeout", args)?;
+    if ms < 0 { return Err(format!("channel_recv_timeout: ms must be >= 0, got {}", ms)); }
+    let deadline = std::time::Instant::now() + std::time::Duration::from_millis(ms as u64);
+    loop {
+        if let Some(s) = try_recv("channel_recv_timeout", id as u64)? { return Ok(recv_result(Some(s))); }
+        let left = deadline.saturating_duration_since(std::time::Instant::now());
+        if left.is_zero() { return Ok(recv_result(None)); }
+        thread::sleep(left.min(std::time::Duration::from_millis(1)));
+    }
+}
+
+/// channel_subscribe(channel_id) -> subscriber_id
+fn channel_subscribe(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let chid = int_arg("channel_subscribe", args, 0, "id")? as u64;
+    // create new tx/rx pair for subscriber
+    let sub_id = CH_NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let (tx, rx) = mpsc::channel::<String>();
+    if let Ok(mut rmap) = ch_receivers().lock() { rmap.insert(sub_id, rx); }
+    if let Ok(mut bmap) = ch_bcast().lock() {
+        bmap.entry(chid).or_insert_with(Vec::new).push((sub_id, tx));
+    }
+    if let Ok(mut m) = sub_to_channel().lock() { m.insert(sub_id, chid); }
+    Ok(Value::Int(sub_id as i64))
+}
+
+/// channel_close(id) - closes channel or subscriber and cleans resources
+fn channel_close(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let id = int_arg("channel_close", args, 0, "id")? as u64;
+    if close(id) { Ok(Value::Int(1)) } else { Err("channel_close: id not found".to_string()) }
+}
+
+/// Close a channel or subscriber; false if the id is unknown
+pub(crate) fn close(id: u64) -> bool {
+    // first, if it's a primary channel
+    if let Ok(mut smap) = ch_senders().lock()
+        && smap.remove(&id).is_some() {
+            if let Ok(mut rmap) = ch_receivers().lock() { rmap.remove(&id); }
+            // remove and cleanup broadcast subscribers
+            if let Ok(mut bmap) = ch_bcast().lock()
+                && let Some(list) = bmap.remove(&id) {
+                    for (subid, _tx) in list {
+                        if let Ok(mut rmap) = ch_receivers().lock() { rmap.remove(&subid); }
+                        if let Ok(mut m) = sub_to_channel().lock() { m.remove(&subid); }
+                    }
+            }
+            return true;
+    }
+    // if it's a subscriber or receiver id
+    if let Ok(mut rmap) = ch_receivers().lock()
+        && rmap.remove(&id).is_some() {
+            // if subscriber, remove its sender from bcast list
+            if let Ok(mut m) = sub_to_channel().lock()
+                && let Some(chid) = m.remove(&id)
+                && let Ok(mut bmap) = ch_bcast().lock()
+                && let Some(list) = bmap.get_mut(&chid) {
+                    list.retain(|(sid, _)| *sid != id);
+            }
+            return true;
+    }
+    false
+}
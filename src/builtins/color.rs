@@ -0,0 +1,51 @@
+//! Color builtins over `crate::color`. Percentages follow CSS: `hsl(30, 100,
+//! 50)` is orange, `lighten(c, 10)` adds 10 points of lightness and
+//! `mix(a, b, 25)` is a quarter of the way from a to b.
+use super::{from_args, int_arg, str_arg, Registry, VmError};
+use crate::color::Rgba;
+use crate::vm::Value;
+
+/// A color argument: a packed int, or a hex string for convenience
+pub(crate) fn color_arg(name: &str, args: &[Value], i: usize) -> Result<Rgba, VmError> {
+    match &args[i] {
+        Value::Int(c) => Rgba::unpack(*c),
+        Value::Str(s) => Rgba::parse_hex(s),
+        other => Err(format!("expected a color, got {}", other.type_name())),
+    }.map_err(|e| format!("{}: {}", name, e))
+}
+
+fn channel(name: &str, args: &[Value], i: usize, what: &str) -> Result<u8, VmError> {
+    let v = int_arg(name, args, i, what)?;
+    u8::try_from(v).map_err(|_| format!("{}: {} must be 0..255, got {}", name, what, v))
+}
+
+pub(super) fn register(r: &mut Registry) {
+    r.register("rgb", 3, None, |_vm, args| {
+        let c = Rgba { r: channel("rgb", args, 0, "r")?, g: channel("rgb", args, 1, "g")?, b: channel("rgb", args, 2, "b")?, a: 255 };
+        Ok(Value::Int(c.pack()))
+    }).describe("rgb(int-r, int-g, int-b) -> int", "Opaque color from channels 0..255");
+    r.register("rgba", 4, None, |_vm, args| {
+        let c = Rgba { r: channel("rgba", args, 0, "r")?, g: channel("rgba", args, 1, "g")?, b: channel("rgba", args, 2, "b")?, a: channel("rgba", args, 3, "a")? };
+        Ok(Value::Int(c.pack()))
+    }).describe("rgba(int-r, int-g, int-b, int-a) -> int", "Color from channels 0..255, alpha included");
+    r.register("hsl", 3, None, |_vm, args| {
+        let (h, s, l): (f64, f64, f64) = from_args("hsl", args)?;
+        Ok(Value::Int(Rgba::from_hsl(h, s / 100.0, l / 100.0).pack()))
+    }).describe("hsl(float-h, float-s, float-l) -> int", "Opaque color from hue in degrees and saturation and lightness in percent");
+    r.register("hex_color", 1, None, |_vm, args| {
+        let s = str_arg("hex_color", args, 0, "hex")?;
+        Rgba::parse_hex(&s).map(|c| Value::Int(c.pack())).map_err(|e| format!("hex_color: {}", e))
+    }).describe("hex_color(str-hex) -> int", "Color from \"#rgb\", \"#rrggbb\" or \"#rrggbbaa\"");
+    r.register("lighten", 2, None, |_vm, args| {
+        let amount = f64::try_from(args[1].clone()).map_err(|e| format!("lighten: {}", e))?;
+        Ok(Value::Int(color_arg("lighten", args, 0)?.lighten(amount / 100.0).pack()))
+    }).describe("lighten(int-color, float-percent) -> int", "Color with lightness raised by percent points");
+    r.register("darken", 2, None, |_vm, args| {
+        let amount = f64::try_from(args[1].clone()).map_err(|e| format!("darken: {}", e))?;
+        Ok(Value::Int(color_arg("darken", args, 0)?.lighten(-amount / 100.0).pack()))
+    }).describe("darken(int-color, float-percent) -> int", "Color with lightness lowered by percent points");
+    r.register("mix", 3, None, |_vm, args| {
+        let t = f64::try_from(args[2].clone()).map_err(|e| format!("mix: {}", e))?;
+        Ok(Value::Int(color_arg("mix", args, 0)?.mix(color_arg("mix", args, 1)?, t / 100.0).pack()))
+    }).describe("mix(int-a, int-b, float-percent) -> int", "Color percent of the way from a to b");
+}
@@ -0,0 +1,39 @@
+//! Digests of strings and byte lists, as lowercase hex, plus HMAC and
+//! encryption (see `crate::crypto`). For files use `file_sha256` /
+//! `file_crc32`, which read in chunks. Encrypted data travels as base64 text.
+use super::{bytes_arg, str_arg, Registry};
+use crate::hash::hex;
+use crate::vm::Value;
+
+pub(super) fn register(r: &mut Registry) {
+    r.register("sha256", 1, None, |_vm, args| {
+        Ok(Value::Str(hex(&crate::hash::sha256(&bytes_arg("sha256", args, 0, "data")?))))
+    }).describe("sha256(data) -> str", "SHA-256 of a string's UTF-8 bytes or a byte list, as 64 hex digits");
+    r.register("sha1", 1, None, |_vm, args| {
+        Ok(Value::Str(hex(&crate::hash::sha1(&bytes_arg("sha1", args, 0, "data")?))))
+    }).describe("sha1(data) -> str", "SHA-1 as 40 hex digits; only for formats that require it, it is not collision-resistant");
+    r.register("crc32", 1, None, |_vm, args| {
+        Ok(Value::Str(format!("{:08x}", crate::hash::crc32(&bytes_arg("crc32", args, 0, "data")?))))
+    }).describe("crc32(data) -> str", "CRC-32 (as in zip) as 8 hex digits");
+    r.register("hmac_sha256", 2, None, |_vm, args| {
+        let (key, msg) = (bytes_arg("hmac_sha256", args, 0, "key")?, bytes_arg("hmac_sha256", args, 1, "msg")?);
+        Ok(Value::Str(hex(&crate::crypto::hmac_sha256(&key, &msg))))
+    }).describe("hmac_sha256(key, msg) -> str", "HMAC-SHA256 of msg under key, as 64 hex digits");
+    r.register("encrypt", 2, None, |_vm, args| {
+        let (key, data) = (bytes_arg("encrypt", args, 0, "key")?, bytes_arg("encrypt", args, 1, "data")?);
+        let sealed = crate::crypto::seal(&key, &data).map_err(|e| format!("encrypt: {}", e))?;
+        Ok(Value::Str(crate::base64::encode(&sealed)))
+    }).describe("encrypt(key, data) -> str", "Encrypt and authenticate data (ChaCha20 + HMAC-SHA256); base64 text, different every call");
+    r.register("decrypt", 2, None, |_vm, args| {
+        let key = bytes_arg("decrypt", args, 0, "key")?;
+        let sealed = crate::base64::decode(&str_arg("decrypt", args, 1, "data")?).map_err(|e| format!("decrypt: {}", e))?;
+        let plain = crate::crypto::open(&key, &sealed).map_err(|e| format!("decrypt: {}", e))?;
+        String::from_utf8(plain).map(Value::Str).map_err(|_| "decrypt: the data is not UTF-8 text; use decrypt_bytes".to_string())
+    }).describe("decrypt(key, str-data) -> str", "Text encrypted by encrypt; fails on a wrong key or changed data");
+    r.register("decrypt_bytes", 2, None, |_vm, args| {
+        let key = bytes_arg("decrypt_bytes", args, 0, "key")?;
+        let sealed = crate::base64::decode(&str_arg("decrypt_bytes", args, 1, "data")?).map_err(|e| format!("decrypt_bytes: {}", e))?;
+        let plain = crate::crypto::open(&key, &sealed).map_err(|e| format!("decrypt_bytes: {}", e))?;
+        Ok(Value::from(plain.into_iter().map(|b| Value::Int(b as i64)).collect::<Vec<_>>()))
+    }).describe("decrypt_bytes(key, str-data) -> list", "Like decrypt, returning the bytes as a list of ints, for data that is not text");
+}
@@ -0,0 +1,111 @@
+//! CSV text (RFC 4180): fields are separated by commas, rows by `\n` or
+//! `\r\n`; a field in double quotes may contain commas, line breaks and `""`
+//! for a quote. Blank lines are skipped. All parsed cells are strings.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::{str_arg, Registry, VmError};
+use crate::vm::{Fields, Object, Value};
+
+pub(super) fn register(r: &mut Registry) {
+    r.register("csv_parse", 1, None, |_vm, args| {
+        let rows = parse(&str_arg("csv_parse", args, 0, "text")?)?;
+        Ok(Value::from(rows.into_iter().map(|row| Value::from(row.into_iter().map(Value::Str).collect::<Vec<_>>())).collect::<Vec<_>>()))
+    }).describe("csv_parse(str-text) -> list", "Rows of CSV text as lists of strings");
+    r.register("csv_records", 1, None, |_vm, args| {
+        let mut rows = parse(&str_arg("csv_records", args, 0, "text")?)?.into_iter();
+        let header = rows.next().unwrap_or_default();
+        let records = rows.map(|row| {
+            let fields: Fields = header.iter().cloned().zip(row.into_iter().map(Value::Str)).collect();
+            Value::Object(Rc::new(RefCell::new(Object::plain("Row", fields))))
+        });
+        Ok(Value::from(records.collect::<Vec<_>>()))
+    }).describe("csv_records(str-text) -> list", "Rows after the header line as Row objects with one field per column");
+    r.register("csv_write", 1, None, |_vm, args| {
+        let Value::List(rows) = &args[0] else { return Err("csv_write: rows must be a list".to_string()) };
+        write(&rows.borrow()).map(Value::Str)
+    }).describe("csv_write(list-rows) -> str", "CSV text for a list of lists, or of objects with a header line from the first one's fields");
+}
+
+fn parse(text: &str) -> Result<Vec<Vec<String>>, VmError> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    // true once the current row has any content, so blank lines add no rows
+    let mut started = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() => {
+                started = true;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => { chars.next(); field.push('"'); }
+                        Some('"') => break,
+                        Some(c) => field.push(c),
+                        None => return Err(format!("csv_parse: unterminated quoted field in row {}", rows.len() + 1)),
+                    }
+                }
+            }
+            ',' => { started = true; row.push(std::mem::take(&mut field)); }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' if started => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+                started = false;
+            }
+            '\n' => {}
+            c => { started = true; field.push(c); }
+        }
+    }
+    if started {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn write(rows: &[Value]) -> Result<String, VmError> {
+    let mut out = String::new();
+    // objects: a header line from the first one's fields, then those fields of each
+    if let Some(Value::Object(first)) = rows.first() {
+        let header: Vec<String> = first.borrow().fields.keys().cloned().collect();
+        line(&mut out, header.iter().map(|h| Ok(h.clone())))?;
+        for row in rows {
+            let Value::Object(o) = row else { return Err("csv_write: rows must all be objects or all be lists".to_string()) };
+            let o = o.borrow();
+            line(&mut out, header.iter().map(|h| o.fields.get(h).map_or(Ok(String::new()), cell)))?;
+        }
+        return Ok(out);
+    }
+    for row in rows {
+        let Value::List(cells) = row else { return Err("csv_write: rows must all be objects or all be lists".to_string()) };
+        line(&mut out, cells.borrow().iter().map(cell))?;
+    }
+    Ok(out)
+}
+
+fn cell(v: &Value) -> Result<String, VmError> {
+    match v {
+        Value::Str(s) => Ok(s.clone()),
+        Value::Int(n) => Ok(n.to_string()),
+        Value::Float(f) => Ok(crate::json::float(*f, false)),
+        other => Err(format!("csv_write: cannot write {} to a cell", other.type_name())),
+    }
+}
+
+fn line(out: &mut String, cells: impl Iterator<Item = Result<String, VmError>>) -> Result<(), VmError> {
+    for (i, c) in cells.enumerate() {
+        if i > 0 { out.push(','); }
+        let c = c?;
+        if c.contains([',', '"', '\n', '\r']) {
+            out.push('"');
+            out.push_str(&c.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(&c);
+        }
+    }
+    out.push('\n');
+    Ok(())
+}
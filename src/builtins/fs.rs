@@ -0,0 +1,128 @@
+//! Directories, paths, temporary files, atomic writes and file checksums. Anything touching
+//! the file system needs `Capability::FileIo`; the path helpers only work on
+//! strings.
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{from_args, str_arg, Registry, VmError};
+use crate::vm::{Capability, Value};
+
+/// Numbers temporary names within the process
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+pub(super) fn register(r: &mut Registry) {
+    let fs = Some(Capability::FileIo);
+    r.register("list_dir", 1, fs, |_vm, args| {
+        let path = str_arg("list_dir", args, 0, "path")?;
+        let entries = std::fs::read_dir(&path).map_err(|e| format!("list_dir: {}: {}", path, e))?;
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("list_dir: {}: {}", path, e))?;
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        Ok(Value::from(names.into_iter().map(Value::Str).collect::<Vec<_>>()))
+    }).describe("list_dir(str-path) -> list", "Names of the entries in a directory, sorted");
+    r.register("mkdir", 1, fs, |_vm, args| {
+        let path = str_arg("mkdir", args, 0, "path")?;
+        std::fs::create_dir_all(&path).map_err(|e| format!("mkdir: {}: {}", path, e))?;
+        Ok(Value::Int(1))
+    }).describe("mkdir(str-path) -> int", "Create a directory and any missing parents");
+    r.register("remove_dir", 1, fs, |_vm, args| {
+        let path = str_arg("remove_dir", args, 0, "path")?;
+        std::fs::remove_dir(&path).map_err(|e| format!("remove_dir: {}: {}", path, e))?;
+        Ok(Value::Int(1))
+    }).describe("remove_dir(str-path) -> int", "Remove an empty directory");
+    r.register("temp_file", 0, fs, |_vm, _args| {
+        let path = create_unique(&std::env::temp_dir(), "userd-", ".tmp", |p| OpenOptions::new().write(true).create_new(true).open(p).map(drop))
+            .map_err(|e| format!("temp_file: {}", e))?;
+        Ok(Value::Str(path.to_string_lossy().into_owned()))
+    }).describe("temp_file() -> str", "Create a new empty file in the system temp directory and return its path");
+    r.register("temp_dir", 0, fs, |_vm, _args| {
+        let path = create_unique(&std::env::temp_dir(), "userd-", "", |p| std::fs::create_dir(p))
+            .map_err(|e| format!("temp_dir: {}", e))?;
+        Ok(Value::Str(path.to_string_lossy().into_owned()))
+    }).describe("temp_dir() -> str", "Create a new empty directory in the system temp directory and return its path");
+    r.register("write_file_atomic", 2, fs, |_vm, args| {
+        let (path, text): (String, String) = from_args("write_file_atomic", args)?;
+        write_atomic(Path::new(&path), text.as_bytes()).map_err(|e| format!("write_file_atomic: {}: {}", path, e))?;
+        Ok(Value::Int(1))
+    }).describe("write_file_atomic(str-path, str-text) -> int", "Replace a file's contents so that a crash leaves either the old or the new text");
+    r.register("file_sha256", 1, fs, |_vm, args| {
+        let path = str_arg("file_sha256", args, 0, "path")?;
+        let mut h = crate::hash::Sha256::new();
+        read_chunks(&path, |chunk| h.update(chunk)).map_err(|e| format!("file_sha256: {}: {}", path, e))?;
+        Ok(Value::Str(crate::hash::hex(&h.finish())))
+    }).describe("file_sha256(str-path) -> str", "SHA-256 of a file as 64 hex digits, read in chunks");
+    r.register("file_crc32", 1, fs, |_vm, args| {
+        let path = str_arg("file_crc32", args, 0, "path")?;
+        let mut h = crate::hash::Crc32::new();
+        read_chunks(&path, |chunk| h.update(chunk)).map_err(|e| format!("file_crc32: {}: {}", path, e))?;
+        Ok(Value::Str(format!("{:08x}", h.finish())))
+    }).describe("file_crc32(str-path) -> str", "CRC-32 of a file (as in zip) as 8 hex digits, read in chunks");
+    r.register("path_join", 2, None, |_vm, args| {
+        let (a, b): (String, String) = from_args("path_join", args)?;
+        Ok(Value::Str(Path::new(&a).join(b).to_string_lossy().into_owned()))
+    }).describe("path_join(str-a, str-b) -> str", "Join two path parts with the system separator");
+    r.register("path_basename", 1, None, |_vm, args| {
+        let path = str_arg("path_basename", args, 0, "path")?;
+        Ok(Value::Str(Path::new(&path).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()))
+    }).describe("path_basename(str-path) -> str", "Last part of a path: \"a/b.txt\" -> \"b.txt\"; \"\" when there is none");
+    r.register("path_ext", 1, None, |_vm, args| {
+        let path = str_arg("path_ext", args, 0, "path")?;
+        Ok(Value::Str(Path::new(&path).extension().map(|e| e.to_string_lossy().into_owned()).unwrap_or_default()))
+    }).describe("path_ext(str-path) -> str", "Extension of a path without the dot; \"\" when there is none");
+}
+
+/// Feed a file to `f` in 64 KiB pieces, so large files are never held in memory
+fn read_chunks(path: &str, mut f: impl FnMut(&[u8])) -> std::io::Result<()> {
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 { return Ok(()); }
+        f(&buf[..n]);
+    }
+}
+
+/// Create `dir/{prefix}{pid}-{n}-{random}{suffix}` with `create`, which must
+/// fail if the path exists; retried on collisions
+fn create_unique(dir: &Path, prefix: &str, suffix: &str, create: impl Fn(&Path) -> std::io::Result<()>) -> Result<PathBuf, VmError> {
+    for _ in 0..100 {
+        let n = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let salt = crate::rand::secure_random_u64(1 << 32)?;
+        let path = dir.join(format!("{}{}-{}-{:08x}{}", prefix, std::process::id(), n, salt, suffix));
+        match create(&path) {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+    Err("no free temporary name".to_string())
+}
+
+/// Write to a temporary file next to `path`, flush it to disk, then rename
+/// it over `path`; rename within a directory replaces the file atomically
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> Result<(), VmError> {
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().ok_or("not a file path")?.to_string_lossy().into_owned();
+    let write = |p: &Path| -> std::io::Result<()> {
+        let mut f = OpenOptions::new().write(true).create_new(true).open(p)?;
+        f.write_all(data)?;
+        f.sync_all()
+    };
+    let tmp = create_unique(dir, &format!(".{}.", name), ".tmp", |p| match write(p) {
+        // a partial temp file must not be left behind
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => { let _ = std::fs::remove_file(p); Err(e) }
+        r => r,
+    })?;
+    std::fs::rename(&tmp, path).map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        e.to_string()
+    })
+}
@@ -0,0 +1,114 @@
+//! Finite state machines, so handlers do not have to track state in flag
+//! variables. An `Fsm` object has `state` (the current state's name),
+//! `transitions` (one field per state: an object mapping event names to the
+//! next state) and `handlers` (names of functions called on every transition).
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::{str_arg, Registry, VmError};
+use crate::vm::{Fields, Object, Value, VM};
+
+pub(super) fn register(r: &mut Registry) {
+    r.register("fsm_create", 1, None, fsm_create).describe("fsm_create(states) -> Fsm", "State machine from an object of states (each maps events to the next state) or a list of list(from, event, to); starts in the first state");
+    r.register("fsm_on", 2, None, fsm_on).describe("fsm_on(Fsm-fsm, str-event) -> int", "Take the event's transition from the current state and call the handlers; 0 if the state has none");
+    r.register("fsm_on_transition", 2, None, fsm_on_transition).describe("fsm_on_transition(Fsm-fsm, str-handler) -> int", "Call the function named handler with (from, event, to) after each transition");
+}
+
+fn object(class_name: &str, fields: Fields) -> Value {
+    Value::Object(Rc::new(RefCell::new(Object::plain(class_name, fields))))
+}
+
+fn text(v: &Value, what: &str) -> Result<String, VmError> {
+    match v {
+        Value::Str(s) => Ok(s.clone()),
+        _ => Err(format!("fsm_create: {} must be a string, got {}", what, v.type_name())),
+    }
+}
+
+/// States in order, each with its (event, next state) pairs
+type Table = Vec<(String, Vec<(String, String)>)>;
+
+fn table(states: &Value) -> Result<Table, VmError> {
+    let mut table: Table = Vec::new();
+    match states {
+        Value::Object(o) => {
+            for (state, events) in o.borrow().fields.iter() {
+                let Value::Object(events) = events else { return Err(format!("fsm_create: transitions of {} must be an object", state)) };
+                let events = events.borrow().fields.iter().map(|(e, to)| Ok((e.clone(), text(to, "a target state")?))).collect::<Result<_, VmError>>()?;
+                table.push((state.clone(), events));
+            }
+        }
+        Value::List(rows) => {
+            for row in rows.borrow().iter() {
+                let Value::List(row) = row else { return Err("fsm_create: transitions must be list(from, event, to)".to_string()) };
+                let [from, event, to] = &row.borrow()[..] else { return Err("fsm_create: transitions must be list(from, event, to)".to_string()) };
+                let (from, event, to) = (text(from, "from")?, text(event, "event")?, text(to, "to")?);
+                for s in [&from, &to] {
+                    if !table.iter().any(|(name, _)| name == s) { table.push((s.clone(), Vec::new())); }
+                }
+                table.iter_mut().find(|(name, _)| *name == from).unwrap().1.push((event, to));
+            }
+        }
+        other => return Err(format!("fsm_create: states must be an object or a list, got {}", other.type_name())),
+    }
+    if table.is_empty() { return Err("fsm_create: no states".to_string()); }
+    for (state, events) in &table {
+        if let Some((event, to)) = events.iter().find(|(_, to)| !table.iter().any(|(name, _)| name == to)) {
+            return Err(format!("fsm_create: {} on {} goes to unknown state {}", state, event, to));
+        }
+    }
+    Ok(table)
+}
+
+/// fsm_create(states) -> Fsm in the first state
+fn fsm_create(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let table = table(&args[0])?;
+    let initial = table[0].0.clone();
+    let transitions: Fields = table.into_iter()
+        .map(|(state, events)| (state, object("FsmState", events.into_iter().map(|(e, to)| (e, Value::Str(to))).collect())))
+        .collect();
+    let mut fields = Fields::new();
+    fields.insert("state".to_string(), Value::Str(initial));
+    fields.insert("transitions".to_string(), object("FsmTransitions", transitions));
+    fields.insert("handlers".to_string(), Value::from(Vec::<Value>::new()));
+    Ok(object("Fsm", fields))
+}
+
+fn fsm<'a>(who: &str, args: &'a [Value]) -> Result<&'a Rc<RefCell<Object>>, VmError> {
+    match &args[0] {
+        Value::Object(o) if o.borrow().class_name == "Fsm" => Ok(o),
+        other => Err(format!("{}: expected an Fsm, got {}", who, other.type_name())),
+    }
+}
+
+fn field(o: &Rc<RefCell<Object>>, name: &str) -> Result<Value, VmError> {
+    o.borrow().fields.get(name).cloned().ok_or_else(|| format!("fsm: missing field {}", name))
+}
+
+/// fsm_on(fsm, event) -> 1 after a transition, 0 if the current state ignores the event
+fn fsm_on(vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let o = fsm("fsm_on", args)?;
+    let event = str_arg("fsm_on", args, 1, "event")?;
+    let Value::Str(from) = field(o, "state")? else { return Err("fsm_on: state must be a string".to_string()) };
+    let Value::Object(transitions) = field(o, "transitions")? else { return Err("fsm_on: transitions must be an object".to_string()) };
+    let Some(Value::Object(events)) = transitions.borrow().fields.get(&from).cloned() else { return Err(format!("fsm_on: unknown state {}", from)) };
+    let Some(to) = events.borrow().fields.get(&event).cloned() else { return Ok(Value::Int(0)) };
+    o.borrow_mut().fields.insert("state".to_string(), to.clone());
+    let Value::List(handlers) = field(o, "handlers")? else { return Err("fsm_on: handlers must be a list".to_string()) };
+    // copied out, so a handler may add handlers
+    let handlers = handlers.borrow().clone();
+    for h in handlers {
+        let Value::Str(name) = h else { continue };
+        vm.call(&name, vec![Value::Str(from.clone()), Value::Str(event.clone()), to.clone()])?;
+    }
+    Ok(Value::Int(1))
+}
+
+/// fsm_on_transition(fsm, handler) -> 1
+fn fsm_on_transition(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let o = fsm("fsm_on_transition", args)?;
+    let handler = str_arg("fsm_on_transition", args, 1, "handler")?;
+    let Value::List(handlers) = field(o, "handlers")? else { return Err("fsm_on_transition: handlers must be a list".to_string()) };
+    handlers.borrow_mut().push(Value::Str(handler));
+    Ok(Value::Int(1))
+}
@@ -0,0 +1,616 @@
+//! Windows and canvas drawing. The real implementations live in
+//! `platform::windows`; elsewhere these are no-ops returning 0.
+//! Text layout (`text_measure`, wrapping) is plain arithmetic over estimated
+//! glyph widths, so it gives the same answers on every platform.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::color::color_arg;
+use super::{int_arg, str_arg, text_arg, Registry, VmError};
+use crate::gui::GuiEvent;
+use crate::vm::{Capability, Fields, Object, Value, VM};
+
+pub(super) fn register(r: &mut Registry) {
+    let gui = Some(Capability::Gui);
+    r.register("gui_window", 3, gui, gui_window).describe("gui_window(str-title, int-w, int-h) -> int", "Open a window and return its id");
+    r.register("gui_blit_b64", 4, gui, gui_blit_b64).describe("gui_blit_b64(int-id, str-data, int-w, int-h) -> int", "Draw base64-encoded RGBA pixels into a window");
+    r.register("draw_rect", 8, gui, draw_rect).describe("draw_rect(int-id, int-canvas_w, int-canvas_h, int-x, int-y, int-w, int-h, int-color) -> int", "Fill a rectangle on a window's canvas; color comes from rgb, hsl or hex_color");
+    r.register("canvas_save", 2, Some(Capability::FileIo), canvas_save).describe("canvas_save(int-id, str-path) -> int", "Write a window's canvas to a BMP file");
+    r.register("canvas_clear", 2, gui, canvas_clear).describe("canvas_clear(int-id, int-color) -> int", "Fill a window's canvas with one color");
+    r.register("canvas_present", 1, gui, canvas_present).describe("canvas_present(int-id) -> int", "Show what was drawn on a window's canvas");
+    r.register("canvas_draw_text", 4, gui, canvas_draw_text).describe("canvas_draw_text(int-id, int-x, int-y, str-text) -> int", "Draw text on a window's canvas");
+    r.register("canvas_draw_text_wrapped", 5, gui, |vm, args| draw_text_block(vm, args, "canvas_draw_text_wrapped", Align::Left))
+        .describe("canvas_draw_text_wrapped(int-id, int-x, int-y, int-w, str-text) -> int", "Draw text word-wrapped to width w; returns the number of lines");
+    r.register("canvas_draw_text_aligned", 6, gui, |vm, args| {
+        let align = match str_arg("canvas_draw_text_aligned", args, 5, "align")?.as_str() {
+            "left" => Align::Left,
+            "center" => Align::Center,
+            "right" => Align::Right,
+            other => return Err(format!("canvas_draw_text_aligned: align must be left, center or right, got {}", other)),
+        };
+        draw_text_block(vm, args, "canvas_draw_text_aligned", align)
+    }).describe("canvas_draw_text_aligned(int-id, int-x, int-y, int-w, str-text, str-align) -> int", "Like canvas_draw_text_wrapped, with each line aligned left, center or right");
+    r.register("text_measure", 2, None, |_vm, args| {
+        let size = int_arg("text_measure", args, 1, "size")?;
+        if size <= 0 { return Err("text_measure: size must be positive".to_string()); }
+        let text = str_arg("text_measure", args, 0, "text")?;
+        let (w, h) = measure(&text, size);
+        Ok(Value::from(vec![Value::Int(w), Value::Int(h)]))
+    }).describe("text_measure(str-text, int-size) -> list", "Width and height in pixels of text at font size size, as list(w, h)");
+    r.register("gui_scroll_panel", 7, gui, gui_scroll_panel)
+        .describe("gui_scroll_panel(int-win_id, int-x, int-y, int-w, int-h, int-content_h, str-handler) -> int", "Add a scrollable area; handler is called with (0, offset) when the wheel or scrollbar moves it");
+    r.register("scroll_set_content", 2, gui, |_vm, args| {
+        let h = int_arg("scroll_set_content", args, 1, "content_h")? as i32;
+        with_panel("scroll_set_content", args, |p| { p.set_content(h); Value::Int(p.offset as i64) })
+    }).describe("scroll_set_content(int-panel, int-content_h) -> int", "Change the height of a scroll panel's content; returns the offset, clamped to it");
+    r.register("scroll_offset", 1, gui, |_vm, args| with_panel("scroll_offset", args, |p| Value::Int(p.offset as i64)))
+        .describe("scroll_offset(int-panel) -> int", "How far a scroll panel is scrolled down, in pixels");
+    r.register("scroll_to", 2, gui, |_vm, args| {
+        let offset = int_arg("scroll_to", args, 1, "offset")?.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+        with_panel("scroll_to", args, |p| { p.scroll_to(offset); Value::Int(p.offset as i64) })
+    }).describe("scroll_to(int-panel, int-offset) -> int", "Scroll a panel to offset, clamped to its content; returns the new offset");
+    r.register("scroll_draw", 1, gui, scroll_draw).describe("scroll_draw(int-panel) -> int", "Draw a scroll panel's scrollbar, and the rows of a list");
+    r.register("gui_dialog", 2, gui, gui_dialog)
+        .describe("gui_dialog(int-win_id, list-layout) -> DialogResult", "Show a modal dialog centered on a window and wait until it is closed; the result has button and one field per checkbox");
+    r.register("gui_list", 7, gui, gui_list)
+        .describe("gui_list(int-win_id, int-x, int-y, int-w, int-h, list-items, str-handler) -> int", "Add a scrolling list of rows; handler is called with (index, 0) when the selection changes");
+    r.register("list_selected", 1, gui, |_vm, args| with_list("list_selected", args, |l, _| Value::Int(l.selected.map_or(-1, |i| i as i64))))
+        .describe("list_selected(int-list) -> int", "Index of the selected row, or -1");
+    r.register("list_select", 2, gui, |_vm, args| {
+        let row = usize::try_from(int_arg("list_select", args, 1, "index")?).ok();
+        with_list("list_select", args, |l, p| { l.select(p, row); Value::Int(l.selected.map_or(-1, |i| i as i64)) })
+    }).describe("list_select(int-list, int-index) -> int", "Select a row and scroll it into view (-1 clears); returns the selected index");
+    r.register("list_set_items", 2, gui, |_vm, args| {
+        let items = list_items("list_set_items", &args[1])?;
+        with_list("list_set_items", args, |l, p| {
+            *l = crate::gui::ListView::new(items);
+            p.set_content(l.content_height());
+            Value::Int(l.items.len() as i64)
+        })
+    }).describe("list_set_items(int-list, list-items) -> int", "Replace a list's rows, clearing the selection; returns the row count");
+    r.register("register_widget", 6, gui, register_widget).describe("register_widget(int-win_id, int-x, int-y, int-w, int-h, str-handler) -> int", "Call the function named handler with (x, y) on clicks inside the area; returns the widget id");
+    r.register("gui_button", 3, gui, gui_button).describe("gui_button(int-win_id, str-label, str-handler) -> int", "Add a button that calls the function named handler; returns the widget id");
+    r.register("gui_focus", 1, gui, gui_focus).describe("gui_focus(int-widget) -> int", "Give the keyboard focus to a widget or list");
+    r.register("gui_focused", 1, gui, |_vm, args| {
+        let window = int_arg("gui_focused", args, 0, "win_id")? as u64;
+        let focus = crate::gui::focus().lock().unwrap_or_else(|e| e.into_inner());
+        Ok(Value::Int(focus.get(&window).and_then(|f| f.current).map_or(-1, |id| id as i64)))
+    }).describe("gui_focused(int-win_id) -> int", "Id of the widget or list with the keyboard focus in a window, or -1");
+    r.register("gui_on_drop", 2, gui, gui_on_drop)
+        .describe("gui_on_drop(int-win_id, str-handler) -> int", "Accept files dropped on a window; handler is called with (list of paths, x, y)");
+    r.register("screen_list", 0, gui, screen_list)
+        .describe("screen_list() -> list", "Monitors as Screen { x, y, w, h, work_x, work_y, work_w, work_h, primary }; empty off Windows");
+    r.register("gui_move", 3, gui, |_vm, args| {
+        let id = int_arg("gui_move", args, 0, "id")? as u64;
+        let (x, y) = (int_arg("gui_move", args, 1, "x")? as i32, int_arg("gui_move", args, 2, "y")? as i32);
+        #[cfg(target_os = "windows")]
+        { crate::platform::windows::move_window(id, x, y)?; Ok(Value::Int(1)) }
+        #[cfg(not(target_os = "windows"))]
+        { let _ = (id, x, y); Ok(Value::Int(0)) }
+    }).describe("gui_move(int-id, int-x, int-y) -> int", "Move a window's top-left corner to (x, y); use screen_list for other monitors");
+    r.register("gui_fullscreen", 2, gui, |_vm, args| {
+        let id = int_arg("gui_fullscreen", args, 0, "id")? as u64;
+        let on = int_arg("gui_fullscreen", args, 1, "flag")? != 0;
+        #[cfg(target_os = "windows")]
+        { crate::platform::windows::set_fullscreen(id, on)?; Ok(Value::Int(1)) }
+        #[cfg(not(target_os = "windows"))]
+        { let _ = (id, on); Ok(Value::Int(0)) }
+    }).describe("gui_fullscreen(int-id, int-flag) -> int", "Fill the window's monitor without borders (flag 1) or restore it (flag 0)");
+    r.register("gui_poll", 0, gui, gui_poll).describe("gui_poll() -> int", "Run handlers for the window events received so far");
+    r.register("gui_run", 0, gui, gui_run).describe("gui_run() -> int", "Dispatch window events until all windows are closed");
+    r.register("gui_close", 1, gui, gui_close).describe("gui_close(int-id) -> int", "Close a window");
+    r.register("gui_label", 2, gui, gui_label).describe("gui_label(window, str-text) -> int", "Set the label field of a window object");
+    r.register("gui_show", 1, gui, gui_show).describe("gui_show(window) -> int", "Show a window object");
+    r.register("gui_message", 2, gui, gui_message).describe("gui_message(str-title, str-text) -> int", "Show a message box; printed to the output off Windows");
+    r.register("set_theme", 1, gui, set_theme).describe("set_theme(str-name) -> int", "Switch the window theme");
+}
+
+/// gui_window(title, w, h) -> window id
+fn gui_window(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    #[cfg_attr(not(target_os = "windows"), allow(unused_variables))]
+    let title = text_arg(args, 0, "window");
+    let w = if let Value::Int(n) = args[1] { n as i32 } else { 400 };
+    let h = if let Value::Int(n) = args[2] { n as i32 } else { 300 };
+    #[cfg(target_os = "windows")]
+    { Ok(Value::Int(crate::platform::windows::create_window(&title, w, h) as i64)) }
+    // no window to show, but an id like a real one, so replayed events find
+    // their handlers, and a software canvas to draw on
+    #[cfg(not(target_os = "windows"))]
+    {
+        let id = crate::gui::next_id();
+        crate::gui::canvases().lock().unwrap_or_else(|e| e.into_inner()).insert(id, crate::raster::Raster::new(w, h));
+        Ok(Value::Int(id as i64))
+    }
+}
+
+/// gui_blit_b64(id, b64str, w, h)
+fn gui_blit_b64(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let id = int_arg("gui_blit_b64", args, 0, "id")? as u64;
+    let data = str_arg("gui_blit_b64", args, 1, "data")?;
+    let w = int_arg("gui_blit_b64", args, 2, "w")? as i32;
+    let h = int_arg("gui_blit_b64", args, 3, "h")? as i32;
+    let bytes = crate::base64::decode_lenient(&data);
+    #[cfg(target_os = "windows")]
+    {
+        crate::platform::windows::blit_window(id, bytes, w, h).map_err(|e| e.to_string())?;
+        Ok(Value::Int(1))
+    }
+    #[cfg(not(target_os = "windows"))]
+    { let _ = (id, bytes, w, h); Ok(Value::Int(0)) }
+}
+
+/// draw_rect(id, canvas_w, canvas_h, x, y, w, h, color)
+fn draw_rect(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let n = |i: usize, what: &str| int_arg("draw_rect", args, i, what);
+    let id = n(0, "id")? as u64;
+    let (canvas_w, canvas_h) = (n(1, "canvas_w")? as i32, n(2, "canvas_h")? as i32);
+    let (x, y, w, h) = (n(3, "x")? as i32, n(4, "y")? as i32, n(5, "w")? as i32, n(6, "h")? as i32);
+    let crate::color::Rgba { r, g, b, a } = color_arg("draw_rect", args, 7)?;
+    #[cfg(target_os = "windows")]
+    {
+        let _ = (canvas_w, canvas_h);
+        crate::platform::windows::canvas_draw_rect(id, x, y, w, h, r, g, b, a).map_err(|e| e.to_string())?;
+        Ok(Value::Int(1))
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        if canvas_w <= 0 || canvas_h <= 0 { return Err("draw_rect: invalid canvas size".to_string()) }
+        let mut canvases = crate::gui::canvases().lock().unwrap_or_else(|e| e.into_inner());
+        let canvas = canvases.get_mut(&id).ok_or_else(|| format!("draw_rect: no window {}", id))?;
+        canvas.fill_rect(x, y, w, h, [r, g, b, a]);
+        Ok(Value::Int(0))
+    }
+}
+
+/// canvas_clear(id, color)
+fn canvas_clear(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let id = int_arg("canvas_clear", args, 0, "id")? as u64;
+    let crate::color::Rgba { r, g, b, a } = color_arg("canvas_clear", args, 1)?;
+    #[cfg(target_os = "windows")]
+    { crate::platform::windows::canvas_clear(id, r, g, b, a).map_err(|e| e.to_string())?; Ok(Value::Int(1)) }
+    #[cfg(not(target_os = "windows"))]
+    {
+        let mut canvases = crate::gui::canvases().lock().unwrap_or_else(|e| e.into_inner());
+        canvases.get_mut(&id).ok_or_else(|| format!("canvas_clear: no window {}", id))?.clear([r, g, b, a]);
+        Ok(Value::Int(0))
+    }
+}
+
+/// The pixels of window `id`'s canvas
+pub(crate) fn canvas_raster(name: &str, id: u64) -> Result<crate::raster::Raster, VmError> {
+    #[cfg(target_os = "windows")]
+    {
+        let (w, h, pixels) = crate::platform::windows::canvas_pixels(id).ok_or_else(|| format!("{}: no window {}", name, id))?;
+        Ok(crate::raster::Raster { w, h, pixels })
+    }
+    #[cfg(not(target_os = "windows"))]
+    { crate::gui::canvases().lock().unwrap_or_else(|e| e.into_inner()).get(&id).cloned().ok_or_else(|| format!("{}: no window {}", name, id)) }
+}
+
+/// canvas_save(id, path): the canvas as a BMP file
+fn canvas_save(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let id = int_arg("canvas_save", args, 0, "id")? as u64;
+    let path = str_arg("canvas_save", args, 1, "path")?;
+    let raster = canvas_raster("canvas_save", id)?;
+    std::fs::write(&path, raster.to_bmp()).map_err(|e| format!("canvas_save: {}: {}", path, e))?;
+    Ok(Value::Int(1))
+}
+
+fn canvas_present(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let id = int_arg("canvas_present", args, 0, "id")? as u64;
+    #[cfg(target_os = "windows")]
+    { crate::platform::windows::canvas_present(id).map_err(|e| e.to_string())?; Ok(Value::Int(1)) }
+    #[cfg(not(target_os = "windows"))]
+    { let _ = id; Ok(Value::Int(0)) }
+}
+
+/// canvas_draw_text(id, x, y, text)
+fn canvas_draw_text(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let id = int_arg("canvas_draw_text", args, 0, "id")? as u64;
+    let x = int_arg("canvas_draw_text", args, 1, "x")? as i32;
+    let y = int_arg("canvas_draw_text", args, 2, "y")? as i32;
+    let text = str_arg("canvas_draw_text", args, 3, "text")?;
+    #[cfg(target_os = "windows")]
+    { crate::platform::windows::canvas_draw_text(id, x, y, &text).map_err(|e| e.to_string())?; Ok(Value::Int(1)) }
+    #[cfg(not(target_os = "windows"))]
+    { let _ = (id, x, y, text); Ok(Value::Int(0)) }
+}
+
+/// register_widget(win_id, x, y, w, h, handler_name)
+fn register_widget(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let n = |i: usize, what: &str| int_arg("register_widget", args, i, what);
+    let id = n(0, "id")? as u64;
+    let (x, y, w, h) = (n(1, "x")? as i32, n(2, "y")? as i32, n(3, "w")? as i32, n(4, "h")? as i32);
+    let handler = str_arg("register_widget", args, 5, "handler")?;
+    #[cfg(target_os = "windows")]
+    { Ok(Value::Int(crate::platform::windows::register_widget(id, x, y, w, h, &handler) as i64)) }
+    #[cfg(not(target_os = "windows"))]
+    { let _ = (x, y, w, h); Ok(Value::Int(headless_widget(id, &handler) as i64)) }
+}
+
+/// gui_focus(widget): the widget's window is found from its id
+fn gui_focus(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let id = int_arg("gui_focus", args, 0, "widget")? as u64;
+    let mut focus = crate::gui::focus().lock().unwrap_or_else(|e| e.into_inner());
+    let (window, order) = focus.iter_mut().find(|(_, f)| f.order.contains(&id)).ok_or_else(|| format!("gui_focus: no widget {}", id))?;
+    let old = order.current;
+    order.set(id);
+    #[cfg(target_os = "windows")]
+    {
+        let window = *window;
+        drop(focus);
+        crate::platform::windows::show_focus(window, old, Some(id))?;
+    }
+    #[cfg(not(target_os = "windows"))]
+    let _ = (window, old);
+    Ok(Value::Int(1))
+}
+
+/// gui_button(win_id, label, handler_name)
+fn gui_button(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let id = int_arg("gui_button", args, 0, "id")? as u64;
+    let handler = str_arg("gui_button", args, 2, "handler")?;
+    // register a widget using simple auto layout and handler name
+    #[cfg(target_os = "windows")]
+    { Ok(Value::Int(crate::platform::windows::register_widget_auto(id, "button", &handler) as i64)) }
+    #[cfg(not(target_os = "windows"))]
+    { Ok(Value::Int(headless_widget(id, &handler) as i64)) }
+}
+
+/// A widget with no window behind it, numbered and focusable like a real one
+#[cfg(not(target_os = "windows"))]
+fn headless_widget(window: u64, handler: &str) -> u64 {
+    let id = crate::gui::next_id();
+    crate::gui::set_handler(id, handler);
+    crate::gui::add_focusable(window, id);
+    id
+}
+
+/// A monitor as the `Screen` objects of `screen_list`
+pub(crate) fn screen_value(m: &crate::gui::Monitor) -> Value {
+    let mut fields = Fields::new();
+    let (x, y, w, h) = m.bounds;
+    let (wx, wy, ww, wh) = m.work;
+    for (name, v) in [("x", x), ("y", y), ("w", w), ("h", h), ("work_x", wx), ("work_y", wy), ("work_w", ww), ("work_h", wh)] {
+        fields.insert(name.to_string(), Value::Int(v as i64));
+    }
+    fields.insert("primary".to_string(), Value::Int(m.primary as i64));
+    Value::Object(Rc::new(RefCell::new(Object::plain("Screen", fields))))
+}
+
+/// screen_list()
+fn screen_list(_vm: &mut VM, _args: &[Value]) -> Result<Value, VmError> {
+    #[cfg(target_os = "windows")]
+    let monitors = crate::platform::windows::monitors();
+    #[cfg(not(target_os = "windows"))]
+    let monitors: Vec<crate::gui::Monitor> = Vec::new();
+    Ok(Value::from(monitors.iter().map(screen_value).collect::<Vec<_>>()))
+}
+
+/// gui_on_drop(win_id, handler_name)
+fn gui_on_drop(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let window = int_arg("gui_on_drop", args, 0, "win_id")? as u64;
+    let handler = str_arg("gui_on_drop", args, 1, "handler")?;
+    crate::gui::set_drop_handler(window, &handler);
+    #[cfg(target_os = "windows")]
+    { crate::platform::windows::accept_files(window)?; Ok(Value::Int(1)) }
+    #[cfg(not(target_os = "windows"))]
+    Ok(Value::Int(0))
+}
+
+/// Call the handlers of pending events: window input (Windows only), dropped
+/// files and events due from a replay. Clicks get (x, y), drops (paths, x, y);
+/// each event is recorded when the VM records
+fn dispatch_events(vm: &mut VM) -> Result<(), VmError> {
+    let mut events = Vec::new();
+    #[cfg(target_os = "windows")]
+    events.extend(crate::platform::windows::drain_events().into_iter().map(|(target, (x, y))| GuiEvent::Click { target, x, y }));
+    events.extend(crate::gui::take_drops().into_iter().map(GuiEvent::Drop));
+    events.extend(vm.gui_events().due());
+    for event in events {
+        vm.gui_events().record(&event);
+        let (handler, args) = match event {
+            GuiEvent::Click { target, x, y } => (crate::gui::handler(target), vec![Value::Int(x as i64), Value::Int(y as i64)]),
+            GuiEvent::Drop(drop) => {
+                let paths = Value::from(drop.paths.into_iter().map(Value::Str).collect::<Vec<_>>());
+                (crate::gui::drop_handler(drop.window), vec![paths, Value::Int(drop.x as i64), Value::Int(drop.y as i64)])
+            }
+        };
+        if let Some(hname) = handler {
+            vm.call_handler(&hname, &args)?;
+        }
+    }
+    Ok(())
+}
+
+/// gui_poll(): run handlers for events received so far
+fn gui_poll(vm: &mut VM, _args: &[Value]) -> Result<Value, VmError> {
+    dispatch_events(vm)?;
+    super::timer::pump(vm)?;
+    super::task::pump(vm)?;
+    super::tween::pump(vm)?;
+    Ok(Value::Int(cfg!(target_os = "windows") as i64))
+}
+
+/// gui_run(): keep dispatching events while windows exist, replayed events
+/// are still to come, or timers or tasks are pending
+fn gui_run(vm: &mut VM, _args: &[Value]) -> Result<Value, VmError> {
+    #[cfg(target_os = "windows")]
+    let windows = crate::platform::windows::has_windows;
+    #[cfg(not(target_os = "windows"))]
+    let windows = || false;
+    while windows() || vm.gui_events().replaying() || !vm.timers().is_empty() || vm.tasks().runnable() {
+        // the event loop itself runs no statements: let the host stop it
+        vm.check_cancelled()?;
+        dispatch_events(vm)?;
+        super::timer::pump(vm)?;
+        super::task::pump(vm)?;
+        super::tween::pump(vm)?;
+        // a frozen clock moves on here instead, so timers still come due
+        vm.sleep_ms(20)?;
+    }
+    Ok(Value::Int(cfg!(target_os = "windows") as i64))
+}
+
+fn gui_close(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let id = int_arg("gui_close", args, 0, "id")? as u64;
+    #[cfg(target_os = "windows")]
+    crate::platform::windows::close_window(id);
+    #[cfg(not(target_os = "windows"))]
+    crate::gui::canvases().lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+    Ok(Value::Int(1))
+}
+
+/// gui_label(window_obj, text): stores the text on the object
+fn gui_label(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let text = text_arg(args, 1, "");
+    let Value::Object(o) = &args[0] else { return Err("gui_label: first arg must be a Window object".to_string()) };
+    o.borrow_mut().fields.insert("label".to_string(), Value::Str(text));
+    Ok(Value::Int(1))
+}
+
+fn gui_show(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    // no-op placeholder; real implementation will present the window
+    match &args[0] {
+        Value::Object(_) => Ok(Value::Int(1)),
+        _ => Err("gui_show: arg must be a Window object".to_string()),
+    }
+}
+
+/// gui_message(title, text): message box, printed to the VM output elsewhere
+fn gui_message(vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let title = text_arg(args, 0, "");
+    let text = text_arg(args, 1, "");
+    #[cfg(target_os = "windows")]
+    { let _ = vm; crate::platform::windows::show_message(&title, &text); }
+    #[cfg(not(target_os = "windows"))]
+    vm.emit(&format!("{}: {}", title, text))?;
+    Ok(Value::Int(1))
+}
+
+fn set_theme(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let name = str_arg("set_theme", args, 0, "arg")?;
+    #[cfg(target_os = "windows")]
+    crate::platform::windows::set_theme(&name);
+    #[cfg(not(target_os = "windows"))]
+    let _ = name;
+    Ok(Value::Int(1))
+}
+
+/// Font size of the text `canvas_draw_text` draws
+pub(crate) const CANVAS_FONT_SIZE: i64 = 16;
+
+/// Width of one character at font size `size`: half the size for most
+/// characters, the full size for wide (CJK, fullwidth) ones, nothing for
+/// combining marks. An estimate that matches the canvas font for ASCII
+fn char_width(c: char, size: i64) -> i64 {
+    match c as u32 {
+        0x0300..=0x036F | 0x200B..=0x200D => 0,
+        0x1100..=0x115F | 0x2E80..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF | 0xFE30..=0xFE4F | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6 | 0x1F300..=0x1FAFF | 0x20000..=0x3FFFD => size,
+        _ => size / 2,
+    }
+}
+
+fn line_height(size: i64) -> i64 {
+    size + size / 4
+}
+
+fn text_width(line: &str, size: i64) -> i64 {
+    line.chars().map(|c| char_width(c, size)).sum()
+}
+
+/// (width, height) of `text`, one line per `\n`
+pub(crate) fn measure(text: &str, size: i64) -> (i64, i64) {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let w = lines.iter().map(|l| text_width(l, size)).max().unwrap_or(0);
+    (w, lines.len() as i64 * line_height(size))
+}
+
+/// Lines of `text` that fit in `width` pixels, breaking at spaces; a word
+/// wider than a whole line is broken between characters. `\n` always breaks
+pub(crate) fn wrap(text: &str, width: i64, size: i64) -> Vec<String> {
+    let space = char_width(' ', size);
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        let mut line_w = 0;
+        for word in paragraph.split_whitespace() {
+            let word_w = text_width(word, size);
+            if !line.is_empty() && line_w + space + word_w <= width {
+                line.push(' ');
+                line.push_str(word);
+                line_w += space + word_w;
+                continue;
+            }
+            if !line.is_empty() { lines.push(std::mem::take(&mut line)); }
+            line_w = 0;
+            for c in word.chars() {
+                let cw = char_width(c, size);
+                if !line.is_empty() && line_w + cw > width {
+                    lines.push(std::mem::take(&mut line));
+                    line_w = 0;
+                }
+                line.push(c);
+                line_w += cw;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// canvas_draw_text_wrapped / canvas_draw_text_aligned: (id, x, y, w, text[, align])
+fn draw_text_block(_vm: &mut VM, args: &[Value], name: &str, align: Align) -> Result<Value, VmError> {
+    let id = int_arg(name, args, 0, "id")? as u64;
+    let (x, y) = (int_arg(name, args, 1, "x")?, int_arg(name, args, 2, "y")?);
+    let w = int_arg(name, args, 3, "w")?;
+    let text = str_arg(name, args, 4, "text")?;
+    let lines = wrap(&text, w, CANVAS_FONT_SIZE);
+    for (i, line) in lines.iter().enumerate() {
+        let free = w - text_width(line, CANVAS_FONT_SIZE);
+        let lx = match align { Align::Left => x, Align::Center => x + free / 2, Align::Right => x + free };
+        let ly = y + i as i64 * line_height(CANVAS_FONT_SIZE);
+        #[cfg(target_os = "windows")]
+        crate::platform::windows::canvas_draw_text(id, lx as i32, ly as i32, line).map_err(|e| e.to_string())?;
+        #[cfg(not(target_os = "windows"))]
+        let _ = (id, lx, ly);
+    }
+    Ok(Value::Int(lines.len() as i64))
+}
+
+/// gui_scroll_panel(win_id, x, y, w, h, content_h, handler_name)
+fn gui_scroll_panel(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let n = |i: usize, what: &str| int_arg("gui_scroll_panel", args, i, what).map(|v| v as i32);
+    let window = n(0, "win_id")? as u64;
+    let (x, y, w, h) = (n(1, "x")?, n(2, "y")?, n(3, "w")?, n(4, "h")?);
+    if w <= crate::gui::SCROLLBAR_WIDTH || h <= 0 {
+        return Err(format!("gui_scroll_panel: a panel must be wider than its {} px scrollbar and have a height", crate::gui::SCROLLBAR_WIDTH));
+    }
+    let panel = crate::gui::ScrollPanel::new(x, y, w, h, n(5, "content_h")?);
+    let handler = str_arg("gui_scroll_panel", args, 6, "handler")?;
+    let id = crate::gui::add_panel(crate::gui::PanelEntry { window, handler: handler.clone(), panel, list: None });
+    crate::gui::set_handler(id, &handler);
+    Ok(Value::Int(id as i64))
+}
+
+/// Run `f` on the scroll panel whose id is the first argument
+fn with_panel(name: &str, args: &[Value], f: impl FnOnce(&mut crate::gui::ScrollPanel) -> Value) -> Result<Value, VmError> {
+    let id = int_arg(name, args, 0, "panel")? as u64;
+    let mut panels = crate::gui::panels().lock().unwrap_or_else(|e| e.into_inner());
+    let entry = panels.get_mut(&id).ok_or_else(|| format!("{}: no scroll panel {}", name, id))?;
+    Ok(f(&mut entry.panel))
+}
+
+/// scroll_draw(panel)
+fn scroll_draw(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let id = int_arg("scroll_draw", args, 0, "panel")? as u64;
+    let entry = crate::gui::panels().lock().unwrap_or_else(|e| e.into_inner()).get(&id).cloned()
+        .ok_or_else(|| format!("scroll_draw: no scroll panel {}", id))?;
+    // a list draws its rows too; a bare panel only its scrollbar
+    let ops = match &entry.list {
+        Some(list) => list.draw(&entry.panel),
+        None => entry.panel.draw_scrollbar(),
+    };
+    #[cfg(target_os = "windows")]
+    { crate::platform::windows::draw_ops(entry.window, &ops)?; Ok(Value::Int(1)) }
+    #[cfg(not(target_os = "windows"))]
+    { let _ = (entry, ops); Ok(Value::Int(0)) }
+}
+
+/// gui_dialog(win_id, layout): blocks until a button, Enter or Escape closes
+/// the dialog. Off Windows the dialog is printed and closed with no button
+fn gui_dialog(vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let window = int_arg("gui_dialog", args, 0, "win_id")? as u64;
+    let layout = list_items("gui_dialog", &args[1])?;
+    let dialog = crate::gui::Dialog::parse(&layout).map_err(|e| format!("gui_dialog: {}", e))?;
+    #[cfg(target_os = "windows")]
+    let dialog = {
+        use crate::platform::windows as win;
+        let mut dialog = dialog;
+        let (w, h) = win::canvas_size(window).ok_or_else(|| format!("gui_dialog: no window {}", window))?;
+        dialog.center(w, h);
+        win::canvas_save(window)?;
+        win::draw_ops(window, &dialog.draw())?;
+        let depth = {
+            let mut modals = crate::gui::modals().lock().unwrap_or_else(|e| e.into_inner());
+            let stack = modals.entry(window).or_default();
+            stack.push(dialog);
+            stack.len()
+        };
+        let closed = |modals: &std::collections::HashMap<u64, Vec<crate::gui::Dialog>>| {
+            modals.get(&window).and_then(|s| s.get(depth - 1)).is_none_or(|d| d.result.is_some())
+        };
+        // the parent's events wait in the queue until the dialog is gone
+        let waited = loop {
+            if let Err(e) = vm.check_cancelled() { break Err(e); }
+            if !win::window_exists(window) || closed(&crate::gui::modals().lock().unwrap_or_else(|e| e.into_inner())) { break Ok(()); }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        };
+        let mut modals = crate::gui::modals().lock().unwrap_or_else(|e| e.into_inner());
+        let dialog = modals.get_mut(&window).and_then(|s| s.pop());
+        drop(modals);
+        if win::window_exists(window) { win::canvas_restore(window)?; }
+        waited?;
+        let mut dialog = dialog.ok_or_else(|| "gui_dialog: the dialog was lost".to_string())?;
+        dialog.result.get_or_insert_with(String::new);
+        dialog
+    };
+    #[cfg(not(target_os = "windows"))]
+    let dialog = {
+        let _ = window;
+        let text: Vec<&str> = dialog.items.iter().filter_map(|i| match i { crate::gui::DialogItem::Text(t) => Some(t.as_str()), _ => None }).collect();
+        vm.emit(&format!("{}: {}", dialog.title, text.join(" ")))?;
+        crate::gui::Dialog { result: Some(String::new()), ..dialog }
+    };
+    let mut fields = Fields::new();
+    fields.insert("button".to_string(), Value::Str(dialog.result.clone().unwrap_or_default()));
+    for (name, on) in dialog.checks() {
+        fields.insert(name, Value::Int(on as i64));
+    }
+    Ok(Value::Object(Rc::new(RefCell::new(Object::plain("DialogResult", fields)))))
+}
+
+/// List items as row texts
+fn list_items(name: &str, v: &Value) -> Result<Vec<String>, VmError> {
+    let Value::List(items) = v else { return Err(format!("{}: items must be a list", name)) };
+    items.borrow().iter().map(|item| match item {
+        Value::Str(s) => Ok(s.clone()),
+        Value::Int(n) => Ok(n.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        other => Err(format!("{}: list items must be strings or numbers, got {}", name, other.type_name())),
+    }).collect()
+}
+
+/// gui_list(win_id, x, y, w, h, items, handler_name)
+fn gui_list(_vm: &mut VM, args: &[Value]) -> Result<Value, VmError> {
+    let n = |i: usize, what: &str| int_arg("gui_list", args, i, what).map(|v| v as i32);
+    let window = n(0, "win_id")? as u64;
+    let (x, y, w, h) = (n(1, "x")?, n(2, "y")?, n(3, "w")?, n(4, "h")?);
+    if w <= crate::gui::SCROLLBAR_WIDTH || h <= 0 {
+        return Err(format!("gui_list: a list must be wider than its {} px scrollbar and have a height", crate::gui::SCROLLBAR_WIDTH));
+    }
+    let list = crate::gui::ListView::new(list_items("gui_list", &args[5])?);
+    let panel = crate::gui::ScrollPanel::new(x, y, w, h, list.content_height());
+    let handler = str_arg("gui_list", args, 6, "handler")?;
+    let id = crate::gui::add_panel(crate::gui::PanelEntry { window, handler: handler.clone(), panel, list: Some(list) });
+    crate::gui::add_focusable(window, id);
+    crate::gui::set_handler(id, &handler);
+    Ok(Value::Int(id as i64))
+}
+
+/// Run `f` on the list widget whose id is the first argument
+fn with_list(name: &str, args: &[Value], f: impl FnOnce(&mut crate::gui::ListView, &mut crate::gui::ScrollPanel) -> Value) -> Result<Value, VmError> {
+    let id = int_arg(name, args, 0, "list")? as u64;
+    let mut panels = crate::gui::panels().lock().unwrap_or_else(|e| e.into_inner());
+    let entry = panels.get_mut(&id).ok_or_else(|| format!("{}: no list {}", name, id))?;
+    let list = entry.list.as_mut().ok_or_else(|| format!("{}: {} is a scroll panel, not a list", name, id))?;
+    Ok(f(list, &mut entry.panel))
+}
@@ -0,0 +1,28 @@
+//! Looking at objects from scripts: `fields(obj)`, `dir(obj)` and `repr(x)`.
+use super::Registry;
+use crate::vm::Value;
+
+pub(super) fn register(r: &mut Registry) {
+    r.register("fields", 1, None, |_vm, args| match &args[0] {
+        Value::Object(o) => Ok(names(o.borrow().fields.keys().cloned())),
+        v => Err(format!("fields: expected object, got {}", v.type_name())),
+    }).describe("fields(obj) -> list", "Field names of an object, in the order they were set");
+    r.register("dir", 1, None, |_vm, args| match &args[0] {
+        Value::Object(o) => {
+            let o = o.borrow();
+            Ok(names(o.fields.keys().cloned().chain(o.method_names())))
+        }
+        Value::Class(c) => {
+            let mut methods: Vec<String> = c.methods.keys().cloned().collect();
+            methods.sort();
+            Ok(names(methods.into_iter()))
+        }
+        v => Err(format!("dir: expected object or class, got {}", v.type_name())),
+    }).describe("dir(x) -> list", "Fields then methods of an object, or the methods of a class");
+    r.register("repr", 1, None, |vm, args| Ok(Value::Str(vm.repr(&args[0]))))
+        .describe("repr(x) -> str", "Structure of a value: quoted strings, objects with their fields, nested values; cyclic parts print as <cycle>");
+}
+
+fn names(names: impl Iterator<Item = String>) -> Value {
+    Value::from(names.map(Value::Str).collect::<Vec<_>>())
+}
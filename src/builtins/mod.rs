@@ -0,0 +1,198 @@
+//! Builtin functions. Each VM owns a `Registry` filled with the standard set
+//! on creation; hosts add their own with `VM::register_builtin`.
+//! Builtins shadow user definitions of the same name.
+//! Standard builtins are documented where they are registered
+//! (`register(..).describe(signature, summary)`); `help("name")`,
+//! `userd doc --builtins` and the editor's `/api/builtins` all read that.
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::vm::{Capability, Value, VM};
+
+mod basic;
+mod color;
+pub(crate) mod channels;
+mod crypto;
+mod csv;
+mod fs;
+mod fsm;
+pub(crate) mod gui;
+mod introspect;
+mod overrides;
+mod patch;
+mod process;
+mod settings;
+mod testing;
+mod task;
+mod timer;
+mod tween;
+pub(crate) mod text;
+pub(crate) mod ws;
+mod xml;
+
+/// Errors raised while running a program
+pub type VmError = String;
+
+/// Rust implementation of a builtin; receives the evaluated arguments
+pub type BuiltinFn = Rc<dyn Fn(&mut VM, &[Value]) -> Result<Value, VmError>>;
+
+/// `Builtin::arity` of a builtin taking any number of arguments, like `list`
+pub const VARIADIC: usize = usize::MAX;
+
+#[derive(Clone)]
+pub struct Builtin {
+    /// Exact number of arguments, checked before `func` runs; `VARIADIC` for any number
+    pub arity: usize,
+    /// Capability a `Policy` must allow for the builtin to run
+    pub capability: Option<Capability>,
+    pub func: BuiltinFn,
+    pub doc: Option<BuiltinDoc>,
+}
+
+/// Signature in userd notation, e.g. `to_int(x) -> int`, and a one-line summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuiltinDoc {
+    pub signature: &'static str,
+    pub summary: &'static str,
+}
+
+impl Builtin {
+    pub fn describe(&mut self, signature: &'static str, summary: &'static str) -> &mut Self {
+        self.doc = Some(BuiltinDoc { signature, summary });
+        self
+    }
+
+    /// Text for `help`: the signature and summary, or just the arity when undocumented
+    pub fn help(&self, name: &str) -> String {
+        match self.doc {
+            Some(d) => format!("{}\n  {}", d.signature, d.summary),
+            None if self.arity == VARIADIC => format!("{}: builtin taking any number of arguments", name),
+            None => format!("{}: builtin taking {} argument{}", name, self.arity, if self.arity == 1 { "" } else { "s" }),
+        }
+    }
+
+    /// Whether `n` arguments are right for this builtin
+    pub fn accepts(&self, n: usize) -> bool {
+        self.arity == VARIADIC || self.arity == n
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Registry {
+    map: HashMap<String, Builtin>,
+}
+
+impl Registry {
+    /// The builtins every VM starts with
+    pub fn standard() -> Self {
+        let mut r = Self::default();
+        basic::register(&mut r);
+        gui::register(&mut r);
+        color::register(&mut r);
+        channels::register(&mut r);
+        introspect::register(&mut r);
+        overrides::register(&mut r);
+        fs::register(&mut r);
+        process::register(&mut r);
+        csv::register(&mut r);
+        fsm::register(&mut r);
+        xml::register(&mut r);
+        settings::register(&mut r);
+        ws::register(&mut r);
+        patch::register(&mut r);
+        crypto::register(&mut r);
+        text::register(&mut r);
+        testing::register(&mut r);
+        tween::register(&mut r);
+        timer::register(&mut r);
+        task::register(&mut r);
+        r
+    }
+
+    /// Add or replace a builtin; returns it so it can be described
+    pub fn register(&mut self, name: &str, arity: usize, capability: Option<Capability>, f: impl Fn(&mut VM, &[Value]) -> Result<Value, VmError> + 'static) -> &mut Builtin {
+        let b = Builtin { arity, capability, func: Rc::new(f), doc: None };
+        self.map.entry(name.to_string()).insert_entry(b).into_mut()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Builtin> {
+        self.map.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.map.contains_key(name)
+    }
+
+    /// Registered names, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.map.keys().map(|k| k.as_str()).collect();
+        names.sort();
+        names
+    }
+}
+
+/// Typed arguments of a builtin, e.g.
+/// `let (id, text): (i64, String) = from_args("channel_send", args)?;`
+pub trait FromArgs: Sized {
+    fn from_args(name: &str, args: &[Value]) -> Result<Self, VmError>;
+}
+
+macro_rules! tuple_from_args {
+    ($n:expr; $($t:ident $i:tt),+) => {
+        impl<$($t: TryFrom<Value, Error = VmError>),+> FromArgs for ($($t,)+) {
+            fn from_args(name: &str, args: &[Value]) -> Result<Self, VmError> {
+                if args.len() != $n {
+                    return Err(format!("{} requires {} argument{}", name, $n, if $n == 1 { "" } else { "s" }));
+                }
+                Ok(($($t::try_from(args[$i].clone()).map_err(|e| format!("{}: argument {}: {}", name, $i + 1, e))?,)+))
+            }
+        }
+    };
+}
+
+tuple_from_args!(1; A 0);
+tuple_from_args!(2; A 0, B 1);
+tuple_from_args!(3; A 0, B 1, C 2);
+tuple_from_args!(4; A 0, B 1, C 2, D 3);
+
+/// Convert a builtin's argument slice into Rust values; see `FromArgs`
+pub fn from_args<T: FromArgs>(name: &str, args: &[Value]) -> Result<T, VmError> {
+    T::from_args(name, args)
+}
+
+/// Integer argument `i` of builtin `name`
+fn int_arg(name: &str, args: &[Value], i: usize, what: &str) -> Result<i64, VmError> {
+    match &args[i] {
+        Value::Int(n) => Ok(*n),
+        _ => Err(format!("{}: {} must be int", name, what)),
+    }
+}
+
+/// String argument `i` of builtin `name`
+fn str_arg(name: &str, args: &[Value], i: usize, what: &str) -> Result<String, VmError> {
+    match &args[i] {
+        Value::Str(s) => Ok(s.clone()),
+        _ => Err(format!("{}: {} must be string", name, what)),
+    }
+}
+
+/// Bytes of a string (UTF-8) or of a list of ints 0..255
+fn bytes_arg(name: &str, args: &[Value], i: usize, what: &str) -> Result<Vec<u8>, VmError> {
+    match &args[i] {
+        Value::Str(s) => Ok(s.as_bytes().to_vec()),
+        Value::List(items) => items.borrow().iter().map(|v| match v {
+            Value::Int(n) if (0..=255).contains(n) => Ok(*n as u8),
+            _ => Err(format!("{}: a byte list must hold ints 0..255", name)),
+        }).collect(),
+        other => Err(format!("{}: {} must be str or a list of bytes, got {}", name, what, other.type_name())),
+    }
+}
+
+/// Text of a string or int argument; anything else becomes `default`
+fn text_arg(args: &[Value], i: usize, default: &str) -> String {
+    match &args[i] {
+        Value::Str(s) => s.clone(),
+        Value::Int(n) => n.to_string(),
+        _ => default.to_string(),
+    }
+}
@@ -0,0 +1,45 @@
+//! Replacing builtins from scripts: `override_builtin("read_file", "fake")`
+//! sends every later `read_file(...)` call to the script function `fake`,
+//! which can still reach the real one with `original_builtin`. Meant for test
+//! doubles and instrumentation; `VM::wrap_builtin` is the embedder's version.
+use super::{str_arg, Registry};
+use crate::vm::Value;
+
+pub(super) fn register(r: &mut Registry) {
+    r.register("override_builtin", 2, None, |vm, args| {
+        let name = str_arg("override_builtin", args, 0, "name")?;
+        let handler = str_arg("override_builtin", args, 1, "function")?;
+        let f = match vm.get_global(&handler) {
+            Some(f @ (Value::Function(_) | Value::Overloaded(_))) => f,
+            _ => return Err(format!("override_builtin: {} is not a function", handler)),
+        };
+        if let Some(n) = vm.builtin_arity(&name) && f.overload(n).is_none() {
+            return Err(match &f {
+                Value::Function(f) => format!("override_builtin: {} takes {} argument(s) but {} takes {}", name, n, handler, f.params.len()),
+                _ => format!("override_builtin: {} takes {} argument(s) but no overload of {} does", name, n, handler),
+            });
+        }
+        let label = name.clone();
+        vm.wrap_builtin(&name, move |vm, args, _original| {
+            // looked up on each call, so redefining the function takes effect
+            match vm.get_global(&handler) {
+                Some(Value::Function(f)) => vm.call_function(&f, args),
+                Some(set @ Value::Overloaded(_)) => vm.call_value(&handler, set, args.to_vec()),
+                _ => Err(format!("{}: override {} is no longer a function", label, handler)),
+            }
+        }).map_err(|e| format!("override_builtin: {}", e))?;
+        Ok(Value::Int(1))
+    }).describe("override_builtin(str-name, str-function) -> int", "Send calls of a builtin to a script function with the same parameters");
+    r.register("original_builtin", 2, None, |vm, args| {
+        let name = str_arg("original_builtin", args, 0, "name")?;
+        let Value::List(list) = &args[1] else {
+            return Err(format!("original_builtin: args must be a list, got {}", args[1].type_name()));
+        };
+        let list = list.borrow().clone();
+        vm.call_original(&name, &list)
+    }).describe("original_builtin(str-name, list-args) -> value", "Call a builtin as it was before override_builtin");
+    r.register("restore_builtin", 1, None, |vm, args| {
+        let name = str_arg("restore_builtin", args, 0, "name")?;
+        Ok(Value::Int(vm.restore_builtin(&name) as i64))
+    }).describe("restore_builtin(str-name) -> int", "Undo override_builtin; 0 if the builtin was not overridden");
+}
@@ -0,0 +1,54 @@
+//! Line diffs of strings (see `crate::diff`). Hunks are `Hunk` objects with
+//! `old_start`, `old_lines`, `new_start` and `new_lines`; scripts may build
+//! them by hand. Only line contents are compared, so a difference in the
+//! final newline alone gives no hunk.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::{str_arg, Registry, VmError};
+use crate::diff::Hunk;
+use crate::vm::{Fields, Object, Value};
+
+pub(super) fn register(r: &mut Registry) {
+    r.register("diff_lines", 2, None, |_vm, args| {
+        let (a, b) = (str_arg("diff_lines", args, 0, "a")?, str_arg("diff_lines", args, 1, "b")?);
+        let hunks = crate::diff::diff(&a.lines().collect::<Vec<_>>(), &b.lines().collect::<Vec<_>>());
+        Ok(Value::from(hunks.into_iter().map(to_value).collect::<Vec<_>>()))
+    }).describe("diff_lines(str-a, str-b) -> list", "Hunks turning text a into text b: Hunk { old_start, old_lines, new_start, new_lines }");
+    r.register("apply_patch", 2, None, |_vm, args| {
+        let text = str_arg("apply_patch", args, 0, "text")?;
+        let Value::List(hunks) = &args[1] else { return Err("apply_patch: hunks must be a list".to_string()) };
+        let hunks = hunks.borrow().iter().map(from_value).collect::<Result<Vec<_>, _>>()?;
+        let lines = crate::diff::apply(&text.lines().collect::<Vec<_>>(), &hunks).map_err(|e| format!("apply_patch: {}", e))?;
+        let mut out = lines.join("\n");
+        if text.ends_with('\n') && !out.is_empty() { out.push('\n'); }
+        Ok(Value::Str(out))
+    }).describe("apply_patch(str-text, list-hunks) -> str", "Apply hunks from diff_lines; fails if a hunk's old lines are not where it says");
+}
+
+fn strings(lines: Vec<String>) -> Value {
+    Value::from(lines.into_iter().map(Value::Str).collect::<Vec<_>>())
+}
+
+fn to_value(h: Hunk) -> Value {
+    let mut fields = Fields::new();
+    fields.insert("old_start".to_string(), Value::Int(h.old_start as i64));
+    fields.insert("old_lines".to_string(), strings(h.old_lines));
+    fields.insert("new_start".to_string(), Value::Int(h.new_start as i64));
+    fields.insert("new_lines".to_string(), strings(h.new_lines));
+    Value::Object(Rc::new(RefCell::new(Object::plain("Hunk", fields))))
+}
+
+fn from_value(v: &Value) -> Result<Hunk, VmError> {
+    let Value::Object(o) = v else { return Err(format!("apply_patch: expected a Hunk, got {}", v.type_name())) };
+    let o = o.borrow();
+    let int = |name: &str| match o.fields.get(name) {
+        Some(Value::Int(n)) if *n >= 0 => Ok(*n as usize),
+        _ => Err(format!("apply_patch: hunk field {} must be a non-negative int", name)),
+    };
+    let lines = |name: &str| match o.fields.get(name) {
+        Some(Value::List(items)) => items.borrow().iter().map(|l| String::try_from(l.clone()).map_err(|_| format!("apply_patch: {} must hold strings", name))).collect(),
+        _ => Err(format!("apply_patch: hunk field {} must be a list", name)),
+    };
+    Ok(Hunk { old_start: int("old_start")?, old_lines: lines("old_lines")?, new_start: int("new_start")?, new_lines: lines("new_lines")? })
+}
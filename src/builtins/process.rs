@@ -0,0 +1,60 @@
+//! Command-line arguments, environment variables and child processes, for
+//! automation scripts. Everything except `argv` and `exit` needs `Capability::Process`.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Mutex, OnceLock};
+
+use super::{from_args, str_arg, Registry};
+use crate::vm::{Capability, Fields, Object, Value};
+
+/// Variables set by `env_set`. The real environment is never changed: `spawn`
+/// workers run on their own threads, where `std::env::set_var` is unsound.
+/// `env_get` reads through it and `exec` passes it to the commands it runs.
+static ENV: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+
+fn env() -> &'static Mutex<HashMap<String, String>> {
+    ENV.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub(super) fn register(r: &mut Registry) {
+    let process = Some(Capability::Process);
+    r.register("argv", 0, None, |vm, _args| {
+        Ok(Value::from(vm.args().iter().cloned().map(Value::Str).collect::<Vec<_>>()))
+    }).describe("argv() -> list", "Arguments given after the script: `userd script.usrd a b` or `userd run script.usrd -- a b`");
+    r.register("env_get", 1, process, |_vm, args| {
+        let name = str_arg("env_get", args, 0, "name")?;
+        let set = env().lock().map_err(|_| "env_get: table poisoned".to_string())?.get(&name).cloned();
+        Ok(Value::Str(set.unwrap_or_else(|| std::env::var(&name).unwrap_or_default())))
+    }).describe("env_get(str-name) -> str", "Value of an environment variable; \"\" when unset");
+    r.register("env_set", 2, process, |_vm, args| {
+        let (name, value): (String, String) = from_args("env_set", args)?;
+        if name.is_empty() || name.contains(['=', '\0']) || value.contains('\0') {
+            return Err(format!("env_set: invalid variable {}", name));
+        }
+        env().lock().map_err(|_| "env_set: table poisoned".to_string())?.insert(name, value);
+        Ok(Value::Int(1))
+    }).describe("env_set(str-name, str-value) -> int", "Set an environment variable for env_get and the commands exec runs");
+    r.register("env_unset", 1, process, |_vm, args| {
+        let name = str_arg("env_unset", args, 0, "name")?;
+        let removed = env().lock().map_err(|_| "env_unset: table poisoned".to_string())?.remove(&name);
+        Ok(Value::Int(removed.is_some() as i64))
+    }).describe("env_unset(str-name) -> int", "Drop a variable set by env_set, so the real environment shows through again; 0 when it was not set");
+    r.register("exec", 2, process, |_vm, args| {
+        let (cmd, argv): (String, Vec<Value>) = from_args("exec", args)?;
+        let argv = argv.into_iter().map(String::try_from).collect::<Result<Vec<_>, _>>().map_err(|e| format!("exec: args: {}", e))?;
+        let vars = env().lock().map_err(|_| "exec: table poisoned".to_string())?.clone();
+        let out = std::process::Command::new(&cmd).args(&argv).envs(vars).output().map_err(|e| format!("exec: {}: {}", cmd, e))?;
+        let mut fields = Fields::new();
+        // -1 when the command was killed by a signal
+        fields.insert("code".to_string(), Value::Int(out.status.code().unwrap_or(-1) as i64));
+        fields.insert("stdout".to_string(), Value::Str(String::from_utf8_lossy(&out.stdout).into_owned()));
+        fields.insert("stderr".to_string(), Value::Str(String::from_utf8_lossy(&out.stderr).into_owned()));
+        Ok(Value::Object(Rc::new(RefCell::new(Object::plain("Process", fields)))))
+    }).describe("exec(str-cmd, list-args) -> Process", "Run a command, wait for it and return Process { code, stdout, stderr }");
+    // the CLI exits with the code once the run is wrapped up
+    r.register("exit", 1, None, |vm, args| {
+        let (code,): (i64,) = from_args("exit", args)?;
+        Err(vm.request_exit(code as i32))
+    }).describe("exit(int-code)", "Stop the program; userd exits with code");
+}
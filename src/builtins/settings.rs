@@ -0,0 +1,133 @@
+//! Settings files and the Windows registry. INI files work everywhere:
+//! `[section]` headers, `key=value` lines and `;` or `#` comments, matched
+//! case-insensitively like Windows does; section "" holds the keys before the
+//! first header. Writing keeps comments and the order of the other lines,
+//! and replaces the file atomically.
+//! The registry builtins fail on other systems. All need `Capability::FileIo`.
+use super::{str_arg, Registry, VmError};
+use crate::vm::{Capability, Value};
+
+pub(super) fn register(r: &mut Registry) {
+    let fs = Some(Capability::FileIo);
+    r.register("ini_read", 3, fs, |_vm, args| {
+        let path = str_arg("ini_read", args, 0, "path")?;
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("ini_read: {}: {}", path, e))?;
+        Ok(Value::Str(lookup(&text, &str_arg("ini_read", args, 1, "section")?, &str_arg("ini_read", args, 2, "key")?).unwrap_or_default()))
+    }).describe("ini_read(str-path, str-section, str-key) -> str", "Value of a key in an INI file; \"\" when the key is missing");
+    r.register("ini_write", 4, fs, |_vm, args| {
+        let path = str_arg("ini_write", args, 0, "path")?;
+        let (section, key) = (str_arg("ini_write", args, 1, "section")?, str_arg("ini_write", args, 2, "key")?);
+        let value = match &args[3] {
+            Value::Str(s) => s.clone(),
+            Value::Int(n) => n.to_string(),
+            Value::Float(f) => crate::json::float(*f, false),
+            other => return Err(format!("ini_write: cannot write {}", other.type_name())),
+        };
+        if [&section, &key, &value].iter().any(|s| s.contains(['\n', '\r'])) || key.contains('=') || key.trim().is_empty() {
+            return Err("ini_write: section, key and value must be single lines, and the key non-empty without =".to_string());
+        }
+        let text = match std::fs::read_to_string(&path) {
+            Ok(t) => t,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("ini_write: {}: {}", path, e)),
+        };
+        super::fs::write_atomic(std::path::Path::new(&path), set(&text, &section, &key, &value).as_bytes()).map_err(|e| format!("ini_write: {}: {}", path, e))?;
+        Ok(Value::Int(1))
+    }).describe("ini_write(str-path, str-section, str-key, value) -> int", "Set a key in an INI file, creating the file and section if needed");
+    r.register("reg_read", 3, fs, |_vm, args| {
+        let (hive, path, name) = (str_arg("reg_read", args, 0, "hive")?, str_arg("reg_read", args, 1, "path")?, str_arg("reg_read", args, 2, "name")?);
+        reg_read(&hive, &path, &name).map_err(|e| format!("reg_read: {}", e))
+    }).describe("reg_read(str-hive, str-path, str-name) -> value", "Registry value (str, or int for DWORD/QWORD) under HKCU, HKLM, HKCR or HKU; Windows only");
+    r.register("reg_write", 4, fs, |_vm, args| {
+        let (hive, path, name) = (str_arg("reg_write", args, 0, "hive")?, str_arg("reg_write", args, 1, "path")?, str_arg("reg_write", args, 2, "name")?);
+        reg_write(&hive, &path, &name, &args[3]).map_err(|e| format!("reg_write: {}", e))?;
+        Ok(Value::Int(1))
+    }).describe("reg_write(str-hive, str-path, str-name, value) -> int", "Set a registry value (str as REG_SZ, int as DWORD or QWORD), creating the key; Windows only");
+}
+
+/// `[name]` of a header line
+fn header(line: &str) -> Option<&str> {
+    line.trim().strip_prefix('[')?.strip_suffix(']').map(str::trim)
+}
+
+/// Key and value of a `key=value` line
+fn entry(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.starts_with([';', '#']) { return None; }
+    let (k, v) = line.split_once('=')?;
+    Some((k.trim(), v.trim()))
+}
+
+fn lookup(text: &str, section: &str, key: &str) -> Option<String> {
+    let mut current = "";
+    for line in text.lines() {
+        if let Some(h) = header(line) { current = h; continue; }
+        if let Some((k, v)) = entry(line)
+            && current.eq_ignore_ascii_case(section.trim()) && k.eq_ignore_ascii_case(key.trim()) {
+                let quoted = v.len() >= 2 && ((v.starts_with('"') && v.ends_with('"')) || (v.starts_with('\'') && v.ends_with('\'')));
+                return Some(if quoted { v[1..v.len() - 1].to_string() } else { v.to_string() });
+        }
+    }
+    None
+}
+
+fn set(text: &str, section: &str, key: &str, value: &str) -> String {
+    let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    let (section, key) = (section.trim(), key.trim());
+    let line = format!("{}={}", key, value);
+    // lines of the section: after its header up to the next one
+    let start = if section.is_empty() { Some(0) } else {
+        lines.iter().position(|l| header(l).is_some_and(|h| h.eq_ignore_ascii_case(section))).map(|i| i + 1)
+    };
+    match start {
+        Some(start) => {
+            let end = lines[start..].iter().position(|l| header(l).is_some()).map_or(lines.len(), |i| start + i);
+            if let Some(i) = (start..end).find(|&i| entry(&lines[i]).is_some_and(|(k, _)| k.eq_ignore_ascii_case(key))) {
+                lines[i] = line;
+            } else {
+                // after the last non-blank line, so a blank separator stays before the next header
+                let at = (start..end).rev().find(|&i| !lines[i].trim().is_empty()).map_or(start, |i| i + 1);
+                lines.insert(at, line);
+            }
+        }
+        None => {
+            if lines.last().is_some_and(|l| !l.trim().is_empty()) { lines.push(String::new()); }
+            lines.push(format!("[{}]", section));
+            lines.push(line);
+        }
+    }
+    let mut out = lines.join(newline);
+    out.push_str(newline);
+    out
+}
+
+#[cfg(target_os = "windows")]
+fn reg_read(hive: &str, path: &str, name: &str) -> Result<Value, VmError> {
+    use crate::platform::windows::RegValue;
+    Ok(match crate::platform::windows::reg_read(hive, path, name)? {
+        RegValue::Str(s) => Value::Str(s),
+        RegValue::Int(n) => Value::Int(n),
+    })
+}
+
+#[cfg(target_os = "windows")]
+fn reg_write(hive: &str, path: &str, name: &str, value: &Value) -> Result<(), VmError> {
+    use crate::platform::windows::RegValue;
+    let value = match value {
+        Value::Str(s) => RegValue::Str(s.clone()),
+        Value::Int(n) => RegValue::Int(*n),
+        other => return Err(format!("cannot store {} in the registry", other.type_name())),
+    };
+    crate::platform::windows::reg_write(hive, path, name, &value)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn reg_read(_hive: &str, _path: &str, _name: &str) -> Result<Value, VmError> {
+    Err("the registry is only available on Windows".to_string())
+}
+
+#[cfg(not(target_os = "windows"))]
+fn reg_write(_hive: &str, _path: &str, _name: &str, _value: &Value) -> Result<(), VmError> {
+    Err("the registry is only available on Windows".to_string())
+}
@@ -0,0 +1,49 @@
+//! Cooperative task builtins over `crate::task`.
+use super::{str_arg, Registry, VmError};
+use crate::vm::{Value, VM};
+
+pub(super) fn register(r: &mut Registry) {
+    r.register("task_spawn", 1, None, |vm, args| {
+        let name = str_arg("task_spawn", args, 0, "fn")?;
+        let fobj = match vm.get_global(&name) {
+            Some(Value::Function(fobj)) if !fobj.params.is_empty() => {
+                return Err(format!("task_spawn: {} must take no parameters, it takes {}", name, fobj.params.len()));
+            }
+            Some(f @ (Value::Function(_) | Value::Overloaded(_))) => f.overload(0).cloned()
+                .ok_or_else(|| format!("task_spawn: no overload of {} takes no parameters", name))?,
+            _ => return Err(format!("task_spawn: {} is not a function", name)),
+        };
+        Ok(Value::Int(vm.tasks().spawn(name, fobj) as i64))
+    }).describe("task_spawn(str-fn) -> int", "Queue fn() as a cooperative task; run_tasks, gui_poll and gui_run run it");
+    r.register("task_yield", 0, None, |vm, _args| {
+        if vm.tasks().current.is_none() { return Ok(Value::Int(0)); }
+        vm.tasks().yield_requested = true;
+        Ok(Value::Int(1))
+    }).describe("task_yield() -> int", "Let the other tasks run after the current statement of this task's body; 0 outside a task");
+    r.register("run_tasks", 0, None, |vm, _args| {
+        if vm.tasks().current.is_some() { return Err("run_tasks: cannot be called from a task".to_string()); }
+        let mut finished = 0;
+        while let Some(task) = vm.tasks().next() {
+            finished += turn(vm, task)? as i64;
+        }
+        Ok(Value::Int(finished))
+    }).describe("run_tasks() -> int", "Run the queued tasks in turns until all have finished; returns how many finished");
+}
+
+/// Give `task` a turn and queue it again unless it finished
+fn turn(vm: &mut VM, mut task: crate::task::Task) -> Result<bool, VmError> {
+    let finished = vm.task_turn(&mut task).map_err(|e| format!("task {} ({}): {}", task.id, task.name, e))?;
+    if !finished { vm.tasks().requeue(task); }
+    Ok(finished)
+}
+
+/// One turn for each queued task, from the GUI loop; nothing when called
+/// from inside a task
+pub(crate) fn pump(vm: &mut VM) -> Result<(), VmError> {
+    if !vm.tasks().runnable() { return Ok(()); }
+    for _ in 0..vm.tasks().len() {
+        let Some(task) = vm.tasks().next() else { break };
+        turn(vm, task)?;
+    }
+    Ok(())
+}
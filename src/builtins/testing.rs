@@ -0,0 +1,62 @@
+//! Assertions for script-level tests. A failure is an ordinary error starting
+//! with `vm::ASSERTION_FAILED`, so hosts can tell it from other errors; in
+//! test mode (`VM::set_test_mode`) it is recorded instead and the call
+//! returns 0.
+use super::{int_arg, str_arg, Registry};
+use crate::raster::Raster;
+use crate::vm::{Capability, Value, VM};
+
+pub(super) fn register(r: &mut Registry) {
+    r.register("assert", 2, None, |vm, args| {
+        let ok = match &args[0] {
+            Value::Int(n) => *n != 0,
+            Value::Float(f) => *f != 0.0,
+            other => return Err(format!("assert: condition must be a number, got {}", other.type_name())),
+        };
+        if ok { return Ok(Value::Int(1)); }
+        vm.assertion_failed(str_arg("assert", args, 1, "msg")?)
+    }).describe("assert(cond, str-msg) -> int", "Fail with msg unless cond is a non-zero number; 1 when it holds");
+    r.register("assert_eq", 2, None, |vm, args| {
+        if vm.values_equal(&args[0], &args[1])? { return Ok(Value::Int(1)); }
+        let message = format!("{} != {}", shown(vm, &args[0])?, shown(vm, &args[1])?);
+        vm.assertion_failed(message)
+    }).describe("assert_eq(a, b) -> int", "Fail unless a equals b (as __eq__ does); 1 when they are equal");
+    r.register("assert_canvas_matches", 3, Some(Capability::FileIo), assert_canvas_matches)
+        .describe("assert_canvas_matches(int-win, str-golden, int-tolerance) -> int", "Fail unless the window's canvas matches a BMP within tolerance per channel; a missing golden file is written");
+}
+
+/// assert_canvas_matches(win, golden, tolerance). The first run, with no
+/// golden file yet, records it; on a mismatch the canvas is written next to
+/// it as `<golden>.actual.bmp` for a look at what changed
+fn assert_canvas_matches(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let id = int_arg("assert_canvas_matches", args, 0, "win")? as u64;
+    let golden = str_arg("assert_canvas_matches", args, 1, "golden")?;
+    let tolerance = int_arg("assert_canvas_matches", args, 2, "tolerance")?;
+    if !(0..=255).contains(&tolerance) { return Err("assert_canvas_matches: tolerance must be 0..255".to_string()); }
+    let canvas = super::gui::canvas_raster("assert_canvas_matches", id)?;
+    let data = match std::fs::read(&golden) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            std::fs::write(&golden, canvas.to_bmp()).map_err(|e| format!("assert_canvas_matches: {}: {}", golden, e))?;
+            return Ok(Value::Int(1));
+        }
+        Err(e) => return Err(format!("assert_canvas_matches: {}: {}", golden, e)),
+    };
+    let expected = Raster::from_bmp(&data).map_err(|e| format!("assert_canvas_matches: {}: {}", golden, e))?;
+    let message = match canvas.compare(&expected, tolerance as u8) {
+        Ok(None) => return Ok(Value::Int(1)),
+        Ok(Some(m)) => format!("canvas differs from {} in {} pixel(s), first at ({}, {})", golden, m.count, m.first.0, m.first.1),
+        Err(e) => format!("canvas differs from {}: {}", golden, e),
+    };
+    let actual = format!("{}.actual.bmp", golden.strip_suffix(".bmp").unwrap_or(&golden));
+    std::fs::write(&actual, canvas.to_bmp()).map_err(|e| format!("assert_canvas_matches: {}: {}", actual, e))?;
+    vm.assertion_failed(message)
+}
+
+/// A value in a failure message; strings are quoted so "1" and 1 differ
+fn shown(vm: &mut VM, v: &Value) -> Result<String, String> {
+    match v {
+        Value::Str(s) => Ok(format!("{:?}", s)),
+        v => vm.value_to_string(v),
+    }
+}
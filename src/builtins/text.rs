@@ -0,0 +1,68 @@
+//! Locale-aware comparison and case folding for user-visible strings.
+//! `compare_locale` compares in levels like a collation table: letters
+//! ignoring case and е/ё first, then е before ё, then lowercase before
+//! uppercase, then code points, so only equal strings compare as 0.
+//! Locales: "ru" (Cyrillic before Latin, ё sorts with е, as in dictionaries),
+//! "ru-yo" (ё is its own letter between е and ж) and anything else, e.g.
+//! "en" or "" (Latin before Cyrillic, ё with е).
+use std::cmp::Ordering;
+
+use super::{str_arg, Registry};
+use crate::vm::Value;
+
+pub(super) fn register(r: &mut Registry) {
+    r.register("compare_locale", 3, None, |_vm, args| {
+        let (a, b) = (str_arg("compare_locale", args, 0, "a")?, str_arg("compare_locale", args, 1, "b")?);
+        let order = compare(&a, &b, &str_arg("compare_locale", args, 2, "locale")?);
+        Ok(Value::Int(order as i64))
+    }).describe("compare_locale(str-a, str-b, str-locale) -> int", "-1, 0 or 1 in the alphabetical order of locale (\"ru\", \"ru-yo\", \"en\")");
+    r.register("casefold", 1, None, |_vm, args| {
+        Ok(Value::Str(casefold(&str_arg("casefold", args, 0, "s")?)))
+    }).describe("casefold(str-s) -> str", "Lowercase form for case-insensitive comparison (ß becomes ss, ς becomes σ)");
+}
+
+pub(crate) fn casefold(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            'ß' | 'ẞ' => out.push_str("ss"),
+            'ς' => out.push('σ'),
+            // char-wise, so Σ never becomes the word-final ς
+            c => out.extend(c.to_lowercase()),
+        }
+    }
+    out
+}
+
+fn is_cyrillic(c: char) -> bool {
+    matches!(c, '\u{0400}'..='\u{04FF}')
+}
+
+/// (group, weight) at the first level: non-letters, then the locale's first
+/// script, then the other, then the remaining letters
+fn primary(c: char, cyrillic_first: bool, yo_letter: bool) -> (u8, u32) {
+    let weight = match c {
+        // between е (U+0435) and ж (U+0436)
+        'ё' if yo_letter => 0x0435 * 2 + 1,
+        'ё' => 0x0435 * 2,
+        c => c as u32 * 2,
+    };
+    let group = if !c.is_alphabetic() { 0 } else if c.is_ascii_alphabetic() {
+        if cyrillic_first { 2 } else { 1 }
+    } else if is_cyrillic(c) {
+        if cyrillic_first { 1 } else { 2 }
+    } else { 3 };
+    (group, weight)
+}
+
+pub(crate) fn compare(a: &str, b: &str, locale: &str) -> Ordering {
+    let locale = locale.to_ascii_lowercase().replace('_', "-");
+    let cyrillic_first = locale == "ru" || locale.starts_with("ru-");
+    let yo_letter = locale == "ru-yo";
+    let (fa, fb) = (casefold(a), casefold(b));
+    let key = |s: &str| s.chars().map(|c| primary(c, cyrillic_first, yo_letter)).collect::<Vec<_>>();
+    key(&fa).cmp(&key(&fb))
+        .then_with(|| fa.chars().map(|c| c == 'ё').cmp(fb.chars().map(|c| c == 'ё')))
+        .then_with(|| a.chars().map(char::is_uppercase).cmp(b.chars().map(char::is_uppercase)))
+        .then_with(|| a.cmp(b))
+}
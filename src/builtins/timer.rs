@@ -0,0 +1,40 @@
+//! Timer builtins over `crate::timer`. `set_timeout(ms, handler)` calls
+//! `handler()` once after ms milliseconds, `set_interval(ms, handler)` every
+//! ms milliseconds until `clear_timer`; both run from `gui_poll` and `gui_run`.
+use super::{from_args, str_arg, Registry, VmError};
+use crate::vm::{Value, VM};
+
+pub(super) fn register(r: &mut Registry) {
+    r.register("set_timeout", 2, None, |vm, args| add(vm, args, "set_timeout", false))
+        .describe("set_timeout(int-ms, str-handler) -> int", "Call handler() once after ms milliseconds, from gui_poll or gui_run; returns a timer id");
+    r.register("set_interval", 2, None, |vm, args| add(vm, args, "set_interval", true))
+        .describe("set_interval(int-ms, str-handler) -> int", "Call handler() every ms milliseconds, from gui_poll or gui_run, until clear_timer");
+    r.register("clear_timer", 1, None, |vm, args| {
+        let (id,): (i64,) = from_args("clear_timer", args)?;
+        Ok(Value::Int(vm.timers().cancel(id as u64) as i64))
+    }).describe("clear_timer(int-id) -> int", "Stop a timeout or interval; 0 if it already fired or was cleared");
+}
+
+fn add(vm: &mut VM, args: &[Value], name: &str, repeat: bool) -> Result<Value, VmError> {
+    let (ms,): (i64,) = from_args(name, &args[..1])?;
+    if ms < 0 || (repeat && ms == 0) {
+        return Err(format!("{}: ms must be {}, got {}", name, if repeat { "> 0" } else { ">= 0" }, ms));
+    }
+    let handler = str_arg(name, args, 1, "handler")?;
+    if !matches!(vm.get_global(&handler), Some(Value::Function(_) | Value::Overloaded(_))) {
+        return Err(format!("{}: {} is not a function", name, handler));
+    }
+    let every = ms as u64 * 1000;
+    let due = vm.perf_now_us() + every;
+    Ok(Value::Int(vm.timers().add(due, repeat.then_some(every), handler) as i64))
+}
+
+/// Call the handler of every timer that is due
+pub(crate) fn pump(vm: &mut VM) -> Result<(), VmError> {
+    if vm.timers().is_empty() { return Ok(()); }
+    let now = vm.perf_now_us();
+    for (_, handler) in vm.timers().due(now) {
+        vm.call_handler(&handler, &[])?;
+    }
+    Ok(())
+}
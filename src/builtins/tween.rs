@@ -0,0 +1,43 @@
+//! Tweening builtins over `crate::tween`. `tween(from, to, ms, easing,
+//! handler)` starts an animation and the pumps call `handler(value)` with a
+//! float until the value reaches `to`.
+use super::{from_args, str_arg, Registry, VmError};
+use crate::tween::Easing;
+use crate::vm::{Value, VM};
+
+pub(super) fn register(r: &mut Registry) {
+    r.register("tween", 5, None, |vm, args| {
+        let (from, to, ms): (f64, f64, i64) = from_args("tween", &args[..3])?;
+        if ms < 0 { return Err(format!("tween: duration_ms must be >= 0, got {}", ms)); }
+        let easing = Easing::parse(&str_arg("tween", args, 3, "easing")?).map_err(|e| format!("tween: {}", e))?;
+        let handler = str_arg("tween", args, 4, "on_update")?;
+        if !matches!(vm.get_global(&handler), Some(Value::Function(_) | Value::Overloaded(_))) {
+            return Err(format!("tween: {} is not a function", handler));
+        }
+        let now = vm.perf_now_us();
+        Ok(Value::Int(vm.tweens().add(from, to, now, ms as u64 * 1000, easing, handler) as i64))
+    }).describe("tween(float-from, float-to, int-duration_ms, str-easing, str-on_update) -> int", "Animate a number; on_update(value) is called from frame_wait, gui_poll or tween_update until it reaches to");
+    r.register("tween_cancel", 1, None, |vm, args| {
+        let (id,): (i64,) = from_args("tween_cancel", args)?;
+        Ok(Value::Int(vm.tweens().cancel(id as u64) as i64))
+    }).describe("tween_cancel(int-id) -> int", "Stop a tween where it is; 0 if it already finished");
+    r.register("tween_update", 0, None, |vm, _args| {
+        pump(vm)?;
+        Ok(Value::Int(vm.tweens().len() as i64))
+    }).describe("tween_update() -> int", "Advance all tweens now; returns how many are still running");
+    r.register("ease", 2, None, |_vm, args| {
+        let easing = Easing::parse(&str_arg("ease", args, 0, "easing")?).map_err(|e| format!("ease: {}", e))?;
+        let t = f64::try_from(args[1].clone()).map_err(|e| format!("ease: {}", e))?;
+        Ok(Value::Float(easing.apply(t)))
+    }).describe("ease(str-easing, float-t) -> float", "An easing curve at t in 0..1, for hand-written animation");
+}
+
+/// Call each running tween's handler with its current value
+pub(crate) fn pump(vm: &mut VM) -> Result<(), VmError> {
+    if vm.tweens().is_empty() { return Ok(()); }
+    let now = vm.perf_now_us();
+    for (handler, value) in vm.tweens().advance(now) {
+        vm.call_handler(&handler, &[Value::Float(value)])?;
+    }
+    Ok(())
+}
@@ -0,0 +1,176 @@
+//! WebSocket client (RFC 6455) over plain TCP: `ws://` URLs only, since the
+//! crate has no TLS. Messages are text; binary messages are received as text
+//! with invalid UTF-8 replaced. Pings are answered while waiting in `ws_recv`.
+//! Connection ids are global to the process, like channel ids.
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use super::{from_args, int_arg, str_arg, Registry, VmError};
+use crate::vm::{Capability, Value};
+
+/// Appended to the client key to form `Sec-WebSocket-Accept`
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Largest message accepted, after joining fragments
+const MAX_MESSAGE: usize = 16 << 20;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+static CONNECTIONS: OnceLock<Mutex<HashMap<u64, TcpStream>>> = OnceLock::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn connections() -> &'static Mutex<HashMap<u64, TcpStream>> {
+    CONNECTIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub(super) fn register(r: &mut Registry) {
+    let net = Some(Capability::Network);
+    r.register("ws_connect", 1, net, |_vm, args| {
+        let url = str_arg("ws_connect", args, 0, "url")?;
+        let stream = connect(&url).map_err(|e| format!("ws_connect: {}: {}", url, e))?;
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        connections().lock().map_err(|_| "ws_connect: connection table poisoned".to_string())?.insert(id, stream);
+        Ok(Value::Int(id as i64))
+    }).describe("ws_connect(str-url) -> int", "Open a WebSocket connection to a ws:// URL and return its id");
+    r.register("ws_send", 2, net, |_vm, args| {
+        let (id, text): (i64, String) = from_args("ws_send", args)?;
+        let mut stream = stream("ws_send", id)?;
+        write_frame(&mut stream, OP_TEXT, text.as_bytes()).map_err(|e| format!("ws_send: {}", e))?;
+        Ok(Value::Int(1))
+    }).describe("ws_send(int-id, str-text) -> int", "Send a text message");
+    r.register("ws_recv", 1, net, |_vm, args| {
+        let mut stream = stream("ws_recv", int_arg("ws_recv", args, 0, "id")?)?;
+        read_message(&mut stream).map(Value::Str).map_err(|e| format!("ws_recv: {}", e))
+    }).describe("ws_recv(int-id) -> str", "Wait for the next message; fails once the server closes the connection");
+    r.register("ws_close", 1, net, |_vm, args| {
+        let id = int_arg("ws_close", args, 0, "id")?;
+        let stream = connections().lock().ok().and_then(|mut c| c.remove(&(id as u64)));
+        let Some(mut stream) = stream else { return Err("ws_close: connection not found".to_string()) };
+        // status 1000: normal closure; the server may already be gone
+        let _ = write_frame(&mut stream, OP_CLOSE, &1000u16.to_be_bytes());
+        let _ = stream.shutdown(std::net::Shutdown::Both);
+        Ok(Value::Int(1))
+    }).describe("ws_close(int-id) -> int", "Close a connection");
+}
+
+/// A handle on connection `id`; the table is not locked while it is used
+fn stream(who: &str, id: i64) -> Result<TcpStream, VmError> {
+    let conns = connections().lock().map_err(|_| format!("{}: connection table poisoned", who))?;
+    let stream = conns.get(&(id as u64)).ok_or_else(|| format!("{}: connection not found", who))?;
+    stream.try_clone().map_err(|e| format!("{}: {}", who, e))
+}
+
+/// `Sec-WebSocket-Accept` the server must answer `key` with
+pub(crate) fn accept_key(key: &str) -> String {
+    crate::base64::encode(&crate::hash::sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+fn connect(url: &str) -> Result<TcpStream, String> {
+    if url.starts_with("wss://") { return Err("wss:// needs TLS, which is not supported".to_string()); }
+    let rest = url.strip_prefix("ws://").ok_or("expected a ws:// URL")?;
+    let (authority, path) = rest.find('/').map_or((rest, "/"), |i| (&rest[..i], &rest[i..]));
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((h, p)) => (h, p.parse::<u16>().map_err(|_| format!("bad port {}", p))?),
+        None => (authority, 80),
+    };
+    if host.is_empty() { return Err("missing host".to_string()); }
+    let mut stream = TcpStream::connect((host, port)).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(Duration::from_secs(10))).map_err(|e| e.to_string())?;
+
+    let mut nonce = [0u8; 16];
+    crate::rand::secure_bytes(&mut nonce)?;
+    let key = crate::base64::encode(&nonce);
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        path, authority, key,
+    );
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+
+    // byte by byte, so no frame data after the headers is consumed
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() > 16 * 1024 { return Err("handshake response too long".to_string()); }
+        stream.read_exact(&mut byte).map_err(|e| format!("handshake: {}", e))?;
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.lines();
+    let status = lines.next().unwrap_or("");
+    if status.split_whitespace().nth(1) != Some("101") {
+        return Err(format!("server refused the upgrade: {}", status));
+    }
+    let accept = lines.filter_map(|l| l.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("sec-websocket-accept"))
+        .map(|(_, v)| v.trim());
+    if accept != Some(accept_key(&key).as_str()) {
+        return Err("bad Sec-WebSocket-Accept in the handshake".to_string());
+    }
+    stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+    Ok(stream)
+}
+
+/// One final frame; client frames are always masked
+fn write_frame(stream: &mut TcpStream, opcode: u8, payload: &[u8]) -> Result<(), String> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        n if n < 126 => frame.push(0x80 | n as u8),
+        n if n <= u16::MAX as usize => { frame.push(0x80 | 126); frame.extend_from_slice(&(n as u16).to_be_bytes()); }
+        n => { frame.push(0x80 | 127); frame.extend_from_slice(&(n as u64).to_be_bytes()); }
+    }
+    let mut mask = [0u8; 4];
+    crate::rand::secure_bytes(&mut mask)?;
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    stream.write_all(&frame).map_err(|e| e.to_string())
+}
+
+/// (fin, opcode, payload) of the next frame
+fn read_frame(stream: &mut TcpStream) -> Result<(bool, u8, Vec<u8>), String> {
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head).map_err(|e| e.to_string())?;
+    let (fin, opcode, masked) = (head[0] & 0x80 != 0, head[0] & 0x0F, head[1] & 0x80 != 0);
+    let len = match head[1] & 0x7F {
+        126 => { let mut b = [0u8; 2]; stream.read_exact(&mut b).map_err(|e| e.to_string())?; u16::from_be_bytes(b) as u64 }
+        127 => { let mut b = [0u8; 8]; stream.read_exact(&mut b).map_err(|e| e.to_string())?; u64::from_be_bytes(b) }
+        n => n as u64,
+    };
+    if len > MAX_MESSAGE as u64 { return Err(format!("frame of {} bytes is too large", len)); }
+    let mut mask = [0u8; 4];
+    if masked { stream.read_exact(&mut mask).map_err(|e| e.to_string())?; }
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload).map_err(|e| e.to_string())?;
+    if masked { for (i, b) in payload.iter_mut().enumerate() { *b ^= mask[i % 4]; } }
+    Ok((fin, opcode, payload))
+}
+
+/// The next text or binary message, joining fragments and answering pings
+fn read_message(stream: &mut TcpStream) -> Result<String, String> {
+    let mut message = Vec::new();
+    loop {
+        let (fin, opcode, payload) = read_frame(stream)?;
+        match opcode {
+            OP_PING => { write_frame(stream, OP_PONG, &payload)?; continue; }
+            OP_PONG => continue,
+            OP_CLOSE => {
+                let _ = write_frame(stream, OP_CLOSE, &payload);
+                let code = payload.get(..2).map_or(1005, |c| u16::from_be_bytes([c[0], c[1]]));
+                return Err(format!("connection closed by the server ({})", code));
+            }
+            OP_TEXT | OP_BINARY | OP_CONTINUATION => {
+                if message.len() + payload.len() > MAX_MESSAGE { return Err("message too large".to_string()); }
+                message.extend_from_slice(&payload);
+                if fin { return Ok(String::from_utf8_lossy(&message).into_owned()); }
+            }
+            op => return Err(format!("unknown opcode {}", op)),
+        }
+    }
+}
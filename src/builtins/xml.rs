@@ -0,0 +1,243 @@
+//! XML documents as `Node` objects: `tag`, `attrs` (an object with one str
+//! field per attribute), `children` (element nodes in order) and `text` (the
+//! character data directly inside the element, joined). The declaration,
+//! comments, processing instructions and the doctype are skipped; CDATA is
+//! text. Text between child elements is joined, so mixed content loses its
+//! position relative to the children.
+use std::cell::RefCell;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::str::CharIndices;
+
+use super::{str_arg, Registry, VmError};
+use crate::vm::{Fields, Object, Value};
+
+pub(super) fn register(r: &mut Registry) {
+    r.register("xml_parse", 1, None, |_vm, args| {
+        let text = str_arg("xml_parse", args, 0, "text")?;
+        Parser { src: &text, chars: text.char_indices().peekable() }.document()
+    }).describe("xml_parse(str-text) -> Node", "Root element of an XML document as a Node { tag, attrs, children, text }");
+    r.register("xml_stringify", 1, None, |_vm, args| {
+        let mut out = String::new();
+        write(&mut out, &args[0], 0)?;
+        Ok(Value::Str(out))
+    }).describe("xml_stringify(Node-node) -> str", "XML text for a node as returned by xml_parse");
+}
+
+/// Deepest element nesting read or written; also stops on cyclic nodes
+const MAX_DEPTH: usize = 256;
+
+fn node(tag: String, attrs: Fields, children: Vec<Value>, text: String) -> Value {
+    let mut fields = Fields::new();
+    fields.insert("tag".to_string(), Value::Str(tag));
+    fields.insert("attrs".to_string(), Value::Object(Rc::new(RefCell::new(Object::plain("Attrs", attrs)))));
+    fields.insert("children".to_string(), Value::from(children));
+    fields.insert("text".to_string(), Value::Str(text));
+    Value::Object(Rc::new(RefCell::new(Object::plain("Node", fields))))
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl Parser<'_> {
+    fn pos(&mut self) -> usize {
+        self.chars.peek().map_or(self.src.len(), |(i, _)| *i)
+    }
+
+    fn rest(&mut self) -> &str {
+        let pos = self.pos();
+        &self.src[pos..]
+    }
+
+    fn error(&mut self, msg: &str) -> VmError {
+        let pos = self.pos();
+        let line = self.src[..pos].matches('\n').count() + 1;
+        format!("xml_parse: {} at line {}", msg, line)
+    }
+
+    fn skip_ws(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    /// Consume `s` if the input continues with it
+    fn eat(&mut self, s: &str) -> bool {
+        if !self.rest().starts_with(s) { return false; }
+        for _ in s.chars() { self.chars.next(); }
+        true
+    }
+
+    /// Everything up to `end`, which is consumed
+    fn until(&mut self, end: &str, what: &str) -> Result<String, VmError> {
+        let Some(len) = self.rest().find(end) else { return Err(self.error(&format!("unterminated {}", what))) };
+        let s = self.rest()[..len].to_string();
+        for _ in s.chars().chain(end.chars()) { self.chars.next(); }
+        Ok(s)
+    }
+
+    /// Skip comments, processing instructions and the doctype; false at anything else
+    fn skip_markup(&mut self) -> Result<bool, VmError> {
+        if self.eat("<!--") { self.until("-->", "comment")?; }
+        else if self.eat("<?") { self.until("?>", "processing instruction")?; }
+        else if self.rest().starts_with("<!DOCTYPE") { self.doctype()?; }
+        else { return Ok(false); }
+        Ok(true)
+    }
+
+    /// The doctype may contain an internal subset in brackets
+    fn doctype(&mut self) -> Result<(), VmError> {
+        let mut depth = 0;
+        loop {
+            match self.chars.next() {
+                Some((_, '[')) => depth += 1,
+                Some((_, ']')) => depth -= 1,
+                Some((_, '>')) if depth == 0 => return Ok(()),
+                Some(_) => {}
+                None => return Err(self.error("unterminated doctype")),
+            }
+        }
+    }
+
+    fn document(&mut self) -> Result<Value, VmError> {
+        loop {
+            self.skip_ws();
+            if !self.skip_markup()? { break; }
+        }
+        if self.chars.peek().map(|(_, c)| *c) != Some('<') { return Err(self.error("expected the root element")); }
+        let root = self.element(0)?;
+        loop {
+            self.skip_ws();
+            if self.chars.peek().is_none() { return Ok(root); }
+            if !self.skip_markup()? { return Err(self.error("content after the root element")); }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, VmError> {
+        let mut name = String::new();
+        while let Some((_, c)) = self.chars.next_if(|(_, c)| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':')) {
+            name.push(c);
+        }
+        if name.is_empty() { return Err(self.error("expected a name")); }
+        Ok(name)
+    }
+
+    /// An element starting at its `<`
+    fn element(&mut self, depth: usize) -> Result<Value, VmError> {
+        if depth > MAX_DEPTH { return Err(self.error("elements nested too deeply")); }
+        self.chars.next();
+        let tag = self.name()?;
+        let mut attrs = Fields::new();
+        loop {
+            self.skip_ws();
+            if self.eat("/>") { return Ok(node(tag, attrs, Vec::new(), String::new())); }
+            if self.eat(">") { break; }
+            let name = self.name()?;
+            self.skip_ws();
+            if !self.eat("=") { return Err(self.error(&format!("expected = after attribute {}", name))); }
+            self.skip_ws();
+            let quote = match self.chars.next() {
+                Some((_, q @ ('"' | '\''))) => q,
+                _ => return Err(self.error("expected a quoted attribute value")),
+            };
+            let raw = self.until(&quote.to_string(), "attribute value")?;
+            attrs.insert(name, Value::Str(self.unescape(&raw)?));
+        }
+        let mut children = Vec::new();
+        let mut text = String::new();
+        loop {
+            if self.eat("</") {
+                let end = self.name()?;
+                if end != tag { return Err(self.error(&format!("</{}> closes <{}>", end, tag))); }
+                self.skip_ws();
+                if !self.eat(">") { return Err(self.error("expected >")); }
+                break;
+            }
+            if self.eat("<![CDATA[") { text.push_str(&self.until("]]>", "CDATA section")?); continue; }
+            if self.skip_markup()? { continue; }
+            match self.chars.peek() {
+                Some((_, '<')) => children.push(self.element(depth + 1)?),
+                Some(_) => {
+                    let len = self.rest().find('<').unwrap_or(self.rest().len());
+                    let raw = self.rest()[..len].to_string();
+                    for _ in raw.chars() { self.chars.next(); }
+                    text.push_str(&self.unescape(&raw)?);
+                }
+                None => return Err(self.error(&format!("unclosed <{}>", tag))),
+            }
+        }
+        // indentation between children is not text
+        if text.trim().is_empty() { text.clear(); }
+        Ok(node(tag, attrs, children, text))
+    }
+
+    fn unescape(&mut self, raw: &str) -> Result<String, VmError> {
+        let mut out = String::new();
+        let mut rest = raw;
+        while let Some(amp) = rest.find('&') {
+            out.push_str(&rest[..amp]);
+            let Some(semi) = rest[amp..].find(';') else { return Err(self.error("unterminated entity")) };
+            let entity = &rest[amp + 1..amp + semi];
+            let c = match entity {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                    None => entity.strip_prefix('#').and_then(|d| d.parse().ok()).and_then(char::from_u32),
+                },
+            };
+            out.push(c.ok_or_else(|| self.error(&format!("unknown entity &{};", entity)))?);
+            rest = &rest[amp + semi + 1..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+fn escape(s: &str, quote: bool) -> String {
+    let s = s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    if quote { s.replace('"', "&quot;") } else { s }
+}
+
+fn write(out: &mut String, v: &Value, depth: usize) -> Result<(), VmError> {
+    if depth > MAX_DEPTH { return Err("xml_stringify: nodes nested too deeply or cyclic".to_string()); }
+    let Value::Object(o) = v else { return Err(format!("xml_stringify: expected a Node, got {}", v.type_name())) };
+    let o = o.borrow();
+    let tag = match o.fields.get("tag") {
+        Some(Value::Str(t)) => t.clone(),
+        _ => return Err("xml_stringify: node has no str field tag".to_string()),
+    };
+    out.push('<');
+    out.push_str(&tag);
+    if let Some(Value::Object(attrs)) = o.fields.get("attrs") {
+        for (name, value) in attrs.borrow().fields.iter() {
+            let value = match value {
+                Value::Str(s) => s.clone(),
+                Value::Int(n) => n.to_string(),
+                Value::Float(f) => crate::json::float(*f, false),
+                other => return Err(format!("xml_stringify: attribute {} cannot be {}", name, other.type_name())),
+            };
+            out.push_str(&format!(" {}=\"{}\"", name, escape(&value, true)));
+        }
+    }
+    let text = match o.fields.get("text") {
+        Some(Value::Str(t)) => t.clone(),
+        _ => String::new(),
+    };
+    let children = match o.fields.get("children") {
+        Some(Value::List(c)) => c.borrow().clone(),
+        _ => Vec::new(),
+    };
+    if text.is_empty() && children.is_empty() {
+        out.push_str("/>");
+        return Ok(());
+    }
+    out.push('>');
+    out.push_str(&escape(&text, false));
+    for child in &children { write(out, child, depth + 1)?; }
+    out.push_str(&format!("</{}>", tag));
+    Ok(())
+}
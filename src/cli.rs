@@ -1,9 +1,12 @@
+use crate::diagnostics::Diagnostic;
+
 /// Very small CLI: supports `userd repl` and `userd <file.usrd>` to run scripts
 pub fn run() {
     let args: Vec<String> = std::env::args().collect();
     if args.len() > 1 {
         match args[1].as_str() {
-            "repl" => crate::repl::start_repl(),
+            // userd repl [--no-color]; NO_COLOR in the environment works too
+            "repl" => crate::repl::start_repl(!args.iter().any(|a| a == "--no-color") && std::env::var_os("NO_COLOR").is_none()),
             "editor" => {
                 // start web editor server and open browser
                 let addr = "127.0.0.1:7878";
@@ -19,18 +22,23 @@ pub fn run() {
                 } else {
                     let _ = std::process::Command::new("xdg-open").arg(&url).spawn();
                 }
-                let _ = crate::web_server::run_server(addr);
+                let config = crate::web_server::ServerConfig { canonical_json: args.iter().any(|a| a == "--canonical-json") };
+                let _ = crate::web_server::run_server_with(addr, config);
             }
             "pack" => {
-                // pack a .usrd script into a self-extracting exe: userd pack script.usrd out.exe
+                // pack a .usrd script into a self-running exe: userd pack script.usrd out.exe [--sandbox]
                 if args.len() < 4 {
-                    eprintln!("usage: userd pack <script.usrd> <out.exe>");
+                    eprintln!("usage: userd pack <script.usrd> <out.exe> [--sandbox]");
                     return;
                 }
+                let sandbox = args.iter().any(|a| a == "--sandbox");
                 let script = &args[2];
                 let out = &args[3];
                 match std::fs::read_to_string(script) {
                     Ok(src) => {
+                        // the exe carries the compiled artifact, not the source, gzipped
+                        let extra: &[&str] = if sandbox { &[SANDBOX_META] } else { &[] };
+                        let payload = crate::gzip::compress(&crate::artifact::compile_with_meta(&src, extra));
                         // read current exe as template
                         let me = std::env::current_exe().expect("failed to locate current exe");
                         match std::fs::read(&me) {
@@ -39,10 +47,9 @@ pub fn run() {
                                 if let Err(e) = std::fs::write(out, &bin) { eprintln!("failed to write output: {}", e); return; }
                                 // append marker and script
                                 let mut f = std::fs::OpenOptions::new().append(true).open(out).expect("open out");
-                                let marker = b"\n__USRDSCRIPT__\n";
                                 use std::io::Write as IoWrite;
-                                let _ = f.write_all(marker);
-                                let _ = f.write_all(src.as_bytes());
+                                let _ = f.write_all(crate::artifact::PACK_MARKER);
+                                let _ = f.write_all(&payload);
                                 println!("packed {} -> {}", script, out);
                             }
                             Err(e) => eprintln!("failed to read current exe: {}", e),
@@ -72,6 +79,13 @@ pub fn run() {
                         #[cfg(not(target_os = "windows") )]
                         { let _ = std::process::Command::new("chmod").args(["+x", &dest]).status(); }
                         println!("installed {} -> {}", me.display(), dest);
+                        // .usrd files open with userd from file managers; `./script.usrd`
+                        // itself needs `#!/usr/bin/env userd` and `chmod +x`
+                        #[cfg(target_os = "linux")]
+                        match register_file_type(&home, &dest) {
+                            Ok(_) => println!("registered .usrd files (text/x-userd) to open with {}", dest),
+                            Err(e) => eprintln!("could not register .usrd files: {}", e),
+                        }
                         // optionally auto-add to PATH on Windows
                         if args.len() > 2 && args[2] == "--add-path" {
                             #[cfg(target_os = "windows")]
@@ -113,6 +127,8 @@ pub fn run() {
                 if std::path::Path::new(&dest).exists() {
                     if let Err(e) = std::fs::remove_file(&dest) { eprintln!("failed to remove {}: {}", dest, e); }
                     else { println!("removed {}", dest); }
+                    #[cfg(target_os = "linux")]
+                    unregister_file_type(&home);
                     // remove PATH entry if --remove-path provided
                     if args.len() > 2 && args[2] == "--remove-path" {
                         #[cfg(target_os = "windows")]
@@ -132,106 +148,283 @@ pub fn run() {
                     println!("{} not found, nothing to uninstall.", dest);
                 }
             }
-            "compile" => {
-                // compile a .usrd source into a .usrdc artifact: userd compile in.usrd out.usrdc
-                if args.len() < 4 {
-                    eprintln!("usage: userd compile <in.usrd> <out.usrdc>");
+            "check" => {
+                // static checks without running: userd check [--types] [--error-format=json] <file.usrd>...
+                let types = args.iter().any(|a| a == "--types");
+                let json = json_errors(&args);
+                let paths: Vec<String> = args[2..].iter().filter(|a| !a.starts_with("--")).cloned().collect();
+                if paths.is_empty() {
+                    eprintln!("usage: userd check [--types] [--error-format=json] <file.usrd>...");
                     return;
                 }
-                let input = &args[2];
-                let out = &args[3];
-                match std::fs::read_to_string(input) {
-                    Ok(src) => {
-                        // basic validation: parse
-                        let mut parser = crate::parser::Parser::new(&src);
-                        let _prog = parser.parse_program();
-                        // try to parse simple metadata headers at top of file
-                        let mut meta_lines: Vec<String> = Vec::new();
-                        for line in src.lines().take(16) {
-                            if line.trim().is_empty() { continue; }
-                            let l = line.trim();
-                            if l.to_lowercase().starts_with("os:") || l.to_lowercase().starts_with("its:") {
-                                meta_lines.push(l.to_string());
+                // files are parsed in parallel, then checked and reported in order
+                let mut failed = false;
+                for (path, parsed) in paths.iter().zip(crate::parser::parse_files(&paths)) {
+                    match parsed {
+                        Ok((prog, hints)) => {
+                            for h in hints.iter() {
+                                report(json, Diagnostic::from_hint(path, h), format_args!("{}:{}: warning: {}", path, h.line, h.message));
                             }
+                            let errors = if types { crate::typeck::type_errors(&prog) } else { Vec::new() };
+                            for e in errors.iter() {
+                                report(json, Diagnostic::from_type_error(path, e), format_args!("{}: {}", path, e));
+                            }
+                            if errors.is_empty() && !json { println!("{}: ok", path); }
+                            failed |= !errors.is_empty();
                         }
-                        // build artifact: META marker + metadata + SRC marker + source
-                        let meta_marker = b"__USRDMETA__\n";
-                        let src_marker = b"__USRDSRC__\n";
-                        let mut out_bytes: Vec<u8> = Vec::new();
-                        out_bytes.extend_from_slice(meta_marker);
-                        for m in meta_lines.iter() {
-                            out_bytes.extend_from_slice(m.as_bytes());
-                            out_bytes.push(b'\n');
-                        }
-                        out_bytes.extend_from_slice(src_marker);
-                        out_bytes.extend_from_slice(src.as_bytes());
-                        match std::fs::write(out, out_bytes) {
-                            Ok(_) => println!("compiled {} -> {}", input, out),
-                            Err(e) => eprintln!("failed to write out file: {}", e),
+                        Err(e) => { report(json, Diagnostic::error(path, "E0001", &e), format_args!("{}", e)); failed = true; }
+                    }
+                }
+                if failed { std::process::exit(1); }
+            }
+            "test" => {
+                // run scripts with failed assertions collected: userd test [--frozen-time[=unix_ms]] [--seed=N] <file.usrd>...
+                let usage = "usage: userd test [--frozen-time[=unix_ms]] [--seed=N] <file.usrd>...";
+                let frozen = args.iter().find_map(|a| match a.strip_prefix("--frozen-time") {
+                    Some("") => Some(Ok(0)),
+                    Some(v) => Some(v.strip_prefix('=').ok_or(()).and_then(|v| v.parse::<u64>().map_err(|_| ()))),
+                    None => None,
+                });
+                let seed = args.iter().find_map(|a| a.strip_prefix("--seed=")).map(|v| v.parse::<u64>());
+                let paths: Vec<String> = args[2..].iter().filter(|a| !a.starts_with("--")).cloned().collect();
+                let (Ok(frozen), Ok(seed)) = (frozen.transpose(), seed.transpose()) else {
+                    eprintln!("{}", usage);
+                    return;
+                };
+                if paths.is_empty() {
+                    eprintln!("{}", usage);
+                    return;
+                }
+                let mut failed = false;
+                for (path, parsed) in paths.iter().zip(crate::parser::parse_files(&paths)) {
+                    let prog = match parsed {
+                        Ok((prog, _)) => prog,
+                        Err(e) => { eprintln!("{}", e); failed = true; continue; }
+                    };
+                    let mut vm = crate::vm::VM::new();
+                    vm.set_test_mode(true);
+                    if let Some(ms) = frozen { vm.freeze_time(ms); }
+                    if let Some(seed) = seed { vm.seed_random(seed); }
+                    let result = vm.execute_program(prog);
+                    let failures = vm.take_failures();
+                    for f in failures.iter() {
+                        match f.line {
+                            Some(line) => eprintln!("{}:{}: {}", path, line, f.message),
+                            None => eprintln!("{}: {}", path, f.message),
                         }
                     }
-                    Err(e) => eprintln!("failed to read {}: {}", input, e),
+                    match result {
+                        Err(e) if vm.exit_code().is_none() => { eprintln!("{}: error: {}", path, e); failed = true; }
+                        _ if !failures.is_empty() => { println!("{}: {} assertion(s) failed", path, failures.len()); failed = true; }
+                        _ => println!("{}: ok", path),
+                    }
                 }
+                if failed { std::process::exit(1); }
             }
-            path => {
-                if path.ends_with(".usrd") {
-                    match std::fs::read_to_string(path) {
-                        Ok(src) => {
-                            // parse and execute
-                            let mut parser = crate::parser::Parser::new(&src);
-                            let prog = parser.parse_program();
-                            let mut vm = crate::vm::VM::new();
-                            if let Err(e) = vm.execute_program(prog) {
-                                eprintln!("Execution error: {}", e);
+            "lint" => {
+                // style rules from the [lint] section of ./userd.toml: userd lint [--error-format=json] <file.usrd>...
+                let json = json_errors(&args);
+                let paths: Vec<String> = args[2..].iter().filter(|a| !a.starts_with("--")).cloned().collect();
+                if paths.is_empty() {
+                    eprintln!("usage: userd lint [--error-format=json] <file.usrd>...");
+                    return;
+                }
+                let config = match crate::lint::LintConfig::load(std::path::Path::new(".")) {
+                    Ok(c) => c,
+                    Err(e) => { report(json, Diagnostic::error("userd.toml", "E0001", &e), format_args!("{}", e)); std::process::exit(2); }
+                };
+                let mut failed = false;
+                for (path, parsed) in paths.iter().zip(crate::parser::parse_files(&paths)) {
+                    match parsed {
+                        Ok((prog, _)) => {
+                            let lints = crate::lint::lint(&prog, &config);
+                            for l in lints.iter() {
+                                let line = l.line.map_or(String::new(), |n| format!("{}:", n));
+                                report(json, Diagnostic::from_lint(path, l), format_args!("{}:{} {}: {}", path, line, l.code, l.message));
                             }
+                            if lints.is_empty() && !json { println!("{}: ok", path); }
+                            failed |= !lints.is_empty();
                         }
-                        Err(e) => eprintln!("Failed to read file {}: {}", path, e),
+                        Err(e) => { report(json, Diagnostic::error(path, "E0001", &e), format_args!("{}", e)); failed = true; }
+                    }
+                }
+                if failed { std::process::exit(1); }
+            }
+            "expand" => {
+                // userd expand <file.usrd>: the program after macro expansion, as source
+                let Some(path) = args.get(2) else {
+                    eprintln!("usage: userd expand <file.usrd>");
+                    return;
+                };
+                let src = match std::fs::read_to_string(path) {
+                    Ok(s) => s,
+                    Err(e) => { eprintln!("failed to read {}: {}", path, e); std::process::exit(2); }
+                };
+                let mut parser = crate::parser::Parser::new(&src);
+                let prog = parser.parse_program();
+                for e in parser.errors() { eprintln!("{}: {}", path, e); }
+                print!("{}", crate::ast::to_source(&prog));
+                if !parser.errors().is_empty() { std::process::exit(1); }
+            }
+            "tokens" | "ast" => {
+                // userd tokens [--format json|text] <file.usrd>: the token stream with positions
+                // userd ast [--format json|text] <file.usrd>: the parsed program; parse errors go
+                // to stderr and make the exit status 1, after the program is printed
+                let command = args[1].as_str();
+                let (format, path) = format_and_file(&args[2..]);
+                let (Some(format @ ("json" | "text")), Some(path)) = (format, path) else {
+                    eprintln!("usage: userd {} [--format json|text] <file.usrd>", command);
+                    std::process::exit(2);
+                };
+                let src = match std::fs::read_to_string(path) {
+                    Ok(s) => s,
+                    Err(e) => { eprintln!("failed to read {}: {}", path, e); std::process::exit(2); }
+                };
+                if command == "tokens" {
+                    let tokens = crate::lexer::Lexer::tokens(&src);
+                    if format == "json" {
+                        println!("[{}]", tokens.iter().map(|(t, span)| t.to_json(span)).collect::<Vec<_>>().join(","));
+                    } else {
+                        for (t, span) in tokens.iter() { println!("{}:{}\t{}..{}\t{:?}", span.line, span.col, span.start, span.end, t); }
                     }
-                } else if path.ends_with(".usrdc") {
-                    // compiled artifact produced by `userd compile` -- contains embedded source after marker
-                    match std::fs::read(path) {
-                        Ok(bytes) => {
-                            // marker kept for backward compatibility (not used below)
-                            let _marker = b"__USRDSRC__\n";
-                                // check for metadata and source markers
-                                let meta_marker = b"__USRDMETA__\n";
-                                let src_marker = b"__USRDSRC__\n";
-                                // optional: read metadata and warn about OS compatibility
-                                if let Some(meta_pos) = find_subslice_from_start(&bytes, meta_marker) {
-                                    if let Some(src_pos) = find_subslice_from_start(&bytes, src_marker) {
-                                        if src_pos > meta_pos {
-                                            let meta = &bytes[meta_pos + meta_marker.len()..src_pos];
-                                            if let Ok(meta_s) = std::str::from_utf8(meta) {
-                                                for line in meta_s.lines() {
-                                                    let l = line.trim();
-                                                    if l.to_lowercase().starts_with("os:") {
-                                                        let oslist = l[3..].trim();
-                                                        // if current os not mentioned, warn
-                                                        let cur = if cfg!(target_os = "windows") { "windows" }
-                                                                  else if cfg!(target_os = "macos") { "macos" }
-                                                                  else { "linux" };
-                                                        if !oslist.to_lowercase().contains(cur) {
-                                                            eprintln!("Warning: artifact targets [{}], current OS {} may be incompatible.", oslist, cur);
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                // find source marker and run
-                                if let Some(pos) = find_subslice_from_start(&bytes, src_marker) {
-                                    let script = &bytes[pos + src_marker.len()..];
-                                    if let Ok(s) = std::str::from_utf8(script) {
-                                        let mut parser = crate::parser::Parser::new(s);
-                                        let prog = parser.parse_program();
-                                        let mut vm = crate::vm::VM::new();
-                                        if let Err(e) = vm.execute_program(prog) { eprintln!("Execution error: {}", e); }
-                                    } else { eprintln!("compiled artifact contains invalid utf8"); }
-                                } else { eprintln!("compiled artifact missing marker"); }
+                    return;
+                }
+                let mut parser = crate::parser::Parser::new(&src);
+                let prog = parser.parse_program();
+                if format == "json" { println!("{}", crate::ast::to_json(&prog)); } else { println!("{:#?}", prog); }
+                for e in parser.errors() { eprintln!("{}: {}", path, e); }
+                if !parser.errors().is_empty() { std::process::exit(1); }
+            }
+            "compile" => {
+                // compile a .usrd source into a .usrdc artifact: userd compile [--force] [--error-format=json] in.usrd out.usrdc
+                // parse errors fail the build; --force writes the artifact anyway, without the unparsed parts
+                let json = json_errors(&args);
+                let force = args.iter().any(|a| a == "--force");
+                let files: Vec<&String> = args[2..].iter().filter(|a| !a.starts_with("--")).collect();
+                let [input, out] = files[..] else {
+                    eprintln!("usage: userd compile [--force] [--error-format=json] <in.usrd> <out.usrdc>");
+                    return;
+                };
+                let src = match std::fs::read_to_string(input) {
+                    Ok(src) => src,
+                    Err(e) => { report(json, Diagnostic::error(input, "E0001", e.to_string()), format_args!("failed to read {}: {}", input, e)); return; }
+                };
+                let bytes = match crate::artifact::compile_checked(&src) {
+                    Ok(bytes) => bytes,
+                    Err(errors) => {
+                        for e in errors.iter() {
+                            report(json, Diagnostic::from_parse_error(input, e), format_args!("{}: {}", input, e));
                         }
-                        Err(e) => eprintln!("Failed to read compiled file {}: {}", path, e),
+                        if !force { std::process::exit(1); }
+                        crate::artifact::compile(&src)
                     }
+                };
+                match std::fs::write(out, bytes) {
+                    Ok(_) => println!("compiled {} -> {}", input, out),
+                    Err(e) => report(json, Diagnostic::error(out, "E0002", e.to_string()), format_args!("failed to write out file: {}", e)),
+                }
+            }
+            "info" => {
+                // what an artifact or packed exe carries: userd info <file.usrdc|file.exe>
+                let Some(path) = args.get(2) else {
+                    eprintln!("usage: userd info <file.usrdc|file.exe>");
+                    return;
+                };
+                let info = match std::fs::read(path).map_err(|e| e.to_string()).and_then(|b| crate::artifact::info(&b)) {
+                    Ok(info) => info,
+                    Err(e) => { eprintln!("{}: {}", path, e); std::process::exit(1); }
+                };
+                print_info(path, &info);
+                if info.checksum_ok == Some(false) { std::process::exit(1); }
+            }
+            "transpile" => {
+                // userd transpile script.usrd out.rs [--build]
+                let files: Vec<&String> = args[2..].iter().filter(|a| !a.starts_with("--")).collect();
+                let [input, out] = files[..] else {
+                    eprintln!("usage: userd transpile <script.usrd> <out.rs> [--build]");
+                    return;
+                };
+                let src = match std::fs::read_to_string(input) {
+                    Ok(s) => s,
+                    Err(e) => { eprintln!("failed to read {}: {}", input, e); return; }
+                };
+                let code = crate::transpile::transpile(&src, input);
+                if let Err(e) = std::fs::write(out, &code) { eprintln!("failed to write {}: {}", out, e); return; }
+                println!("transpiled {} -> {}", input, out);
+                if args.iter().any(|a| a == "--build") {
+                    match crate::transpile::build(std::path::Path::new(out), &code) {
+                        Ok(exe) => println!("built {}", exe.display()),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+            }
+            "doc" => {
+                // userd doc --builtins: the standard builtins with their signatures
+                if !args.iter().any(|a| a == "--builtins") {
+                    eprintln!("usage: userd doc --builtins");
+                    return;
+                }
+                let registry = crate::builtins::Registry::standard();
+                for name in registry.names() {
+                    let Some(b) = registry.get(name) else { continue };
+                    println!("{}", b.help(name));
+                    if let Some(cap) = b.capability { println!("  needs {:?}", cap); }
+                }
+            }
+            "diff" => {
+                // userd diff <old> <new>: unified diff; exit status 1 when the files differ
+                let (Some(old), Some(new)) = (args.get(2), args.get(3)) else {
+                    eprintln!("usage: userd diff <old-file> <new-file>");
+                    return;
+                };
+                let read = |p: &String| std::fs::read_to_string(p).map_err(|e| format!("failed to read {}: {}", p, e));
+                match read(old).and_then(|a| Ok((a, read(new)?))) {
+                    Ok((a, b)) => {
+                        let text = crate::diff::unified(old, new, &a.lines().collect::<Vec<_>>(), &b.lines().collect::<Vec<_>>(), 3);
+                        print!("{}", text);
+                        if !text.is_empty() { std::process::exit(1); }
+                    }
+                    Err(e) => { eprintln!("{}", e); std::process::exit(2); }
+                }
+            }
+            "replay" => {
+                // step through a trace saved by `userd run --record=trace.bin`
+                let Some(path) = args.get(2) else {
+                    eprintln!("usage: userd replay <trace.bin>");
+                    return;
+                };
+                match std::fs::read(path).map_err(|e| e.to_string()).and_then(|b| crate::recorder::Trace::decode(&b)) {
+                    Ok(trace) => crate::recorder::replay(&trace),
+                    Err(e) => eprintln!("failed to load trace {}: {}", path, e),
+                }
+            }
+            "eval" => {
+                // userd eval [run flags] -e '<code>' [args...] | userd eval [run flags] - (code from stdin)
+                // runs the code and prints its final value
+                let rest: Vec<String> = args[2..].iter().filter(|a| *a != "-e").cloned().collect();
+                let inline = args[2..].iter().any(|a| a == "-e");
+                let Some(code) = script_arg(&rest).filter(|c| inline || *c == "-") else {
+                    eprintln!("usage: userd eval [--sandbox] [--no-truncate] [--error-format=json] -e '<code>' [args...]\n   or: userd eval [...] - < script.usrd");
+                    std::process::exit(2);
+                };
+                let opts = RunOptions { print_last: true, ..RunOptions::from_args(&rest) };
+                if inline {
+                    execute("<eval>", &opts, crate::parser::Parser::new(code).parse_program());
+                } else {
+                    run_file("-", opts);
+                }
+            }
+            "run" => {
+                // userd run [--sandbox] [--no-truncate] [--profile] [--record=trace.bin] [--trace-out trace.json] [--record-events events.txt] [--replay-events events.txt] [--error-format=json] <file.usrd|file.usrdc> [args...] [-- args...]
+                let Some(path) = script_arg(&args[2..]) else {
+                    eprintln!("usage: userd run [--sandbox] [--no-truncate] [--profile] [--record=trace.bin] [--trace-out trace.json] [--record-events events.txt] [--replay-events events.txt] [--error-format=json] <file.usrd|file.usrdc> [args...] [-- args...]");
+                    return;
+                };
+                run_file(path, RunOptions::from_args(&args[2..]));
+            }
+            path => {
+                if path.ends_with(".usrd") || path.ends_with(".usrdc") || path == "-" || has_shebang(path) {
+                    run_file(path, RunOptions::from_args(&args[1..]));
                 } else {
                     println!("unknown command or file: {}\nUse `userd repl`, `userd editor` or pass a .usrd file", path);
                 }
@@ -246,41 +439,343 @@ pub fn run() {
     }
 }
 
-fn find_subslice_from_start(hay: &[u8], needle: &[u8]) -> Option<usize> {
-    if needle.len() == 0 || hay.len() < needle.len() { return None }
-    for start in 0..=(hay.len() - needle.len()) {
-        if &hay[start..start + needle.len()] == needle { return Some(start) }
+/// `--format X` (or `--format=X`, default `text`) and the file among `args`
+fn format_and_file(args: &[String]) -> (Option<&str>, Option<&String>) {
+    let mut format = Some("text");
+    let mut file = None;
+    let mut rest = args.iter();
+    while let Some(a) = rest.next() {
+        match a.as_str() {
+            "--format" => format = rest.next().map(String::as_str),
+            a if a.starts_with("--format=") => format = Some(&a["--format=".len()..]),
+            _ if file.is_none() => file = Some(a),
+            _ => {}
+        }
+    }
+    (format, file)
+}
+
+/// A file starting with `#!`, run by the shell through `#!/usr/bin/env userd`
+/// whatever its name
+fn has_shebang(path: &str) -> bool {
+    let mut start = [0u8; 2];
+    std::fs::File::open(path).and_then(|mut f| std::io::Read::read_exact(&mut f, &mut start)).is_ok() && &start == b"#!"
+}
+
+/// Where `install` puts the MIME type and desktop entry for .usrd files
+#[cfg(target_os = "linux")]
+fn file_type_paths(home: &str) -> (String, String) {
+    (format!("{}/.local/share/mime/packages/userd.xml", home), format!("{}/.local/share/applications/userd.desktop", home))
+}
+
+/// Declare `text/x-userd` for *.usrd and make `exe` its default application
+/// (freedesktop shared MIME database and desktop entry, per user)
+#[cfg(target_os = "linux")]
+fn register_file_type(home: &str, exe: &str) -> Result<(), String> {
+    let (mime, desktop) = file_type_paths(home);
+    let write = |path: &str, text: String| {
+        if let Some(dir) = std::path::Path::new(path).parent() { std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?; }
+        std::fs::write(path, text).map_err(|e| format!("{}: {}", path, e))
+    };
+    write(&mime, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<mime-info xmlns=\"http://www.freedesktop.org/standards/shared-mime-info\">\n  <mime-type type=\"text/x-userd\">\n    <comment>userd script</comment>\n    <sub-class-of type=\"text/plain\"/>\n    <glob pattern=\"*.usrd\"/>\n  </mime-type>\n</mime-info>\n".to_string())?;
+    write(&desktop, format!("[Desktop Entry]\nType=Application\nName=userd\nComment=Run a userd script\nExec={} run %f\nTerminal=true\nMimeType=text/x-userd;\nNoDisplay=true\n", desktop_quote(exe)))?;
+    // the tools may be missing; the files are picked up once they run
+    let _ = std::process::Command::new("update-mime-database").arg(format!("{}/.local/share/mime", home)).status();
+    let _ = std::process::Command::new("xdg-mime").args(["default", "userd.desktop", "text/x-userd"]).status();
+    Ok(())
+}
+
+/// `exe` as one quoted argument of a desktop entry's `Exec` key: `"`, `` ` ``,
+/// `$` and `\` are escaped inside the quotes, `%` is doubled, and the
+/// backslashes are escaped once more for the key's string value
+#[cfg(target_os = "linux")]
+fn desktop_quote(exe: &str) -> String {
+    let mut out = String::from("\"");
+    for c in exe.chars() {
+        match c {
+            '"' | '`' | '$' => { out.push_str("\\\\"); out.push(c); }
+            '\\' => out.push_str("\\\\\\\\"),
+            '%' => out.push_str("%%"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(target_os = "linux")]
+fn unregister_file_type(home: &str) {
+    let (mime, desktop) = file_type_paths(home);
+    for path in [mime, desktop] {
+        if std::fs::remove_file(&path).is_ok() { println!("removed {}", path); }
+    }
+    let _ = std::process::Command::new("update-mime-database").arg(format!("{}/.local/share/mime", home)).status();
+}
+
+/// `--error-format=json`: report errors as JSON lines instead of text
+fn json_errors(args: &[String]) -> bool {
+    args.iter().any(|a| a == "--error-format=json")
+}
+
+/// Print a diagnostic to stderr as JSON, or `human` in the usual text form
+/// followed by the suggestion
+fn report(json: bool, diagnostic: Diagnostic, human: std::fmt::Arguments) {
+    if json { eprintln!("{}", diagnostic.to_json()); return; }
+    eprintln!("{}", human);
+    if let Some(s) = &diagnostic.suggestion { eprintln!("  help: {}", s); }
+}
+
+/// `userd info` output, one `key: value` line each
+fn print_info(path: &str, info: &crate::artifact::Info) {
+    println!("file: {}", path);
+    match info.exe_size {
+        Some(exe) => println!("kind: packed exe ({} bytes of exe, {} bytes of gzipped script)", exe, info.stored_size),
+        None => println!("kind: artifact ({} bytes)", info.stored_size),
+    }
+    match info.version {
+        Some(v) => println!("format version: {}", v),
+        None => println!("format version: legacy (embedded source)"),
+    }
+    println!("compiler: {}", info.header("compiler:").unwrap_or("unknown (not recorded)"));
+    println!("target os: {}", info.header("os:").unwrap_or("any"));
+    let rest: Vec<&String> = info.meta.iter().filter(|m| {
+        let m = m.to_lowercase();
+        !m.starts_with("os:") && !m.starts_with("compiler:")
+    }).collect();
+    for m in rest { println!("header: {}", m); }
+    match info.statements {
+        Some(n) => println!("payload: {} bytes, {} top-level statements", info.payload_size, n),
+        None => println!("payload: {} bytes of source", info.payload_size),
+    }
+    println!("checksum: {}", match info.checksum_ok { Some(true) => "ok", Some(false) => "MISMATCH", None => "none (legacy format)" });
+    // the format has no asset section yet; scripts read their files at run time
+    println!("assets: none");
+}
+
+/// Artifact header that makes the program run under `Policy::sandboxed`
+const SANDBOX_META: &str = "policy: sandbox";
+
+/// Flags of `userd run` / `userd <file>`
+#[derive(Default, Clone)]
+struct RunOptions {
+    sandbox: bool,
+    /// print large values in full
+    no_truncate: bool,
+    /// report calls and time per function at exit
+    profile: bool,
+    /// `--record=trace.bin`: save a time-travel trace of the run
+    record: Option<String>,
+    /// `--trace-out trace.json`: save call spans in the Chrome tracing format
+    trace_out: Option<String>,
+    /// `--record-events events.txt`: save the GUI events the handlers receive
+    record_events: Option<String>,
+    /// `--replay-events events.txt`: deliver recorded GUI events again
+    replay_events: Option<String>,
+    /// `--error-format=json`
+    json_errors: bool,
+    /// Arguments for the script, see `argv`
+    argv: Vec<String>,
+    /// `userd eval`: after a run that ends with a declaration, print what it declared
+    print_last: bool,
+}
+
+impl RunOptions {
+    /// `args` are the ones after the command name
+    fn from_args(args: &[String]) -> Self {
+        let (args, argv) = split_at_separator(args);
+        let has = |flag: &str| args.iter().any(|a| a == flag);
+        let record = args.iter().find_map(|a| a.strip_prefix("--record=")).map(str::to_string);
+        let value = |flag: &str| args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).cloned()
+            .or_else(|| args.iter().find_map(|a| a.strip_prefix(flag)?.strip_prefix('=')).map(str::to_string));
+        Self {
+            sandbox: has("--sandbox"), no_truncate: has("--no-truncate"), profile: has("--profile"), record,
+            trace_out: value("--trace-out"), record_events: value("--record-events"), replay_events: value("--replay-events"),
+            json_errors: json_errors(args), argv: script_argv(args, argv), print_last: false,
+        }
+    }
+}
+
+/// Flags followed by a separate value, which is not the script or its argument
+const VALUE_FLAGS: [&str; 3] = ["--trace-out", "--record-events", "--replay-events"];
+
+/// Split at the first `--`: userd's own arguments, then ones only for the script
+fn split_at_separator(args: &[String]) -> (&[String], &[String]) {
+    match args.iter().position(|a| a == "--") {
+        Some(i) => (&args[..i], &args[i + 1..]),
+        None => (args, &[]),
+    }
+}
+
+/// Arguments for the script: the plain ones after the script path, then
+/// everything after `--`, so `userd s.usrd a -- --b` gives `a`, `--b`
+fn script_argv(own: &[String], after: &[String]) -> Vec<String> {
+    let mut argv = Vec::new();
+    let mut seen_script = false;
+    let mut rest = own.iter();
+    while let Some(a) = rest.next() {
+        if VALUE_FLAGS.contains(&a.as_str()) { rest.next(); continue; }
+        if a.starts_with("--") { continue; }
+        if seen_script { argv.push(a.clone()); } else { seen_script = true; }
+    }
+    argv.extend(after.iter().cloned());
+    argv
+}
+
+/// The script argument: the first one that is neither a flag nor a flag's value
+fn script_arg(args: &[String]) -> Option<&String> {
+    let mut rest = split_at_separator(args).0.iter();
+    while let Some(a) = rest.next() {
+        if VALUE_FLAGS.contains(&a.as_str()) { rest.next(); continue; }
+        if !a.starts_with("--") { return Some(a); }
     }
     None
 }
 
+fn new_vm(opts: &RunOptions) -> crate::vm::VM {
+    let mut vm = crate::vm::VM::new();
+    if opts.sandbox { vm.set_policy(crate::vm::Policy::sandboxed()); }
+    if opts.no_truncate { vm.set_display_limits(None); }
+    if opts.profile { vm.enable_profiling(); }
+    if opts.trace_out.is_some() { vm.enable_call_trace(); }
+    vm.set_args(opts.argv.clone());
+    vm
+}
+
+/// Run a program; then save its trace when recording, print the profile
+/// report to stderr when profiling and exit with the code given to `exit`
+fn execute(file: &str, opts: &RunOptions, prog: Vec<crate::ast::Stmt>) {
+    let mut vm = new_vm(opts);
+    if let Some(path) = &opts.replay_events {
+        match std::fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|t| crate::gui::parse_events(&t)) {
+            Ok(events) => vm.gui_events().start_replay(events),
+            Err(e) => { report(opts.json_errors, Diagnostic::error(path, "E0001", &e), format_args!("failed to load events {}: {}", path, e)); return; }
+        }
+    }
+    if opts.record_events.is_some() { vm.gui_events().start_recording(); }
+    let recorder = opts.record.as_ref().map(|_| crate::recorder::Recorder::start(&mut vm));
+    // a trailing expression prints itself; a trailing declaration doesn't
+    let last_decl = match prog.last() {
+        Some(crate::ast::Stmt::VarDecl { name, .. }) if opts.print_last => Some(name.clone()),
+        _ => None,
+    };
+    // `exit(code)` stops the program without an error message
+    match vm.execute_program(prog) {
+        Err(e) if vm.exit_code().is_none() => {
+            let globals: Vec<&str> = vm.globals_iter().map(|(n, _)| n.as_str()).collect();
+            let diagnostic = Diagnostic::runtime(file, &e, vm.error_line(), globals.into_iter().chain(vm.builtin_names()));
+            report(opts.json_errors, diagnostic, format_args!("Execution error: {}", e));
+        }
+        Ok(_) => if let Some(v) = last_decl.and_then(|name| vm.get_global(&name)) {
+            let _ = vm.print_value(&v);
+        },
+        Err(_) => {}
+    }
+    if let (Some(recorder), Some(path)) = (recorder, &opts.record) {
+        let trace = recorder.finish(&mut vm);
+        match std::fs::write(path, trace.encode()) {
+            Ok(_) => {
+                eprintln!("recorded {} steps to {}", trace.steps.len(), path);
+                if let Some(why) = &trace.stopped { eprintln!("{}", why); }
+            }
+            Err(e) => eprintln!("failed to write {}: {}", path, e),
+        }
+    }
+    if let Some(path) = &opts.record_events {
+        let events = vm.gui_events().finish_recording();
+        match std::fs::write(path, crate::gui::encode_events(&events)) {
+            Ok(_) => eprintln!("recorded {} GUI events to {}", events.len(), path),
+            Err(e) => eprintln!("failed to write {}: {}", path, e),
+        }
+    }
+    if let Some(path) = &opts.trace_out {
+        match std::fs::write(path, crate::json::chrome_trace(vm.call_spans())) {
+            Ok(_) => eprintln!("wrote {} call spans to {}", vm.call_spans().len(), path),
+            Err(e) => eprintln!("failed to write {}: {}", path, e),
+        }
+    }
+    let report = vm.profile_report();
+    if !report.is_empty() {
+        eprintln!("{:>10} {:>12}  function", "calls", "total ms");
+        for e in report {
+            eprintln!("{:>10} {:>12.3}  {}", e.calls, e.total.as_secs_f64() * 1000.0, e.name);
+        }
+    }
+    if let Some(code) = vm.exit_code() { std::process::exit(code); }
+}
+
+/// Run a script: `.usrdc` artifacts are loaded without parsing, anything else is treated as source
+fn run_file(path: &str, opts: RunOptions) {
+    // `-` reads the program from stdin
+    if path == "-" {
+        let mut src = String::new();
+        match std::io::Read::read_to_string(&mut std::io::stdin(), &mut src) {
+            Ok(_) => execute("<stdin>", &opts, crate::parser::Parser::new(&src).parse_program()),
+            Err(e) => report(opts.json_errors, Diagnostic::error("<stdin>", "E0001", e.to_string()), format_args!("Failed to read stdin: {}", e)),
+        }
+        return;
+    }
+    if path.ends_with(".usrdc") {
+        match std::fs::read(path) {
+            Ok(bytes) => run_artifact(path, &bytes, opts),
+            Err(e) => report(opts.json_errors, Diagnostic::error(path, "E0001", e.to_string()), format_args!("Failed to read compiled file {}: {}", path, e)),
+        }
+        return;
+    }
+    match std::fs::read_to_string(path) {
+        Ok(src) => {
+            // parse and execute
+            let mut parser = crate::parser::Parser::new(&src);
+            let prog = parser.parse_program();
+            execute(path, &opts, prog);
+        }
+        Err(e) => report(opts.json_errors, Diagnostic::error(path, "E0001", e.to_string()), format_args!("Failed to read file {}: {}", path, e)),
+    }
+}
+
+fn run_artifact(path: &str, bytes: &[u8], opts: RunOptions) {
+    let art = match crate::artifact::load(bytes) {
+        Ok(a) => a,
+        Err(e) => { report(opts.json_errors, Diagnostic::error(path, "E0001", &e), format_args!("{}", e)); return; }
+    };
+    // warn when the artifact declares target systems that do not include this one
+    for l in art.meta.iter() {
+        if l.to_lowercase().starts_with("os:") {
+            let oslist = l[3..].trim();
+            let cur = if cfg!(target_os = "windows") { "windows" }
+                      else if cfg!(target_os = "macos") { "macos" }
+                      else { "linux" };
+            if !oslist.to_lowercase().contains(cur) {
+                eprintln!("Warning: artifact targets [{}], current OS {} may be incompatible.", oslist, cur);
+            }
+        }
+    }
+    let sandbox = opts.sandbox || art.meta.iter().any(|m| m.eq_ignore_ascii_case(SANDBOX_META));
+    execute(path, &RunOptions { sandbox, ..opts }, art.program);
+}
+
 fn try_run_embedded() -> Result<(), ()> {
     // read this executable and look for marker
     let me = match std::env::current_exe() { Ok(p) => p, Err(_) => return Err(()) };
     let data = match std::fs::read(&me) { Ok(d) => d, Err(_) => return Err(()) };
-    let marker = b"\n__USRDSCRIPT__\n";
-    if let Some(idx) = find_subslice_from_end(&data, marker) {
-        let script = &data[idx + marker.len()..];
-        if script.is_empty() { return Err(()) }
-        // execute script
+    if let Some(script) = crate::artifact::embedded(&data) {
+        // packs made before compression carry the artifact as is
+        let unpacked;
+        let script = if crate::gzip::is_gzip(script) {
+            unpacked = crate::gzip::decompress(script).map_err(|_| ())?;
+            &unpacked[..]
+        } else { script };
+        // packed artifact (current `pack`) or plain source (older packs)
+        if crate::artifact::is_artifact(script) {
+            run_artifact("<embedded>", script, RunOptions::default());
+            return Ok(());
+        }
         if let Ok(s) = std::str::from_utf8(script) {
             let mut parser = crate::parser::Parser::new(s);
             let prog = parser.parse_program();
-            let mut vm = crate::vm::VM::new();
-            if let Err(e) = vm.execute_program(prog) {
-                eprintln!("Execution error: {}", e);
-            }
+            execute("<embedded>", &RunOptions::default(), prog);
             return Ok(());
         }
     }
     Err(())
 }
 
-fn find_subslice_from_end(hay: &[u8], needle: &[u8]) -> Option<usize> {
-    if needle.len() == 0 || hay.len() < needle.len() { return None }
-    // search backwards
-    for start in (0..=(hay.len() - needle.len())).rev() {
-        if &hay[start..start + needle.len()] == needle { return Some(start) }
-    }
-    None
-}
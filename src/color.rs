@@ -0,0 +1,86 @@
+//! Packed colors for the drawing builtins: one int `0xAARRGGBB`, so a color
+//! is a single argument and can be stored in a variable. `rgb`, `hsl` and
+//! `hex_color` give opaque colors (alpha 255); `lighten`, `darken` and `mix`
+//! derive new ones.
+
+/// Channels of a packed color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Rgba {
+    pub fn pack(self) -> i64 {
+        (self.a as i64) << 24 | (self.r as i64) << 16 | (self.g as i64) << 8 | self.b as i64
+    }
+
+    /// A packed color; ints outside 0..=0xFFFFFFFF are not colors
+    pub fn unpack(c: i64) -> Result<Self, String> {
+        if !(0..=0xFFFF_FFFF).contains(&c) { return Err(format!("{} is not a packed color (0xAARRGGBB)", c)); }
+        Ok(Rgba { a: (c >> 24) as u8, r: (c >> 16) as u8, g: (c >> 8) as u8, b: c as u8 })
+    }
+
+    /// Opaque color from hue in degrees and saturation and lightness in 0..=1
+    pub fn from_hsl(h: f64, s: f64, l: f64) -> Self {
+        let (s, l) = (s.clamp(0.0, 1.0), l.clamp(0.0, 1.0));
+        let h = h.rem_euclid(360.0) / 60.0;
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = l - c / 2.0;
+        let channel = |v: f64| ((v + m) * 255.0).round() as u8;
+        Rgba { r: channel(r), g: channel(g), b: channel(b), a: 255 }
+    }
+
+    /// (hue in degrees, saturation, lightness)
+    pub fn to_hsl(self) -> (f64, f64, f64) {
+        let (r, g, b) = (self.r as f64 / 255.0, self.g as f64 / 255.0, self.b as f64 / 255.0);
+        let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+        let l = (max + min) / 2.0;
+        let d = max - min;
+        if d == 0.0 { return (0.0, 0.0, l); }
+        let s = d / (1.0 - (2.0 * l - 1.0).abs());
+        let h = if max == r { ((g - b) / d).rem_euclid(6.0) } else if max == g { (b - r) / d + 2.0 } else { (r - g) / d + 4.0 };
+        (h * 60.0, s, l)
+    }
+
+    /// `#rgb`, `#rrggbb` or `#rrggbbaa`; the `#` is optional
+    pub fn parse_hex(s: &str) -> Result<Self, String> {
+        let hex = s.trim().trim_start_matches('#');
+        let bad = || format!("{:?} is not a hex color like #ff8800", s);
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) { return Err(bad()); }
+        let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| bad());
+        match hex.len() {
+            3 => {
+                let nib = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).map(|v| v * 17).map_err(|_| bad());
+                Ok(Rgba { r: nib(0)?, g: nib(1)?, b: nib(2)?, a: 255 })
+            }
+            6 => Ok(Rgba { r: byte(0)?, g: byte(2)?, b: byte(4)?, a: 255 }),
+            8 => Ok(Rgba { r: byte(0)?, g: byte(2)?, b: byte(4)?, a: byte(6)? }),
+            _ => Err(bad()),
+        }
+    }
+
+    /// Lightness raised by `amount` (0..=1, negative darkens), keeping alpha
+    pub fn lighten(self, amount: f64) -> Self {
+        let (h, s, l) = self.to_hsl();
+        Rgba { a: self.a, ..Rgba::from_hsl(h, s, l + amount) }
+    }
+
+    /// `t` of the way from `self` to `other` (0 gives self, 1 other), alpha included
+    pub fn mix(self, other: Rgba, t: f64) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let lerp = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+        Rgba { r: lerp(self.r, other.r), g: lerp(self.g, other.g), b: lerp(self.b, other.b), a: lerp(self.a, other.a) }
+    }
+}
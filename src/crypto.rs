@@ -0,0 +1,99 @@
+//! HMAC-SHA256 and authenticated symmetric encryption for the `hmac_sha256`,
+//! `encrypt` and `decrypt` builtins, built on `hash` without dependencies.
+//!
+//! `seal` encrypts with ChaCha20 (RFC 8439) and authenticates with
+//! HMAC-SHA256 over nonce and ciphertext (encrypt-then-MAC). Output layout:
+//! 12-byte random nonce, ciphertext, 32-byte tag. The cipher and MAC keys are
+//! derived from the caller's key with HMAC, so any string works as a key, but
+//! it is not stretched: use a long random secret, not a password.
+use crate::hash::sha256;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 32;
+
+pub fn hmac_sha256(key: &[u8], msg: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 { block[..32].copy_from_slice(&sha256(key)); } else { block[..key.len()].copy_from_slice(key); }
+    let mut inner = crate::hash::Sha256::new();
+    inner.update(&block.map(|b| b ^ 0x36));
+    inner.update(msg);
+    let mut outer = crate::hash::Sha256::new();
+    outer.update(&block.map(|b| b ^ 0x5c));
+    outer.update(&inner.finish());
+    outer.finish()
+}
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]); s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]); s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]); s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]); s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+fn chacha20_block(key: &[u8; 32], counter: u32, nonce: &[u8; NONCE_LEN]) -> [u8; 64] {
+    let word = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+    for i in 0..8 { state[4 + i] = word(&key[4 * i..]); }
+    state[12] = counter;
+    for i in 0..3 { state[13 + i] = word(&nonce[4 * i..]); }
+    let mut s = state;
+    for _ in 0..10 {
+        quarter_round(&mut s, 0, 4, 8, 12);
+        quarter_round(&mut s, 1, 5, 9, 13);
+        quarter_round(&mut s, 2, 6, 10, 14);
+        quarter_round(&mut s, 3, 7, 11, 15);
+        quarter_round(&mut s, 0, 5, 10, 15);
+        quarter_round(&mut s, 1, 6, 11, 12);
+        quarter_round(&mut s, 2, 7, 8, 13);
+        quarter_round(&mut s, 3, 4, 9, 14);
+    }
+    let mut out = [0u8; 64];
+    for (i, chunk) in out.chunks_exact_mut(4).enumerate() {
+        chunk.copy_from_slice(&s[i].wrapping_add(state[i]).to_le_bytes());
+    }
+    out
+}
+
+/// XOR `data` with the ChaCha20 key stream starting at block `counter`
+pub fn chacha20(key: &[u8; 32], counter: u32, nonce: &[u8; NONCE_LEN], data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let stream = chacha20_block(key, counter.wrapping_add(i as u32), nonce);
+        for (b, k) in chunk.iter_mut().zip(stream) { *b ^= k; }
+    }
+}
+
+/// (cipher key, MAC key)
+fn derive(key: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let root = sha256(key);
+    (hmac_sha256(&root, b"userd encrypt"), hmac_sha256(&root, b"userd mac"))
+}
+
+/// Equal without an early exit, so timing does not reveal the tag
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub fn seal(key: &[u8], plain: &[u8]) -> Result<Vec<u8>, String> {
+    let (enc, mac) = derive(key);
+    let mut nonce = [0u8; NONCE_LEN];
+    crate::rand::secure_bytes(&mut nonce)?;
+    let mut out = nonce.to_vec();
+    out.extend_from_slice(plain);
+    chacha20(&enc, 1, &nonce, &mut out[NONCE_LEN..]);
+    let tag = hmac_sha256(&mac, &out);
+    out.extend_from_slice(&tag);
+    Ok(out)
+}
+
+/// Fails when the data was changed or the key is wrong
+pub fn open(key: &[u8], sealed: &[u8]) -> Result<Vec<u8>, String> {
+    if sealed.len() < NONCE_LEN + TAG_LEN { return Err("data too short".to_string()); }
+    let (enc, mac) = derive(key);
+    let (body, tag) = sealed.split_at(sealed.len() - TAG_LEN);
+    if !same(&hmac_sha256(&mac, body), tag) { return Err("wrong key or corrupted data".to_string()); }
+    let nonce: [u8; NONCE_LEN] = body[..NONCE_LEN].try_into().unwrap();
+    let mut plain = body[NONCE_LEN..].to_vec();
+    chacha20(&enc, 1, &nonce, &mut plain);
+    Ok(plain)
+}
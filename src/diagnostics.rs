@@ -0,0 +1,121 @@
+//! Machine-readable diagnostics for `--error-format=json`: one JSON object per
+//! line on stderr, so editors and CI bots do not have to scrape the human text.
+//!
+//! `{"file":"a.usrd","span":{"line":3},"severity":"error","code":"T0001","message":"...","suggestion":null}`
+//! `span` is `null` when the line is unknown (read errors, generated code).
+//! Codes: `E0001` read failure, `E0002` write failure, `E0003` runtime error,
+//! `E0004` parse error,
+//! `T0001`..`T0006` type errors (see `typeck`), `W0001` missing `;`,
+//! `L0001`..`L0004` style warnings from `userd lint` (see `lint`).
+//! `suggestion` is a fix in words: a nearby name, the missing `;`, the
+//! expected arguments. The CLI prints it as a `help:` line in text mode.
+use std::fmt;
+
+use crate::json::escape;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self { Severity::Error => "error", Severity::Warning => "warning" })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: Option<usize>,
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(file: &str, code: &'static str, message: impl Into<String>) -> Self {
+        Self { file: file.to_string(), line: None, severity: Severity::Error, code, message: message.into(), suggestion: None }
+    }
+
+    pub fn at(mut self, line: Option<usize>) -> Self {
+        self.line = line;
+        self
+    }
+
+    pub fn suggest(mut self, suggestion: Option<String>) -> Self {
+        self.suggestion = suggestion;
+        self
+    }
+
+    /// A type error found by `userd check --types`
+    pub fn from_type_error(file: &str, e: &crate::typeck::TypeError) -> Self {
+        Self::error(file, e.code, e.message.clone()).at(e.line).suggest(e.suggestion.clone())
+    }
+
+    /// A parser hint, reported as a warning
+    pub fn from_hint(file: &str, h: &crate::parser::Hint) -> Self {
+        let d = Self::error(file, "W0001", h.message.clone()).at(Some(h.line)).suggest(Some(h.suggestion.clone()));
+        Self { severity: Severity::Warning, ..d }
+    }
+
+    /// A lex or parse error, at its line
+    pub fn from_parse_error(file: &str, e: &crate::error::Error) -> Self {
+        let message = match e {
+            crate::error::Error::Lex { message, .. } | crate::error::Error::Parse { message, .. } => message.clone(),
+            e => e.to_string(),
+        };
+        Self::error(file, "E0004", message).at(e.line())
+    }
+
+    /// A style warning from `userd lint`
+    pub fn from_lint(file: &str, l: &crate::lint::Lint) -> Self {
+        let d = Self::error(file, l.code, l.message.clone()).at(l.line);
+        Self { severity: Severity::Warning, ..d }
+    }
+
+    /// A runtime error; `names` are the globals and builtins defined when it
+    /// happened, used to suggest a spelling for undefined names
+    pub fn runtime<'a>(file: &str, message: &str, line: Option<usize>, names: impl Iterator<Item = &'a str>) -> Self {
+        let first = message.lines().next().unwrap_or("");
+        let missing = first.strip_prefix("undefined: ").or_else(|| first.strip_prefix("undefined function/class "));
+        let suggestion = missing.and_then(|m| closest(m, names)).map(|n| format!("did you mean `{}`?", n));
+        Self::error(file, "E0003", message).at(line).suggest(suggestion)
+    }
+
+    /// One line of JSON
+    pub fn to_json(&self) -> String {
+        let span = self.line.map_or("null".to_string(), |l| format!("{{\"line\":{}}}", l));
+        let suggestion = self.suggestion.as_ref().map_or("null".to_string(), |s| format!("\"{}\"", escape(s)));
+        format!(
+            "{{\"file\":\"{}\",\"span\":{},\"severity\":\"{}\",\"code\":\"{}\",\"message\":\"{}\",\"suggestion\":{}}}",
+            escape(&self.file), span, self.severity, self.code, escape(&self.message), suggestion,
+        )
+    }
+}
+
+/// The candidate nearest to `name` by edit distance, if it is a plausible typo
+fn closest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let limit = match name.chars().count() { 0..=2 => 1, 3..=5 => 2, n => n / 3 };
+    candidates
+        .filter(|c| *c != name)
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= limit)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            cur.push((prev[j] + (ca != *cb) as usize).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
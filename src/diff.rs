@@ -0,0 +1,134 @@
+//! Line diffs for `diff_lines` / `apply_patch` and `userd diff`: Myers'
+//! shortest edit script, grouped into hunks without context lines so a patch
+//! applies by exact line match. `unified` adds context for people to read.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Keep,
+    Delete,
+    Insert,
+}
+
+/// One changed region: `old_lines` starting at line `old_start` of the old
+/// text become `new_lines` starting at `new_start` of the new one. Lines are
+/// numbered from 1; for a pure insertion `old_start` is the line it goes before.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_lines: Vec<String>,
+    pub new_start: usize,
+    pub new_lines: Vec<String>,
+}
+
+/// Shortest edit script from `a` to `b`, in order
+fn edits(a: &[&str], b: &[&str]) -> Vec<Op> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let off = max + 1;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    // v[-d..=d] before each round, for the walk back
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    'search: for d in 0..=max {
+        trace.push(v[(off - d) as usize..=(off + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let down = k == -d || (k != d && v[(off + k - 1) as usize] < v[(off + k + 1) as usize]);
+            let mut x = if down { v[(off + k + 1) as usize] } else { v[(off + k - 1) as usize] + 1 };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] { x += 1; y += 1; }
+            v[(off + k) as usize] = x;
+            if x >= n && y >= m { break 'search; }
+        }
+    }
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (0..trace.len() as isize).rev() {
+        let at = |k: isize| trace[d as usize][(k + d) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) { k + 1 } else { k - 1 };
+        let prev_x = if d == 0 { 0 } else { at(prev_k) };
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y { ops.push(Op::Keep); x -= 1; y -= 1; }
+        if d > 0 {
+            if x == prev_x { ops.push(Op::Insert); y -= 1; } else { ops.push(Op::Delete); x -= 1; }
+        }
+    }
+    ops.reverse();
+    ops
+}
+
+pub fn diff(a: &[&str], b: &[&str]) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut open = false;
+    for op in edits(a, b) {
+        if op == Op::Keep { i += 1; j += 1; open = false; continue; }
+        if !open {
+            hunks.push(Hunk { old_start: i + 1, old_lines: Vec::new(), new_start: j + 1, new_lines: Vec::new() });
+            open = true;
+        }
+        let h = hunks.last_mut().unwrap();
+        if op == Op::Delete { h.old_lines.push(a[i].to_string()); i += 1; } else { h.new_lines.push(b[j].to_string()); j += 1; }
+    }
+    hunks
+}
+
+/// Apply hunks from `diff` in order; each must find its old lines where it says
+pub fn apply(lines: &[&str], hunks: &[Hunk]) -> Result<Vec<String>, String> {
+    let mut out: Vec<String> = Vec::new();
+    let mut cursor = 0;
+    for (n, h) in hunks.iter().enumerate() {
+        let start = h.old_start.saturating_sub(1);
+        let end = start + h.old_lines.len();
+        if start < cursor || end > lines.len() || lines[start..end] != h.old_lines {
+            return Err(format!("hunk {} does not match the text at line {}", n + 1, h.old_start));
+        }
+        out.extend(lines[cursor..start].iter().map(|l| l.to_string()));
+        out.extend(h.new_lines.iter().cloned());
+        cursor = end;
+    }
+    out.extend(lines[cursor..].iter().map(|l| l.to_string()));
+    Ok(out)
+}
+
+/// `diff -u` style text with `context` unchanged lines around each change;
+/// empty when the inputs are equal
+pub fn unified(old_name: &str, new_name: &str, a: &[&str], b: &[&str], context: usize) -> String {
+    let ops = edits(a, b);
+    let changes: Vec<usize> = (0..ops.len()).filter(|&i| ops[i] != Op::Keep).collect();
+    if changes.is_empty() { return String::new(); }
+    // ranges of ops to print, with changes closer than 2 * context merged
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &c in &changes {
+        let (s, e) = (c.saturating_sub(context), (c + 1 + context).min(ops.len()));
+        match ranges.last_mut() {
+            Some(last) if s <= last.1 => last.1 = e,
+            _ => ranges.push((s, e)),
+        }
+    }
+    // line positions in a and b before each op
+    let mut pos = Vec::with_capacity(ops.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for op in &ops {
+        pos.push((i, j));
+        match op { Op::Keep => { i += 1; j += 1; } Op::Delete => i += 1, Op::Insert => j += 1 }
+    }
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for (s, e) in ranges {
+        let ops = &ops[s..e];
+        let (i, j) = pos[s];
+        let old_count = ops.iter().filter(|o| **o != Op::Insert).count();
+        let new_count = ops.iter().filter(|o| **o != Op::Delete).count();
+        // an empty side names the line before it
+        let start = |at: usize, count: usize| if count == 0 { at } else { at + 1 };
+        out.push_str(&format!("@@ -{},{} +{},{} @@\n", start(i, old_count), old_count, start(j, new_count), new_count));
+        let (mut i, mut j) = (i, j);
+        for op in ops {
+            match op {
+                Op::Keep => { out.push_str(&format!(" {}\n", a[i])); i += 1; j += 1; }
+                Op::Delete => { out.push_str(&format!("-{}\n", a[i])); i += 1; }
+                Op::Insert => { out.push_str(&format!("+{}\n", b[j])); j += 1; }
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,60 @@
+//! `userd::Error`: one error type for the embedding API (`parser::parse`,
+//! `VM::eval`, `VM::eval_file`), so hosts can use `?` instead of matching on
+//! strings. The interpreter itself still passes `String`s around
+//! (`builtins::VmError`); they become `Error::Runtime` at the API boundary.
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A character that starts no token
+    Lex { line: usize, message: String },
+    /// Tokens that do not form a statement
+    Parse { line: usize, message: String },
+    /// An error raised while running; `message` includes the call trace
+    Runtime { line: Option<usize>, message: String },
+    /// Reading or writing a file; `path` is `None` when converted with `?`
+    Io { path: Option<String>, source: io::Error },
+    /// Artifacts, snapshots and traces that cannot be decoded
+    Format(String),
+}
+
+impl Error {
+    /// Source line the error points at, when known
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Error::Lex { line, .. } | Error::Parse { line, .. } => Some(*line),
+            Error::Runtime { line, .. } => *line,
+            Error::Io { .. } | Error::Format(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Lex { line, message } | Error::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            Error::Runtime { line: Some(line), message } => write!(f, "line {}: {}", line, message),
+            Error::Runtime { line: None, message } => write!(f, "{}", message),
+            Error::Io { path: Some(path), source } => write!(f, "{}: {}", path, source),
+            Error::Io { path: None, source } => write!(f, "{}", source),
+            Error::Format(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Error::Io { path: None, source }
+    }
+}
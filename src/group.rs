@@ -0,0 +1,118 @@
+//! Named VMs in one host process that exchange values as messages, for
+//! actor-style hosts (a UI VM and a worker VM) without a thread per script.
+//!
+//! Every member gets an inbox in the channel subsystem. A value is sent in
+//! its snapshot encoding (`snapshot::encode_value`, as hex text, since
+//! channels carry strings), so the receiver gets a deep copy and the VMs
+//! never share objects. Scripts use `group_send(str-to, value)` and
+//! `group_recv()`; the host starts programs on members and interleaves them
+//! with `VmGroup::run`, which is built on `VM::run_steps`.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::Program;
+use crate::builtins::{channels, VmError};
+use crate::vm::{Value, VM};
+
+/// Inbox channel id of each member, by name
+type Inboxes = Rc<RefCell<HashMap<String, u64>>>;
+
+/// What a member VM knows about its group
+#[derive(Clone)]
+pub(crate) struct GroupLink {
+    name: String,
+    inboxes: Inboxes,
+}
+
+impl GroupLink {
+    /// Copy `v` into the inbox of member `to`
+    pub(crate) fn send(&self, to: &str, v: &Value) -> Result<(), VmError> {
+        let id = *self.inboxes.borrow().get(to).ok_or_else(|| format!("group_send: no VM named {}", to))?;
+        if !channels::send(id, crate::hash::hex(&crate::snapshot::encode_value(v))) {
+            return Err(format!("group_send: inbox of {} is closed", to));
+        }
+        Ok(())
+    }
+
+    /// The next value in this member's inbox, if any
+    pub(crate) fn recv(&self) -> Result<Option<Value>, VmError> {
+        let Some(&id) = self.inboxes.borrow().get(&self.name) else { return Err("group_recv: inbox is closed".to_string()) };
+        match channels::try_recv("group_recv", id)? {
+            Some(text) => crate::snapshot::decode_value(&unhex(&text)?).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct VmGroup {
+    members: Vec<(String, VM)>,
+    inboxes: Inboxes,
+}
+
+impl VmGroup {
+    pub fn new() -> Self { Self::default() }
+
+    /// Add `vm` under `name`, unique within the group
+    pub fn add(&mut self, name: &str, mut vm: VM) -> Result<&mut VM, String> {
+        if self.inboxes.borrow().contains_key(name) {
+            return Err(format!("VM {} is already in the group", name));
+        }
+        self.inboxes.borrow_mut().insert(name.to_string(), channels::create());
+        vm.join_group(GroupLink { name: name.to_string(), inboxes: self.inboxes.clone() });
+        self.members.push((name.to_string(), vm));
+        Ok(&mut self.members.last_mut().unwrap().1)
+    }
+
+    /// Member names in the order they were added
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.members.iter().map(|(n, _)| n.as_str())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&VM> {
+        self.members.iter().find(|(n, _)| n == name).map(|(_, vm)| vm)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut VM> {
+        self.members.iter_mut().find(|(n, _)| n == name).map(|(_, vm)| vm)
+    }
+
+    /// Send a copy of `v` from the host to member `to`
+    pub fn send(&self, to: &str, v: &Value) -> Result<(), String> {
+        let link = GroupLink { name: String::new(), inboxes: self.inboxes.clone() };
+        link.send(to, v)
+    }
+
+    /// Load a program on member `name`; it runs on the next `run`
+    pub fn start(&mut self, name: &str, prog: Program) -> Result<(), String> {
+        self.get_mut(name).ok_or_else(|| format!("no VM named {}", name))?.start(prog);
+        Ok(())
+    }
+
+    /// Run the started programs in turn, a `VM::run_steps(slice)` each, until
+    /// all have finished. Turns end between top-level statements only, so a
+    /// member busy in one long statement holds up the others until it is done. The first error stops the
+    /// round and is returned as `name: message`; the other members keep
+    /// their place and continue on the next `run`.
+    pub fn run(&mut self, slice: u64) -> Result<(), String> {
+        while self.members.iter().any(|(_, vm)| vm.is_running()) {
+            for (name, vm) in self.members.iter_mut().filter(|(_, vm)| vm.is_running()) {
+                vm.run_steps(slice).map_err(|e| format!("{}: {}", name, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for VmGroup {
+    fn drop(&mut self) {
+        for (_, id) in self.inboxes.borrow_mut().drain() { channels::close(id); }
+    }
+}
+
+fn unhex(s: &str) -> Result<Vec<u8>, String> {
+    (0..s.len()).step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|h| u8::from_str_radix(h, 16).ok()).ok_or_else(|| "group_recv: malformed message".to_string()))
+        .collect()
+}
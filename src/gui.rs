@@ -4,7 +4,13 @@
 //! native desktop GUI backends (winit/egui, gtk, or native platform toolkits).
 //!
 //! Right now this file contains only documentation and light helpers — the
-//! real implementation will be provided later behind feature flags.
+//! real implementation will be provided later behind feature flags. The
+//! platform-independent part of widgets that need state, such as scroll
+//! panels, lives here so that every platform layer shares it.
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
 
 /// Represents a platform-window handle (placeholder)
 pub struct WindowHandle;
@@ -28,3 +34,604 @@ impl WindowHandle {
 // - `gui-winit` feature -> use winit + egui
 // - `gui-gtk` feature -> use gtk bindings
 // - `gui-native` feature -> platform APIs
+
+/// Width of the scrollbar drawn at the right edge of a scroll panel
+pub const SCROLLBAR_WIDTH: i32 = 12;
+/// Pixels scrolled per mouse-wheel notch (120 units of wheel delta)
+pub const WHEEL_STEP: i32 = 48;
+/// The thumb never gets shorter than this, however long the content
+const MIN_THUMB: i32 = 16;
+
+/// A viewport of `w` x `h` at (`x`, `y`) over content `content_h` pixels
+/// tall, scrolled down by `offset`. Only the geometry lives here; the script
+/// draws the visible part of the content itself, shifted up by `offset`.
+/// The scrollbar takes the rightmost `SCROLLBAR_WIDTH` pixels of the panel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScrollPanel {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+    pub content_h: i32,
+    pub offset: i32,
+    /// While the thumb is dragged, where in it the mouse grabbed it
+    grab: Option<i32>,
+}
+
+impl ScrollPanel {
+    pub fn new(x: i32, y: i32, w: i32, h: i32, content_h: i32) -> Self {
+        ScrollPanel { x, y, w, h: h.max(1), content_h, offset: 0, grab: None }
+    }
+
+    pub fn max_offset(&self) -> i32 {
+        (self.content_h - self.h).max(0)
+    }
+
+    /// Change the content height, keeping the offset in range
+    pub fn set_content(&mut self, content_h: i32) {
+        self.content_h = content_h;
+        self.scroll_to(self.offset);
+    }
+
+    /// Scroll to `offset`, clamped; true when the offset changed
+    pub fn scroll_to(&mut self, offset: i32) -> bool {
+        let old = self.offset;
+        self.offset = offset.clamp(0, self.max_offset());
+        self.offset != old
+    }
+
+    /// A wheel turn of `delta` (positive away from the user scrolls up)
+    pub fn wheel(&mut self, delta: i32) -> bool {
+        self.scroll_to(self.offset - delta * WHEEL_STEP / 120)
+    }
+
+    pub fn contains(&self, px: i32, py: i32) -> bool {
+        px >= self.x && px < self.x + self.w && py >= self.y && py < self.y + self.h
+    }
+
+    /// (x, y, w, h) of the scrollbar track
+    pub fn track(&self) -> (i32, i32, i32, i32) {
+        (self.x + self.w - SCROLLBAR_WIDTH, self.y, SCROLLBAR_WIDTH, self.h)
+    }
+
+    /// (x, y, w, h) of the thumb; `None` when everything fits
+    pub fn thumb(&self) -> Option<(i32, i32, i32, i32)> {
+        let max = self.max_offset();
+        if max == 0 { return None; }
+        let len = ((self.h as i64 * self.h as i64 / self.content_h as i64) as i32).clamp(MIN_THUMB.min(self.h), self.h);
+        let top = self.y + ((self.h - len) as i64 * self.offset as i64 / max as i64) as i32;
+        let (tx, _, tw, _) = self.track();
+        Some((tx, top, tw, len))
+    }
+
+    /// Mouse button down at (px, py): grabs the thumb, or pages towards the
+    /// click on the track. True when the panel took the click
+    pub fn press(&mut self, px: i32, py: i32) -> bool {
+        let (tx, ty, tw, th) = self.track();
+        if px < tx || px >= tx + tw || py < ty || py >= ty + th { return false; }
+        let Some((_, top, _, len)) = self.thumb() else { return true };
+        if py < top {
+            self.scroll_to(self.offset - self.h);
+        } else if py >= top + len {
+            self.scroll_to(self.offset + self.h);
+        } else {
+            self.grab = Some(py - top);
+        }
+        true
+    }
+
+    /// Mouse moved to height `py` while the button is down
+    pub fn drag_to(&mut self, py: i32) -> bool {
+        let (Some(grab), Some((_, _, _, len))) = (self.grab, self.thumb()) else { return false };
+        let room = (self.h - len).max(1);
+        let top = py - grab - self.y;
+        self.scroll_to((top as i64 * self.max_offset() as i64 / room as i64) as i32)
+    }
+
+    pub fn release(&mut self) {
+        self.grab = None;
+    }
+
+    pub fn dragging(&self) -> bool {
+        self.grab.is_some()
+    }
+
+    /// The track and, when the content does not fit, the thumb
+    pub fn draw_scrollbar(&self) -> Vec<DrawOp> {
+        let (x, y, w, h) = self.track();
+        let mut ops = vec![DrawOp::Rect { x, y, w, h, color: [220, 220, 220] }];
+        if let Some((x, y, w, h)) = self.thumb() {
+            ops.push(DrawOp::Rect { x: x + 2, y, w: w - 4, h, color: [140, 140, 140] });
+        }
+        ops
+    }
+}
+
+/// Height of a list row; fits the 16 px canvas font
+pub const ROW_HEIGHT: i32 = 20;
+
+/// Rows of a list widget, shown in a scroll panel one `ROW_HEIGHT` each
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ListView {
+    pub items: Vec<String>,
+    /// Row under the mouse
+    pub hover: Option<usize>,
+    pub selected: Option<usize>,
+}
+
+/// One drawing step of a widget, in window coordinates
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DrawOp {
+    Rect { x: i32, y: i32, w: i32, h: i32, color: [u8; 3] },
+    Text { x: i32, y: i32, text: String },
+}
+
+impl ListView {
+    pub fn new(items: Vec<String>) -> Self {
+        ListView { items, hover: None, selected: None }
+    }
+
+    pub fn content_height(&self) -> i32 {
+        (self.items.len() as i64 * ROW_HEIGHT as i64).min(i32::MAX as i64) as i32
+    }
+
+    /// Row at (px, py) in `panel`, if any; the scrollbar has none
+    pub fn row_at(&self, panel: &ScrollPanel, px: i32, py: i32) -> Option<usize> {
+        if !panel.contains(px, py) || px >= panel.track().0 { return None; }
+        let row = ((py - panel.y) as i64 + panel.offset as i64) / ROW_HEIGHT as i64;
+        (row < self.items.len() as i64).then_some(row as usize)
+    }
+
+    /// Move the hover to the row at (px, py); true when it changed
+    pub fn hover_at(&mut self, panel: &ScrollPanel, px: i32, py: i32) -> bool {
+        let row = self.row_at(panel, px, py);
+        std::mem::replace(&mut self.hover, row) != row
+    }
+
+    /// Select `row` and scroll it into view; true when the selection changed
+    pub fn select(&mut self, panel: &mut ScrollPanel, row: Option<usize>) -> bool {
+        let row = row.filter(|&r| r < self.items.len());
+        if let Some(r) = row {
+            let top = r as i32 * ROW_HEIGHT;
+            if top < panel.offset {
+                panel.scroll_to(top);
+            } else if top + ROW_HEIGHT > panel.offset + panel.h {
+                panel.scroll_to(top + ROW_HEIGHT - panel.h);
+            }
+        }
+        std::mem::replace(&mut self.selected, row) != row
+    }
+
+    /// Background, visible rows and scrollbar. Rows cut by the panel edge
+    /// get their background clipped; their text is left out, since the
+    /// canvas cannot clip text
+    pub fn draw(&self, panel: &ScrollPanel) -> Vec<DrawOp> {
+        let width = panel.w - SCROLLBAR_WIDTH;
+        let mut ops = vec![DrawOp::Rect { x: panel.x, y: panel.y, w: width, h: panel.h, color: [255, 255, 255] }];
+        let first = (panel.offset / ROW_HEIGHT) as usize;
+        let last = ((panel.offset + panel.h - 1) / ROW_HEIGHT) as usize;
+        for (i, item) in self.items.iter().enumerate().take(last + 1).skip(first) {
+            let top = panel.y + i as i32 * ROW_HEIGHT - panel.offset;
+            let (clip_top, clip_bottom) = (top.max(panel.y), (top + ROW_HEIGHT).min(panel.y + panel.h));
+            let color = if self.selected == Some(i) { Some([204, 228, 247]) } else if self.hover == Some(i) { Some([234, 243, 252]) } else { None };
+            if let Some(color) = color {
+                ops.push(DrawOp::Rect { x: panel.x, y: clip_top, w: width, h: clip_bottom - clip_top, color });
+            }
+            if clip_top == top && clip_bottom == top + ROW_HEIGHT {
+                ops.push(DrawOp::Text { x: panel.x + 4, y: top + 2, text: item.clone() });
+            }
+        }
+        ops.extend(panel.draw_scrollbar());
+        ops
+    }
+}
+
+/// A scroll panel in a window, with the function called when it scrolls.
+/// A list widget is a scroll panel with rows; its handler is called when
+/// the selection changes instead
+#[derive(Debug, Clone)]
+pub struct PanelEntry {
+    pub window: u64,
+    pub handler: String,
+    pub panel: ScrollPanel,
+    pub list: Option<ListView>,
+}
+
+static PANELS: OnceLock<Mutex<HashMap<u64, PanelEntry>>> = OnceLock::new();
+static NEXT_PANEL: AtomicU64 = AtomicU64::new(1);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static HANDLERS: OnceLock<Mutex<HashMap<u64, String>>> = OnceLock::new();
+
+/// Id for a new window or widget. Every backend numbers them from here, so
+/// the same script gets the same ids and recorded events replay anywhere
+pub fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+/// Name of the function called for events of window, widget or panel `id`
+pub fn set_handler(id: u64, handler: &str) {
+    HANDLERS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner()).insert(id, handler.to_string());
+}
+
+pub fn handler(id: u64) -> Option<String> {
+    HANDLERS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner()).get(&id).cloned()
+}
+
+/// Scroll panels of every window by id; shared with the window threads,
+/// which scroll them on wheel and drag input
+pub fn panels() -> &'static Mutex<HashMap<u64, PanelEntry>> {
+    PANELS.get_or_init(Default::default)
+}
+
+/// Add a panel and return its id. Ids count down from the top of the range
+/// so they never collide with the window and widget ids used for events
+pub fn add_panel(entry: PanelEntry) -> u64 {
+    let id = u64::MAX - NEXT_PANEL.fetch_add(1, Ordering::SeqCst);
+    panels().lock().unwrap_or_else(|e| e.into_inner()).insert(id, entry);
+    id
+}
+
+/// One row of a dialog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DialogItem {
+    Text(String),
+    Check { name: String, label: String, on: bool },
+    Button { name: String, label: String },
+}
+
+const DIALOG_PAD: i32 = 12;
+const DIALOG_TITLE_H: i32 = 24;
+const BUTTON_H: i32 = 28;
+const CHECK_BOX: i32 = 12;
+
+/// A modal dialog: a title bar, rows of text and checkboxes, and a row of
+/// buttons at the bottom. It is closed by a button (its name is the result),
+/// Enter (the first button) or Escape (no button)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dialog {
+    pub title: String,
+    pub items: Vec<DialogItem>,
+    pub x: i32,
+    pub y: i32,
+    /// `Some` once closed: the name of the button, "" for Escape
+    pub result: Option<String>,
+}
+
+fn text_width(text: &str) -> i32 {
+    crate::builtins::gui::measure(text, crate::builtins::gui::CANVAS_FONT_SIZE).0 as i32
+}
+
+impl Dialog {
+    /// A dialog from layout lines `title: Text`, `text: Text`,
+    /// `check: name: Label` (add `: on` to start checked) and
+    /// `button: name: Label`
+    pub fn parse(layout: &[String]) -> Result<Self, String> {
+        let mut dialog = Dialog { title: String::new(), items: Vec::new(), x: 0, y: 0, result: None };
+        for line in layout {
+            let (kind, rest) = line.split_once(':').ok_or_else(|| format!("dialog line {:?}: expected kind: ...", line))?;
+            let parts: Vec<&str> = rest.split(':').map(str::trim).collect();
+            match (kind.trim(), parts.as_slice()) {
+                ("title", _) => dialog.title = rest.trim().to_string(),
+                ("text", _) => dialog.items.push(DialogItem::Text(rest.trim().to_string())),
+                ("check", [name, label]) | ("check", [name, label, "off"]) => {
+                    dialog.items.push(DialogItem::Check { name: name.to_string(), label: label.to_string(), on: false });
+                }
+                ("check", [name, label, "on"]) => dialog.items.push(DialogItem::Check { name: name.to_string(), label: label.to_string(), on: true }),
+                ("button", [name, label]) => dialog.items.push(DialogItem::Button { name: name.to_string(), label: label.to_string() }),
+                _ => return Err(format!("dialog line {:?}: expected title:, text:, check: name: label[: on] or button: name: label", line)),
+            }
+        }
+        if !dialog.items.iter().any(|i| matches!(i, DialogItem::Button { .. })) {
+            return Err("a dialog needs at least one button".to_string());
+        }
+        Ok(dialog)
+    }
+
+    fn buttons(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.items.iter().filter_map(|i| match i { DialogItem::Button { name, label } => Some((name.as_str(), label.as_str())), _ => None })
+    }
+
+    /// Rows above the buttons
+    fn rows(&self) -> impl Iterator<Item = &DialogItem> {
+        self.items.iter().filter(|i| !matches!(i, DialogItem::Button { .. }))
+    }
+
+    pub fn size(&self) -> (i32, i32) {
+        let buttons: i32 = self.buttons().map(|(_, label)| text_width(label) + 2 * DIALOG_PAD + 8).sum();
+        let rows = self.rows().map(|i| match i {
+            DialogItem::Text(t) => text_width(t),
+            DialogItem::Check { label, .. } => CHECK_BOX + 8 + text_width(label),
+            DialogItem::Button { .. } => 0,
+        });
+        let inner = rows.chain([text_width(&self.title), buttons]).max().unwrap_or(0);
+        let h = DIALOG_TITLE_H + DIALOG_PAD + self.rows().count() as i32 * ROW_HEIGHT + DIALOG_PAD + BUTTON_H + DIALOG_PAD;
+        ((inner + 2 * DIALOG_PAD).max(200), h)
+    }
+
+    /// Place the dialog in the middle of a `win_w` x `win_h` window
+    pub fn center(&mut self, win_w: i32, win_h: i32) {
+        let (w, h) = self.size();
+        self.x = ((win_w - w) / 2).max(0);
+        self.y = ((win_h - h) / 2).max(0);
+    }
+
+    /// (x, y, w, h) of each item, in the order of `items`
+    fn item_rects(&self) -> Vec<(i32, i32, i32, i32)> {
+        let (w, h) = self.size();
+        let button_w = |label: &str| text_width(label) + 2 * DIALOG_PAD;
+        // buttons sit side by side 8 px apart, flush right
+        let buttons: i32 = self.buttons().map(|(_, label)| button_w(label) + 8).sum();
+        let mut button_x = self.x + w - DIALOG_PAD - (buttons - 8);
+        let mut row_y = self.y + DIALOG_TITLE_H + DIALOG_PAD;
+        self.items.iter().map(|item| match item {
+            DialogItem::Button { label, .. } => {
+                let r = (button_x, self.y + h - DIALOG_PAD - BUTTON_H, button_w(label), BUTTON_H);
+                button_x += r.2 + 8;
+                r
+            }
+            _ => {
+                row_y += ROW_HEIGHT;
+                (self.x + DIALOG_PAD, row_y - ROW_HEIGHT, w - 2 * DIALOG_PAD, ROW_HEIGHT)
+            }
+        }).collect()
+    }
+
+    /// A click at (px, py): toggles a checkbox or closes with a button.
+    /// True when the dialog changed
+    pub fn click(&mut self, px: i32, py: i32) -> bool {
+        let rects = self.item_rects();
+        let Some(i) = rects.iter().position(|&(x, y, w, h)| px >= x && px < x + w && py >= y && py < y + h) else { return false };
+        match &mut self.items[i] {
+            DialogItem::Check { on, .. } => *on = !*on,
+            DialogItem::Button { name, .. } => self.result = Some(name.clone()),
+            DialogItem::Text(_) => return false,
+        }
+        true
+    }
+
+    /// Enter picks the first button, Escape closes with no button
+    pub fn key(&mut self, enter: bool) {
+        self.result = Some(if enter { self.buttons().next().map(|(n, _)| n.to_string()).unwrap_or_default() } else { String::new() });
+    }
+
+    /// Checkbox names and states, in order
+    pub fn checks(&self) -> Vec<(String, bool)> {
+        self.items.iter().filter_map(|i| match i { DialogItem::Check { name, on, .. } => Some((name.clone(), *on)), _ => None }).collect()
+    }
+
+    pub fn draw(&self) -> Vec<DrawOp> {
+        let (w, h) = self.size();
+        let (x, y) = (self.x, self.y);
+        let mut ops = vec![
+            // border, body and title bar
+            DrawOp::Rect { x: x - 1, y: y - 1, w: w + 2, h: h + 2, color: [90, 90, 90] },
+            DrawOp::Rect { x, y, w, h, color: [245, 245, 245] },
+            DrawOp::Rect { x, y, w, h: DIALOG_TITLE_H, color: [0, 90, 158] },
+            DrawOp::Text { x: x + DIALOG_PAD, y: y + 4, text: self.title.clone() },
+        ];
+        for (item, (ix, iy, iw, ih)) in self.items.iter().zip(self.item_rects()) {
+            match item {
+                DialogItem::Text(t) => ops.push(DrawOp::Text { x: ix, y: iy + 2, text: t.clone() }),
+                DialogItem::Check { label, on, .. } => {
+                    let by = iy + (ROW_HEIGHT - CHECK_BOX) / 2;
+                    ops.push(DrawOp::Rect { x: ix, y: by, w: CHECK_BOX, h: CHECK_BOX, color: [90, 90, 90] });
+                    ops.push(DrawOp::Rect { x: ix + 1, y: by + 1, w: CHECK_BOX - 2, h: CHECK_BOX - 2, color: [255, 255, 255] });
+                    if *on { ops.push(DrawOp::Rect { x: ix + 3, y: by + 3, w: CHECK_BOX - 6, h: CHECK_BOX - 6, color: [0, 90, 158] }); }
+                    ops.push(DrawOp::Text { x: ix + CHECK_BOX + 8, y: iy + 2, text: label.clone() });
+                }
+                DialogItem::Button { label, .. } => {
+                    ops.push(DrawOp::Rect { x: ix, y: iy, w: iw, h: ih, color: [173, 173, 173] });
+                    ops.push(DrawOp::Rect { x: ix + 1, y: iy + 1, w: iw - 2, h: ih - 2, color: [225, 225, 225] });
+                    ops.push(DrawOp::Text { x: ix + DIALOG_PAD, y: iy + 6, text: label.clone() });
+                }
+            }
+        }
+        ops
+    }
+}
+
+static MODALS: OnceLock<Mutex<HashMap<u64, Vec<Dialog>>>> = OnceLock::new();
+
+/// Open dialogs per window, innermost last. While a window has one, only the
+/// last gets the window's input
+pub fn modals() -> &'static Mutex<HashMap<u64, Vec<Dialog>>> {
+    MODALS.get_or_init(Default::default)
+}
+
+/// Keyboard focus of one window: widget and list ids in Tab order, and the
+/// focused one
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FocusOrder {
+    pub order: Vec<u64>,
+    pub current: Option<u64>,
+}
+
+impl FocusOrder {
+    pub fn add(&mut self, id: u64) {
+        if !self.order.contains(&id) { self.order.push(id); }
+    }
+
+    /// Tab (or Shift+Tab with `back`): the next id, wrapping around; from
+    /// no focus, the first (or last)
+    pub fn advance(&mut self, back: bool) -> Option<u64> {
+        let n = self.order.len();
+        if n == 0 { return None; }
+        let at = self.current.and_then(|c| self.order.iter().position(|&id| id == c));
+        let next = match (at, back) {
+            (None, false) => 0,
+            (None, true) => n - 1,
+            (Some(i), false) => (i + 1) % n,
+            (Some(i), true) => (i + n - 1) % n,
+        };
+        self.current = Some(self.order[next]);
+        self.current
+    }
+
+    /// Focus `id`; false when it is not in this window
+    pub fn set(&mut self, id: u64) -> bool {
+        let known = self.order.contains(&id);
+        if known { self.current = Some(id); }
+        known
+    }
+}
+
+static FOCUS: OnceLock<Mutex<HashMap<u64, FocusOrder>>> = OnceLock::new();
+
+/// Focus order of every window by window id
+pub fn focus() -> &'static Mutex<HashMap<u64, FocusOrder>> {
+    FOCUS.get_or_init(Default::default)
+}
+
+/// Add a widget or list to the end of its window's Tab order
+pub fn add_focusable(window: u64, id: u64) {
+    focus().lock().unwrap_or_else(|e| e.into_inner()).entry(window).or_default().add(id);
+}
+
+static CANVASES: OnceLock<Mutex<HashMap<u64, crate::raster::Raster>>> = OnceLock::new();
+
+/// Software canvases of windows opened without a platform window, by window id
+pub fn canvases() -> &'static Mutex<HashMap<u64, crate::raster::Raster>> {
+    CANVASES.get_or_init(Default::default)
+}
+
+/// Files dropped on a window, with the point they were dropped at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDrop {
+    pub window: u64,
+    pub paths: Vec<String>,
+    pub x: i32,
+    pub y: i32,
+}
+
+static DROPS: OnceLock<Mutex<Vec<FileDrop>>> = OnceLock::new();
+static DROP_HANDLERS: OnceLock<Mutex<HashMap<u64, String>>> = OnceLock::new();
+
+/// Queue a drop for the window's handler; backends call this from their
+/// window threads, `gui_poll` and `gui_run` deliver it
+pub fn push_drop(drop: FileDrop) {
+    DROPS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner()).push(drop);
+}
+
+pub fn take_drops() -> Vec<FileDrop> {
+    std::mem::take(&mut *DROPS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+pub fn set_drop_handler(window: u64, handler: &str) {
+    DROP_HANDLERS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner()).insert(window, handler.to_string());
+}
+
+pub fn drop_handler(window: u64) -> Option<String> {
+    DROP_HANDLERS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner()).get(&window).cloned()
+}
+
+/// A display, as (x, y, w, h) rectangles on the virtual screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Monitor {
+    pub bounds: (i32, i32, i32, i32),
+    /// `bounds` without the taskbar and docked toolbars
+    pub work: (i32, i32, i32, i32),
+    pub primary: bool,
+}
+
+/// An event as a script's handlers see it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuiEvent {
+    /// `handler(x, y)` of a window, widget or panel: clicks, keyboard
+    /// activation, list selection `(row, 0)` and scrolling `(0, offset)`
+    Click { target: u64, x: i32, y: i32 },
+    Drop(FileDrop),
+}
+
+/// An event and when it came, in milliseconds from the start of recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedEvent {
+    pub at_ms: u64,
+    pub event: GuiEvent,
+}
+
+/// Text form of an event log: one event per tab-separated line,
+/// `ms click target x y` or `ms drop window x y path...`
+pub fn encode_events(events: &[TimedEvent]) -> String {
+    let mut out = String::from("# userd gui events\n");
+    for e in events {
+        let line = match &e.event {
+            GuiEvent::Click { target, x, y } => format!("{}\tclick\t{}\t{}\t{}", e.at_ms, target, x, y),
+            GuiEvent::Drop(d) => {
+                let mut line = format!("{}\tdrop\t{}\t{}\t{}", e.at_ms, d.window, d.x, d.y);
+                for p in &d.paths { line.push('\t'); line.push_str(p); }
+                line
+            }
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Read what `encode_events` wrote; blank lines and `#` comments are skipped
+pub fn parse_events(text: &str) -> Result<Vec<TimedEvent>, String> {
+    let mut events = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') { continue; }
+        let bad = || format!("line {}: expected `ms click target x y` or `ms drop window x y path...`", n + 1);
+        let f: Vec<&str> = line.split('\t').collect();
+        if f.len() < 5 { return Err(bad()); }
+        let num = |s: &str| s.parse::<i64>().map_err(|_| bad());
+        let (at_ms, id, x, y) = (num(f[0])? as u64, num(f[2])? as u64, num(f[3])? as i32, num(f[4])? as i32);
+        let event = match f[1] {
+            "click" if f.len() == 5 => GuiEvent::Click { target: id, x, y },
+            "drop" => GuiEvent::Drop(FileDrop { window: id, paths: f[5..].iter().map(|p| p.to_string()).collect(), x, y }),
+            _ => return Err(bad()),
+        };
+        events.push(TimedEvent { at_ms, event });
+    }
+    Ok(events)
+}
+
+/// Recording and replaying of the events one VM's handlers receive, for
+/// `userd run --record-events` and `--replay-events`. Replayed events go to
+/// the handlers directly, so they work with or without real windows
+#[derive(Debug, Default)]
+pub struct EventTape {
+    recording: Option<(Instant, Vec<TimedEvent>)>,
+    replaying: Option<(Instant, VecDeque<TimedEvent>)>,
+}
+
+impl EventTape {
+    pub fn start_recording(&mut self) {
+        self.recording = Some((Instant::now(), Vec::new()));
+    }
+
+    /// Add an event delivered now; nothing happens unless recording
+    pub fn record(&mut self, event: &GuiEvent) {
+        if let Some((start, events)) = &mut self.recording {
+            events.push(TimedEvent { at_ms: start.elapsed().as_millis() as u64, event: event.clone() });
+        }
+    }
+
+    /// Stop recording and return the events so far
+    pub fn finish_recording(&mut self) -> Vec<TimedEvent> {
+        self.recording.take().map(|(_, events)| events).unwrap_or_default()
+    }
+
+    /// Deliver `events` at their times, counted from now
+    pub fn start_replay(&mut self, events: Vec<TimedEvent>) {
+        self.replaying = Some((Instant::now(), events.into()));
+    }
+
+    /// Whether replayed events are still to come; `gui_run` waits for them
+    pub fn replaying(&self) -> bool {
+        self.replaying.as_ref().is_some_and(|(_, events)| !events.is_empty())
+    }
+
+    /// Replayed events whose time has come, in order
+    pub fn due(&mut self) -> Vec<GuiEvent> {
+        let Some((start, events)) = &mut self.replaying else { return Vec::new() };
+        let now = start.elapsed().as_millis() as u64;
+        let mut due = Vec::new();
+        while events.front().is_some_and(|e| e.at_ms <= now) {
+            due.extend(events.pop_front().map(|e| e.event));
+        }
+        due
+    }
+}
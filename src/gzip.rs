@@ -0,0 +1,331 @@
+//! DEFLATE (RFC 1951) and the gzip wrapper (RFC 1952) without dependencies,
+//! for the `gzip_*` builtins, `userd pack` and the editor server.
+//!
+//! The compressor finds matches with hash chains over the 32 KiB window and
+//! writes one block with the fixed Huffman codes, or stored blocks when that
+//! would come out larger. That is well behind zlib on ratio but cheap, and
+//! source text still shrinks to about a third. The decompressor reads every
+//! block type, so it accepts the output of any gzip.
+use crate::hash::crc32;
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Candidates tried per position; longer chains barely help on text
+const MAX_CHAIN: usize = 128;
+const HASH_BITS: u32 = 15;
+/// Decompressed size refused as a likely zip bomb, counted over all members
+/// of a gzip file; packed artifacts and uploads are far smaller
+const MAX_OUTPUT: usize = 64 << 20;
+
+const LEN_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LEN_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// Order of the code length code lengths in a dynamic block header
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// gzip member with no name and no timestamp
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // magic, deflate, no flags, mtime 0, no extra flags, unknown OS
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1f, 0x8b])
+}
+
+/// Contents of gzip data; concatenated members are joined like `gunzip` does.
+/// `MAX_OUTPUT` applies to the joined output, so many small members cannot
+/// add up to more than one large one
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    decompress_limited(data, MAX_OUTPUT)
+}
+
+/// `decompress` refusing more than `max_output` bytes in total
+pub(crate) fn decompress_limited(data: &[u8], max_output: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let member = &data[pos..];
+        if member.len() < 18 || !is_gzip(member) { return Err("not gzip data".to_string()); }
+        if member[2] != 8 { return Err("unknown compression method".to_string()); }
+        let flags = member[3];
+        let mut at = 10;
+        if flags & 4 != 0 {
+            let extra = member.get(at..at + 2).ok_or("truncated header")?;
+            at += 2 + u16::from_le_bytes([extra[0], extra[1]]) as usize;
+        }
+        // file name and comment, zero terminated
+        for flag in [8, 16] {
+            if flags & flag != 0 {
+                at += member.get(at..).and_then(|r| r.iter().position(|&b| b == 0)).ok_or("truncated header")? + 1;
+            }
+        }
+        if flags & 2 != 0 { at += 2; }
+        let body = member.get(at..).ok_or("truncated header")?;
+        let start = out.len();
+        let used = inflate_into(body, &mut out, max_output)?;
+        let trailer = body.get(used..used + 8).ok_or("missing gzip trailer")?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32(&out[start..]) || size != (out.len() - start) as u32 {
+            return Err("gzip checksum mismatch".to_string());
+        }
+        pos += at + used + 8;
+    }
+    Ok(out)
+}
+
+/// Raw DEFLATE stream of `data`
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter::default();
+    w.bits(1, 1); // final block
+    w.bits(1, 2); // fixed Huffman codes
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+    let hash = |p: usize| {
+        let v = (data[p] as u32) << 16 | (data[p + 1] as u32) << 8 | data[p + 2] as u32;
+        (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    };
+    let insert = |p: usize, head: &mut [usize], prev: &mut [usize]| {
+        if p + MIN_MATCH <= data.len() {
+            let h = hash(p);
+            prev[p] = head[h];
+            head[h] = p;
+        }
+    };
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let limit = MAX_MATCH.min(data.len() - i);
+            let mut cand = head[hash(i)];
+            let mut chain = 0;
+            while cand != usize::MAX && i - cand <= WINDOW && chain < MAX_CHAIN {
+                let len = (0..limit).take_while(|&k| data[cand + k] == data[i + k]).count();
+                if len > best_len {
+                    (best_len, best_dist) = (len, i - cand);
+                    if len == limit { break; }
+                }
+                cand = prev[cand];
+                chain += 1;
+            }
+        }
+        if best_len >= MIN_MATCH {
+            let k = (0..LEN_BASE.len()).rev().find(|&k| LEN_BASE[k] as usize <= best_len).unwrap();
+            w.fixed_literal(257 + k as u16);
+            w.bits((best_len - LEN_BASE[k] as usize) as u32, LEN_EXTRA[k] as u32);
+            let d = (0..DIST_BASE.len()).rev().find(|&d| DIST_BASE[d] as usize <= best_dist).unwrap();
+            w.code(d as u32, 5);
+            w.bits((best_dist - DIST_BASE[d] as usize) as u32, DIST_EXTRA[d] as u32);
+            for p in i..i + best_len { insert(p, &mut head, &mut prev); }
+            i += best_len;
+        } else {
+            w.fixed_literal(data[i] as u16);
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+    w.fixed_literal(256);
+    let fixed = w.finish();
+    // stored blocks cost 5 bytes per 64 KiB
+    let stored_len = data.len() + 5 * (data.len() / 0xFFFF + 1);
+    if fixed.len() <= stored_len { return fixed; }
+    let mut out = Vec::with_capacity(stored_len);
+    let mut chunks = data.chunks(0xFFFF).peekable();
+    if chunks.peek().is_none() { out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]); }
+    while let Some(chunk) = chunks.next() {
+        out.push(chunks.peek().is_none() as u8);
+        out.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(chunk.len() as u16)).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out
+}
+
+/// Contents of a raw DEFLATE stream
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    inflate_into(data, &mut out, MAX_OUTPUT)?;
+    Ok(out)
+}
+
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    /// `n` bits of `value`, least significant first
+    fn bits(&mut self, value: u32, n: u32) {
+        self.acc |= (value as u64) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// A Huffman code, which goes most significant bit first
+    fn code(&mut self, code: u32, len: u32) {
+        self.bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn fixed_literal(&mut self, sym: u16) {
+        let sym = sym as u32;
+        match sym {
+            0..=143 => self.code(0x30 + sym, 8),
+            144..=255 => self.code(0x190 + sym - 144, 9),
+            256..=279 => self.code(sym - 256, 7),
+            _ => self.code(0xC0 + sym - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 { self.out.push(self.acc as u8); }
+        self.out
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> Result<u32, String> {
+        let byte = *self.data.get(self.pos).ok_or("unexpected end of compressed data")?;
+        let b = (byte >> self.bit) & 1;
+        self.bit += 1;
+        if self.bit == 8 { self.bit = 0; self.pos += 1; }
+        Ok(b as u32)
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        let mut v = 0;
+        for i in 0..n { v |= self.bit()? << i; }
+        Ok(v)
+    }
+
+    fn align(&mut self) {
+        if self.bit > 0 { self.bit = 0; self.pos += 1; }
+    }
+}
+
+/// Canonical Huffman code as counts per length and symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &l in lengths { counts[l as usize] += 1; }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 { offsets[len + 1] = offsets[len] + counts[len]; }
+        let mut symbols = vec![0; lengths.len()];
+        for (sym, &l) in lengths.iter().enumerate() {
+            if l > 0 { symbols[offsets[l as usize] as usize] = sym as u16; offsets[l as usize] += 1; }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, r: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= r.bit()? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count { return Ok(self.symbols[(index + code - first) as usize]); }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_tables(r: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let nlen = r.bits(5)? as usize + 257;
+    let ndist = r.bits(5)? as usize + 1;
+    let ncode = r.bits(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 { return Err("bad dynamic block header".to_string()); }
+    let mut clens = [0u8; 19];
+    for &i in &CLEN_ORDER[..ncode] { clens[i] = r.bits(3)? as u8; }
+    let clen_code = Huffman::new(&clens);
+    let mut lengths = Vec::with_capacity(nlen + ndist);
+    while lengths.len() < nlen + ndist {
+        let (value, repeat) = match clen_code.decode(r)? {
+            sym @ 0..=15 => (sym as u8, 1),
+            16 => (*lengths.last().ok_or("repeat with no previous length")?, 3 + r.bits(2)?),
+            17 => (0, 3 + r.bits(3)?),
+            _ => (0, 11 + r.bits(7)?),
+        };
+        if lengths.len() + repeat as usize > nlen + ndist { return Err("too many code lengths".to_string()); }
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths[256] == 0 { return Err("no end-of-block code".to_string()); }
+    Ok((Huffman::new(&lengths[..nlen]), Huffman::new(&lengths[nlen..])))
+}
+
+/// Append the contents of a DEFLATE stream to `out`; returns the bytes consumed
+fn inflate_into(data: &[u8], out: &mut Vec<u8>, max_output: usize) -> Result<usize, String> {
+    let mut r = BitReader { data, pos: 0, bit: 0 };
+    let start = out.len();
+    loop {
+        let last = r.bit()? == 1;
+        match r.bits(2)? {
+            0 => {
+                r.align();
+                let head = data.get(r.pos..r.pos + 4).ok_or("truncated stored block")?;
+                let len = u16::from_le_bytes([head[0], head[1]]);
+                if len != !u16::from_le_bytes([head[2], head[3]]) { return Err("corrupt stored block".to_string()); }
+                let block = data.get(r.pos + 4..r.pos + 4 + len as usize).ok_or("truncated stored block")?;
+                out.extend_from_slice(block);
+                r.pos += 4 + len as usize;
+            }
+            kind @ (1 | 2) => {
+                let (lit, dist) = if kind == 1 { fixed_tables() } else { dynamic_tables(&mut r)? };
+                loop {
+                    // checked per symbol: one block can expand to gigabytes;
+                    // `out` holds the earlier members too, so this is the running total
+                    if out.len() > max_output { return Err("decompressed data too large".to_string()); }
+                    let sym = lit.decode(&mut r)? as usize;
+                    if sym < 256 { out.push(sym as u8); continue; }
+                    if sym == 256 { break; }
+                    let k = sym - 257;
+                    if k >= LEN_BASE.len() { return Err("bad length code".to_string()); }
+                    let len = LEN_BASE[k] as usize + r.bits(LEN_EXTRA[k] as u32)? as usize;
+                    let d = dist.decode(&mut r)? as usize;
+                    if d >= DIST_BASE.len() { return Err("bad distance code".to_string()); }
+                    let back = DIST_BASE[d] as usize + r.bits(DIST_EXTRA[d] as u32)? as usize;
+                    if back > out.len() - start { return Err("distance before the start of the data".to_string()); }
+                    // byte by byte: the match may overlap what it copies
+                    for _ in 0..len { out.push(out[out.len() - back]); }
+                }
+            }
+            _ => return Err("invalid block type".to_string()),
+        }
+        if out.len() > max_output { return Err("decompressed data too large".to_string()); }
+        if last { break; }
+    }
+    r.align();
+    Ok(r.pos)
+}
@@ -0,0 +1,74 @@
+//! Host objects: Rust values an embedder hands to scripts as `Value::Host`,
+//! such as a database handle or a game entity, instead of encoding them as
+//! ints into a global registry. Scripts only see methods and fields; calls
+//! are routed back to the object's `HostApi`.
+//!
+//! An embedder implements `HostApi` for its type, wraps an instance with
+//! `Value::host` and passes it in with `VM::set_global` or as a builtin's
+//! result; `Value::with_host` gets the Rust object back.
+//!
+//! A snapshot cannot hold the Rust object, so it stores the `describe` text
+//! instead. Two host values are equal only when they are the same object.
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::builtins::VmError;
+use crate::vm::{Value, VM};
+
+pub trait HostApi: Any {
+    /// Shown by `type_name`, in messages and as the annotation that matches it
+    fn type_name(&self) -> &str;
+
+    /// `obj.method(args)` from a script
+    fn call(&mut self, vm: &mut VM, method: &str, args: &[Value]) -> Result<Value, VmError>;
+
+    /// `obj.field` from a script; `None` makes the access fail
+    fn field(&self, _name: &str) -> Option<Value> {
+        None
+    }
+
+    /// Text used when the value is printed
+    fn describe(&self) -> String {
+        format!("<{}>", self.type_name())
+    }
+}
+
+/// Error for a method the object does not have
+pub fn no_method(obj: &dyn HostApi, method: &str) -> VmError {
+    format!("{} has no method {}", obj.type_name(), method)
+}
+
+impl Value {
+    pub fn host(obj: impl HostApi) -> Value {
+        Value::Host(Rc::new(RefCell::new(Box::new(obj))))
+    }
+
+    /// Run `f` on the Rust object behind a host value of type `T`; `None`
+    /// for other values, other types, or while a method of it is running
+    pub fn with_host<T: HostApi, R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let Value::Host(h) = self else { return None };
+        let mut obj = h.try_borrow_mut().ok()?;
+        let any: &mut dyn Any = &mut **obj;
+        any.downcast_mut::<T>().map(f)
+    }
+}
+
+/// `receiver.method(args)` on a host value. The object stays borrowed during
+/// the call, so a method that reaches the same object again gets an error
+pub(crate) fn call_method(vm: &mut VM, h: &Rc<RefCell<Box<dyn HostApi>>>, method: &str, args: &[Value]) -> Result<Value, VmError> {
+    let mut obj = h.try_borrow_mut().map_err(|_| format!("{}: the host object is already in use by a running method", method))?;
+    obj.call(vm, method, args)
+}
+
+/// `receiver.field` on a host value
+pub(crate) fn field(h: &Rc<RefCell<Box<dyn HostApi>>>, name: &str) -> Result<Value, VmError> {
+    let obj = h.try_borrow().map_err(|_| format!("{}: the host object is in use by a running method", name))?;
+    obj.field(name).ok_or_else(|| format!("{} has no field {}", obj.type_name(), name))
+}
+
+impl std::fmt::Debug for dyn HostApi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.describe())
+    }
+}
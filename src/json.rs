@@ -0,0 +1,87 @@
+//! JSON text for values (`json_stringify`, the web server's responses) and
+//! for call traces.
+//! Canonical output sorts object keys and always writes floats with a
+//! fractional part, so equal values give byte-identical text.
+use std::rc::Rc;
+
+use crate::builtins::VmError;
+use crate::vm::Value;
+
+pub fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\r', "\\r").replace('\t', "\\t")
+}
+
+/// JSON number for a float; NaN and infinities have none and become `null`
+pub fn float(f: f64, canonical: bool) -> String {
+    if !f.is_finite() { return "null".to_string(); }
+    // `{}` is already the shortest text that reads back as the same float
+    let s = f.to_string();
+    if canonical && !s.contains(['.', 'e']) { format!("{}.0", s) } else { s }
+}
+
+/// Object fields in output order
+pub fn field_order(fields: &crate::vm::Fields, canonical: bool) -> Vec<(&String, &Value)> {
+    let mut entries: Vec<(&String, &Value)> = fields.iter().collect();
+    if canonical { entries.sort_by(|a, b| a.0.cmp(b.0)); }
+    entries
+}
+
+/// Plain JSON: numbers, strings, arrays for lists, objects for their fields
+pub fn stringify(v: &Value, canonical: bool) -> Result<String, VmError> {
+    let mut out = String::new();
+    write_value(&mut out, v, canonical, &mut Vec::new())?;
+    Ok(out)
+}
+
+/// Short view of a value for debuggers: its JSON, or `<type>` when it has none
+pub fn inspect(v: &Value) -> String {
+    stringify(v, false).unwrap_or_else(|_| format!("<{}>", v.type_name()))
+}
+
+/// `open` holds the objects and lists being written, to stop on cycles
+fn write_value(out: &mut String, v: &Value, canonical: bool, open: &mut Vec<*const ()>) -> Result<(), VmError> {
+    match v {
+        Value::Int(n) => out.push_str(&n.to_string()),
+        Value::Float(f) => out.push_str(&float(*f, canonical)),
+        Value::Str(s) => { out.push('"'); out.push_str(&escape(s)); out.push('"'); }
+        Value::List(items) => {
+            let ptr = Rc::as_ptr(items) as *const ();
+            if open.contains(&ptr) { return Err("json_stringify: cyclic value".to_string()); }
+            open.push(ptr);
+            out.push('[');
+            for (i, item) in items.borrow().iter().enumerate() {
+                if i > 0 { out.push(','); }
+                write_value(out, item, canonical, open)?;
+            }
+            out.push(']');
+            open.pop();
+        }
+        Value::Object(o) => {
+            let ptr = Rc::as_ptr(o) as *const ();
+            if open.contains(&ptr) { return Err("json_stringify: cyclic value".to_string()); }
+            open.push(ptr);
+            out.push('{');
+            let o = o.borrow();
+            for (i, (k, val)) in field_order(&o.fields, canonical).into_iter().enumerate() {
+                if i > 0 { out.push(','); }
+                out.push('"');
+                out.push_str(&escape(k));
+                out.push_str("\":");
+                write_value(out, val, canonical, open)?;
+            }
+            out.push('}');
+            open.pop();
+        }
+        other => return Err(format!("json_stringify: cannot encode {}", other.type_name())),
+    }
+    Ok(())
+}
+
+/// Call spans in the Chrome tracing format, for chrome://tracing or Perfetto
+pub fn chrome_trace(spans: &[crate::vm::CallSpan]) -> String {
+    let events: Vec<String> = spans.iter().map(|s| format!(
+        "{{\"name\":\"{}\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":1,\"args\":{{\"depth\":{}}}}}",
+        escape(&s.name), s.start.as_micros(), s.duration.as_micros(), s.depth,
+    )).collect();
+    format!("{{\"traceEvents\":[{}],\"displayTimeUnit\":\"ms\"}}", events.join(","))
+}
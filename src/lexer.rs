@@ -1,13 +1,44 @@
-use crate::token::Token;
+use crate::token::{Span, Token};
 
 pub struct Lexer {
     input: Vec<char>,
     pos: usize,
+    /// 1-based line of `pos`; every newline is consumed through `next_char`
+    line: usize,
+    /// Offset of the first char of `line`
+    line_start: usize,
+    /// Where the token returned last starts
+    start: usize,
+    start_line: usize,
+    start_col: usize,
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Self {
-        Self { input: input.chars().collect(), pos: 0 }
+        // a `#!/usr/bin/env userd` line is for the shell; its newline is kept
+        // so the lines after it keep their numbers
+        let pos = if input.starts_with("#!") { input.find('\n').map_or(input.len(), |i| input[..i].chars().count()) } else { 0 };
+        Self { input: input.chars().collect(), pos, line: 1, line_start: 0, start: 0, start_line: 1, start_col: 1 }
+    }
+
+    /// Every token of `input` with its position, ending with `Eof`
+    pub fn tokens(input: &str) -> Vec<(Token, Span)> {
+        let mut l = Lexer::new(input);
+        let mut tokens = Vec::new();
+        loop {
+            let t = l.next_token();
+            let eof = t.is_eof();
+            tokens.push((t, l.span()));
+            if eof { return tokens; }
+        }
+    }
+
+    /// Line of the token returned last
+    pub fn line(&self) -> usize { self.line }
+
+    /// Position of the token returned last
+    pub fn span(&self) -> Span {
+        Span { line: self.start_line, col: self.start_col, start: self.start, end: self.pos }
     }
 
     fn peek(&self) -> Option<char> {
@@ -17,12 +48,13 @@ impl Lexer {
     fn next_char(&mut self) -> Option<char> {
         let ch = self.peek();
         if ch.is_some() { self.pos += 1; }
+        if ch == Some('\n') { self.line += 1; self.line_start = self.pos; }
         ch
     }
 
     fn skip_whitespace(&mut self) {
         while let Some(c) = self.peek() {
-            if c.is_whitespace() { self.pos += 1; } else { break; }
+            if c.is_whitespace() { self.next_char(); } else { break; }
         }
     }
 
@@ -60,25 +92,30 @@ impl Lexer {
 
     pub fn next_token(&mut self) -> Token {
         self.skip_whitespace();
+        (self.start, self.start_line, self.start_col) = (self.pos, self.line, self.pos - self.line_start + 1);
         if let Some(ch) = self.next_char() {
             match ch {
                 '+' => Token::Plus,
-                '-' => Token::Minus,
+                '-' => {
+                    // `->` introduces a return type annotation
+                    if let Some('>') = self.peek() {
+                        self.pos += 1;
+                        Token::Arrow
+                    } else { Token::Minus }
+                }
                 '*' => Token::Asterisk,
                 '/' => {
                     // support single-line comments starting with '//'
-                    if let Some(next) = self.peek() {
-                        if next == '/' {
+                    if let Some(next) = self.peek()
+                        && next == '/' {
                             // consume the second '/'
                             self.pos += 1;
                             // skip until end of line or EOF
-                            while let Some(c) = self.peek() {
-                                self.pos += 1;
+                            while let Some(c) = self.next_char() {
                                 if c == '\n' { break; }
                             }
                             // after skipping comment, fetch next token
                             return self.next_token();
-                        }
                     }
                     Token::Slash
                 }
@@ -102,8 +139,10 @@ impl Lexer {
                         let val = num.parse::<f64>().unwrap_or(0.0);
                         Token::Float(val)
                     } else {
-                        let val = num.parse::<i64>().unwrap_or(0);
-                        Token::Int(val)
+                        match num.parse::<i64>() {
+                            Ok(val) => Token::Int(val),
+                            Err(_) => Token::BigInt(num),
+                        }
                     }
                 }
                 c if c.is_alphabetic() || c == '_' => {
@@ -3,17 +3,75 @@
 //! Этот crate содержит базовые модули: токены, AST, лексер, парсер, VM, REPL и CLI.
 #![allow(dead_code)]
 
+// The supported API; `prelude` re-exports what embedders need
+pub mod parser;
+pub mod error;
+pub mod vm;
+pub mod host;
+pub mod group;
+pub mod builtins;
+pub mod usage;
+pub mod prelude;
+
+// Internals of the interpreter and the `userd` tools, with no stability promise
+#[doc(hidden)]
 pub mod token;
+#[doc(hidden)]
 pub mod ast;
+#[doc(hidden)]
 pub mod lexer;
-pub mod parser;
-pub mod vm;
+pub(crate) mod macros;
+pub(crate) mod resolver;
+#[doc(hidden)]
+pub mod typeck;
+#[doc(hidden)]
+pub mod lint;
+#[doc(hidden)]
+pub mod artifact;
+#[doc(hidden)]
+pub mod snapshot;
+#[doc(hidden)]
+pub mod session;
+#[doc(hidden)]
+pub mod recorder;
+#[doc(hidden)]
+pub mod json;
+#[doc(hidden)]
+pub mod hash;
+#[doc(hidden)]
+pub mod crypto;
+#[doc(hidden)]
+pub mod base64;
+#[doc(hidden)]
+pub mod diff;
+#[doc(hidden)]
+pub mod gzip;
+#[doc(hidden)]
+pub mod diagnostics;
+#[doc(hidden)]
+pub mod transpile;
+#[doc(hidden)]
 pub mod repl;
+#[doc(hidden)]
 pub mod cli;
+#[doc(hidden)]
 pub mod web_server;
+#[doc(hidden)]
 pub mod gui;
-pub mod platform;
+#[doc(hidden)]
+pub mod color;
+#[doc(hidden)]
+pub mod raster;
+pub(crate) mod platform;
+#[doc(hidden)]
 pub mod rand;
+pub(crate) mod task;
+pub(crate) mod timer;
+pub(crate) mod tween;
+#[doc(hidden)]
+pub mod utils;
+
+pub use error::Error;
 
 #[cfg(test)]
 mod tests {
@@ -21,10 +79,13 @@ mod tests {
     use crate::parser::Parser;
     use crate::vm::VM;
 
+    /// Held by tests that deliver GUI events, which go through process-wide queues
+    static GUI_EVENTS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
     #[test]
     fn lexer_eof() {
         let mut l = Lexer::new("");
-        assert_eq!(l.next_token().is_eof(), true);
+        assert!(l.next_token().is_eof());
     }
 
     #[test]
@@ -68,6 +129,2279 @@ mod tests {
         } else { panic!("p is not object") }
     }
 
+    #[test]
+    fn constructor_field_shorthand() {
+        use crate::vm::Value;
+        let src = r#"
+        class Point { rtd __init__(self, self.x, self.y) { } }
+        class Size(w, h) { }
+        Point-p = Point(1,2);
+        Size-s = Size(3,4);
+        "#;
+        let mut pr = Parser::new(src);
+        let prog = pr.parse_program();
+        let mut vm = VM::new();
+        vm.execute_program(prog).unwrap();
+        let field = |name: &str, f: &str| match vm.get_global(name) {
+            Some(Value::Object(o)) => match o.borrow().fields.get(f) { Some(Value::Int(n)) => *n, _ => panic!("{}.{} missing", name, f) },
+            _ => panic!("{} is not object", name),
+        };
+        assert_eq!(field("p", "x"), 1);
+        assert_eq!(field("p", "y"), 2);
+        assert_eq!(field("s", "w"), 3);
+        assert_eq!(field("s", "h"), 4);
+    }
+
+    #[test]
+    fn data_class_generated_methods() {
+        use crate::vm::Value;
+        let src = r#"
+        data class Point(x, y);
+        Point-a = Point(1, 2);
+        Point-b = Point(1, 2);
+        Point-c = a.copy_with("y", 5);
+        int-same = a.__eq__(b);
+        int-diff = a.__eq__(c);
+        "#;
+        let mut pr = Parser::new(src);
+        let prog = pr.parse_program();
+        let mut vm = VM::new();
+        vm.execute_program(prog).unwrap();
+        assert!(matches!(vm.get_global("same"), Some(Value::Int(1))));
+        assert!(matches!(vm.get_global("diff"), Some(Value::Int(0))));
+        let c = vm.get_global("c").unwrap();
+        assert_eq!(vm.value_to_string(&c).unwrap(), "Point(x=1, y=5)");
+    }
+
+    #[test]
+    fn instances_share_class_methods() {
+        use crate::vm::Value;
+        use std::rc::Rc;
+        let src = r#"
+        class Counter(n) { rtd get(self) { self.n; } }
+        Counter-a = Counter(1);
+        Counter-b = Counter(2);
+        "#;
+        let mut pr = Parser::new(src);
+        let mut vm = VM::new();
+        vm.execute_program(pr.parse_program()).unwrap();
+        let (Some(Value::Object(a)), Some(Value::Object(b))) = (vm.get_global("a"), vm.get_global("b")) else { panic!("objects missing") };
+        let (ca, cb) = (a.borrow().class.clone().unwrap(), b.borrow().class.clone().unwrap());
+        assert!(Rc::ptr_eq(&ca, &cb));
+        assert!(a.borrow().methods.is_empty());
+        assert!(a.borrow().find_method("get").is_some());
+    }
+
+    #[test]
+    fn overloads_dispatch_by_arity() {
+        use crate::vm::Value;
+        let src = r#"
+        rtd area(s) { s * s; }
+        rtd area(w, h) { w * h; }
+        int-sq = area(3);
+        int-rect = area(2, 5);
+        "#;
+        let mut pr = Parser::new(src);
+        let mut vm = VM::new();
+        vm.execute_program(pr.parse_program()).unwrap();
+        assert!(matches!(vm.get_global("sq"), Some(Value::Int(9))));
+        assert!(matches!(vm.get_global("rect"), Some(Value::Int(10))));
+        let mut pr = Parser::new("area(1, 2, 3);");
+        assert!(vm.execute_program(pr.parse_program()).is_err());
+        // builtins that take a function by name pick the overload they call
+        let src = r#"
+        rtd pick(a) { a; }
+        rtd pick() { int-ran = 1; 7; }
+        int-j = join(spawn("pick"));
+        task_spawn("pick");
+        run_tasks();
+        rtd conv(s) { 42; }
+        rtd conv(s, base) { 0; }
+        override_builtin("to_int", "conv");
+        int-c = to_int("5");
+        "#;
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        for (name, want) in [("j", 7), ("ran", 1), ("c", 42)] {
+            assert!(matches!(vm.get_global(name), Some(Value::Int(v)) if v == want), "{}", name);
+        }
+        let err = vm.execute_program(Parser::new(r#"task_spawn("area");"#).parse_program()).unwrap_err();
+        assert!(err.contains("no overload of area takes no parameters"), "{}", err);
+        let err = vm.execute_program(Parser::new("class C { rtd m(self) { 1; } rtd m(self, x) { x; } }").parse_program()).unwrap_err();
+        assert!(err.contains("methods cannot be overloaded"), "{}", err);
+    }
+
+    #[test]
+    fn overload_arity_mismatch_and_redefinition() {
+        use crate::vm::Value;
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        let src = r#"
+        rtd f(a) { 1; }
+        rtd f(a, b) { 2; }
+        "#;
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        for (src, want) in [
+            ("f();", "no overload of f takes 0 arguments"),
+            ("f(1, 2, 3);", "no overload of f takes 3 arguments"),
+            (r#"spawn_with("f", list(1, 2, 3));"#, "spawn_with: no overload of f takes 3 argument(s)"),
+            (r#"override_builtin("to_float", "f");"#, ""),
+            (r#"override_builtin("time_now", "f");"#, "override_builtin: time_now takes 0 argument(s) but no overload of f does"),
+        ] {
+            let res = vm.execute_program(Parser::new(src).parse_program());
+            if want.is_empty() {
+                assert!(res.is_ok(), "{}: {:?}", src, res);
+            } else {
+                let err = res.unwrap_err();
+                assert!(err.contains(want), "{}: {}", src, err);
+            }
+        }
+        // redefining one member replaces only that member of the set
+        let src = r#"
+        rtd f(a, b) { 20; }
+        int-one = f(0);
+        int-two = f(0, 0);
+        "#;
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        assert!(matches!(vm.get_global("one"), Some(Value::Int(1))));
+        assert!(matches!(vm.get_global("two"), Some(Value::Int(20))));
+        assert!(matches!(vm.get_global("f"), Some(Value::Overloaded(set)) if set.len() == 2));
+    }
+
+    #[test]
+    fn params_resolve_to_slots() {
+        use crate::vm::Value;
+        let src = r#"
+        int-k = 10;
+        rtd twice(x) { x * 2; }
+        rtd apply(f, x) { f(x) + k; }
+        int-r = apply(twice, 4);
+        "#;
+        let mut pr = Parser::new(src);
+        let mut vm = VM::new();
+        vm.execute_program(pr.parse_program()).unwrap();
+        assert!(matches!(vm.get_global("r"), Some(Value::Int(18))));
+    }
+
+    #[test]
+    fn type_check_reports_mismatches() {
+        let src = r#"
+        class Point(x, y) { }
+        int-a = 1 + 2;
+        float-f = a * 2.5;
+        str-s = 3;
+        Point-p = Point(1, 2);
+        int-q = Point(1, 2);
+        int-g = get("n") * 2;
+        "#;
+        let mut pr = Parser::new(src);
+        let errors = crate::typeck::check_program(&pr.parse_program());
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors[0].contains("str-s"));
+        assert!(errors[1].contains("int-q"));
+        assert!(errors[2].contains("unsupported operands"));
+    }
+
+    #[test]
+    fn annotated_functions_check_types() {
+        use crate::vm::Value;
+        let src = r#"
+        rtd add(int-a, int-b) -> int { a + b; }
+        rtd half(float-x) -> float { x / 2; }
+        int-s = add(2, 3);
+        float-h = half(3);
+        "#;
+        let mut pr = Parser::new(src);
+        let prog = pr.parse_program();
+        assert!(crate::typeck::check_program(&prog).is_empty());
+        let mut vm = VM::new();
+        vm.execute_program(prog).unwrap();
+        assert!(matches!(vm.get_global("s"), Some(Value::Int(5))));
+        let mut pr = Parser::new(r#"str-t = help(add);"#);
+        vm.execute_program(pr.parse_program()).unwrap();
+        assert!(matches!(vm.get_global("t"), Some(Value::Str(s)) if s == "add(int-a, int-b) -> int"));
+
+        let mut pr = Parser::new(r#"add("x", 1);"#);
+        let err = vm.execute_program(pr.parse_program()).unwrap_err();
+        assert!(err.contains("param a expects int, got str"), "{}", err);
+
+        let mut pr = Parser::new(r#"rtd bad(int-n) -> str { n; } bad(1);"#);
+        let prog = pr.parse_program();
+        assert_eq!(crate::typeck::check_program(&prog).len(), 1);
+        assert!(vm.execute_program(prog).unwrap_err().contains("expected return of str"));
+    }
+
+    #[test]
+    fn errors_carry_stack_trace() {
+        let src = "rtd inner(a) { a + missing; }\nrtd middle(a) { inner(a); }\nrtd outer() {\n  middle(1);\n}\nouter();\n";
+        let mut pr = Parser::new(src);
+        let mut vm = VM::new();
+        let err = vm.execute_program(pr.parse_program()).unwrap_err();
+        assert_eq!(err, "undefined: missing\n  in inner, called at line 2\n  in middle, called at line 4\n  in outer, called at line 6");
+    }
+
+    #[test]
+    fn artifact_roundtrip() {
+        use crate::vm::Value;
+        let src = "os: linux, windows\ndata class P(x, y);\nrtd f(int-a) -> float { a * 1.5; }\nP-p = P(1, \"s\");\nfloat-r = f(p.x + 3);\n";
+        let bytes = crate::artifact::compile(src);
+        assert!(!bytes.windows(3).any(|w| w == b"rtd"));
+        let art = crate::artifact::load(&bytes).unwrap();
+        assert_eq!(art.meta, vec!["os: linux, windows".to_string(), format!("compiler: userd {}", env!("CARGO_PKG_VERSION"))]);
+        let mut vm = VM::new();
+        vm.execute_program(art.program).unwrap();
+        assert!(matches!(vm.get_global("r"), Some(Value::Float(f)) if f == 6.0));
+        let mut bad = bytes.clone();
+        let last = bad.len() - 9;
+        bad[last] ^= 1;
+        assert!(crate::artifact::load(&bad).is_err());
+        // version 1 stored no statement lines: `int-x = 7;`
+        let mut payload = crate::artifact::Writer::default();
+        payload.u32(1);
+        payload.u8(0); payload.str("int"); payload.str("x"); payload.u8(0); payload.u64(7);
+        let mut v1 = crate::artifact::Writer::default();
+        v1.buf.extend_from_slice(b"USRDC\x01\x00");
+        v1.u32(0);
+        v1.u64(payload.buf.len() as u64);
+        v1.buf.extend_from_slice(&payload.buf);
+        v1.u64(crate::artifact::fnv1a(&payload.buf));
+        let art = crate::artifact::load(&v1.buf).unwrap();
+        assert!(matches!(&art.program[..], [crate::ast::Stmt::VarDecl { line: 0, .. }]));
+        vm.execute_program(art.program).unwrap();
+        assert!(matches!(vm.get_global("x"), Some(Value::Int(7))));
+    }
+
+    #[test]
+    fn limits_stop_runaway_programs() {
+        let src = "rtd spin(n) { n + 1; spin(n + 1); } spin(0);";
+        let mut vm = VM::with_limits(50, 0);
+        let err = vm.execute_program(Parser::new(src).parse_program()).unwrap_err();
+        assert!(err.starts_with("step limit of 50 exceeded"), "{}", err);
+        let mut vm = VM::with_limits(0, 0);
+        let err = vm.execute_program(Parser::new(src).parse_program()).unwrap_err();
+        assert!(err.starts_with("call depth limit of 100 exceeded"), "{}", err);
+        vm.set_call_depth_limit(10);
+        let err = vm.execute_program(Parser::new(src).parse_program()).unwrap_err();
+        assert!(err.starts_with("call depth limit of 10 exceeded"), "{}", err);
+        // without limits only the native stack bounds recursion: a chain of
+        // 1000 calls runs on a thread with room for it (debug builds use
+        // about 14 KiB of stack per call)
+        let chain: String = (0..1000).map(|i| format!("rtd f{}() {{ f{}(); }}\n", i, i + 1)).collect();
+        let src = format!("{}rtd f1000() {{ 7; }}\nint-end = f0();", chain);
+        let end = std::thread::Builder::new().stack_size(64 << 20).spawn(move || {
+            let mut vm = VM::new();
+            vm.execute_program(Parser::new(&src).parse_program()).map(|_| vm.get_global("end").and_then(|v| i64::try_from(v).ok()))
+        }).unwrap().join().unwrap();
+        assert_eq!(end, Ok(Some(7)));
+        // the budget is per program
+        let mut vm = VM::with_limits(3, 0);
+        for _ in 0..3 {
+            vm.execute_program(Parser::new("int-a = 1; int-b = 2;").parse_program()).unwrap();
+        }
+    }
+
+    #[test]
+    fn transpile_emits_native_functions() {
+        let src = "rtd sq(int-n) -> int { n * n; }\nrtd sq2(n) { n; }\nrtd sq2(a, b) { a; }\nint-a = sq(3);\nsq2(a);\n";
+        let code = crate::transpile::transpile(src, "t.usrd");
+        assert!(code.contains("fn u_sq(vm: &mut VM, l_n: Value)"));
+        assert!(code.contains("u_sq(vm, a1)?"));
+        // overloads stay dynamic
+        assert!(!code.contains("fn u_sq2"));
+        assert!(code.contains("vm.call(\"sq2\", vec![a1])?"));
+        assert!(code.contains("fn main()"));
+    }
+
+    #[test]
+    fn sandbox_policy_blocks_capabilities() {
+        use crate::vm::{Policy, Value};
+        let mut vm = VM::new();
+        vm.set_policy(Policy::sandboxed());
+        let err = vm.execute_program(Parser::new("int-w = gui_window(\"t\", 10, 10);").parse_program()).unwrap_err();
+        assert!(err.contains("gui_window is not allowed"), "{}", err);
+        assert!(vm.execute_program(Parser::new("str-s = get(\"> \");").parse_program()).is_err());
+        // pure builtins keep working
+        vm.execute_program(Parser::new("int-n = to_int(\"42\");").parse_program()).unwrap();
+        assert!(matches!(vm.get_global("n"), Some(Value::Int(42))));
+    }
+
+    #[test]
+    fn sandbox_denials_report_the_policy() {
+        use crate::vm::Policy;
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.set_policy(Policy::sandboxed());
+        for (src, want) in [
+            (r#"list_dir(".");"#, "list_dir is not allowed by the sandbox policy (FileIo)"),
+            (r#"env_get("HOME");"#, "env_get is not allowed by the sandbox policy (Process)"),
+            (r#"ws_connect("ws://localhost:1");"#, "ws_connect is not allowed by the sandbox policy (Network)"),
+            (r#"get("> ");"#, "get is not allowed by the sandbox policy (Stdin)"),
+            (r#"gui_window("t", 10, 10);"#, "gui_window is not allowed by the sandbox policy (Gui)"),
+            // an override does not open a way around the policy to the original
+            (r#"rtd fake(p) { list(); } override_builtin("list_dir", "fake"); original_builtin("list_dir", list("."));"#,
+                "list_dir is not allowed by the sandbox policy (FileIo)"),
+        ] {
+            let err = vm.execute_program(Parser::new(src).parse_program()).unwrap_err();
+            assert!(err.contains(want), "{}: {}", src, err);
+        }
+    }
+
+    #[test]
+    fn member_call_cache_follows_class() {
+        use crate::vm::Value;
+        let src = r#"
+        class A(n) { rtd get(self) { self.n; } }
+        class B(n) { rtd get(self) { self.n * 10; } }
+        rtd read(o) { o.get(); }
+        int-a = read(A(1));
+        int-b = read(B(2));
+        int-c = read(A(3));
+        "#;
+        let mut vm = VM::new();
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        assert!(matches!(vm.get_global("b"), Some(Value::Int(20))));
+        assert!(matches!(vm.get_global("c"), Some(Value::Int(3))));
+        // a redeclared class gets a new identity, so the same site re-resolves
+        vm.execute_program(Parser::new("class A(n) { rtd get(self) { 0 - self.n; } } int-d = read(A(4));").parse_program()).unwrap();
+        assert!(matches!(vm.get_global("d"), Some(Value::Int(-4))));
+    }
+
+    #[test]
+    fn output_goes_to_sink() {
+        use crate::vm::OutputBuffer;
+        let out = OutputBuffer::new();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(out.clone()));
+        vm.execute_program(Parser::new("int-a = 2; a * 3; \"hi\";").parse_program()).unwrap();
+        assert_eq!(out.text(), "6\nhi\n");
+    }
+
+    #[test]
+    fn host_builtins_are_callable() {
+        use crate::vm::Value;
+        let mut vm = VM::new();
+        vm.register_builtin("twice", 1, |_vm, args| match &args[0] {
+            Value::Int(n) => Ok(Value::Int(n * 2)),
+            _ => Err("twice: expects int".to_string()),
+        });
+        vm.execute_program(Parser::new("int-a = twice(to_int(\"21\"));").parse_program()).unwrap();
+        assert!(matches!(vm.get_global("a"), Some(Value::Int(42))));
+        let err = vm.execute_program(Parser::new("twice(1, 2);").parse_program()).unwrap_err();
+        assert_eq!(err, "twice requires 1 argument");
+    }
+
+    #[test]
+    fn value_conversions() {
+        use crate::builtins::from_args;
+        use crate::vm::Value;
+        let list = Value::from(vec![Value::from(1), Value::from("a"), Value::from(2.5)]);
+        let mut vm = VM::new();
+        assert_eq!(vm.value_to_string(&list).unwrap(), "[1, \"a\", 2.5]");
+        assert_eq!(Vec::<Value>::try_from(list).unwrap().len(), 3);
+        assert_eq!(f64::try_from(Value::from(2)).unwrap(), 2.0);
+        assert_eq!(String::try_from(Value::from(1)).unwrap_err(), "expected str, got int");
+        let args = [Value::from(7), Value::from("x")];
+        let (n, s): (i64, String) = from_args("f", &args).unwrap();
+        assert_eq!((n, s.as_str()), (7, "x"));
+        let err = from_args::<(String, String)>("f", &args).unwrap_err();
+        assert_eq!(err, "f: argument 1: expected str, got int");
+    }
+
+    #[test]
+    fn parse_files_keeps_order() {
+        let dir = std::env::temp_dir().join(format!("userd_parse_files_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut paths = Vec::new();
+        for i in 0..6 {
+            let p = dir.join(format!("m{}.usrd", i));
+            // unparseable call arguments must not stall the parser
+            std::fs::write(&p, format!("int-v{} = {}; f(a == 1);", i, i)).unwrap();
+            paths.push(p.to_string_lossy().into_owned());
+        }
+        paths.push(dir.join("missing.usrd").to_string_lossy().into_owned());
+        let parsed = crate::parser::parse_files(&paths);
+        assert_eq!(parsed.len(), 7);
+        for (i, prog) in parsed[..6].iter().enumerate() {
+            let (prog, _) = prog.as_ref().unwrap();
+            assert!(matches!(&prog[0], crate::ast::Stmt::VarDecl { name, .. } if *name == format!("v{}", i)));
+        }
+        assert!(parsed[6].is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn intrinsics_fast_path() {
+        use crate::vm::Value;
+        let src = r#"
+        int-xs = list(3, 1);
+        push(xs, 4);
+        int-n = len(xs);
+        int-m = max(at(xs, 0), at(xs, 2));
+        float-r = sqrt(abs(0 - 16));
+        int-c = len(at("héllo", 1));
+        rtd use(len) { len(5); }
+        rtd sq(v) { v * v; }
+        int-shadow = use(sq);
+        "#;
+        let prog = crate::artifact::load(&crate::artifact::compile(src)).unwrap().program;
+        let mut vm = VM::new();
+        vm.execute_program(prog).unwrap();
+        for (name, want) in [("n", 3), ("m", 4), ("c", 1), ("shadow", 25)] {
+            assert!(matches!(vm.get_global(name), Some(Value::Int(v)) if v == want), "{}", name);
+        }
+        assert!(matches!(vm.get_global("r"), Some(Value::Float(f)) if f == 4.0));
+        let err = vm.execute_program(Parser::new("at(xs, 5);").parse_program()).unwrap_err();
+        assert!(err.starts_with("at: index 5 out of range (len 3)"), "{}", err);
+        let err = vm.execute_program(Parser::new("abs(0 - 9223372036854775807 - 1);").parse_program()).unwrap_err();
+        assert!(err.starts_with("abs: -9223372036854775808 has no int absolute value"), "{}", err);
+        // a user function with the same name replaces the intrinsic
+        vm.execute_program(Parser::new("rtd max(a, b) { a; } int-u = max(1, 2);").parse_program()).unwrap();
+        assert!(matches!(vm.get_global("u"), Some(Value::Int(1))));
+        // a host builtin with the same name replaces the intrinsic
+        vm.register_builtin("len", 1, |_vm, _args| Ok(Value::Int(-1)));
+        vm.execute_program(Parser::new("int-h = len(xs);").parse_program()).unwrap();
+        assert!(matches!(vm.get_global("h"), Some(Value::Int(-1))));
+    }
+
+    #[test]
+    fn intrinsics_are_registered_builtins() {
+        use crate::vm::Value;
+        let registry = crate::builtins::Registry::standard();
+        for op in crate::ast::Intrinsic::ALL {
+            assert!(registry.get(op.name()).is_some_and(|b| b.doc.is_some()), "{}", op.name());
+        }
+        let json = crate::web_server::builtins_json();
+        assert!(json.contains(r#"{"name":"len","signature":"len(x) -> int","#), "{}", json);
+        assert!(json.contains(r#""name":"list","signature":"list(values...) -> list","summary":"A new list holding the arguments","arity":null}"#), "{}", json);
+
+        let mut vm = VM::new();
+        let err = vm.execute_program(Parser::new("int-a = abs(-9223372036854775808);").parse_program()).unwrap_err();
+        assert!(err.starts_with("abs: -9223372036854775808 has no int absolute value"), "{}", err);
+        let mut parser = Parser::new("int-b = 9223372036854775808;");
+        parser.parse_program();
+        assert_eq!(parser.errors()[0].to_string(), "line 1: integer literal 9223372036854775808 does not fit in an int");
+        // through the registry (as a host's `call` does) the result is the same
+        assert_eq!(vm.call("abs", vec![Value::Int(i64::MIN)]).unwrap_err(), "abs: -9223372036854775808 has no int absolute value");
+        assert!(matches!(vm.call("abs", vec![Value::Int(-5)]), Ok(Value::Int(5))));
+
+        // a script-defined len wins over the intrinsic, in calls and for `call`
+        let src = "rtd len(x) { 42; } int-n = len(list(1, 2));";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        assert!(matches!(vm.get_global("n"), Some(Value::Int(42))));
+        assert!(matches!(vm.call("len", vec![Value::Int(0)]), Ok(Value::Int(42))));
+        // a variable of the same name does not stop list from being called
+        vm.execute_program(Parser::new("int-list = 3; int-k = len(list(1, 2, 3));").parse_program()).unwrap();
+        assert!(matches!(vm.get_global("k"), Some(Value::Int(42))));
+        let mut vm = VM::new();
+        vm.execute_program(Parser::new("int-list = 3; int-k = len(list(1, 2, 3));").parse_program()).unwrap();
+        assert!(matches!(vm.get_global("k"), Some(Value::Int(3))));
+    }
+
+    #[test]
+    fn snapshot_restores_session() {
+        use crate::vm::Value;
+        let src = r#"
+        data class P(x, y);
+        class Node(v) { rtd get(self) { self.v; } }
+        rtd area(s) { s * s; }
+        rtd area(w, h) { w * h; }
+        P-p = P(1, "a");
+        Node-n = Node(5);
+        n.me = n;
+        int-xs = list(p, p);
+        "#;
+        let mut vm = VM::new();
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let bytes = vm.snapshot();
+        assert_eq!(bytes, vm.snapshot());
+
+        let mut vm = VM::new();
+        vm.restore(&bytes).unwrap();
+        let check = "int-a = area(2, 3) + area(2); Node-m = n.me; int-b = m.get(); int-c = p.__eq__(at(xs, 1)); str-s = p.__str__();";
+        vm.execute_program(Parser::new(check).parse_program()).unwrap();
+        assert!(matches!(vm.get_global("a"), Some(Value::Int(10))));
+        assert!(matches!(vm.get_global("b"), Some(Value::Int(5))));
+        assert!(matches!(vm.get_global("c"), Some(Value::Int(1))));
+        assert!(matches!(vm.get_global("s"), Some(Value::Str(s)) if s == "P(x=1, y=\"a\")"));
+        // sharing survives: both list items are the same object
+        let Some(Value::List(xs)) = vm.get_global("xs") else { panic!("xs missing") };
+        let xs = xs.borrow();
+        let (Value::Object(a), Value::Object(b)) = (&xs[0], &xs[1]) else { panic!("not objects") };
+        assert!(std::rc::Rc::ptr_eq(a, b));
+        assert!(vm.restore(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn deterministic_runs_repeat() {
+        use crate::vm::{OutputBuffer, Value};
+        let src = r#"int-a = secure_random(1000); int-b = secure_random(1000); sleep_ms(250); str-name = get("who? "); name;"#;
+        let run = || {
+            let mut vm = VM::deterministic(7);
+            let out = OutputBuffer::new();
+            vm.set_output(Box::new(out.clone()));
+            vm.feed_stdin(["ann"]);
+            vm.execute_program(Parser::new(src).parse_program()).unwrap();
+            assert_eq!(vm.logical_time_ms(), Some(250));
+            let n = |g: &str| match vm.get_global(g) { Some(Value::Int(n)) => n, _ => panic!("{} missing", g) };
+            (n("a"), n("b"), out.text())
+        };
+        let first = run();
+        assert_eq!(first, run());
+        assert_eq!(first.2, "1\nwho? ann\n");
+        let mut vm = VM::deterministic(7);
+        assert!(vm.execute_program(Parser::new("get(\"x\");").parse_program()).unwrap_err().contains("scripted input exhausted"));
+    }
+
+    #[test]
+    fn fields_keep_insertion_order() {
+        use crate::vm::Value;
+        let src = "class Box { } Box-b = Box(); b.zeta = 1; b.alpha = 2; b.mid = 3; b.zeta = 4; int-z = 1; int-a = 2;";
+        let mut vm = VM::new();
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let bytes = vm.snapshot();
+        let mut vm = VM::new();
+        vm.restore(&bytes).unwrap();
+        let Some(Value::Object(b)) = vm.get_global("b") else { panic!("b missing") };
+        let names: Vec<String> = b.borrow().fields.keys().cloned().collect();
+        assert_eq!(names, ["zeta", "alpha", "mid"]);
+        assert!(matches!(b.borrow().fields.get("zeta"), Some(Value::Int(4))));
+        // same session, same bytes
+        assert_eq!(bytes, vm.snapshot());
+    }
+
+    #[test]
+    fn json_stringify_canonical() {
+        use crate::vm::Value;
+        let src = r#"class Box { } Box-b = Box(); b.zeta = 2.0; b.alpha = list(1, "q"); str-plain = json_stringify(b, 0); str-canon = json_stringify(b, 1);"#;
+        let mut vm = VM::new();
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        assert!(matches!(vm.get_global("plain"), Some(Value::Str(s)) if s == r#"{"zeta":2,"alpha":[1,"q"]}"#));
+        assert!(matches!(vm.get_global("canon"), Some(Value::Str(s)) if s == r#"{"alpha":[1,"q"],"zeta":2.0}"#));
+        vm.execute_program(Parser::new("b.me = b;").parse_program()).unwrap();
+        let err = vm.execute_program(Parser::new("json_stringify(b, 1);").parse_program()).unwrap_err();
+        assert!(err.contains("cyclic"));
+    }
+
+    #[test]
+    fn trace_hook_sees_each_statement() {
+        use crate::ast::Stmt;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        let src = "rtd sq(x) { x * x; } int-a = sq(3); int-b = a + 1;";
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        let mut vm = VM::new();
+        vm.set_trace_hook(move |stmt, vm| {
+            let what = match stmt {
+                Stmt::VarDecl { name, .. } => format!("decl {}", name),
+                Stmt::FunctionDecl { name, .. } => format!("rtd {}", name),
+                Stmt::ExprStmt { expr: _, .. } => "expr".to_string(),
+                _ => "other".to_string(),
+            };
+            log.borrow_mut().push(format!("{}@{}", what, vm.call_depth()));
+        });
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        assert_eq!(*seen.borrow(), ["rtd sq@0", "decl a@0", "expr@1", "decl b@0"]);
+        vm.clear_trace_hook();
+        vm.execute_program(Parser::new("int-c = 1;").parse_program()).unwrap();
+        assert_eq!(seen.borrow().len(), 4);
+    }
+
+    #[test]
+    fn debugger_breakpoints_and_stepping() {
+        use crate::vm::{DebugAction, Value};
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        let src = "rtd sq(x) {\n  x * x;\n}\nint-a = sq(3);\nint-b = a + 1;\nint-c = b + 1;\n";
+        let stops = Rc::new(RefCell::new(Vec::new()));
+        let log = stops.clone();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.set_file("main.usrd");
+        vm.set_breakpoint("main.usrd", 2);
+        vm.set_breakpoint("other.usrd", 6);
+        vm.set_debug_handler(move |pause, vm| {
+            let x = vm.locals(0).into_iter().find(|(n, _)| n == "x").map(|(_, v)| matches!(v, Value::Int(3)));
+            log.borrow_mut().push((pause.line, pause.breakpoint, vm.call_depth(), x));
+            // step once from the breakpoint, then run on
+            if pause.breakpoint { DebugAction::Step } else { DebugAction::Continue }
+        });
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        assert_eq!(*stops.borrow(), [(2, true, 1, Some(true)), (5, false, 0, None)]);
+    }
+
+    #[test]
+    fn large_values_are_truncated() {
+        use crate::vm::{DisplayLimits, OutputBuffer, Value};
+        let mut vm = VM::new();
+        let out = OutputBuffer::new();
+        vm.set_output(Box::new(out.clone()));
+        vm.set_display_limits(Some(DisplayLimits { max_items: 3, max_depth: 2 }));
+        let items: Vec<Value> = (1..=10).map(Value::from).collect();
+        vm.set_global("xs", Value::from(items));
+        vm.execute_program(Parser::new("xs; int-ys = list(1, 2); push(ys, ys); ys;").parse_program()).unwrap();
+        vm.set_display_limits(None);
+        // without limits a list that contains itself still ends
+        vm.execute_program(Parser::new("xs; ys; list(ys, list(ys));").parse_program()).unwrap();
+        assert_eq!(out.text(), "[1, 2, 3, ... (7 more)]\n3\n[1, 2, <cycle>]\n[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]\n[1, 2, <cycle>]\n[[1, 2, <cycle>], [[1, 2, <cycle>]]]\n");
+    }
+
+    #[test]
+    fn profiler_counts_calls() {
+        let src = "rtd sq(x) { x * x; } rtd twice(x) { sq(x) + sq(x); } class P { rtd get(self) { 1; } } P-p = P(); int-a = twice(2); int-b = sq(3); int-c = p.get(); int-d = to_int(\"4\");";
+        let mut vm = VM::new();
+        assert!(vm.profile_report().is_empty());
+        vm.enable_profiling();
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let report = vm.profile_report();
+        let calls = |name: &str| report.iter().find(|e| e.name == name).map(|e| e.calls);
+        assert_eq!(calls("sq"), Some(3));
+        assert_eq!(calls("twice"), Some(1));
+        assert_eq!(calls("P"), Some(1));
+        assert_eq!(calls("P.get"), Some(1));
+        assert_eq!(calls("to_int"), Some(1));
+        // slowest first
+        assert!(report.windows(2).all(|w| w[0].total >= w[1].total));
+    }
+
+    #[test]
+    fn web_debug_self_referencing_object() {
+        use crate::web_server::{run_code, ServerConfig};
+        let res = run_code("class Obj { } rtd look(o) {\n  o;\n}\nObj-o = Obj(); o.me = o; look(o);\n", ServerConfig::default(), Some(vec![2]), None);
+        let cyclic = r#"{"type":"object","class":"Obj","fields":{"me":{"type":"cycle"}}}"#;
+        assert!(res.contains(&format!(r#""locals":{{"o":{}}}"#, cyclic)), "{}", res);
+        assert!(res.contains(&format!(r#""result":{}"#, cyclic)), "{}", res);
+    }
+
+    #[test]
+    fn web_inspect_one_level() {
+        use crate::web_server::{inspect, run_code, ServerConfig};
+        let res = run_code("class Node { } Node-n = Node(); n.v = 5; n.kids = list(Node(), \"x\");", ServerConfig::default(), None, None);
+        let id = res.split("\"session\":\"").nth(1).and_then(|r| r.split('"').next()).expect("session id");
+        let root = inspect(id, "");
+        assert!(root.contains(r#""n":{"type":"object","class":"Node","size":2,"expandable":true}"#), "{}", root);
+        assert_eq!(inspect(id, "n"), r#"{"ok":true,"value":{"type":"object","class":"Node","fields":{"v":{"type":"int","value":5},"kids":{"type":"list","size":2,"expandable":true}}}}"#);
+        assert_eq!(inspect(id, "n.kids.1"), r#"{"ok":true,"value":{"type":"str","value":"x"}}"#);
+        assert!(inspect(id, "n.nope").contains("\"ok\":false"));
+        assert!(inspect("1", "").contains("unknown session"));
+    }
+
+    #[test]
+    fn recorder_steps_back_in_time() {
+        use crate::recorder::{Recorder, Trace};
+        use crate::vm::Value;
+        let src = "int-a = 1;\nint-a = a + 1;\nint-b = 0;\nint-a = a * 10;\n";
+        let mut vm = VM::new();
+        let recorder = Recorder::start(&mut vm);
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let trace = recorder.finish(&mut vm);
+        let lines: Vec<usize> = trace.steps.iter().map(|s| s.line).collect();
+        assert_eq!(lines, [1, 2, 3, 4, 0]);
+        let trace = Trace::decode(&trace.encode()).unwrap();
+        let mut past = VM::new();
+        let a_at = |past: &mut VM, i: usize| { trace.restore(i, past).unwrap(); past.get_global("a") };
+        assert!(a_at(&mut past, 0).is_none());
+        assert!(matches!(a_at(&mut past, 2), Some(Value::Int(2))));
+        assert!(matches!(a_at(&mut past, 4), Some(Value::Int(20))));
+        assert!(matches!(a_at(&mut past, 1), Some(Value::Int(1))));
+        assert_eq!(trace.stopped, None);
+        // a long run stops recording instead of keeping a snapshot per statement forever
+        let mut vm = VM::new();
+        let recorder = Recorder::start(&mut vm);
+        vm.execute_program(Parser::new(&"int-a = a + 1;\n".repeat(100_005).replacen("a + 1", "0", 1)).parse_program()).unwrap();
+        let trace = recorder.finish(&mut vm);
+        assert_eq!(trace.steps.len(), 100_000);
+        assert_eq!(trace.stopped.as_deref(), Some("recording stopped after 100000 steps"));
+        assert_eq!(Trace::decode(&trace.encode()).unwrap().stopped, trace.stopped);
+    }
+
+    #[test]
+    fn recording_stops_at_the_snapshot_limit() {
+        use crate::recorder::{Recorder, Trace};
+        use crate::vm::Value;
+        // every statement changes the globals, so each step stores a bigger snapshot
+        let src = format!("list-xs = list();\n{}int-done = 1;\n", "push(xs, \"0123456789abcdef\");\n".repeat(200));
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        let recorder = Recorder::start_limited(&mut vm, 1000, 8 << 10);
+        vm.execute_program(Parser::new(&src).parse_program()).unwrap();
+        let trace = recorder.finish(&mut vm);
+        assert!(matches!(vm.get_global("done"), Some(Value::Int(1))));
+        let why = trace.stopped.as_deref().expect("recording should stop");
+        assert!(why.starts_with("recording stopped at step") && why.ends_with("snapshots reached 8192 bytes"), "{}", why);
+        let kept = trace.steps.len();
+        assert!(kept > 1 && kept < 200, "{}", kept);
+        assert!(trace.steps.iter().all(|s| s.line != 0), "the final state is past the limit");
+        let back = Trace::decode(&trace.encode()).unwrap();
+        assert_eq!(back, trace);
+        let mut past = VM::new();
+        back.restore(kept - 1, &mut past).unwrap();
+        assert!(past.get_global("done").is_none());
+        assert!(back.restore(kept, &mut past).is_err());
+    }
+
+    #[test]
+    fn introspection_of_globals_and_objects() {
+        use crate::vm::Value;
+        let src = "class P { rtd get(self) { 1; } } P-p = P(); p.y = 2; p.x = 1; int-n = 3; rtd sq(int-x) -> int { x * x; } int-f = fields(p); int-d = dir(p); int-m = dir(P);";
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let names: Vec<&str> = vm.globals_iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["P", "p", "n", "sq", "f", "d", "m"]);
+        assert_eq!(vm.describe("n").as_deref(), Some("n: int = 3"));
+        assert_eq!(vm.describe("p").as_deref(), Some(r#"p: P = {"y":2,"x":1}"#));
+        assert!(vm.describe("sq").is_some_and(|d| d.contains("sq(")));
+        assert!(vm.describe("nope").is_none());
+        let strs = |name: &str| match vm.get_global(name) {
+            Some(Value::List(items)) => items.borrow().iter().map(|v| String::try_from(v.clone()).unwrap()).collect::<Vec<_>>(),
+            _ => panic!("{} is not a list", name),
+        };
+        assert_eq!(strs("f"), ["y", "x"]);
+        assert_eq!(strs("d"), ["y", "x", "get"]);
+        assert_eq!(strs("m"), ["get"]);
+        assert!(vm.execute_program(Parser::new("fields(1);").parse_program()).is_err());
+    }
+
+    #[test]
+    fn call_trace_exports_chrome_format() {
+        let src = "rtd sq(x) { x * x; } rtd twice(x) { sq(x) + sq(x); } int-a = twice(2);";
+        let mut vm = VM::new();
+        vm.enable_call_trace();
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let spans: Vec<(&str, usize)> = vm.call_spans().iter().map(|s| (s.name.as_str(), s.depth)).collect();
+        assert_eq!(spans, [("sq", 1), ("sq", 1), ("twice", 0)]);
+        // callees lie inside their caller
+        let [a, _, outer] = vm.call_spans() else { panic!() };
+        assert!(a.start >= outer.start && a.start + a.duration <= outer.start + outer.duration);
+        let json = crate::json::chrome_trace(vm.call_spans());
+        assert!(json.starts_with(r#"{"traceEvents":[{"name":"sq","ph":"X","ts":"#));
+        assert_eq!(json.matches(r#""ph":"X""#).count(), 3);
+    }
+
+    #[test]
+    fn directory_and_path_builtins() {
+        use crate::vm::{Policy, Value};
+        let dir = std::env::temp_dir().join(format!("userd-fs-{}", std::process::id()));
+        let dir = dir.to_string_lossy().into_owned();
+        let src = format!(r#"str-d = path_join("{dir}", "sub"); mkdir(d); mkdir(path_join(d, "b")); mkdir(path_join(d, "a")); int-names = list_dir(d); remove_dir(path_join(d, "a")); int-left = list_dir(d); str-base = path_basename("x/y/notes.txt"); str-ext = path_ext("notes.txt"); str-none = path_ext("Makefile");"#);
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        let res = vm.execute_program(Parser::new(&src).parse_program());
+        let _ = std::fs::remove_dir_all(&dir);
+        res.unwrap();
+        let strs = |name: &str| match vm.get_global(name) {
+            Some(Value::List(items)) => items.borrow().iter().map(|v| String::try_from(v.clone()).unwrap()).collect::<Vec<_>>(),
+            _ => panic!("{} is not a list", name),
+        };
+        assert_eq!(strs("names"), ["a", "b"]);
+        assert_eq!(strs("left"), ["b"]);
+        assert!(matches!(vm.get_global("base"), Some(Value::Str(s)) if s == "notes.txt"));
+        assert!(matches!(vm.get_global("ext"), Some(Value::Str(s)) if s == "txt"));
+        assert!(matches!(vm.get_global("none"), Some(Value::Str(s)) if s.is_empty()));
+        vm.set_policy(Policy::sandboxed());
+        assert!(vm.execute_program(Parser::new(r#"list_dir(".");"#).parse_program()).is_err());
+    }
+
+    #[test]
+    fn diagnostics_as_json_lines() {
+        use crate::diagnostics::Diagnostic;
+        let prog = Parser::new("int-count = 1;\nint-x = \"a\";").parse_program();
+        let errors = crate::typeck::type_errors(&prog);
+        assert_eq!(
+            Diagnostic::from_type_error("a.usrd", &errors[0]).to_json(),
+            r#"{"file":"a.usrd","span":{"line":2},"severity":"error","code":"T0001","message":"int-x: declared int but value is str","suggestion":"convert the value with to_int(...)"}"#,
+        );
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        let e = vm.execute_program(Parser::new("int-count = 1;\ncount;\ncuont + 1;").parse_program()).unwrap_err();
+        assert_eq!(vm.error_line(), Some(3));
+        let d = Diagnostic::runtime("a.usrd", &e, vm.error_line(), ["count", "x"].into_iter());
+        assert_eq!(d.suggestion.as_deref(), Some("did you mean `count`?"));
+        assert!(Diagnostic::error("b.usrd", "E0001", "missing").to_json().contains(r#""span":null"#));
+    }
+
+    #[test]
+    fn environment_and_process_builtins() {
+        use crate::vm::{Policy, Value};
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        let src = r#"env_set("USERD_TEST_ENV", "on"); str-v = env_get("USERD_TEST_ENV"); str-none = env_get("USERD_TEST_UNSET");"#;
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        assert!(matches!(vm.get_global("v"), Some(Value::Str(s)) if s == "on"));
+        assert!(matches!(vm.get_global("none"), Some(Value::Str(s)) if s.is_empty()));
+        #[cfg(unix)]
+        {
+            vm.execute_program(Parser::new(r#"Process-p = exec("sh", list("-c", "echo hi; exit 3")); int-code = p.code; str-out = p.stdout;"#).parse_program()).unwrap();
+            assert!(matches!(vm.get_global("code"), Some(Value::Int(3))));
+            assert!(matches!(vm.get_global("out"), Some(Value::Str(s)) if s == "hi\n"));
+            // commands see what env_set set, though the process environment is left alone
+            vm.execute_program(Parser::new(r#"Process-q = exec("sh", list("-c", "printf %s $USERD_TEST_ENV")); str-seen = q.stdout;"#).parse_program()).unwrap();
+            assert!(matches!(vm.get_global("seen"), Some(Value::Str(s)) if s == "on"));
+        }
+        assert!(std::env::var("USERD_TEST_ENV").is_err());
+        assert!(vm.execute_program(Parser::new("int-a = 1; exit(4); int-b = 2;").parse_program()).is_err());
+        assert_eq!(vm.exit_code(), Some(4));
+        assert!(vm.get_global("b").is_none());
+        vm.set_policy(Policy::sandboxed());
+        assert!(vm.execute_program(Parser::new(r#"env_get("HOME");"#).parse_program()).is_err());
+        assert_eq!(vm.exit_code(), None);
+    }
+
+    #[test]
+    fn environment_overlay_shadows_and_unsets() {
+        use crate::vm::Value;
+        let Ok(home) = std::env::var("HOME") else { return };
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        // the overlay value hides the real one until it is dropped again
+        let src = r#"env_set("HOME", "/userd-overlay"); str-inside = env_get("HOME"); int-gone = env_unset("HOME"); str-after = env_get("HOME"); int-again = env_unset("HOME");"#;
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        assert!(matches!(vm.get_global("inside"), Some(Value::Str(s)) if s == "/userd-overlay"));
+        assert!(matches!(vm.get_global("gone"), Some(Value::Int(1))));
+        assert!(matches!(vm.get_global("after"), Some(Value::Str(s)) if *s == home));
+        assert!(matches!(vm.get_global("again"), Some(Value::Int(0))));
+        assert_eq!(std::env::var("HOME").as_deref(), Ok(home.as_str()));
+    }
+
+    #[test]
+    fn quick_fix_suggestions() {
+        use crate::diagnostics::Diagnostic;
+        let mut parser = Parser::new("int-a = 1\nint-b = 2;\nrtd add(x, y) { x + y; }\nadd(1);\nto_int(1, 2);");
+        let prog = parser.parse_program();
+        assert_eq!(prog.len(), 5);
+        let hints = parser.hints();
+        assert_eq!(hints.len(), 1);
+        assert_eq!(Diagnostic::from_hint("a.usrd", &hints[0]).to_json(), r#"{"file":"a.usrd","span":{"line":1},"severity":"warning","code":"W0001","message":"missing `;` after statement","suggestion":"add `;` at the end of line 1"}"#);
+        let errors = crate::typeck::type_errors(&prog);
+        let fixes: Vec<(&str, Option<&str>)> = errors.iter().map(|e| (e.code, e.suggestion.as_deref())).collect();
+        assert_eq!(fixes, [("T0006", Some("call it as add(x, y)")), ("T0006", Some("to_int takes exactly 1 argument"))]);
+        let vm = VM::new();
+        let d = Diagnostic::runtime("a.usrd", "undefined function/class to_itn", None, vm.builtin_names().into_iter());
+        assert_eq!(d.suggestion.as_deref(), Some("did you mean `to_int`?"));
+    }
+
+    #[test]
+    fn builtin_documentation() {
+        use crate::vm::Value;
+        let registry = crate::builtins::Registry::standard();
+        for name in registry.names() {
+            let b = registry.get(name).unwrap();
+            let doc = b.doc.unwrap_or_else(|| panic!("{} is not documented", name));
+            let params = doc.signature.split_once('(').and_then(|(_, rest)| rest.split_once(')')).unwrap().0;
+            let count = if params.is_empty() { 0 } else { params.split(',').count() };
+            let arity = if params.ends_with("...") { crate::builtins::VARIADIC } else { count };
+            assert_eq!((name, arity), (name, b.arity), "signature of {}", name);
+        }
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.execute_program(Parser::new(r#"str-a = help("to_int"); str-b = help(to_int); rtd f(x) { x; } str-c = help("f");"#).parse_program()).unwrap();
+        assert!(matches!(vm.get_global("a"), Some(Value::Str(s)) if s.starts_with("to_int(x) -> int\n  ")));
+        assert_eq!(vm.global("a").and_then(String::try_from), vm.global("b").and_then(String::try_from));
+        assert!(matches!(vm.get_global("c"), Some(Value::Str(s)) if s == "f(x)"));
+        assert!(crate::web_server::builtins_json().contains(r#"{"name":"mkdir","signature":"mkdir(str-path) -> int","summary":"Create a directory and any missing parents","arity":1}"#));
+    }
+
+    #[test]
+    fn script_arguments() {
+        use crate::vm::Value;
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.execute_program(Parser::new("int-none = len(argv());").parse_program()).unwrap();
+        assert!(matches!(vm.get_global("none"), Some(Value::Int(0))));
+        vm.set_args(vec!["a".to_string(), "--b".to_string()]);
+        vm.set_policy(crate::vm::Policy::sandboxed());
+        vm.execute_program(Parser::new("int-n = len(argv()); str-second = at(argv(), 1);").parse_program()).unwrap();
+        assert!(matches!(vm.get_global("n"), Some(Value::Int(2))));
+        assert!(matches!(vm.get_global("second"), Some(Value::Str(s)) if s == "--b"));
+    }
+
+    #[test]
+    fn embedding_errors() {
+        use crate::vm::Value;
+        use std::error::Error as _;
+        fn run(vm: &mut VM, src: &str) -> Result<i64, crate::Error> {
+            vm.eval(src)?;
+            Ok(i64::try_from(vm.global("n").unwrap()).unwrap())
+        }
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        assert_eq!(run(&mut vm, "int-n = 1;; int-m = 2; int-n = n + m;").unwrap(), 3);
+        assert!(matches!(vm.eval("int-x = 1 + #;"), Err(crate::Error::Lex { line: 1, .. })));
+        let e = vm.eval("int-a = 1;\nint-b = ) 2;").unwrap_err();
+        assert_eq!((e.to_string(), e.line()), ("line 2: unexpected `)`".to_string(), Some(2)));
+        let e = vm.eval("int-a = 1;\nint-b = a + missing;").unwrap_err();
+        assert!(matches!(&e, crate::Error::Runtime { line: Some(2), message } if message == "undefined: missing"));
+        let e = vm.eval_file("/nonexistent/x.usrd").unwrap_err();
+        assert!(e.to_string().starts_with("/nonexistent/x.usrd: ") && e.source().is_some());
+        assert!(matches!(vm.get_global("a"), Some(Value::Int(1))));
+    }
+
+    #[test]
+    fn cancel_from_another_thread() {
+        use crate::vm::CANCELLED;
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        let token = vm.cancel_token();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            token.cancel();
+        });
+        let start = std::time::Instant::now();
+        let res = vm.execute_program(Parser::new("int-a = 1; sleep_ms(10000); int-b = 2;").parse_program());
+        canceller.join().unwrap();
+        assert_eq!(res.unwrap_err(), CANCELLED);
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert!(vm.get_global("a").is_some() && vm.get_global("b").is_none());
+        // a cancel is used up by the run it stops
+        vm.cancel_token().cancel();
+        assert!(vm.execute_program(Parser::new("int-c = 3;").parse_program()).is_err());
+        assert!(vm.execute_program(Parser::new("int-c = 3;").parse_program()).is_ok());
+    }
+
+    #[test]
+    fn csv_builtins() {
+        use crate::vm::Value;
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.set_global("text", Value::from("name,note\r\nann,\"likes \"\"tea\"\", coffee\"\n\nbob,\"two\nlines\"\n"));
+        let src = "list-rows = csv_parse(text); int-n = len(rows); list-recs = csv_records(text); Row-first = at(recs, 0); str-note = first.note; str-out = csv_write(rows); str-again = csv_write(recs); str-nums = csv_write(list(list(1, 2.5, \"a,b\")));";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let s = |name: &str| String::try_from(vm.get_global(name).unwrap()).unwrap();
+        assert!(matches!(vm.get_global("n"), Some(Value::Int(3))));
+        assert_eq!(s("note"), "likes \"tea\", coffee");
+        assert_eq!(s("out"), "name,note\nann,\"likes \"\"tea\"\", coffee\"\nbob,\"two\nlines\"\n");
+        assert_eq!(s("again"), s("out"));
+        assert_eq!(s("nums"), "1,2.5,\"a,b\"\n");
+        vm.set_global("text", Value::from("a,\"open"));
+        assert!(vm.execute_program(Parser::new("csv_parse(text);").parse_program()).is_err());
+    }
+
+    #[test]
+    fn run_in_slices() {
+        use crate::vm::RunState;
+        let mut a = VM::new();
+        let mut b = VM::new();
+        a.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        b.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        assert!(a.run_steps(1).is_err());
+        a.start(Parser::new("int-x = 1; int-y = 2; yield(); int-z = 3; x + y + z;").parse_program());
+        b.start(Parser::new("rtd f(int-a) -> int { a * 3; } int-r = f(1); int-s = 4;").parse_program());
+        // one step runs one top-level statement; a call finishes within its slice
+        assert!(matches!(a.run_steps(1), Ok(RunState::Paused)));
+        assert!(a.get_global("x").is_some() && a.get_global("y").is_none());
+        assert!(matches!(b.run_steps(1), Ok(RunState::Paused)));
+        assert!(matches!(b.run_steps(1), Ok(RunState::Paused)));
+        assert!(matches!(b.get_global("r"), Some(crate::vm::Value::Int(3))) && b.get_global("s").is_none());
+        // yield() ends the slice early
+        assert!(matches!(a.run_steps(100), Ok(RunState::Paused)));
+        assert!(a.get_global("z").is_none());
+        assert!(matches!(a.run_steps(100), Ok(RunState::Finished(Some(crate::vm::Value::Int(6))))));
+        assert!(matches!(b.run_steps(100), Ok(RunState::Finished(None))));
+        assert!(a.run_steps(1).is_err());
+    }
+
+    #[test]
+    fn vm_group_messages() {
+        use crate::group::VmGroup;
+        use crate::vm::Value;
+        let mut group = VmGroup::new();
+        for name in ["ui", "worker"] {
+            group.add(name, VM::new()).unwrap().set_output(Box::new(crate::vm::OutputBuffer::new()));
+        }
+        assert!(group.add("ui", VM::new()).is_err());
+        group.send("worker", &Value::List(std::rc::Rc::new(std::cell::RefCell::new(vec![Value::Int(1), Value::from("a")])))).unwrap();
+        group.start("worker", Parser::new("Result-m = group_recv(); list-xs = m.value; group_send(\"ui\", len(xs) * 10);").parse_program()).unwrap();
+        group.start("ui", Parser::new("Result-none = group_recv(); yield(); yield(); Result-r = group_recv(); int-got = r.value;").parse_program()).unwrap();
+        group.run(1).unwrap();
+        let ui = group.get("ui").unwrap();
+        assert!(matches!(ui.get_global("got"), Some(Value::Int(20))));
+        assert!(matches!(group.get("worker").unwrap().get_global("xs"), Some(Value::List(xs)) if xs.borrow().len() == 2));
+        assert!(group.send("nobody", &Value::Int(1)).is_err());
+        let mut alone = VM::new();
+        assert!(alone.execute_program(Parser::new("group_recv();").parse_program()).unwrap_err().contains("not in a group"));
+    }
+
+    #[test]
+    fn state_machines() {
+        use crate::vm::Value;
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        let src = "rtd log(a, e, b) { str-from = a; str-via = e; str-to = b; }
+            Fsm-door = fsm_create(list(list(\"closed\", \"open\", \"opened\"), list(\"opened\", \"close\", \"closed\"), list(\"closed\", \"lock\", \"locked\")));
+            fsm_on_transition(door, \"log\");
+            str-s0 = door.state; int-moved = fsm_on(door, \"open\"); int-ignored = fsm_on(door, \"lock\"); str-s1 = door.state;";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let s = |vm: &VM, name: &str| String::try_from(vm.get_global(name).unwrap()).unwrap();
+        assert_eq!((s(&vm, "s0"), s(&vm, "s1"), s(&vm, "via")), ("closed".to_string(), "opened".to_string(), "open".to_string()));
+        assert_eq!((s(&vm, "from"), s(&vm, "to")), ("closed".to_string(), "opened".to_string()));
+        assert!(matches!(vm.get_global("moved"), Some(Value::Int(1))) && matches!(vm.get_global("ignored"), Some(Value::Int(0))));
+        // the object form: one field per state
+        let src = "class S(open) { } class T(closed, opened) { } Fsm-f = fsm_create(T(S(\"opened\"), S(\"closed\"))); fsm_on(f, \"open\"); str-s2 = f.state;";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        assert_eq!(s(&vm, "s2"), "opened");
+        let err = vm.execute_program(Parser::new("fsm_create(list(list(\"a\", \"go\")));").parse_program()).unwrap_err();
+        assert!(err.contains("list(from, event, to)"), "{}", err);
+    }
+
+    #[test]
+    fn xml_builtins() {
+        use crate::vm::Value;
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.set_global("text", Value::from("<?xml version=\"1.0\"?>\n<!-- settings -->\n<config name='app' v=\"1 &amp; 2\">\n  <item id=\"a\">x &lt; y</item>\n  <item id=\"b\"><![CDATA[<raw>]]></item>\n  <empty/>\n</config>\n"));
+        let src = "Node-root = xml_parse(text); str-tag = root.tag; Attrs-a = root.attrs; str-v = a.v; list-kids = root.children; Node-first = at(kids, 0); str-t0 = first.text; Node-second = at(kids, 1); str-t1 = second.text; str-out = xml_stringify(root);";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let s = |name: &str| String::try_from(vm.get_global(name).unwrap()).unwrap();
+        assert_eq!((s("tag"), s("v"), s("t0"), s("t1")), ("config".to_string(), "1 & 2".to_string(), "x < y".to_string(), "<raw>".to_string()));
+        assert!(matches!(vm.get_global("kids"), Some(Value::List(k)) if k.borrow().len() == 3));
+        assert_eq!(s("out"), "<config name=\"app\" v=\"1 &amp; 2\"><item id=\"a\">x &lt; y</item><item id=\"b\">&lt;raw&gt;</item><empty/></config>");
+        for bad in ["<a><b></a>", "<a>", "<a/><b/>", "<a x=1/>", "<a>&nope;</a>"] {
+            vm.set_global("text", Value::from(bad));
+            assert!(vm.execute_program(Parser::new("xml_parse(text);").parse_program()).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn ini_settings() {
+        use crate::vm::Value;
+        let dir = std::env::temp_dir().join(format!("userd-ini-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.ini");
+        std::fs::write(&path, "top=1\r\n; comment\r\n[Main]\r\nName = \"demo\"\r\n\r\n[Other]\r\nx=2\r\n").unwrap();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.set_global("path", Value::from(path.to_string_lossy().as_ref()));
+        let src = "str-name = ini_read(path, \"main\", \"NAME\"); str-top = ini_read(path, \"\", \"top\"); str-none = ini_read(path, \"Main\", \"missing\");
+            ini_write(path, \"Main\", \"size\", 10); ini_write(path, \"Other\", \"x\", \"3\"); ini_write(path, \"New\", \"k\", \"v\");";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let s = |name: &str| String::try_from(vm.get_global(name).unwrap()).unwrap();
+        assert_eq!((s("name"), s("top"), s("none")), ("demo".to_string(), "1".to_string(), String::new()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "top=1\r\n; comment\r\n[Main]\r\nName = \"demo\"\r\nsize=10\r\n\r\n[Other]\r\nx=3\r\n\r\n[New]\r\nk=v\r\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+        #[cfg(not(target_os = "windows"))]
+        assert!(vm.execute_program(Parser::new("reg_read(\"HKCU\", \"Software\", \"x\");").parse_program()).unwrap_err().contains("only available on Windows"));
+        vm.set_policy(crate::vm::Policy::sandboxed());
+        assert!(vm.execute_program(Parser::new("ini_read(path, \"Main\", \"Name\");").parse_program()).is_err());
+    }
+
+    #[test]
+    fn websocket_client() {
+        use std::io::{Read, Write};
+        assert_eq!(crate::builtins::ws::accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut s, _) = listener.accept().unwrap();
+            let mut head = Vec::new();
+            let mut b = [0u8; 1];
+            while !head.ends_with(b"\r\n\r\n") { s.read_exact(&mut b).unwrap(); head.push(b[0]); }
+            let head = String::from_utf8(head).unwrap();
+            let key = head.lines().find_map(|l| l.strip_prefix("Sec-WebSocket-Key: ")).unwrap();
+            write!(s, "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", crate::builtins::ws::accept_key(key)).unwrap();
+            // client frames are masked
+            let frame = |s: &mut std::net::TcpStream| {
+                let mut h = [0u8; 6];
+                s.read_exact(&mut h).unwrap();
+                assert_eq!(h[1] & 0x80, 0x80);
+                let mut p = vec![0u8; (h[1] & 0x7F) as usize];
+                s.read_exact(&mut p).unwrap();
+                (h[0] & 0x0F, p.iter().enumerate().map(|(i, c)| c ^ h[2 + i % 4]).collect::<Vec<u8>>())
+            };
+            let (op, text) = frame(&mut s);
+            assert_eq!((op, text.as_slice()), (1, b"hi".as_slice()));
+            s.write_all(&[0x89, 1, b'p']).unwrap();
+            s.write_all(&[0x01, 5, b'e', b'c', b'h', b'o', b':']).unwrap();
+            s.write_all(&[0x80, 2, b'h', b'i']).unwrap();
+            assert_eq!(frame(&mut s), (0xA, b"p".to_vec()));
+            assert_eq!(frame(&mut s).0, 8);
+        });
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        let src = format!("int-c = ws_connect(\"ws://127.0.0.1:{}/live\"); ws_send(c, \"hi\"); str-got = ws_recv(c); ws_close(c);", port);
+        vm.execute_program(Parser::new(&src).parse_program()).unwrap();
+        server.join().unwrap();
+        assert_eq!(String::try_from(vm.get_global("got").unwrap()).unwrap(), "echo:hi");
+        assert!(vm.execute_program(Parser::new("ws_connect(\"wss://example.com\");").parse_program()).unwrap_err().contains("TLS"));
+    }
+
+    #[test]
+    fn temp_files_and_atomic_writes() {
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.execute_program(Parser::new("str-f = temp_file(); str-g = temp_file(); str-d = temp_dir(); str-p = path_join(d, \"config.txt\"); write_file_atomic(p, \"one\"); write_file_atomic(p, \"two\");").parse_program()).unwrap();
+        let s = |name: &str| String::try_from(vm.get_global(name).unwrap()).unwrap();
+        assert_ne!(s("f"), s("g"));
+        assert_eq!(std::fs::read_to_string(s("f")).unwrap(), "");
+        assert_eq!(std::fs::read_to_string(s("p")).unwrap(), "two");
+        // the temporary file was renamed away
+        assert_eq!(std::fs::read_dir(s("d")).unwrap().count(), 1);
+        std::fs::remove_dir_all(s("d")).unwrap();
+        for f in ["f", "g"] { std::fs::remove_file(s(f)).unwrap(); }
+        vm.set_policy(crate::vm::Policy::sandboxed());
+        assert!(vm.execute_program(Parser::new("temp_file();").parse_program()).is_err());
+    }
+
+    #[test]
+    fn file_checksums() {
+        use crate::hash::{hex, Sha256};
+        let mut h = Sha256::new();
+        h.update(b"ab");
+        h.update(b"c");
+        assert_eq!(hex(&h.finish()), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(hex(&Sha256::new().finish()), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        let dir = std::env::temp_dir().join(format!("userd-hash-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // larger than one read chunk, so the streaming path is taken
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.join("big.bin"), &data).unwrap();
+        std::fs::write(dir.join("check.txt"), "123456789").unwrap();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.set_global("dir", crate::vm::Value::from(dir.to_string_lossy().as_ref()));
+        vm.execute_program(Parser::new("str-big = file_sha256(path_join(dir, \"big.bin\")); str-crc = file_crc32(path_join(dir, \"check.txt\"));").parse_program()).unwrap();
+        let mut whole = Sha256::new();
+        whole.update(&data);
+        assert_eq!(String::try_from(vm.get_global("big").unwrap()).unwrap(), hex(&whole.finish()));
+        assert_eq!(String::try_from(vm.get_global("crc").unwrap()).unwrap(), "cbf43926");
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(vm.execute_program(Parser::new("file_crc32(\"/no/such/file\");").parse_program()).is_err());
+    }
+
+    #[test]
+    fn base64_builtins() {
+        use crate::base64::{decode, decode_lenient, encode};
+        for (plain, coded) in [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foobar", "Zm9vYmFy")] {
+            assert_eq!(encode(plain.as_bytes()), coded);
+            assert_eq!(decode(coded).unwrap(), plain.as_bytes());
+        }
+        assert_eq!(decode("Zm8\n").unwrap(), b"fo");
+        assert!(decode("Zm=9").is_err() && decode("Z").is_err() && decode("Zm9*").is_err());
+        assert_eq!(decode_lenient("Zm*9v=junk"), b"foo");
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.execute_program(Parser::new("str-a = b64_encode(\"héllo\"); str-b = b64_decode(a); str-c = b64_encode(list(0, 255)); list-d = b64_decode_bytes(c);").parse_program()).unwrap();
+        let s = |name: &str| String::try_from(vm.get_global(name).unwrap()).unwrap();
+        assert_eq!((s("b"), s("c")), ("héllo".to_string(), "AP8=".to_string()));
+        assert!(matches!(vm.get_global("d"), Some(crate::vm::Value::List(d)) if d.borrow().len() == 2));
+        assert!(vm.execute_program(Parser::new("b64_decode(\"AP8=\");").parse_program()).unwrap_err().contains("UTF-8"));
+        assert!(vm.execute_program(Parser::new("b64_encode(list(256));").parse_program()).is_err());
+    }
+
+    #[test]
+    fn line_diffs() {
+        use crate::diff::{diff, unified};
+        let a = ["a", "b", "c", "d", "e"];
+        let b = ["a", "c", "d", "x", "e", "f"];
+        let hunks = diff(&a, &b);
+        assert_eq!(hunks.iter().map(|h| (h.old_start, h.old_lines.len(), h.new_start, h.new_lines.len())).collect::<Vec<_>>(), vec![(2, 1, 2, 0), (5, 0, 4, 1), (6, 0, 6, 1)]);
+        assert_eq!(crate::diff::apply(&a, &hunks).unwrap(), b);
+        assert_eq!(unified("old", "new", &a, &b, 1), "--- old\n+++ new\n@@ -1,5 +1,6 @@\n a\n-b\n c\n d\n+x\n e\n+f\n");
+        assert_eq!(unified("old", "new", &a, &a, 3), "");
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.set_global("a", crate::vm::Value::from("port=1\nhost=x\n"));
+        vm.set_global("b", crate::vm::Value::from("port=2\nhost=x\nmode=y\n"));
+        vm.execute_program(Parser::new("list-h = diff_lines(a, b); str-c = apply_patch(a, h); Hunk-first = at(h, 0); int-at = first.old_start;").parse_program()).unwrap();
+        assert_eq!(String::try_from(vm.get_global("c").unwrap()).unwrap(), "port=2\nhost=x\nmode=y\n");
+        assert!(matches!(vm.get_global("at"), Some(crate::vm::Value::Int(1))));
+        let err = vm.execute_program(Parser::new("apply_patch(\"other\", h);").parse_program()).unwrap_err();
+        assert!(err.contains("does not match"), "{}", err);
+    }
+
+    #[test]
+    fn digest_builtins() {
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.execute_program(Parser::new("str-a = sha256(\"abc\"); str-b = sha1(\"abc\"); str-c = crc32(\"123456789\"); str-d = sha256(list(97, 98, 99));").parse_program()).unwrap();
+        let s = |name: &str| String::try_from(vm.get_global(name).unwrap()).unwrap();
+        assert_eq!(s("a"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(s("b"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(s("c"), "cbf43926");
+        assert_eq!(s("d"), s("a"));
+        assert!(vm.execute_program(Parser::new("sha1(1.5);").parse_program()).is_err());
+    }
+
+    #[test]
+    fn hmac_and_encryption() {
+        use crate::crypto::{chacha20, hmac_sha256};
+        use crate::hash::hex;
+        assert_eq!(hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        // RFC 8439, 2.4.2
+        let key: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let nonce = [0, 0, 0, 0, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+        let mut data = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.".to_vec();
+        chacha20(&key, 1, &nonce, &mut data);
+        assert_eq!(hex(&data[..16]), "6e2e359a2568f98041ba0728dd0d6981");
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.execute_program(Parser::new("str-m = hmac_sha256(\"Jefe\", \"x\"); str-e1 = encrypt(\"secret\", \"привет\"); str-e2 = encrypt(\"secret\", \"привет\"); str-d = decrypt(\"secret\", e1);").parse_program()).unwrap();
+        let s = |name: &str| String::try_from(vm.get_global(name).unwrap()).unwrap();
+        assert_eq!(s("d"), "привет");
+        assert_ne!(s("e1"), s("e2"));
+        assert!(vm.execute_program(Parser::new("decrypt(\"other\", e1);").parse_program()).unwrap_err().contains("wrong key"));
+        // bytes that are not UTF-8 round-trip through decrypt_bytes
+        vm.execute_program(Parser::new("list-raw = list(0, 255, 192, 128, 10); str-e3 = encrypt(\"secret\", raw); list-back = decrypt_bytes(\"secret\", e3);").parse_program()).unwrap();
+        let Some(crate::vm::Value::List(back)) = vm.get_global("back") else { panic!("back is not a list") };
+        assert_eq!(back.borrow().iter().map(|v| i64::try_from(v.clone()).unwrap()).collect::<Vec<_>>(), [0, 255, 192, 128, 10]);
+        assert!(vm.execute_program(Parser::new("decrypt(\"secret\", e3);").parse_program()).unwrap_err().contains("use decrypt_bytes"));
+        let mut sealed = crate::crypto::seal(b"k", b"data").unwrap();
+        sealed[13] ^= 1;
+        assert!(crate::crypto::open(b"k", &sealed).is_err());
+    }
+
+    #[test]
+    fn locale_collation() {
+        let mut vm = VM::new();
+        let src = "int-a = compare_locale(\"ёж\", \"ель\", \"ru\"); int-b = compare_locale(\"ёж\", \"ель\", \"ru-yo\");\
+            int-c = compare_locale(\"Яблоко\", \"apple\", \"ru\"); int-d = compare_locale(\"Яблоко\", \"apple\", \"en\");\
+            int-e = compare_locale(\"Ёлка\", \"ёлка\", \"ru\"); int-f = compare_locale(\"елка\", \"ёлка\", \"ru\");\
+            int-g = compare_locale(\"дом\", \"дом\", \"ru\"); str-h = casefold(\"Straße ΣΑΣ Ёж\");";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let int = |name: &str| i64::try_from(vm.get_global(name).unwrap()).unwrap();
+        assert_eq!([int("a"), int("b"), int("c"), int("d"), int("e"), int("f"), int("g")], [-1, 1, -1, 1, 1, -1, 0]);
+        assert_eq!(String::try_from(vm.get_global("h").unwrap()).unwrap(), "strasse σασ ёж");
+        let mut words = vec!["ёлка", "Ель", "ель", "жук", "дом", "zoo", "10"];
+        words.sort_by(|a, b| crate::builtins::text::compare(a, b, "ru"));
+        assert_eq!(words, ["10", "дом", "ёлка", "ель", "Ель", "жук", "zoo"]);
+    }
+
+    #[test]
+    fn lint_rules() {
+        use crate::lint::{lint, LintConfig};
+        let src = "int-limit = 10; int-MaxSize = 3;\
+            rtd area(int-limit, int-w) -> int { int-t = limit * w; t * 42; }\
+            rtd len(int-x) -> int { x; }\
+            class shape(w) { rtd scale(self, int-k) -> int { self.w * k * 2; } }";
+        let prog = Parser::new(src).parse_program();
+        let codes = |config: &LintConfig| lint(&prog, config).iter().map(|l| l.code).collect::<Vec<_>>();
+        // MaxSize, limit shadowing, 42, len hidden by the builtin, class shape
+        assert_eq!(codes(&LintConfig::default()), ["L0001", "L0003", "L0004", "L0003", "L0001"]);
+        let config = LintConfig::parse("[other]\nx = 1\n[lint]\nnaming = false # off\nmagic_numbers = false\nmax_function_length = 1\n").unwrap();
+        assert_eq!(config, LintConfig { naming: false, shadowing: true, magic_numbers: false, max_function_length: 1 });
+        assert_eq!(codes(&config), ["L0003", "L0002", "L0003"]);
+        assert!(LintConfig::parse("[lint]\nspelling = true").unwrap_err().contains("unknown lint rule"));
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        use crate::gzip::{compress, decompress, deflate, inflate};
+        // from Python's gzip: one block with dynamic Huffman codes
+        let hex = "1f8b0800000000000203358fd111c0300842ffdd1270ff190a98daebb5417c4180981d0103094b5870c90f7672b0a6a814483b6b24b176717c74a325f2bcf6f83b9487b6ad32371064b4389b67fca780d1c1beab430b67cc801e00159b81b9f8a1a24678c5563643b9c2c347fd57d4ad890694b3cf6b38582e4f7413786a681f5919b1172c010000";
+        let bytes: Vec<u8> = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect();
+        let plain = decompress(&bytes).unwrap();
+        assert_eq!(plain.len(), 300);
+        assert_eq!(crate::hash::hex(&crate::hash::sha256(&plain)), "51cb0bc71c945bce3de1a60d55a7938566e7c27b4741362cb79996ab99bf1558");
+
+        let text = "rtd add(int-a, int-b) -> int { a + b; }\n".repeat(200);
+        let packed = compress(text.as_bytes());
+        assert!(packed.len() < text.len() / 10);
+        assert_eq!(decompress(&packed).unwrap(), text.as_bytes());
+        // incompressible data falls back to stored blocks
+        let mut noise = vec![0u8; 70_000];
+        crate::rand::secure_bytes(&mut noise).unwrap();
+        assert!(deflate(&noise).len() < noise.len() + 16);
+        assert_eq!(inflate(&deflate(&noise)).unwrap(), noise);
+        assert_eq!(inflate(&deflate(b"")).unwrap(), b"");
+        let mut bad = packed.clone();
+        let n = bad.len();
+        bad[n - 5] ^= 1;
+        assert!(decompress(&bad).is_err());
+        // each member is under the cap, together they pass it
+        let member = compress(&vec![0u8; 64 << 10]);
+        let bomb = member.repeat(20);
+        assert!(bomb.len() < 64 << 10);
+        assert_eq!(crate::gzip::decompress_limited(&bomb, 1 << 20), Err("decompressed data too large".to_string()));
+        assert_eq!(crate::gzip::decompress_limited(&member.repeat(16), 1 << 20).map(|d| d.len()), Ok(1 << 20));
+        assert_eq!(decompress(&bomb).map(|d| d.len()), Ok(20 * (64 << 10)));
+
+        let mut vm = VM::new();
+        vm.execute_program(Parser::new("list-z = gzip_compress(\"привет привет привет\"); str-s = gzip_decompress_text(z); list-b = gzip_decompress(z);").parse_program()).unwrap();
+        assert_eq!(String::try_from(vm.get_global("s").unwrap()).unwrap(), "привет привет привет");
+    }
+
+    #[test]
+    fn macro_expansion() {
+        let src = "macro square(x) { x * x; }\
+            macro swap(a, b) { int-tmp = a; int-a = b; int-b = tmp; }\
+            macro define(name, v) { int-name = square(v) + 1; }\
+            int-tmp = 100; int-p = 1; int-q = 2;\
+            swap(p, q); define(r, 3);\
+            rtd twice(int-n) -> int { square(n) * 2; }\
+            int-s = twice(p + 1);";
+        let mut parser = Parser::new(src);
+        let prog = parser.parse_program();
+        assert!(parser.errors().is_empty());
+        let text = crate::ast::to_source(&prog);
+        assert!(text.contains("int-tmp__1 = p;") && text.contains("int-r = 3 * 3 + 1;"), "{}", text);
+        // the printed program parses back to the same thing
+        assert_eq!(crate::ast::to_source(&Parser::new(&text).parse_program()), text);
+        let mut vm = VM::new();
+        vm.execute_program(prog).unwrap();
+        let int = |name: &str| i64::try_from(vm.get_global(name).unwrap()).unwrap();
+        assert_eq!([int("tmp"), int("p"), int("q"), int("r"), int("s")], [100, 2, 1, 10, 18]);
+
+        let errors = |src: &str| { let mut p = Parser::new(src); p.parse_program(); p.errors().iter().map(|e| e.to_string()).collect::<Vec<_>>() };
+        assert!(errors("macro m(a) { a; } m(1, 2);")[0].contains("takes 1 argument(s), got 2"));
+        assert!(errors("macro m(a) { m(a); } m(1);")[0].contains("levels deep"));
+        assert!(errors("macro m(a) { int-a = 1; } m(2);")[0].contains("must be a name"));
+        assert!(errors("macro m() { int-x = 1; int-y = 2; } int-z = m();")[0].contains("cannot be used as a value"));
+    }
+
+    #[test]
+    fn host_objects() {
+        use crate::host::{no_method, HostApi};
+        use crate::vm::Value;
+        struct Counter { n: i64 }
+        impl HostApi for Counter {
+            fn type_name(&self) -> &str { "Counter" }
+            fn call(&mut self, vm: &mut VM, method: &str, args: &[Value]) -> Result<Value, String> {
+                match method {
+                    "add" => { self.n += i64::try_from(args[0].clone())?; Ok(Value::Int(self.n)) }
+                    // calls back into the script while the object is busy
+                    "each" => vm.call(&String::try_from(args[0].clone())?, vec![Value::Int(self.n)]),
+                    _ => Err(no_method(self, method)),
+                }
+            }
+            fn field(&self, name: &str) -> Option<Value> { (name == "n").then_some(Value::Int(self.n)) }
+        }
+        let mut vm = VM::new();
+        vm.set_output(Box::new(crate::vm::OutputBuffer::new()));
+        vm.set_global("counter", Value::host(Counter { n: 1 }));
+        let src = "rtd bump(Counter-c, int-k) -> int { c.add(k); } int-a = bump(counter, 4); int-n = counter.n; rtd show(int-x) -> int { x * 10; } int-s = counter.each(\"show\");";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let int = |vm: &VM, name: &str| i64::try_from(vm.get_global(name).unwrap()).unwrap();
+        assert_eq!([int(&vm, "a"), int(&vm, "n"), int(&vm, "s")], [5, 5, 50]);
+        assert_eq!(vm.get_global("counter").unwrap().with_host(|c: &mut Counter| c.n), Some(5));
+        assert!(vm.execute_program(Parser::new("counter.reset();").parse_program()).unwrap_err().contains("Counter has no method reset"));
+        assert!(vm.execute_program(Parser::new("rtd again(int-x) -> int { counter.add(1); } counter.each(\"again\");").parse_program()).unwrap_err().contains("already in use"));
+        assert_eq!(vm.value_to_string(&vm.get_global("counter").unwrap()).unwrap(), "<Counter>");
+    }
+
+    #[test]
+    fn assertions_and_test_mode() {
+        let mut vm = VM::new();
+        let err = vm.execute_program(Parser::new("assert_eq(1, 1); assert(2 - 2, \"must not be zero\");").parse_program()).unwrap_err();
+        assert!(err.starts_with(crate::vm::ASSERTION_FAILED) && err.contains("must not be zero"), "{}", err);
+        vm.set_test_mode(true);
+        let src = "rtd check(int-x) -> int { assert_eq(x * 2, 4); }\nint-a = check(2);\nint-b = check(3);\nassert_eq(\"1\", 1);\nint-done = 1;";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        assert_eq!(vm.get_global("done").and_then(|v| i64::try_from(v).ok()), Some(1));
+        let failures = vm.take_failures();
+        assert_eq!(failures.iter().map(|f| (f.line, f.message.as_str())).collect::<Vec<_>>(), [(Some(1), "6 != 4"), (Some(4), "\"1\" != 1")]);
+        assert!(vm.take_failures().is_empty());
+    }
+
+    #[test]
+    fn random_builtins() {
+        let src = "float-f = random_float(); int-r = random_range(10, 13); list-l = list(1, 2, 3, 4, 5, 6, 7, 8); list-s = shuffle(l); int-c = choice(l);";
+        let run = |seed: u64| {
+            let mut vm = VM::deterministic(seed);
+            vm.execute_program(Parser::new(src).parse_program()).unwrap();
+            let f = f64::try_from(vm.get_global("f").unwrap()).unwrap();
+            let r = i64::try_from(vm.get_global("r").unwrap()).unwrap();
+            let c = i64::try_from(vm.get_global("c").unwrap()).unwrap();
+            let Some(crate::vm::Value::List(l)) = vm.get_global("l") else { panic!() };
+            let l: Vec<i64> = l.borrow().iter().map(|v| i64::try_from(v.clone()).unwrap()).collect();
+            (f, r, c, l)
+        };
+        let (f, r, c, l) = run(7);
+        assert!((0.0..1.0).contains(&f) && (10..13).contains(&r) && l.contains(&c));
+        let mut sorted = l.clone();
+        sorted.sort();
+        assert_eq!(sorted, [1, 2, 3, 4, 5, 6, 7, 8]);
+        // seeded runs repeat exactly
+        assert_eq!(run(7), (f, r, c, l));
+        let mut vm = VM::new();
+        assert!(vm.execute_program(Parser::new("choice(list());").parse_program()).unwrap_err().contains("empty"));
+        assert!(vm.execute_program(Parser::new("random_range(2, 2);").parse_program()).unwrap_err().contains("greater"));
+    }
+
+    #[test]
+    fn text_layout() {
+        use crate::builtins::gui::{measure, wrap};
+        assert_eq!(measure("hello", 16), (40, 20));
+        assert_eq!(measure("ab\nabcd", 10), (20, 24));
+        // wide characters take a full em
+        assert_eq!(measure("日本", 16).0, 32);
+        assert_eq!(wrap("the quick brown fox", 64, 16), ["the", "quick", "brown", "fox"]);
+        assert_eq!(wrap("the quick brown fox", 100, 16), ["the quick", "brown fox"]);
+        assert_eq!(wrap("abcdefghij", 32, 16), ["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("one\n\ntwo", 500, 16), ["one", "", "two"]);
+        let mut vm = VM::new();
+        let src = "list-m = text_measure(\"abc\", 20); int-n = canvas_draw_text_aligned(1, 0, 0, 80, \"the quick brown fox\", \"center\");";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        assert_eq!(vm.get_global("n").and_then(|v| i64::try_from(v).ok()), Some(2));
+        let err = vm.execute_program(Parser::new("canvas_draw_text_aligned(1, 0, 0, 80, \"x\", \"middle\");").parse_program()).unwrap_err();
+        assert!(err.contains("left, center or right"));
+    }
+
+    #[test]
+    fn scroll_panel() {
+        use crate::gui::{ScrollPanel, WHEEL_STEP};
+        let mut p = ScrollPanel::new(0, 0, 100, 200, 1000);
+        assert_eq!(p.max_offset(), 800);
+        // one notch down, then far past the top
+        assert!(p.wheel(-120));
+        assert_eq!(p.offset, WHEEL_STEP);
+        assert!(p.wheel(120 * 10));
+        assert_eq!(p.offset, 0);
+        assert!(!p.wheel(120));
+        // the thumb is a fifth of the track and starts at the top
+        assert_eq!(p.thumb(), Some((88, 0, 12, 40)));
+        // clicking the track below the thumb pages down; elsewhere is not ours
+        assert!(p.press(90, 150));
+        assert_eq!(p.offset, 200);
+        assert!(!p.press(10, 150));
+        // drag the thumb (now at 40..80) to the bottom
+        assert!(p.press(90, 50) && p.dragging());
+        assert!(p.drag_to(170));
+        assert_eq!(p.offset, 800);
+        p.release();
+        assert!(!p.drag_to(0));
+        p.set_content(150);
+        assert_eq!((p.offset, p.thumb()), (0, None));
+
+        let mut vm = VM::new();
+        let src = "int-p = gui_scroll_panel(1, 0, 0, 100, 200, 1000, \"on_scroll\"); int-a = scroll_to(p, 5000); int-b = scroll_set_content(p, 500); int-c = scroll_offset(p);";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let get = |vm: &VM, n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok());
+        assert_eq!((get(&vm, "a"), get(&vm, "b"), get(&vm, "c")), (Some(800), Some(300), Some(300)));
+        assert!(vm.execute_program(Parser::new("scroll_offset(42);").parse_program()).unwrap_err().contains("no scroll panel"));
+    }
+
+    #[test]
+    fn seedable_prng() {
+        let mut x = crate::rand::Xoshiro256::new(0);
+        let first: Vec<u64> = (0..3).map(|_| x.next_u64()).collect();
+        assert_eq!(first, (0..3).map({ let mut y = crate::rand::Xoshiro256::new(0); move |_| y.next_u64() }).collect::<Vec<_>>());
+        assert_ne!(first[0], crate::rand::Xoshiro256::new(1).next_u64());
+        let src = "rand_seed(42); int-a = rand_next(); int-b = rand_below(6); rand_seed(42); int-c = rand_next(); int-d = rand_below(6);";
+        let mut vm = VM::new();
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok()).unwrap();
+        assert_eq!((get("a"), get("b")), (get("c"), get("d")));
+        assert!(get("a") >= 0 && (0..6).contains(&get("b")));
+        // unseeded, it follows the VM's seed in deterministic mode
+        let unseeded = |seed| {
+            let mut vm = VM::deterministic(seed);
+            vm.execute_program(Parser::new("int-n = rand_next();").parse_program()).unwrap();
+            vm.get_global("n").and_then(|v| i64::try_from(v).ok())
+        };
+        assert_eq!(unseeded(5), unseeded(5));
+    }
+
+    #[test]
+    fn normal_distribution() {
+        let mut rng = crate::rand::SeededRng::new(3);
+        let samples: Vec<f64> = (0..4000).map(|_| crate::rand::normal_with(10.0, 2.0, |m| rng.below(m)).unwrap()).collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let var = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+        assert!((mean - 10.0).abs() < 0.15, "mean {}", mean);
+        assert!((var.sqrt() - 2.0).abs() < 0.15, "stddev {}", var.sqrt());
+        assert_eq!(crate::rand::normal(5.0, 0.0), Ok(5.0));
+        let mut vm = VM::deterministic(1);
+        vm.execute_program(Parser::new("float-x = random_normal(0, 1);").parse_program()).unwrap();
+        assert!(f64::try_from(vm.get_global("x").unwrap()).unwrap().is_finite());
+        let err = vm.execute_program(Parser::new("random_normal(0, 0 - 1);").parse_program()).unwrap_err();
+        assert!(err.contains("random_normal: stddev"), "{}", err);
+    }
+
+    #[test]
+    fn list_widget() {
+        use crate::gui::{DrawOp, ListView, ScrollPanel, ROW_HEIGHT};
+        let items: Vec<String> = (0..10).map(|i| format!("item {}", i)).collect();
+        let mut list = ListView::new(items);
+        let mut panel = ScrollPanel::new(10, 10, 100, 50, list.content_height());
+        assert_eq!(list.row_at(&panel, 20, 10 + ROW_HEIGHT + 1), Some(1));
+        // the scrollbar column has no rows
+        assert_eq!(list.row_at(&panel, 105, 20), None);
+        assert!(list.hover_at(&panel, 20, 15) && !list.hover_at(&panel, 20, 16));
+        // selecting a row below the viewport scrolls it into view
+        assert!(list.select(&mut panel, Some(5)));
+        assert_eq!(panel.offset, 6 * ROW_HEIGHT - 50);
+        assert!(!list.select(&mut panel, Some(5)));
+        let ops = list.draw(&panel);
+        let texts: Vec<&str> = ops.iter().filter_map(|op| match op { DrawOp::Text { text, .. } => Some(text.as_str()), _ => None }).collect();
+        // row 3 is cut by the top edge
+        assert_eq!(texts, ["item 4", "item 5"]);
+        assert!(ops.contains(&DrawOp::Rect { x: 10, y: 40, w: 88, h: 20, color: [204, 228, 247] }));
+
+        let mut vm = VM::new();
+        let src = "int-l = gui_list(1, 0, 0, 100, 60, list(\"a\", \"b\", \"c\", 4), \"on_pick\"); int-s = list_select(l, 3); int-n = list_set_items(l, list(\"x\")); int-t = list_selected(l);";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok());
+        assert_eq!((get("s"), get("n"), get("t")), (Some(3), Some(1), Some(-1)));
+        let err = vm.execute_program(Parser::new("int-p = gui_scroll_panel(1, 0, 0, 100, 60, 10, \"f\"); list_selected(p);").parse_program()).unwrap_err();
+        assert!(err.contains("not a list"), "{}", err);
+    }
+
+    #[test]
+    fn modal_dialog() {
+        use crate::gui::{Dialog, DrawOp};
+        let layout: Vec<String> = ["title: Save changes?", "text: The file was modified", "check: backup: Keep a backup: on", "button: save: Save", "button: cancel: Cancel"]
+            .iter().map(|s| s.to_string()).collect();
+        let mut d = Dialog::parse(&layout).unwrap();
+        d.center(800, 600);
+        let (w, h) = d.size();
+        assert_eq!((d.x, d.y), ((800 - w) / 2, (600 - h) / 2));
+        assert_eq!(d.checks(), [("backup".to_string(), true)]);
+        // the checkbox row is the second row under the title bar
+        assert!(d.click(d.x + 20, d.y + 24 + 12 + 25));
+        assert_eq!(d.checks(), [("backup".to_string(), false)]);
+        assert!(!d.click(0, 0) && d.result.is_none());
+        // buttons are flush right at the bottom; the last one is Cancel
+        assert!(d.click(d.x + w - 20, d.y + h - 20));
+        assert_eq!(d.result.as_deref(), Some("cancel"));
+        d.key(true);
+        assert_eq!(d.result.as_deref(), Some("save"));
+        assert!(d.draw().contains(&DrawOp::Text { x: d.x + 12, y: d.y + 4, text: "Save changes?".to_string() }));
+        assert!(Dialog::parse(&["text: no way out".to_string()]).unwrap_err().contains("button"));
+        assert!(Dialog::parse(&["slider: x".to_string()]).is_err());
+
+        let out = crate::vm::OutputBuffer::new();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(out.clone()));
+        let src = "DialogResult-r = gui_dialog(1, list(\"title: Hi\", \"text: Hello\", \"check: again: Ask again: on\", \"button: ok: OK\")); str-b = r.button; int-a = r.again;";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        assert_eq!(out.text(), "Hi: Hello\n");
+        assert_eq!(vm.get_global("b").and_then(|v| String::try_from(v).ok()).as_deref(), Some(""));
+        assert_eq!(vm.get_global("a").and_then(|v| i64::try_from(v).ok()), Some(1));
+    }
+
+    #[test]
+    fn focus_order() {
+        use crate::gui::FocusOrder;
+        let mut f = FocusOrder::default();
+        assert_eq!(f.advance(false), None);
+        for id in [10, 20, 30, 20] { f.add(id); }
+        assert_eq!(f.order, [10, 20, 30]);
+        assert_eq!(f.advance(true), Some(30));
+        assert_eq!(f.advance(false), Some(10));
+        assert_eq!(f.advance(false), Some(20));
+        assert!(f.set(30) && !f.set(99));
+        assert_eq!(f.advance(false), Some(10));
+
+        let mut vm = VM::new();
+        let src = "int-a = gui_list(77, 0, 0, 100, 60, list(\"x\"), \"f\"); int-b = gui_list(77, 0, 80, 100, 60, list(\"y\"), \"f\"); int-before = gui_focused(77); gui_focus(b); int-after = gui_focused(77);";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok());
+        assert_eq!((get("before"), get("after")), (Some(-1), get("b")));
+        assert!(vm.execute_program(Parser::new("gui_focus(12345);").parse_program()).unwrap_err().contains("no widget"));
+    }
+
+    #[test]
+    fn frame_timing() {
+        let mut vm = VM::deterministic(0);
+        // 50 fps = 20 ms frames; the second frame takes 45 ms of work
+        let src = "int-t0 = perf_now_us(); int-a = frame_wait(50); int-b = frame_wait(50); sleep_ms(45); int-c = frame_wait(50); int-t1 = perf_now_us();";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok()).unwrap();
+        assert_eq!((get("a"), get("b"), get("c")), (0, 0, 2));
+        // boundaries stay on the 20 ms grid: 20, 40, then 100 after skipping 60 and 80
+        assert_eq!(get("t1") - get("t0"), 100_000);
+        assert_eq!(vm.logical_time_ms(), Some(100));
+
+        let mut vm = VM::new();
+        let start = std::time::Instant::now();
+        vm.execute_program(Parser::new("frame_wait(100); frame_wait(100);").parse_program()).unwrap();
+        assert!(start.elapsed() >= std::time::Duration::from_millis(10));
+    }
+
+    #[test]
+    fn file_drop_events() {
+        let _events = GUI_EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+        use crate::gui::{push_drop, FileDrop};
+        let mut vm = VM::new();
+        let src = "int-n = 0; int-at = 0; rtd on_drop(paths, x, y) { int-n = len(paths); int-at = x + y; } gui_on_drop(55, \"on_drop\");";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        push_drop(FileDrop { window: 55, paths: vec!["a.csv".into(), "b.csv".into()], x: 3, y: 4 });
+        // a window without a handler drops its files silently
+        push_drop(FileDrop { window: 56, paths: vec!["c.csv".into()], x: 0, y: 0 });
+        vm.execute_program(Parser::new("gui_poll();").parse_program()).unwrap();
+        let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok());
+        assert_eq!((get("n"), get("at")), (Some(2), Some(7)));
+        assert!(crate::gui::take_drops().is_empty());
+    }
+
+    #[test]
+    fn screens_and_placement() {
+        let m = crate::gui::Monitor { bounds: (-1920, 0, 1920, 1080), work: (-1920, 0, 1920, 1040), primary: false };
+        let crate::vm::Value::Object(o) = crate::builtins::gui::screen_value(&m) else { panic!() };
+        let o = o.borrow();
+        assert_eq!(o.class_name, "Screen");
+        let field = |n: &str| o.fields.get(n).cloned().and_then(|v| i64::try_from(v).ok());
+        assert_eq!((field("x"), field("w"), field("work_h"), field("primary")), (Some(-1920), Some(1920), Some(1040), Some(0)));
+        let mut vm = VM::new();
+        vm.execute_program(Parser::new("list-s = screen_list(); int-n = len(s); gui_move(1, 10, 10); gui_fullscreen(1, 1);").parse_program()).unwrap();
+        #[cfg(not(target_os = "windows"))]
+        assert_eq!(vm.get_global("n").and_then(|v| i64::try_from(v).ok()), Some(0));
+    }
+
+    #[test]
+    fn color_utilities() {
+        use crate::color::Rgba;
+        assert_eq!(Rgba::parse_hex("#ff8800").map(Rgba::pack), Ok(0xFFFF8800));
+        assert_eq!(Rgba::parse_hex("f80"), Rgba::parse_hex("#ff8800"));
+        assert_eq!(Rgba::parse_hex("#11223344").map(|c| c.a), Ok(0x44));
+        assert!(Rgba::parse_hex("#12345").is_err() && Rgba::parse_hex("#gg0000").is_err());
+        assert!(Rgba::unpack(-1).is_err());
+        let orange = Rgba::from_hsl(30.0, 1.0, 0.5);
+        assert_eq!((orange.r, orange.g, orange.b), (255, 128, 0));
+        let (h, s, l) = orange.to_hsl();
+        assert!((h - 30.0).abs() < 0.5 && (s - 1.0).abs() < 0.01 && (l - 0.5).abs() < 0.01);
+        let mut vm = VM::new();
+        let src = "int-a = rgb(255, 136, 0); int-b = hex_color(\"#ff8800\"); int-c = hsl(0, 100, 50);\n\
+                   int-w = lighten(rgb(128, 128, 128), 100); int-k = darken(a, 100); int-m = mix(rgb(0, 0, 0), \"#ffffff\", 50);\n\
+                   int-win = gui_window(\"colors\", 8, 8); canvas_clear(win, a); draw_rect(win, 8, 8, 0, 0, 4, 4, \"#00ff00\");";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok());
+        assert_eq!(get("a"), get("b"));
+        assert_eq!(get("c"), Some(0xFFFF0000));
+        assert_eq!((get("w"), get("k")), (Some(0xFFFFFFFF), Some(0xFF000000)));
+        assert_eq!(get("m"), Some(0xFF808080));
+        let mut vm = VM::new();
+        let err = vm.execute_program(Parser::new("int-x = rgb(300, 0, 0);").parse_program()).unwrap_err();
+        assert!(err.to_string().contains("r must be 0..255"), "{}", err);
+    }
+
+    #[test]
+    fn channel_recv_timeout() {
+        let id = crate::builtins::channels::create();
+        let mut vm = VM::new();
+        let src = format!("Result-a = channel_recv_timeout({id}, 20); int-a_ok = a.ok;\n\
+                           channel_send({id}, \"done\"); Result-b = channel_recv_timeout({id}, 1000); int-b_ok = b.ok; str-msg = b.msg;");
+        let start = std::time::Instant::now();
+        vm.execute_program(Parser::new(&src).parse_program()).unwrap();
+        assert!(start.elapsed() >= std::time::Duration::from_millis(20));
+        assert!(start.elapsed() < std::time::Duration::from_millis(1000));
+        let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok());
+        assert_eq!((get("a_ok"), get("b_ok")), (Some(0), Some(1)));
+        assert!(matches!(vm.get_global("msg"), Some(crate::vm::Value::Str(s)) if s == "done"));
+        crate::builtins::channels::close(id);
+    }
+
+    #[test]
+    fn tweens() {
+        use crate::tween::Easing;
+        for name in Easing::NAMES {
+            let e = Easing::parse(name).unwrap();
+            assert!(e.apply(0.0).abs() < 1e-9 && (e.apply(1.0) - 1.0).abs() < 1e-9, "{}", name);
+        }
+        assert!(Easing::OutBack.apply(0.8) > 1.0);
+        assert!(Easing::parse("bouncy").is_err());
+        let mut vm = VM::deterministic(0);
+        let src = "list-seen = list(); rtd on_x(v) { push(seen, v); }\n\
+                   int-id = tween(10, 20, 100, \"linear\", \"on_x\");\n\
+                   sleep_ms(50); tween_update(); frame_wait(20); sleep_ms(100); int-left = tween_update(); int-more = tween_update();\n\
+                   int-c = tween(0, 1, 100, \"in_quad\", \"on_x\"); int-stopped = tween_cancel(c); int-again = tween_cancel(c);";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let Some(crate::vm::Value::List(seen)) = vm.get_global("seen") else { panic!() };
+        let seen: Vec<f64> = seen.borrow().iter().map(|v| f64::try_from(v.clone()).unwrap()).collect();
+        // halfway, then the 50 ms frame boundary at 100 ms is the end, then nothing more
+        assert_eq!(seen, vec![15.0, 20.0]);
+        let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok());
+        assert_eq!((get("left"), get("more"), get("stopped"), get("again")), (Some(0), Some(0), Some(1), Some(0)));
+        assert!(vm.execute_program(Parser::new("tween(0, 1, 10, \"linear\", \"missing\");").parse_program()).is_err());
+    }
+
+    #[test]
+    fn builtin_overrides() {
+        let mut vm = VM::new();
+        let src = "int-calls = 0;\n\
+                   rtd fake_get(prompt) { int-calls = calls + 1; \"42\"; }\n\
+                   rtd counted(x) { int-calls = calls + 1; original_builtin(\"to_int\", list(x)); }\n\
+                   override_builtin(\"get\", \"fake_get\"); override_builtin(\"to_int\", \"counted\");\n\
+                   int-n = to_int(get(\"number? \")) + 1; int-restored = restore_builtin(\"to_int\"); int-m = to_int(\"5\");";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok());
+        assert_eq!((get("n"), get("calls"), get("restored"), get("m")), (Some(43), Some(2), Some(1), Some(5)));
+        assert!(vm.help("get").unwrap().starts_with("get(prompt) -> str"));
+        for bad in ["override_builtin(\"to_int\", \"fake_get2\");", "rtd two(a, b) { a; } override_builtin(\"to_int\", \"two\");", "rtd one(a) { a; } override_builtin(\"len\", \"one\");"] {
+            assert!(VM::new().execute_program(Parser::new(bad).parse_program()).is_err(), "{}", bad);
+        }
+
+        let mut vm = VM::new();
+        let log = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let seen = log.clone();
+        vm.wrap_builtin("casefold", move |vm, args, original| {
+            seen.borrow_mut().push(args.len());
+            original(vm, args)
+        }).unwrap();
+        vm.execute_program(Parser::new("str-s = casefold(\"ABC\");").parse_program()).unwrap();
+        assert!(matches!(vm.get_global("s"), Some(crate::vm::Value::Str(s)) if s == "abc"));
+        assert_eq!(*log.borrow(), vec![1]);
+    }
+
+    #[test]
+    fn spawn_with_arguments() {
+        let id = crate::builtins::channels::create();
+        let mut vm = VM::new();
+        let src = format!("int-ch = {id}; str-greeting = \"hi\";\n\
+                           rtd worker(name, n) {{ channel_send(ch, greeting); channel_send(ch, name); int-greeting = n; }}\n\
+                           rtd ping() {{ channel_send(ch, \"ping\"); }}\n\
+                           spawn_with(\"worker\", list(\"bob\", 7)); Result-ra = channel_recv_timeout(ch, 2000); str-a = ra.msg; str-name = channel_recv(ch);\n\
+                           spawn(\"ping\"); Result-rb = channel_recv_timeout(ch, 2000); str-b = rb.msg;");
+        vm.execute_program(Parser::new(&src).parse_program()).unwrap();
+        let s = |n: &str| match vm.get_global(n) { Some(crate::vm::Value::Str(s)) => s, _ => String::new() };
+        assert_eq!((s("a"), s("name"), s("b")), ("hi".to_string(), "bob".to_string(), "ping".to_string()));
+        // the worker changed its own copy
+        assert_eq!(s("greeting"), "hi");
+        let err = vm.execute_program(Parser::new("spawn(\"worker\");").parse_program()).unwrap_err();
+        assert!(err.to_string().contains("takes 2 argument(s)"), "{}", err);
+
+        let src = "rtd square(x) { x * x; } rtd broken() { undefined_thing(); }\n\
+                   int-h = spawn_with(\"square\", list(9)); int-sq = join(h); int-b = spawn(\"broken\");";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        assert_eq!(vm.get_global("sq").and_then(|v| i64::try_from(v).ok()), Some(81));
+        let err = vm.execute_program(Parser::new("join(b);").parse_program()).unwrap_err();
+        assert!(err.starts_with("join: worker failed: broken: ") && err.contains("undefined_thing"), "{}", err);
+        let err = vm.execute_program(Parser::new("join(b);").parse_program()).unwrap_err();
+        assert!(err.contains("already joined"), "{}", err);
+        crate::builtins::channels::close(id);
+    }
+
+    #[test]
+    fn detach_and_cancel_join() {
+        use std::time::{Duration, Instant};
+        let mut vm = VM::new();
+        let src = "rtd slow() { sleep_ms(5000); 1; }\n\
+                   int-h = spawn(\"slow\"); int-d1 = detach(h); int-d2 = detach(h); int-after = 5;";
+        let started = Instant::now();
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        // the detached worker is still sleeping; the VM did not wait for it
+        assert!(started.elapsed() < Duration::from_secs(2));
+        let get = |vm: &VM, n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok());
+        assert_eq!((get(&vm, "d1"), get(&vm, "d2"), get(&vm, "after")), (Some(1), Some(0), Some(5)));
+        let err = vm.execute_program(Parser::new("join(h);").parse_program()).unwrap_err();
+        assert!(err.contains("already joined"), "{}", err);
+
+        // a cancel ends a join on a worker that is still running
+        vm.execute_program(Parser::new("int-h2 = spawn(\"slow\");").parse_program()).unwrap();
+        let token = vm.cancel_token();
+        std::thread::spawn(move || { std::thread::sleep(Duration::from_millis(50)); token.cancel(); });
+        let started = Instant::now();
+        let err = vm.execute_program(Parser::new("join(h2);").parse_program()).unwrap_err();
+        assert_eq!(err, crate::vm::CANCELLED);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        // the handle is still there to detach
+        vm.execute_program(Parser::new("int-d3 = detach(h2);").parse_program()).unwrap();
+        assert_eq!(get(&vm, "d3"), Some(1));
+    }
+
+    #[test]
+    fn frozen_time_and_seeded_random() {
+        let src = "int-t0 = time_now(); int-p0 = perf_now_us(); sleep_ms(250); int-t1 = time_now(); int-p1 = perf_now_us(); int-r = secure_random(1000000);";
+        let run = |seed: u64| {
+            let mut vm = VM::new();
+            vm.freeze_time(1_700_000_000_000);
+            vm.seed_random(seed);
+            let start = std::time::Instant::now();
+            vm.execute_program(Parser::new(src).parse_program()).unwrap();
+            assert!(start.elapsed() < std::time::Duration::from_millis(250));
+            let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok()).unwrap();
+            (get("t0"), get("t1") - get("t0"), get("p1") - get("p0"), get("r"))
+        };
+        let (t0, dt, dp, r) = run(3);
+        assert_eq!((t0, dt, dp), (1_700_000_000_000, 250, 250_000));
+        assert_eq!(run(3).3, r);
+        // the clock alone can be faked; get then still reads real stdin
+        let mut vm = VM::new();
+        vm.freeze_time(5);
+        vm.execute_program(Parser::new("int-t = time_now();").parse_program()).unwrap();
+        assert_eq!(vm.get_global("t").and_then(|v| i64::try_from(v).ok()), Some(5));
+        assert_eq!(vm.logical_time_ms(), Some(0));
+        assert_eq!(VM::new().logical_time_ms(), None);
+    }
+
+    #[test]
+    fn gui_event_replay() {
+        use crate::gui::{encode_events, parse_events, FileDrop, GuiEvent, TimedEvent};
+        let _events = GUI_EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+        let mut vm = VM::new();
+        let setup = "int-win = gui_window(\"t\", 100, 100); int-sum = 0; int-files = 0;\n\
+                     rtd on_ok(x, y) { int-sum = sum + x + y; } rtd on_files(paths, x, y) { int-files = len(paths); }\n\
+                     int-b = gui_button(win, \"OK\", \"on_ok\"); gui_on_drop(win, \"on_files\");";
+        vm.execute_program(Parser::new(setup).parse_program()).unwrap();
+        let get = |vm: &VM, n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok()).unwrap();
+        let (win, button) = (get(&vm, "win") as u64, get(&vm, "b") as u64);
+        let events = vec![
+            TimedEvent { at_ms: 0, event: GuiEvent::Click { target: button, x: 3, y: 4 } },
+            TimedEvent { at_ms: 20, event: GuiEvent::Drop(FileDrop { window: win, paths: vec!["a b.txt".into(), "c.txt".into()], x: 1, y: 2 }) },
+            TimedEvent { at_ms: 40, event: GuiEvent::Click { target: button, x: 10, y: 0 } },
+        ];
+        let text = encode_events(&events);
+        assert_eq!(parse_events(&text).unwrap(), events);
+        assert!(parse_events("0\tclick\t1\t2").is_err() && parse_events("0\tpress\t1\t2\t3").is_err());
+
+        vm.gui_events().start_replay(events.clone());
+        vm.gui_events().start_recording();
+        let start = std::time::Instant::now();
+        // with no real windows, gui_run lasts until the replay is over
+        vm.execute_program(Parser::new("gui_run();").parse_program()).unwrap();
+        assert!(start.elapsed() >= std::time::Duration::from_millis(40));
+        assert_eq!((get(&vm, "sum"), get(&vm, "files")), (17, 2));
+        let recorded = vm.gui_events().finish_recording();
+        assert_eq!(recorded.iter().map(|e| e.event.clone()).collect::<Vec<_>>(), events.into_iter().map(|e| e.event).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn shared_cells() {
+        let mut vm = VM::new();
+        let src = "int-cell = shared_create(list(1, 2)); int-flag = shared_create(0);\n\
+                   rtd worker() { list-xs = shared_get(cell); push(xs, 3); shared_set(cell, xs); shared_cas(flag, 0, 7); }\n\
+                   int-won = join(spawn(\"worker\")); int-lost = shared_cas(flag, 0, 9);\n\
+                   list-seen = shared_get(cell); int-n = len(seen); int-f = shared_get(flag);\n\
+                   push(seen, 4); int-still = len(shared_get(cell));\n\
+                   int-del = shared_delete(cell); int-again = shared_delete(cell);";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok());
+        assert_eq!((get("won"), get("lost"), get("n"), get("f"), get("still")), (Some(1), Some(0), Some(3), Some(7), Some(3)));
+        assert_eq!((get("del"), get("again")), (Some(1), Some(0)));
+        let err = vm.execute_program(Parser::new("shared_get(0 - 1);").parse_program()).unwrap_err();
+        assert!(err.contains("no shared cell"), "{}", err);
+        let err = vm.execute_program(Parser::new("shared_get(cell);").parse_program()).unwrap_err();
+        assert!(err.contains("no shared cell"), "{}", err);
+    }
+
+    #[test]
+    fn shared_delete_frees_the_cell() {
+        let mut vm = VM::new();
+        // deleted on another thread, gone for every thread
+        let src = "int-cell = shared_create(5); rtd drop(id) { shared_delete(id); }\n\
+                   int-first = join(spawn_with(\"drop\", list(cell))); int-second = shared_delete(cell); int-third = shared_delete(cell);";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok());
+        assert_eq!((get("first"), get("second"), get("third")), (Some(1), Some(0), Some(0)));
+        let id = get("cell").unwrap();
+        for (src, who) in [("shared_get(cell);", "shared_get"), ("shared_set(cell, 1);", "shared_set"), ("shared_cas(cell, 5, 6);", "shared_cas")] {
+            let err = vm.execute_program(Parser::new(src).parse_program()).unwrap_err();
+            assert!(err.contains(&format!("{}: no shared cell {}", who, id)), "{}", err);
+        }
+        // a failed set does not bring the cell back
+        vm.execute_program(Parser::new("int-fourth = shared_delete(cell);").parse_program()).unwrap();
+        assert_eq!(vm.get_global("fourth").and_then(|v| i64::try_from(v).ok()), Some(0));
+    }
+
+    #[test]
+    fn canvas_snapshots() {
+        use crate::raster::Raster;
+        let mut img = Raster::new(3, 2);
+        img.fill_rect(1, 0, 5, 1, [10, 20, 30, 255]);
+        assert_eq!(Raster::from_bmp(&img.to_bmp()), Ok(img.clone()));
+        let mut near = img.clone();
+        near.fill_rect(0, 1, 1, 1, [250, 255, 255, 255]);
+        assert_eq!(img.compare(&near, 5), Ok(None));
+        assert_eq!(img.compare(&near, 4).map(|m| m.map(|m| (m.count, m.first))), Ok(Some((1, (0, 1)))));
+        assert!(img.compare(&Raster::new(2, 3), 0).is_err());
+
+        if cfg!(target_os = "windows") { return; }
+        let dir = std::env::temp_dir().join(format!("userd-golden-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let golden = dir.join("box.bmp").to_string_lossy().replace('\\', "/");
+        let mut vm = VM::new();
+        vm.set_test_mode(true);
+        let src = format!("int-win = gui_window(\"t\", 16, 8); canvas_clear(win, \"#202020\"); draw_rect(win, 16, 8, 2, 2, 4, 4, \"#ff0000\");\n\
+                           int-first = assert_canvas_matches(win, \"{0}\", 0);\nint-again = assert_canvas_matches(win, \"{0}\", 0);\n\
+                           draw_rect(win, 16, 8, 10, 0, 2, 1, \"#212121\");\nint-close = assert_canvas_matches(win, \"{0}\", 1);\n\
+                           draw_rect(win, 16, 8, 10, 0, 2, 1, \"#00ff00\");\nint-off = assert_canvas_matches(win, \"{0}\", 1);", golden);
+        vm.execute_program(Parser::new(&src).parse_program()).unwrap();
+        let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok());
+        assert_eq!((get("first"), get("again"), get("close"), get("off")), (Some(1), Some(1), Some(1), Some(0)));
+        let failures = vm.take_failures();
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].message.contains("in 2 pixel(s), first at (10, 0)"), "{}", failures[0].message);
+        let actual = Raster::from_bmp(&std::fs::read(dir.join("box.actual.bmp")).unwrap()).unwrap();
+        assert_eq!(actual.pixels[40..44], [0, 255, 0, 255]);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn timers_in_gui_loop() {
+        let mut timers = crate::timer::Timers::default();
+        let every = timers.add(10, Some(10), "every".to_string());
+        timers.add(25, None, "once".to_string());
+        assert_eq!(timers.due(9), vec![]);
+        // 3 periods behind: one call, then the next boundary after now
+        assert_eq!(timers.due(42), vec![(every, "every".to_string()), (2, "once".to_string())]);
+        assert_eq!((timers.len(), timers.due(49).len(), timers.due(50).len()), (1, 0, 1));
+        assert!(timers.cancel(every) && !timers.cancel(every) && timers.is_empty());
+
+        let mut vm = VM::new();
+        vm.freeze_time(0);
+        let src = "list-seen = list(); rtd tick() { push(seen, perf_now_us() / 1000); } rtd stop() { clear_timer(iv); push(seen, 0); }\n\
+                   int-iv = set_interval(30, \"tick\"); int-t = set_timeout(100, \"stop\"); int-gone = clear_timer(set_timeout(10, \"stop\"));\n\
+                   gui_run(); int-after = clear_timer(t);";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let Some(crate::vm::Value::List(seen)) = vm.get_global("seen") else { panic!() };
+        let seen: Vec<i64> = seen.borrow().iter().map(|v| i64::try_from(v.clone()).unwrap()).collect();
+        // the loop ticks every 20 ms; the interval due at 90 fires before the timeout due at 100
+        assert_eq!(seen, vec![40, 60, 100, 0]);
+        let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok());
+        assert_eq!((get("gone"), get("after")), (Some(1), Some(0)));
+        assert!(vm.execute_program(Parser::new("set_interval(0, \"tick\");").parse_program()).is_err());
+        assert!(vm.execute_program(Parser::new("set_timeout(5, \"missing\");").parse_program()).is_err());
+    }
+
+    #[test]
+    fn compile_rejects_parse_errors() {
+        let good = "os: linux\nint-x = 1;\nx;";
+        assert_eq!(crate::artifact::compile_checked(good).ok(), Some(crate::artifact::compile(good)));
+        let errors = crate::artifact::compile_checked("int-x = 1;\n) ) );\nint-y = 2;").unwrap_err();
+        assert_eq!(errors.first().and_then(|e| e.line()), Some(2), "{:?}", errors);
+        let d = crate::diagnostics::Diagnostic::from_parse_error("bad.usrd", &errors[0]);
+        assert_eq!((d.code, d.line), ("E0004", Some(2)));
+        assert!(d.to_json().contains(r#""span":{"line":2}"#));
+    }
+
+    #[test]
+    fn cooperative_tasks() {
+        let mut vm = VM::new();
+        let src = "list-log = list();\n\
+                   rtd a() { push(log, \"a1\"); task_yield(); push(log, \"a2\"); task_yield(); push(log, \"a3\"); }\n\
+                   rtd b() { push(log, \"b1\"); task_spawn(\"c\"); task_yield(); push(log, \"b2\"); }\n\
+                   rtd c() { push(log, \"c1\"); }\n\
+                   int-outside = task_yield(); task_spawn(\"a\"); task_spawn(\"b\"); int-done = run_tasks(); int-again = run_tasks();";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let Some(crate::vm::Value::List(log)) = vm.get_global("log") else { panic!() };
+        let log: Vec<String> = log.borrow().iter().map(|v| match v { crate::vm::Value::Str(s) => s.clone(), _ => panic!() }).collect();
+        assert_eq!(log, ["a1", "b1", "a2", "c1", "b2", "a3"]);
+        let get = |n: &str| vm.get_global(n).and_then(|v| i64::try_from(v).ok());
+        assert_eq!((get("outside"), get("done"), get("again")), (Some(0), Some(3), Some(0)));
+        // the GUI loop gives the tasks their turns too
+        vm.execute_program(Parser::new("task_spawn(\"a\"); gui_run();").parse_program()).unwrap();
+        assert_eq!(vm.get_global("log").map(|l| match l { crate::vm::Value::List(l) => l.borrow().len(), _ => 0 }), Some(9));
+        let err = vm.execute_program(Parser::new("rtd bad() { run_tasks(); } task_spawn(\"bad\"); run_tasks();").parse_program()).unwrap_err();
+        assert!(err.contains("task 5 (bad): run_tasks: cannot be called from a task"), "{}", err);
+        assert!(vm.execute_program(Parser::new("rtd one(x) { x; } task_spawn(\"one\");").parse_program()).is_err());
+    }
+
+    #[test]
+    fn token_spans_and_ast_json() {
+        let mut l = Lexer::new("int-x = 1; // note\n  f(\"ё\");");
+        let mut spans = Vec::new();
+        loop {
+            let t = l.next_token();
+            if t.is_eof() { break; }
+            spans.push((t.to_string(), l.span()));
+        }
+        let at = |i: usize| (spans[i].0.as_str(), spans[i].1.line, spans[i].1.col, spans[i].1.start, spans[i].1.end);
+        assert_eq!(at(0), ("int", 1, 1, 0, 3));
+        assert_eq!(at(6), ("f", 2, 3, 21, 22));
+        assert_eq!(at(8), ("\"ё\"", 2, 5, 23, 26));
+        let prog = Parser::new("rtd f(int-a) { a.b(2.5, \"q\\\"); } f(1 - 2);").parse_program();
+        let json = crate::ast::to_json(&prog);
+        assert!(json.starts_with(r#"[{"kind":"FunctionDecl","name":"f","params":["a"],"param_types":["int"],"ret_type":null,"body":[{"kind":"ExprStmt""#), "{}", json);
+        assert!(json.contains(r#""method":"b","args":[{"kind":"Float","value":2.5},{"kind":"Str","value":"q\\"}]"#), "{}", json);
+        assert!(json.ends_with(r#""args":[{"kind":"BinaryOp","op":"-","left":{"kind":"Int","value":1},"right":{"kind":"Int","value":2}}]}}]"#), "{}", json);
+    }
+
+    #[test]
+    fn artifact_info() {
+        use crate::artifact::{info, PACK_MARKER};
+        let bytes = crate::artifact::compile_with_meta("os: windows\nint-x = 1;\nx;", &["policy: sandbox"]);
+        let art = info(&bytes).unwrap();
+        assert_eq!((art.exe_size, art.stored_size, art.version), (None, bytes.len(), Some(2)));
+        assert_eq!((art.header("os:"), art.header("Policy:"), art.statements, art.checksum_ok), (Some("windows"), Some("sandbox"), Some(2), Some(true)));
+        assert_eq!(art.header("compiler:"), Some(concat!("userd ", env!("CARGO_PKG_VERSION"))));
+        let mut exe = b"MZ fake exe".to_vec();
+        exe.extend_from_slice(PACK_MARKER);
+        exe.extend_from_slice(&crate::gzip::compress(&bytes));
+        let packed = info(&exe).unwrap();
+        assert_eq!((packed.exe_size, packed.meta, packed.payload_size), (Some(11), art.meta.clone(), art.payload_size));
+        let mut bad = bytes.clone();
+        let last = bad.len() - 9;
+        bad[last] ^= 1;
+        assert_eq!(info(&bad).unwrap().checksum_ok, Some(false));
+        let mut legacy = b"__USRDMETA__\nos: linux\n__USRDSRC__\n".to_vec();
+        legacy.extend_from_slice(b"int-x = 1;");
+        let old = info(&legacy).unwrap();
+        assert_eq!((old.version, old.header("os:"), old.payload_size, old.checksum_ok), (None, Some("linux"), 10, None));
+        assert!(info(b"hello").is_err());
+        let mut binary = b"\x7fELF".to_vec();
+        binary.extend_from_slice(PACK_MARKER);
+        binary.extend_from_slice(&[0xff, 0xfe, 0x00]);
+        assert!(info(&binary).unwrap_err().contains("no script"));
+    }
+
+    #[test]
+    fn repl_completions() {
+        use crate::repl::completions;
+        let mut vm = VM::new();
+        let src = "class Point(x, y) { rtd norm(self) { self.x; } }\nclass Box(point, label);\nPoint-p = Point(1, 2);\nBox-b = Box(p, \"l\");\nint-channel_count = 3;";
+        vm.execute_program(Parser::new(src).parse_program()).unwrap();
+        let (start, names) = completions(&vm, "int-n = channel_c");
+        assert_eq!((start, names), (8, vec!["channel_close".to_string(), "channel_count".to_string(), "channel_create".to_string()]));
+        assert_eq!(completions(&vm, "p.").1, ["norm", "x", "y"]);
+        assert_eq!(completions(&vm, "len(b.point.n"), (12, vec!["norm".to_string()]));
+        assert_eq!(completions(&vm, "b.lab").1, ["label"]);
+        assert!(completions(&vm, "nothing.").1.is_empty());
+        assert_eq!(completions(&vm, "li").1, ["lighten", "list", "list_dir", "list_select", "list_selected", "list_set_items"]);
+        assert_eq!(completions(&vm, ":re").1, [":restore-session ", ":reset", ":record", ":replay"]);
+    }
+
+    #[test]
+    fn usage_hook() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        let runs: Rc<RefCell<Vec<crate::usage::Usage>>> = Rc::default();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(std::io::sink()));
+        let sink = runs.clone();
+        vm.set_usage_hook(move |u| sink.borrow_mut().push(u.clone()));
+        let src = "class P { rtd get(self) { 1; } }\nrtd f(int-a) -> int { len(list(a, 2.5)); }\nf(1);\nf(2);\nint-n = to_int(1 + 2);\n";
+        vm.eval(src).unwrap();
+        assert!(vm.eval("undefined_thing(1);").is_err());
+        let runs = runs.borrow();
+        assert_eq!(runs.len(), 2);
+        let u = &runs[0];
+        assert_eq!(u.builtins.get("len"), Some(&2));
+        assert_eq!(u.builtins.get("list"), Some(&2));
+        assert_eq!(u.builtins.get("to_int"), Some(&1));
+        for (feature, n) in [("class", 1), ("method", 1), ("function", 1), ("typed_param", 1), ("return_type", 1), ("float_literal", 1), ("arithmetic", 1)] {
+            assert_eq!(u.features.get(feature), Some(&n), "{}", feature);
+        }
+        assert!(!u.features.contains_key("data_class"));
+        assert!(u.features.keys().all(|f| crate::usage::FEATURES.contains(f)));
+        // counts start over for each run, failed ones included
+        assert!(runs[1].builtins.is_empty());
+    }
+
+    #[test]
+    fn repl_input_complete() {
+        use crate::repl::input_complete;
+        assert!(input_complete("int-x = 1;"));
+        assert!(!input_complete("class P {"));
+        assert!(!input_complete("class P {\n  rtd get(self) { 1; }"));
+        assert!(input_complete("class P {\n  rtd get(self) { 1; }\n}"));
+        assert!(!input_complete("print(1,\n"));
+        assert!(!input_complete("str-s = \"a; {"));
+        assert!(input_complete("str-s = \"a; {\";"));
+        assert!(input_complete("print(\"http://x\");"));
+        assert!(!input_complete("int-x = 1 // done;"));
+        assert!(input_complete("int-x = 1; // done {"));
+        assert!(!input_complete("int-x = 1"));
+        // a stray closer goes to the parser, which reports it
+        assert!(input_complete("}"));
+    }
+
+    #[test]
+    fn prelude_api() {
+        use crate::prelude::*;
+        let out = OutputBuffer::new();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(out.clone()));
+        vm.set_policy(Policy::sandboxed());
+        vm.register_builtin("twice", 1, |_, args: &[Value]| {
+            let (n,): (i64,) = from_args("twice", args)?;
+            Ok(Value::Int(n * 2))
+        });
+        let usage = std::rc::Rc::new(std::cell::Cell::new(0));
+        let seen = usage.clone();
+        vm.set_usage_hook(move |u: &Usage| seen.set(u.builtins.len()));
+        vm.eval("twice(21);").unwrap();
+        assert_eq!(out.text().trim(), "42");
+        assert_eq!(usage.get(), 1);
+        assert!(matches!(vm.eval("int-x = 1 + #;"), Err(Error::Lex { .. })));
+        assert!(matches!(vm.eval("list_dir(\".\");"), Err(Error::Runtime { .. })));
+        assert!(parse("int-x = 1;").is_ok());
+        let mut group = VmGroup::new();
+        assert!(group.add("a", VM::new()).is_ok());
+    }
+
+    #[test]
+    fn repl_commands() {
+        let vm = VM::new();
+        let (_, options) = crate::repl::completions(&vm, ":re");
+        assert_eq!(options, [":restore-session ", ":reset", ":record", ":replay"]);
+        assert_eq!(crate::repl::completions(&vm, ":he").1, [":help "]);
+        assert_eq!(crate::repl::completions(&vm, ":help to_in"), (6, vec!["to_int".to_string()]));
+        // `:reset` hands the new VM the Ctrl-C token of the old one
+        let token = vm.cancel_token();
+        let mut fresh = VM::new();
+        fresh.set_output(Box::new(std::io::sink()));
+        fresh.set_cancel_token(token.clone());
+        token.cancel();
+        let err = fresh.eval("int-x = 1;").unwrap_err();
+        assert!(err.to_string().contains(crate::vm::CANCELLED));
+    }
+
+    #[test]
+    fn printing_stops_on_cycles() {
+        let out = crate::vm::OutputBuffer::new();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(out.clone()));
+        vm.set_pretty_objects(true);
+        vm.set_display_limits(None);
+        // a statement that is only an expression prints its value
+        let src = "class Node { rtd __init__(self, v) { self.v = v; } }\nNode-n = Node(1);\nn.next = n;\nn;\n\
+                   list-xs = list(1);\nint-k = push(xs, xs);\nxs;\n\
+                   Node-m = Node(xs);\nint-j = push(xs, m);\nm;\nxs;\n";
+        vm.eval(src).unwrap();
+        assert_eq!(out.text(), "Node { v: 1, next: <cycle> }\n[1, <cycle>]\nNode { v: [1, <cycle>, <cycle>] }\n[1, <cycle>, Node { v: <cycle> }]\n");
+    }
+
+    #[test]
+    fn repr_values() {
+        let out = crate::vm::OutputBuffer::new();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(out.clone()));
+        let src = "class Node { rtd __init__(self, v) { self.v = v; self.tags = list(\"a\", 2); } }\nNode-n = Node(1);\nn.next = n;\nstr-r = repr(n);\nstr-l = repr(list(1.5, Node));\nn;\n";
+        vm.eval(src).unwrap();
+        let text = |vm: &VM, name| match vm.get_global(name) { Some(crate::vm::Value::Str(s)) => s, v => panic!("{:?}", v) };
+        assert_eq!(text(&vm, "r"), "Node { v: 1, tags: [\"a\", 2], next: <cycle> }");
+        assert_eq!(text(&vm, "l"), "[1.5, <class Node>]");
+        assert_eq!(out.text().trim(), "<object>");
+        vm.set_pretty_objects(true);
+        vm.set_display_limits(Some(crate::vm::DisplayLimits { max_items: 1, max_depth: 1 }));
+        vm.eval("n;").unwrap();
+        assert_eq!(out.text().lines().last(), Some("Node { v: 1, ... (2 more) }"));
+        vm.eval("str-d = repr(list(n));").unwrap();
+        assert_eq!(text(&vm, "d"), "[Node {...}]");
+    }
+
+    #[test]
+    fn repl_highlight_and_error_spans() {
+        let line = "rtd f(x) { x + 12; } // \"done\"";
+        let colored = crate::repl::highlight(line);
+        assert!(colored.starts_with("\x1b[35mrtd\x1b[0m f(x)"));
+        assert!(colored.contains("\x1b[36m12\x1b[0m"));
+        assert!(colored.ends_with("\x1b[90m// \"done\"\x1b[0m"));
+        // removing the escapes gives the line back
+        let plain: String = colored.split('\x1b').enumerate()
+            .map(|(i, part)| if i == 0 { part } else { &part[part.find('m').unwrap() + 1..] })
+            .collect();
+        assert_eq!(plain, line);
+        assert_eq!(crate::repl::highlight("str-s = \"open"), "str-s = \x1b[32m\"open\x1b[0m");
+
+        let mut p = Parser::new("int-a = 1;\nint-b = 2 ) ;");
+        p.parse_program();
+        assert_eq!(p.errors().len(), 1);
+        let span = p.error_spans()[0];
+        assert_eq!((span.line, span.col, span.end - span.start), (2, 11, 1));
+    }
+
+    #[test]
+    fn vm_step_count() {
+        let mut vm = VM::new();
+        vm.set_output(Box::new(std::io::sink()));
+        vm.eval("rtd f(x) { x + 1; x + 2; } int-a = f(1);").unwrap();
+        // the declaration, then the statement calling f and the two in its body
+        assert_eq!(vm.steps(), 4);
+        // counted per run
+        vm.eval("int-b = f(1); int-c = f(2);").unwrap();
+        assert_eq!(vm.steps(), 6);
+        assert_eq!(crate::repl::completions(&vm, ":ti").1, [":time "]);
+    }
+
+    #[test]
+    fn repl_paste() {
+        let mut input = std::io::Cursor::new("class P {\n\trtd get(self) { 5; }\n}\n:end\nafter;\n");
+        let text = crate::repl::read_paste(&mut input);
+        assert_eq!(text, "class P {\n\trtd get(self) { 5; }\n}\n");
+        // the rest stays for the prompt
+        let mut rest = String::new();
+        std::io::Read::read_to_string(&mut input, &mut rest).unwrap();
+        assert_eq!(rest, "after;\n");
+        // without `:end` everything up to the end of input
+        assert_eq!(crate::repl::read_paste(&mut std::io::Cursor::new("int-x = 1;")), "int-x = 1;");
+        assert_eq!(crate::repl::completions(&VM::new(), ":pa").1, [":paste"]);
+    }
+
+    #[test]
+    fn sessions_resume() {
+        use crate::session::Session;
+        use crate::web_server::{query_param, run_code, upload_session, ServerConfig};
+        let mut vm = VM::new();
+        vm.set_output(Box::new(std::io::sink()));
+        vm.eval("class C { } C-c = C(); c.n = 41;").unwrap();
+        let bytes = Session::capture(&vm, vec!["c.n = 41;".to_string()]).encode();
+        let session = Session::decode(&bytes).unwrap();
+        assert_eq!(session.inputs, ["c.n = 41;"]);
+        let mut resumed = VM::new();
+        resumed.set_output(Box::new(std::io::sink()));
+        session.restore_into(&mut resumed).unwrap();
+        resumed.eval("int-m = c.n + 1;").unwrap();
+        assert_eq!(resumed.get_global("m").and_then(|v| i64::try_from(v).ok()), Some(42));
+        let mut bad = bytes.clone();
+        *bad.last_mut().unwrap() ^= 1;
+        assert!(Session::decode(&bad).is_err());
+
+        // the web editor continues an uploaded session
+        let uploaded = upload_session(&bytes);
+        assert!(uploaded.contains("\"inputs\":[\"c.n = 41;\"]"), "{}", uploaded);
+        let id = uploaded.split("\"session\":\"").nth(1).unwrap().split('"').next().unwrap();
+        // random, not a counter another client could guess
+        assert!(id.len() == 32 && id.bytes().all(|b| b.is_ascii_hexdigit()), "{}", id);
+        assert!(!upload_session(&bytes).contains(id));
+        let res = run_code("c.n + 1;", ServerConfig::default(), None, Some(id));
+        assert!(res.contains("\"output\":\"42\\n\""), "{}", res);
+        assert!(run_code("1;", ServerConfig::default(), None, Some("1")).contains("unknown session"));
+        // a crafted snapshot of 200k nested lists is refused instead of overflowing the stack
+        let mut payload = crate::artifact::Writer::default();
+        payload.u32(1);
+        payload.str("");
+        for _ in 0..200_000 { payload.u8(7); payload.u8(1); payload.u32(1); }
+        payload.u8(0); payload.u64(0);
+        let mut deep = crate::artifact::Writer::default();
+        deep.buf.extend_from_slice(b"USRDS\x02\x00");
+        deep.u64(payload.buf.len() as u64);
+        deep.buf.extend_from_slice(&payload.buf);
+        deep.u64(crate::artifact::fnv1a(&payload.buf));
+        let err = crate::snapshot::decode(&deep.buf).unwrap_err();
+        assert!(err.contains("nested more than"), "{}", err);
+        let crafted = Session { inputs: Vec::new(), snapshot: deep.buf }.encode();
+        assert!(Session::decode(&crafted).is_err());
+        assert!(upload_session(&crafted).contains("\"ok\":false"));
+        let huge = Session { inputs: vec!["x".repeat(2 << 20)], snapshot: vm.snapshot() }.encode();
+        assert!(upload_session(&huge).contains("session too large to keep"));
+        assert_eq!(query_param("a=1&path=n.kids%2E1&q=a+b%zz", "path").as_deref(), Some("n.kids.1"));
+        assert_eq!(query_param("a=1&q=a+b%zz", "q").as_deref(), Some("a b%zz"));
+    }
+
+    #[test]
+    fn query_params_are_url_decoded() {
+        use crate::web_server::query_param;
+        for (query, want) in [
+            ("v=1%2C2%2c3", "1,2,3"),
+            ("v=a+b++c", "a b  c"),
+            ("v=a%2Bb", "a+b"),
+            ("v=100%", "100%"),
+            ("v=50%2", "50%2"),
+            ("v=%zz%2", "%zz%2"),
+            ("v=%41%zz%42", "A%zzB"),
+        ] {
+            assert_eq!(query_param(query, "v").as_deref(), Some(want), "{}", query);
+        }
+        // names are decoded too, and the first match wins
+        assert_eq!(query_param("x=1&my+key=2&my%20key=3", "my key").as_deref(), Some("2"));
+        assert_eq!(query_param("flag&v=1", "flag").as_deref(), Some(""));
+        assert_eq!(query_param("v=1", "w"), None);
+    }
+
+    #[test]
+    fn token_stream_json() {
+        let tokens = crate::lexer::Lexer::tokens("int-x = \"a\";");
+        assert_eq!(tokens.len(), 7);
+        assert!(tokens.last().unwrap().0.is_eof());
+        let (t, span) = &tokens[4];
+        assert_eq!(t.to_json(span), "{\"kind\":\"Str\",\"text\":\"\\\"a\\\"\",\"line\":1,\"col\":9,\"start\":8,\"end\":11}");
+        assert_eq!(tokens[6].0.to_json(&tokens[6].1), "{\"kind\":\"Eof\",\"text\":\"\",\"line\":1,\"col\":13,\"start\":12,\"end\":12}");
+    }
+
+    #[test]
+    fn shebang_line() {
+        let src = "#!/usr/bin/env userd\nint-x = 3;\nundefined_call();\n";
+        let tokens = crate::lexer::Lexer::tokens(src);
+        assert_eq!((tokens[0].1.line, tokens[0].1.col), (2, 1));
+        let mut vm = VM::new();
+        vm.set_output(Box::new(std::io::sink()));
+        assert_eq!(vm.eval(src).unwrap_err().line(), Some(3));
+        assert_eq!(vm.get_global("x").and_then(|v| i64::try_from(v).ok()), Some(3));
+        // only a first line is a shebang
+        assert!(matches!(vm.eval("int-y = 1;\n#!x\n"), Err(crate::Error::Lex { line: 2, .. })));
+        assert!(crate::lexer::Lexer::tokens("#!only")[0].0.is_eof());
+    }
+
     #[test]
     fn calculator_simple() {
         use crate::vm::Value;
@@ -0,0 +1,226 @@
+//! Style rules for `userd lint`, for teams and teachers enforcing a house
+//! style. Rules and their codes:
+//! - `L0001` naming: variables, functions and parameters in snake_case,
+//!   classes in UpperCamelCase
+//! - `L0002` function length: more statements than `max_function_length`
+//! - `L0003` shadowing: a parameter named like a global, or a declaration
+//!   hidden by a builtin of the same name (builtins win at run time, except
+//!   that a function or class replaces `len`, `push` and the other intrinsics)
+//! - `L0004` magic numbers: numeric literals other than 0, 1 and 2 inside
+//!   function bodies; top-level declarations are how constants get names
+//!
+//! Rules are switched in the `[lint]` section of `userd.toml`:
+//! ```toml
+//! [lint]
+//! naming = true
+//! shadowing = true
+//! magic_numbers = false
+//! max_function_length = 40   # 0 turns the rule off
+//! ```
+use std::collections::HashSet;
+
+use crate::ast::{walk_expr, walk_stmt, Expr, Intrinsic, Stmt, Visitor};
+use crate::builtins::Registry;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintConfig {
+    pub naming: bool,
+    pub shadowing: bool,
+    pub magic_numbers: bool,
+    /// Statements per function, nested ones included; 0 = no limit
+    pub max_function_length: usize,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self { naming: true, shadowing: true, magic_numbers: true, max_function_length: 30 }
+    }
+}
+
+impl LintConfig {
+    /// Read the `[lint]` section of a `userd.toml`; missing keys keep their default
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = Self::default();
+        let mut in_lint = false;
+        for (n, raw) in text.lines().enumerate() {
+            let line = raw.split('#').next().unwrap_or("").trim();
+            if line.is_empty() { continue; }
+            if line.starts_with('[') { in_lint = line == "[lint]"; continue; }
+            if !in_lint { continue; }
+            let (key, value) = line.split_once('=').ok_or_else(|| format!("userd.toml:{}: expected key = value", n + 1))?;
+            let (key, value) = (key.trim(), value.trim());
+            let flag = || match value {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(format!("userd.toml:{}: {} must be true or false", n + 1, key)),
+            };
+            match key {
+                "naming" => config.naming = flag()?,
+                "shadowing" => config.shadowing = flag()?,
+                "magic_numbers" => config.magic_numbers = flag()?,
+                "max_function_length" => {
+                    config.max_function_length = value.parse().map_err(|_| format!("userd.toml:{}: max_function_length must be a number", n + 1))?;
+                }
+                _ => return Err(format!("userd.toml:{}: unknown lint rule {}", n + 1, key)),
+            }
+        }
+        Ok(config)
+    }
+
+    /// `userd.toml` in `dir`, or the defaults when there is none
+    pub fn load(dir: &std::path::Path) -> Result<Self, String> {
+        match std::fs::read_to_string(dir.join("userd.toml")) {
+            Ok(text) => Self::parse(&text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("userd.toml: {}", e)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub line: Option<usize>,
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Lints of a program, in source order
+pub fn lint(prog: &[Stmt], config: &LintConfig) -> Vec<Lint> {
+    let mut globals = HashSet::new();
+    collect_globals(prog, &mut globals);
+    let mut linter = Linter { config, globals, builtins: Registry::standard(), line: None, in_function: false, lints: Vec::new() };
+    for s in prog { linter.visit_stmt(s); }
+    linter.lints
+}
+
+/// Names a program declares globally; declarations inside functions are global too
+fn collect_globals(stmts: &[Stmt], out: &mut HashSet<String>) {
+    for s in stmts {
+        match s {
+            Stmt::VarDecl { name, .. } | Stmt::ClassDecl { name, .. } => { out.insert(name.clone()); }
+            Stmt::FunctionDecl { name, body, .. } => { out.insert(name.clone()); collect_globals(body, out); }
+            Stmt::Block(b) => collect_globals(b, out),
+            _ => {}
+        }
+    }
+}
+
+fn is_snake_case(name: &str) -> bool {
+    name.chars().all(|c| c.is_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn is_camel_case(name: &str) -> bool {
+    name.chars().next().is_some_and(char::is_uppercase) && name.chars().all(char::is_alphanumeric)
+}
+
+/// Statements in a body, counting those in nested blocks
+fn length(body: &[Stmt]) -> usize {
+    body.iter().map(|s| match s { Stmt::Block(b) => length(b), _ => 1 }).sum()
+}
+
+/// First line inside a body, for statements that have none of their own
+fn first_line(body: &[Stmt]) -> Option<usize> {
+    body.iter().find_map(|s| match s { Stmt::Block(b) => first_line(b), s => s.line() })
+}
+
+struct Linter<'a> {
+    config: &'a LintConfig,
+    globals: HashSet<String>,
+    builtins: Registry,
+    /// Line of the statement being checked
+    line: Option<usize>,
+    in_function: bool,
+    lints: Vec<Lint>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, code: &'static str, message: String) {
+        self.lints.push(Lint { line: self.line, code, message });
+    }
+
+    /// Naming and builtin-shadowing checks shared by every declaration
+    fn declared(&mut self, kind: &str, name: &str, class: bool) {
+        if self.config.naming && !(if class { is_camel_case(name) } else { is_snake_case(name) }) {
+            let style = if class { "UpperCamelCase" } else { "snake_case" };
+            self.report("L0001", format!("{} {} should be {}", kind, name, style));
+        }
+        if self.config.shadowing && self.builtins.contains(name) {
+            let message = if kind != "variable" && Intrinsic::from_name(name).is_some() {
+                format!("{} {} replaces the builtin of the same name", kind, name)
+            } else {
+                format!("{} {} is hidden by the builtin of the same name", kind, name)
+            };
+            self.report("L0003", message);
+        }
+    }
+
+    /// A function, or a method of class `owner`
+    fn function(&mut self, owner: Option<&str>, name: &str, params: &[String], body: &[Stmt]) {
+        if let Some(l) = first_line(body) { self.line = Some(l); }
+        let (kind, name) = match owner {
+            None => { self.declared("function", name, false); ("function", name.to_string()) }
+            Some(class) => {
+                if self.config.naming && !is_snake_case(name) {
+                    self.report("L0001", format!("method {}.{} should be snake_case", class, name));
+                }
+                ("method", format!("{}.{}", class, name))
+            }
+        };
+        for p in params {
+            if self.config.naming && !is_snake_case(p) {
+                self.report("L0001", format!("parameter {} of {} should be snake_case", p, name));
+            }
+            if self.config.shadowing && self.globals.contains(p) {
+                self.report("L0003", format!("parameter {} of {} shadows the global {}", p, name, p));
+            }
+        }
+        let max = self.config.max_function_length;
+        if max > 0 && length(body) > max {
+            self.report("L0002", format!("{} {} has {} statements, more than {}", kind, name, length(body), max));
+        }
+        let outer = std::mem::replace(&mut self.in_function, true);
+        for s in body { self.visit_stmt(s); }
+        self.in_function = outer;
+    }
+}
+
+impl Visitor for Linter<'_> {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if stmt.line().is_some() { self.line = stmt.line(); }
+        match stmt {
+            Stmt::VarDecl { name, .. } => {
+                self.declared("variable", name, false);
+                walk_stmt(self, stmt);
+            }
+            Stmt::FunctionDecl { name, params, body, .. } => self.function(None, name, params, body),
+            Stmt::ClassDecl { name, fields, body, .. } => {
+                if let Some(l) = first_line(body) { self.line = Some(l); }
+                self.declared("class", name, true);
+                for f in fields {
+                    if self.config.naming && !is_snake_case(f) {
+                        self.report("L0001", format!("field {} of {} should be snake_case", f, name));
+                    }
+                }
+                for s in body {
+                    match s {
+                        Stmt::FunctionDecl { name: m, params, body, .. } => self.function(Some(name), m, params, body),
+                        s => self.visit_stmt(s),
+                    }
+                }
+            }
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Int(n) if self.in_function && self.config.magic_numbers && !(0..=2).contains(n) => {
+                self.report("L0004", format!("magic number {}; give it a name with a top-level declaration", n));
+            }
+            Expr::Float(f) if self.in_function && self.config.magic_numbers && ![0.0, 1.0, 2.0].contains(f) => {
+                self.report("L0004", format!("magic number {}; give it a name with a top-level declaration", f));
+            }
+            _ => walk_expr(self, expr),
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Macros: `macro name(a, b) { ... }` at the top level declares a template
+//! that `Parser::parse_program` expands once the whole file is parsed, before
+//! any name is resolved or checked, so the VM, `typeck` and `lint` only ever
+//! see the expanded program. `userd expand file.usrd` prints it.
+//!
+//! A call `name(x, y)` used as a statement is replaced by the macro body with
+//! `a` and `b` replaced by the argument expressions (not their values: an
+//! argument used twice is evaluated twice). Used inside an expression, the
+//! body must be a single expression. Hygiene is partial: variables the body
+//! declares get a unique name per expansion so they cannot clobber the
+//! caller's, unless the declared name is a parameter whose argument is a name,
+//! which is how a macro declares a variable for its caller. Functions and
+//! classes declared in a body keep their names.
+use std::collections::HashMap;
+
+use crate::ast::{Expr, Program, Stmt};
+use crate::error::Error;
+
+/// Expansions nested deeper than this are taken to be endless recursion
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct Macro {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Stmt>,
+    /// Line of the `macro` keyword
+    pub line: usize,
+}
+
+/// Expand every macro call in `prog`; calls that cannot be expanded are left
+/// in place and reported
+pub fn expand(prog: Program, macros: &[Macro]) -> (Program, Vec<Error>) {
+    if macros.is_empty() { return (prog, Vec::new()); }
+    let mut ex = Expander { macros, expansions: 0, depth: 0, errors: Vec::new() };
+    let prog = ex.stmts(prog);
+    (prog, ex.errors)
+}
+
+struct Expander<'a> {
+    macros: &'a [Macro],
+    /// Expansions so far, numbering the renamed variables
+    expansions: usize,
+    depth: usize,
+    errors: Vec<Error>,
+}
+
+/// The macro name and arguments of `expr` if it is a macro call
+fn macro_call<'e>(macros: &[Macro], expr: &'e Expr) -> Option<(usize, &'e [Expr], usize)> {
+    let Expr::Call { func, args, line } = expr else { return None };
+    let Expr::Ident(name) = func.as_ref() else { return None };
+    macros.iter().position(|m| &m.name == name).map(|i| (i, &args[..], *line))
+}
+
+impl Expander<'_> {
+    fn error(&mut self, line: usize, message: String) {
+        self.errors.push(Error::Parse { line, message });
+    }
+
+    fn stmts(&mut self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        let mut out = Vec::with_capacity(stmts.len());
+        for s in stmts {
+            if let Stmt::ExprStmt { expr, .. } = &s
+                && let Some((m, args, line)) = macro_call(self.macros, expr)
+                && let Some(body) = self.instantiate(m, args, line) {
+                    self.depth += 1;
+                    out.extend(self.stmts(body));
+                    self.depth -= 1;
+                    continue;
+            }
+            out.push(self.stmt(s));
+        }
+        out
+    }
+
+    fn stmt(&mut self, stmt: Stmt) -> Stmt {
+        match stmt {
+            Stmt::VarDecl { type_name, name, value, line } => Stmt::VarDecl { type_name, name, value: self.expr(value), line },
+            Stmt::ExprStmt { expr, line } => Stmt::ExprStmt { expr: self.expr(expr), line },
+            Stmt::FunctionDecl { name, params, param_types, ret_type, body } => {
+                Stmt::FunctionDecl { name, params, param_types, ret_type, body: self.stmts(body) }
+            }
+            Stmt::ClassDecl { name, fields, body, is_data } => Stmt::ClassDecl { name, fields, body: self.stmts(body), is_data },
+            Stmt::MemberAssign { receiver, name, value, line } => {
+                Stmt::MemberAssign { receiver: self.expr(receiver), name, value: self.expr(value), line }
+            }
+            Stmt::Block(b) => Stmt::Block(self.stmts(b)),
+        }
+    }
+
+    fn expr(&mut self, expr: Expr) -> Expr {
+        if let Some((m, args, line)) = macro_call(self.macros, &expr) {
+            let Some(mut body) = self.instantiate(m, args, line) else { return expr };
+            let single = matches!(body.as_slice(), [Stmt::ExprStmt { .. }]);
+            let Some(Stmt::ExprStmt { expr: inner, .. }) = body.pop().filter(|_| single) else {
+                self.error(line, format!("macro {} expands to statements and cannot be used as a value", self.macros[m].name));
+                return expr;
+            };
+            self.depth += 1;
+            let inner = self.expr(inner);
+            self.depth -= 1;
+            return inner;
+        }
+        let each = |ex: &mut Self, args: Vec<Expr>| args.into_iter().map(|a| ex.expr(a)).collect();
+        match expr {
+            Expr::BinaryOp { left, op, right } => Expr::BinaryOp { left: Box::new(self.expr(*left)), op, right: Box::new(self.expr(*right)) },
+            Expr::Call { func, args, line } => Expr::Call { func: Box::new(self.expr(*func)), args: each(self, args), line },
+            Expr::MemberCall { receiver, method, args, line, site } => {
+                Expr::MemberCall { receiver: Box::new(self.expr(*receiver)), method, args: each(self, args), line, site }
+            }
+            Expr::MemberAccess { receiver, field } => Expr::MemberAccess { receiver: Box::new(self.expr(*receiver)), field },
+            Expr::Intrinsic { op, args, line } => Expr::Intrinsic { op, args: each(self, args), line },
+            e => e,
+        }
+    }
+
+    /// Body of macro `m` for one call, not yet expanded itself
+    fn instantiate(&mut self, m: usize, args: &[Expr], line: usize) -> Option<Vec<Stmt>> {
+        let mac = &self.macros[m];
+        if args.len() != mac.params.len() {
+            let message = format!("macro {} takes {} argument(s), got {}", mac.name, mac.params.len(), args.len());
+            self.error(line, message);
+            return None;
+        }
+        if self.depth >= MAX_DEPTH {
+            self.error(line, format!("macro {} expands more than {} levels deep; is it recursive?", mac.name, MAX_DEPTH));
+            return None;
+        }
+        self.expansions += 1;
+        let mut subst: HashMap<String, Expr> = mac.params.iter().cloned().zip(args.iter().cloned()).collect();
+        let mut locals = Vec::new();
+        declared(&mac.body, &mut locals);
+        for name in locals {
+            if !subst.contains_key(&name) {
+                subst.insert(name.clone(), Expr::Ident(format!("{}__{}", name, self.expansions)));
+            }
+        }
+        let mut errors = Vec::new();
+        let body = mac.body.iter().map(|s| substitute_stmt(s, &subst, line, &mut errors)).collect();
+        for e in errors { self.error(line, format!("macro {}: {}", mac.name, e)); }
+        Some(body)
+    }
+}
+
+/// Variables declared by `stmts`, outside nested functions and classes
+fn declared(stmts: &[Stmt], out: &mut Vec<String>) {
+    for s in stmts {
+        match s {
+            Stmt::VarDecl { name, .. } => out.push(name.clone()),
+            Stmt::Block(b) => declared(b, out),
+            _ => {}
+        }
+    }
+}
+
+/// A copy of `stmt` with names replaced per `subst`, placed at line `line`
+fn substitute_stmt(stmt: &Stmt, subst: &HashMap<String, Expr>, line: usize, errors: &mut Vec<String>) -> Stmt {
+    let at = |l: usize| if l == 0 { 0 } else { line };
+    match stmt {
+        Stmt::VarDecl { type_name, name, value, line: l } => {
+            let name = match subst.get(name) {
+                Some(Expr::Ident(n)) => n.clone(),
+                Some(_) => { errors.push(format!("the argument for {} must be a name, since the macro declares it", name)); name.clone() }
+                None => name.clone(),
+            };
+            Stmt::VarDecl { type_name: type_name.clone(), name, value: substitute(value, subst), line: at(*l) }
+        }
+        Stmt::ExprStmt { expr, line: l } => Stmt::ExprStmt { expr: substitute(expr, subst), line: at(*l) },
+        Stmt::FunctionDecl { name, params, param_types, ret_type, body } => {
+            // the function's own params hide macro params of the same name
+            let inner: HashMap<String, Expr> = subst.iter().filter(|(k, _)| !params.contains(k)).map(|(k, v)| (k.clone(), v.clone())).collect();
+            let body = body.iter().map(|s| substitute_stmt(s, &inner, line, errors)).collect();
+            Stmt::FunctionDecl { name: name.clone(), params: params.clone(), param_types: param_types.clone(), ret_type: ret_type.clone(), body }
+        }
+        Stmt::ClassDecl { name, fields, body, is_data } => {
+            let body = body.iter().map(|s| substitute_stmt(s, subst, line, errors)).collect();
+            Stmt::ClassDecl { name: name.clone(), fields: fields.clone(), body, is_data: *is_data }
+        }
+        Stmt::MemberAssign { receiver, name, value, line: l } => {
+            Stmt::MemberAssign { receiver: substitute(receiver, subst), name: name.clone(), value: substitute(value, subst), line: at(*l) }
+        }
+        Stmt::Block(b) => Stmt::Block(b.iter().map(|s| substitute_stmt(s, subst, line, errors)).collect()),
+    }
+}
+
+fn substitute(expr: &Expr, subst: &HashMap<String, Expr>) -> Expr {
+    let all = |args: &[Expr]| args.iter().map(|a| substitute(a, subst)).collect();
+    match expr {
+        Expr::Ident(name) => subst.get(name).cloned().unwrap_or_else(|| expr.clone()),
+        Expr::BinaryOp { left, op, right } => Expr::BinaryOp { left: Box::new(substitute(left, subst)), op: op.clone(), right: Box::new(substitute(right, subst)) },
+        Expr::Call { func, args, line } => Expr::Call { func: Box::new(substitute(func, subst)), args: all(args), line: *line },
+        Expr::MemberCall { receiver, method, args, line, site } => {
+            Expr::MemberCall { receiver: Box::new(substitute(receiver, subst)), method: method.clone(), args: all(args), line: *line, site: *site }
+        }
+        Expr::MemberAccess { receiver, field } => Expr::MemberAccess { receiver: Box::new(substitute(receiver, subst)), field: field.clone() },
+        Expr::Intrinsic { op, args, line } => Expr::Intrinsic { op: *op, args: all(args), line: *line },
+        e => e.clone(),
+    }
+}
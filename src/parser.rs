@@ -1,42 +1,118 @@
 use crate::ast::{Expr, Stmt, BinOp, Program};
+use crate::error::Error;
 use crate::lexer::Lexer;
-use crate::token::Token;
+use crate::macros::Macro;
+use crate::token::{Span, Token};
+
+/// Something the parser accepted but probably was not meant, with a fix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hint {
+    pub line: usize,
+    pub message: String,
+    pub suggestion: String,
+}
 
 pub struct Parser {
     lexer: Lexer,
     cur: Token,
     peek: Token,
+    /// source lines of `cur` and `peek`
+    cur_line: usize,
+    peek_line: usize,
+    cur_span: Span,
+    peek_span: Span,
+    /// source line of the last token consumed
+    prev_line: usize,
+    /// member call sites numbered so far
+    sites: usize,
+    hints: Vec<Hint>,
+    /// Tokens that had to be skipped; `parse_program` still goes on after them
+    errors: Vec<Error>,
+    /// Where each of `errors` is
+    error_spans: Vec<Span>,
+    /// Macros declared so far, expanded at the end of `parse_program`
+    macros: Vec<Macro>,
 }
 
 impl Parser {
     pub fn new(input: &str) -> Self {
         let mut l = Lexer::new(input);
         let cur = l.next_token();
+        let (cur_line, cur_span) = (l.line(), l.span());
         let peek = l.next_token();
-        Self { lexer: l, cur, peek }
+        let (peek_line, peek_span) = (l.line(), l.span());
+        Self {
+            lexer: l, cur, peek, cur_line, peek_line, cur_span, peek_span, prev_line: cur_line, sites: 0,
+            hints: Vec::new(), errors: Vec::new(), error_spans: Vec::new(), macros: Vec::new(),
+        }
     }
 
     fn bump(&mut self) {
+        self.prev_line = self.cur_line;
         self.cur = std::mem::replace(&mut self.peek, self.lexer.next_token());
+        self.cur_line = std::mem::replace(&mut self.peek_line, self.lexer.line());
+        self.cur_span = std::mem::replace(&mut self.peek_span, self.lexer.span());
     }
 
     pub fn parse_program(&mut self) -> Program {
         let mut prog = Vec::new();
         while !self.cur.is_eof() {
+            if matches!(&self.cur, Token::Ident(id) if id == "macro") && matches!(self.peek, Token::Ident(_)) {
+                match self.parse_macro_decl() {
+                    Some(m) => self.macros.push(m),
+                    None => { self.skip_unexpected(); }
+                }
+                continue;
+            }
+            let empty = self.cur == Token::Semicolon;
             if let Some(stmt) = self.parse_statement() {
                 prog.push(stmt);
-            } else {
-                // skip unknown token
-                self.bump();
+            } else if !empty {
+                // skip unknown token; an empty statement already consumed its `;`
+                if self.cur.is_eof() { self.bump(); } else { self.skip_unexpected(); }
             }
         }
+        let (prog, errors) = crate::macros::expand(prog, &self.macros);
+        self.errors.extend(errors);
         prog
     }
 
+    fn parse_macro_decl(&mut self) -> Option<Macro> {
+        // cur == Ident("macro"), peek == Ident(name)
+        let line = self.cur_line;
+        self.bump(); // to name
+        let name = if let Token::Ident(s) = &self.cur { s.clone() } else { return None };
+        self.bump(); // to LParen
+        if let Token::LParen = &self.cur { self.bump(); } else { return None }
+        let mut params = Vec::new();
+        while let Token::Ident(p) = &self.cur {
+            params.push(p.clone());
+            self.bump();
+            if let Token::Comma = &self.cur { self.bump(); } else { break; }
+        }
+        if let Token::RParen = &self.cur { self.bump(); } else { return None }
+        if let Token::LBrace = &self.cur { self.bump(); } else { return None }
+        let mut body = Vec::new();
+        while !matches!(self.cur, Token::RBrace | Token::Eof) {
+            if let Some(s) = self.parse_statement() { body.push(s); } else { self.bump(); }
+        }
+        if let Token::RBrace = &self.cur { self.bump(); }
+        Some(Macro { name, params, body, line })
+    }
+
     fn parse_statement(&mut self) -> Option<Stmt> {
+        let line = self.cur_line;
         match &self.cur {
             Token::Class => self.parse_class_decl(),
             Token::Rtd => self.parse_function_decl(),
+            Token::Ident(id) if id == "data" && matches!(self.peek, Token::Class) => {
+                // data class Point(x, y);
+                self.bump(); // to Class
+                match self.parse_class_decl()? {
+                    Stmt::ClassDecl { name, fields, body, .. } => Some(Stmt::ClassDecl { name, fields, body, is_data: true }),
+                    other => Some(other),
+                }
+            }
             Token::Ident(_) => {
                 // could be var-decl if pattern: Ident - Ident = ... ;
                 if let Token::Minus = &self.peek {
@@ -47,30 +123,30 @@ impl Parser {
                 let expr = self.parse_expression();
                 // if after parsing we have an assignment token, and the parsed expr is a member access,
                 // treat it as a member assignment statement: receiver.field = expr;
-                if let Some(Expr::MemberAccess { receiver, field }) = &expr {
-                    if let Token::Assign = &self.cur {
+                if let Some(Expr::MemberAccess { receiver, field }) = &expr
+                    && let Token::Assign = &self.cur {
                         self.bump();
                         if let Some(value) = self.parse_expression() {
-                            self.consume_semicolon();
-                            return Some(Stmt::MemberAssign { receiver: *receiver.clone(), name: field.clone(), value });
+                            self.end_statement();
+                            return Some(Stmt::MemberAssign { receiver: *receiver.clone(), name: field.clone(), value, line });
                         }
-                    }
                 }
-                self.consume_semicolon();
-                expr.map(Stmt::ExprStmt)
+                if expr.is_some() { self.end_statement(); } else { self.consume_semicolon(); }
+                expr.map(|expr| Stmt::ExprStmt { expr, line })
             }
             Token::Semicolon => { self.bump(); None }
             Token::Eof => None,
             _ => {
                 let expr = self.parse_expression();
-                self.consume_semicolon();
-                expr.map(Stmt::ExprStmt)
+                if expr.is_some() { self.end_statement(); } else { self.consume_semicolon(); }
+                expr.map(|expr| Stmt::ExprStmt { expr, line })
             }
         }
     }
 
     fn parse_var_decl(&mut self) -> Option<Stmt> {
         // cur: Ident(type), peek: Minus
+        let line = self.cur_line;
         let type_name = if let Token::Ident(s) = &self.cur { s.clone() } else { return None };
         self.bump(); // to Minus
         self.bump(); // to var name
@@ -79,8 +155,8 @@ impl Parser {
         if let Token::Assign = &self.cur {
             self.bump();
             let expr = self.parse_expression()?;
-            self.consume_semicolon();
-            Some(Stmt::VarDecl { type_name, name, value: expr })
+            self.end_statement();
+            Some(Stmt::VarDecl { type_name, name, value: expr, line })
         } else {
             None
         }
@@ -93,43 +169,87 @@ impl Parser {
         self.bump(); // to LParen
         // parse params
         let mut params = Vec::new();
+        let mut param_types = Vec::new();
+        // `self.x` params are captured into fields: they bind `x` and prepend `self.x = x;` to the body
+        let mut body = Vec::new();
         if let Token::LParen = &self.cur {
             self.bump();
             while let Token::Ident(p) = &self.cur {
-                params.push(p.clone());
+                let mut p = p.clone();
                 self.bump();
+                // `int-a` annotates the parameter with a type
+                let mut ty = None;
+                if let Token::Minus = &self.cur {
+                    self.bump();
+                    let Token::Ident(n) = &self.cur else { return None };
+                    ty = Some(std::mem::replace(&mut p, n.clone()));
+                    self.bump();
+                }
+                param_types.push(ty);
+                if let Token::Dot = &self.cur {
+                    self.bump(); // to field name
+                    let field = if let Token::Ident(f) = &self.cur { f.clone() } else { return None };
+                    self.bump();
+                    body.push(Stmt::MemberAssign { receiver: Expr::Ident(p), name: field.clone(), value: Expr::Ident(field.clone()), line: 0 });
+                    params.push(field);
+                } else {
+                    params.push(p);
+                }
                 if let Token::Comma = &self.cur { self.bump(); } else { break; }
             }
             if let Token::RParen = &self.cur { self.bump(); } else { return None }
         } else { return None }
+        // optional return type: -> int
+        let mut ret_type = None;
+        if let Token::Arrow = &self.cur {
+            self.bump();
+            let Token::Ident(t) = &self.cur else { return None };
+            ret_type = Some(t.clone());
+            self.bump();
+        }
         // expect block
         if let Token::LBrace = &self.cur { self.bump(); } else { return None }
-        let mut body = Vec::new();
-        while let Token::RBrace = &self.cur { break; }
         while !matches!(self.cur, Token::RBrace | Token::Eof) {
             if let Some(s) = self.parse_statement() { body.push(s); } else { self.bump(); }
         }
         if let Token::RBrace = &self.cur { self.bump(); }
-        Some(Stmt::FunctionDecl { name, params, body })
+        Some(Stmt::FunctionDecl { name, params, param_types, ret_type, body })
     }
 
     fn parse_class_decl(&mut self) -> Option<Stmt> {
         // cur == Class
         self.bump(); // to name
         let name = if let Token::Ident(s) = &self.cur { s.clone() } else { return None };
-        self.bump(); // to LBrace
+        self.bump(); // to LParen or LBrace
+        // optional record header: class Point(x, y) { ... }
+        let mut fields = Vec::new();
+        if let Token::LParen = &self.cur {
+            self.bump();
+            while let Token::Ident(f) = &self.cur {
+                fields.push(f.clone());
+                self.bump();
+                if let Token::Comma = &self.cur { self.bump(); } else { break; }
+            }
+            if let Token::RParen = &self.cur { self.bump(); } else { return None }
+        }
+        // a header-only declaration may omit the body: class Point(x, y);
+        if let Token::Semicolon = &self.cur {
+            self.bump();
+            return Some(Stmt::ClassDecl { name, fields, body: Vec::new(), is_data: false });
+        }
         if let Token::LBrace = &self.cur { self.bump(); } else { return None }
         let mut body = Vec::new();
         while !matches!(self.cur, Token::RBrace | Token::Eof) {
             if let Some(s) = self.parse_statement() { body.push(s); } else { self.bump(); }
         }
         if let Token::RBrace = &self.cur { self.bump(); }
-        Some(Stmt::ClassDecl { name, body })
+        Some(Stmt::ClassDecl { name, fields, body, is_data: false })
     }
 
     fn parse_member_assign(&mut self) -> Option<Stmt> {
         // pattern: receiver . name = expr ;
         // cur is Ident(receiver)
+        let line = self.cur_line;
         let receiver = if let Token::Ident(s) = &self.cur { Expr::Ident(s.clone()) } else { return None };
         self.bump(); // to Dot
         self.bump(); // to name
@@ -139,7 +259,7 @@ impl Parser {
             self.bump();
             let value = self.parse_expression()?;
             self.consume_semicolon();
-            return Some(Stmt::MemberAssign { receiver, name, value });
+            return Some(Stmt::MemberAssign { receiver, name, value, line });
         }
         None
     }
@@ -166,9 +286,21 @@ impl Parser {
         match &self.cur {
             Token::Int(n) => { let v = *n; self.bump(); Some(Expr::Int(v)) }
             Token::Float(f) => { let v = *f; self.bump(); Some(Expr::Float(v)) }
+            // a minus sign in front of a number literal makes it negative
+            Token::Minus if matches!(self.peek, Token::Int(_) | Token::Float(_)) || self.peek == Token::BigInt(i64::MIN.unsigned_abs().to_string()) => {
+                self.bump();
+                let e = match &self.cur {
+                    Token::Int(n) => Expr::Int(-n),
+                    Token::Float(f) => Expr::Float(-f),
+                    _ => Expr::Int(i64::MIN),
+                };
+                self.bump();
+                Some(e)
+            }
             Token::Str(s) => { let s2 = s.clone(); self.bump(); Some(Expr::Str(s2)) }
             Token::Ident(name) => {
                 let id = name.clone();
+                let line = self.cur_line;
                 self.bump();
                 // member access/call: receiver.method(...)
                 if let Token::Dot = &self.cur {
@@ -177,13 +309,16 @@ impl Parser {
                     self.bump();
                     if let Token::LParen = &self.cur {
                         self.bump();
+                        let site = self.sites;
+                        self.sites += 1;
                         let mut args = Vec::new();
                         while !matches!(self.cur, Token::RParen | Token::Eof) {
-                            if let Some(e) = self.parse_expression() { args.push(e); }
+                            // skip tokens that start no expression, as statements do
+                            match self.parse_expression() { Some(e) => args.push(e), None => self.bump() }
                             if let Token::Comma = &self.cur { self.bump(); }
                         }
                         if let Token::RParen = &self.cur { self.bump(); }
-                        Some(Expr::MemberCall { receiver: Box::new(Expr::Ident(id)), method, args })
+                        Some(Expr::MemberCall { receiver: Box::new(Expr::Ident(id)), method, args, line, site })
                     } else {
                         Some(Expr::MemberAccess { receiver: Box::new(Expr::Ident(id)), field: method })
                     }
@@ -192,11 +327,14 @@ impl Parser {
                     self.bump();
                     let mut args = Vec::new();
                     while !matches!(self.cur, Token::RParen | Token::Eof) {
-                        if let Some(e) = self.parse_expression() { args.push(e); }
+                        match self.parse_expression() { Some(e) => args.push(e), None => self.bump() }
                         if let Token::Comma = &self.cur { self.bump(); }
                     }
                     if let Token::RParen = &self.cur { self.bump(); }
-                    Some(Expr::Call { func: Box::new(Expr::Ident(id)), args })
+                    match crate::ast::Intrinsic::from_name(&id) {
+                        Some(op) => Some(Expr::Intrinsic { op, args, line }),
+                        None => Some(Expr::Call { func: Box::new(Expr::Ident(id)), args, line }),
+                    }
                 } else { Some(Expr::Ident(id)) }
             }
             Token::LParen => {
@@ -212,4 +350,85 @@ impl Parser {
     fn consume_semicolon(&mut self) {
         if let Token::Semicolon = &self.cur { self.bump(); }
     }
+
+    /// End of a parsed statement: a statement ending at a line break or the
+    /// end of input without `;` is still accepted, but noted in `hints`
+    fn end_statement(&mut self) {
+        match &self.cur {
+            Token::Semicolon => self.bump(),
+            Token::RBrace => {}
+            t if t.is_eof() || self.cur_line > self.prev_line => self.hints.push(Hint {
+                line: self.prev_line,
+                message: "missing `;` after statement".to_string(),
+                suggestion: format!("add `;` at the end of line {}", self.prev_line),
+            }),
+            _ => {}
+        }
+    }
+
+    /// Error for the token parsing stopped at
+    fn unexpected(&self) -> Error {
+        match &self.cur {
+            Token::Illegal(c) => Error::Lex { line: self.cur_line, message: format!("unexpected character `{}`", c) },
+            Token::BigInt(s) => Error::Lex { line: self.cur_line, message: format!("integer literal {} does not fit in an int", s) },
+            t => Error::Parse { line: self.cur_line, message: format!("unexpected `{}`", t) },
+        }
+    }
+
+    /// Record the current token as an error and move past it
+    fn skip_unexpected(&mut self) {
+        self.errors.push(self.unexpected());
+        self.error_spans.push(self.cur_span);
+        self.bump();
+    }
+
+    /// Tokens skipped by `parse_program`, in source order
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    /// `errors`, moved out of the parser
+    pub fn take_errors(&mut self) -> Vec<Error> {
+        self.error_spans.clear();
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Where each of `errors` is in the source, for pointing at it
+    pub fn error_spans(&self) -> &[Span] {
+        &self.error_spans
+    }
+
+    /// Hints collected so far, in source order
+    pub fn hints(&self) -> &[Hint] {
+        &self.hints
+    }
+}
+
+/// Parse a whole program, failing on the first token that had to be skipped
+pub fn parse(src: &str) -> Result<Program, Error> {
+    let mut parser = Parser::new(src);
+    let prog = parser.parse_program();
+    match parser.errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(prog),
+    }
+}
+
+/// Read and parse several files, spreading them over up to `available_parallelism`
+/// threads. Parsers share no state, so files are independent; results keep the
+/// order of `paths`.
+pub fn parse_files(paths: &[String]) -> Vec<Result<(Program, Vec<Hint>), String>> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get()).min(paths.len().max(1));
+    let chunk = paths.len().div_ceil(threads).max(1);
+    std::thread::scope(|s| {
+        let workers: Vec<_> = paths.chunks(chunk).map(|part| s.spawn(move || {
+            part.iter().map(|path| {
+                let src = std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+                let mut parser = Parser::new(&src);
+                let prog = parser.parse_program();
+                Ok((prog, parser.hints))
+            }).collect::<Vec<_>>()
+        })).collect();
+        workers.into_iter().flat_map(|w| w.join().unwrap_or_else(|_| vec![Err("parser thread panicked".to_string())])).collect()
+    })
 }
@@ -0,0 +1,33 @@
+//! Monotonic microsecond clock for `perf_now_us` and `frame_wait`:
+//! QueryPerformanceCounter on Windows, `Instant` elsewhere. Counts from the
+//! first call, so values fit an `i64` for any realistic run.
+use std::sync::OnceLock;
+
+#[cfg(windows)]
+pub fn now_us() -> u64 {
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn QueryPerformanceCounter(count: *mut i64) -> i32;
+        fn QueryPerformanceFrequency(freq: *mut i64) -> i32;
+    }
+    static START: OnceLock<(i64, i64)> = OnceLock::new();
+    let read = || {
+        let mut count = 0i64;
+        // SAFETY: both calls only write the i64 they are given and cannot fail since Windows XP
+        unsafe { QueryPerformanceCounter(&mut count); }
+        count
+    };
+    let &(start, freq) = START.get_or_init(|| {
+        let mut freq = 0i64;
+        unsafe { QueryPerformanceFrequency(&mut freq); }
+        (read(), freq.max(1))
+    });
+    // widen so ticks * 1e6 cannot overflow
+    ((read() - start) as i128 * 1_000_000 / freq as i128) as u64
+}
+
+#[cfg(not(windows))]
+pub fn now_us() -> u64 {
+    static START: OnceLock<std::time::Instant> = OnceLock::new();
+    START.get_or_init(std::time::Instant::now).elapsed().as_micros() as u64
+}
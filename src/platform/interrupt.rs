@@ -0,0 +1,47 @@
+//! Ctrl-C without external crates: the handler cancels one `CancelToken`
+//! instead of killing the process, so the REPL can stop a runaway program
+//! and keep its session.
+use std::sync::OnceLock;
+
+use crate::vm::CancelToken;
+
+static TARGET: OnceLock<CancelToken> = OnceLock::new();
+
+fn interrupted() {
+    if let Some(t) = TARGET.get() { t.cancel(); }
+}
+
+/// Cancel `token` on Ctrl-C from now on; only the first call installs a handler
+pub fn cancel_on_ctrl_c(token: CancelToken) {
+    if TARGET.set(token).is_ok() { install(); }
+}
+
+#[cfg(unix)]
+fn install() {
+    const SIGINT: i32 = 2;
+    unsafe extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+    extern "C" fn on_sigint(_: i32) { interrupted(); }
+    // SAFETY: the handler only does atomic stores
+    unsafe { signal(SIGINT, on_sigint); }
+}
+
+#[cfg(windows)]
+fn install() {
+    const CTRL_C_EVENT: u32 = 0;
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn SetConsoleCtrlHandler(handler: extern "system" fn(u32) -> i32, add: i32) -> i32;
+    }
+    extern "system" fn on_ctrl(kind: u32) -> i32 {
+        if kind != CTRL_C_EVENT { return 0; }
+        interrupted();
+        1
+    }
+    // SAFETY: the handler only does atomic stores
+    unsafe { SetConsoleCtrlHandler(on_ctrl, 1); }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn install() {}
@@ -1,5 +1,8 @@
 // Platform-specific helpers. Each OS backend lives in a submodule.
 #[cfg(target_os = "windows")]
 pub mod windows;
+pub mod interrupt;
+pub mod terminal;
+pub mod clock;
 
 // Future: add linux and mac backends here.
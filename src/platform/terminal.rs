@@ -0,0 +1,87 @@
+//! Raw terminal input for the REPL's line editor, without external crates:
+//! `stty` on Unix, the console mode on Windows. In raw mode each key arrives
+//! as it is pressed and is not echoed, so the REPL can act on Tab itself.
+//! Signals stay on, so Ctrl-C still reaches `interrupt`.
+use std::io::IsTerminal;
+
+/// Raw mode while alive; the previous mode comes back on drop
+pub struct RawMode {
+    #[cfg(unix)]
+    saved: String,
+    #[cfg(windows)]
+    saved: u32,
+}
+
+impl RawMode {
+    /// `None` when stdin is not a terminal or the mode cannot be changed
+    pub fn enable() -> Option<Self> {
+        if !std::io::stdin().is_terminal() { return None; }
+        Self::enable_terminal()
+    }
+
+    #[cfg(unix)]
+    fn enable_terminal() -> Option<Self> {
+        let out = stty(&["-g"])?;
+        let saved = String::from_utf8(out).ok()?.trim().to_string();
+        stty(&["-icanon", "-echo", "min", "1"])?;
+        Some(RawMode { saved })
+    }
+
+    #[cfg(windows)]
+    fn enable_terminal() -> Option<Self> {
+        const ENABLE_LINE_INPUT: u32 = 0x0002;
+        const ENABLE_ECHO_INPUT: u32 = 0x0004;
+        let mut saved = 0u32;
+        // SAFETY: plain console calls on this process's own input handle
+        unsafe {
+            let h = GetStdHandle(STD_INPUT_HANDLE);
+            if GetConsoleMode(h, &mut saved) == 0 { return None; }
+            if SetConsoleMode(h, saved & !(ENABLE_LINE_INPUT | ENABLE_ECHO_INPUT)) == 0 { return None; }
+        }
+        Some(RawMode { saved })
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn enable_terminal() -> Option<Self> {
+        None
+    }
+}
+
+impl Drop for RawMode {
+    #[cfg(unix)]
+    fn drop(&mut self) {
+        stty(&[self.saved.as_str()]);
+    }
+
+    #[cfg(windows)]
+    fn drop(&mut self) {
+        // SAFETY: as in `enable_terminal`
+        unsafe { SetConsoleMode(GetStdHandle(STD_INPUT_HANDLE), self.saved); }
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn drop(&mut self) {}
+}
+
+/// Run `stty` on this terminal; its output, or `None` if it failed
+#[cfg(unix)]
+fn stty(args: &[&str]) -> Option<Vec<u8>> {
+    let out = std::process::Command::new("stty")
+        .args(args)
+        .stdin(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    out.status.success().then_some(out.stdout)
+}
+
+#[cfg(windows)]
+const STD_INPUT_HANDLE: u32 = -10i32 as u32;
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+unsafe extern "system" {
+    fn GetStdHandle(which: u32) -> *mut std::ffi::c_void;
+    fn GetConsoleMode(console: *mut std::ffi::c_void, mode: *mut u32) -> i32;
+    fn SetConsoleMode(console: *mut std::ffi::c_void, mode: u32) -> i32;
+}
@@ -6,7 +6,6 @@ use std::os::windows::ffi::OsStrExt;
 use std::ptr::{null, null_mut};
 use std::sync::{Mutex, OnceLock, mpsc};
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicU64, Ordering};
 
 use std::os::raw::{c_int, c_void};
 
@@ -70,8 +69,22 @@ struct MSG {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 struct RECT { left: i32, top: i32, right: i32, bottom: i32 }
 
+#[repr(C)]
+struct MONITORINFO { cbSize: u32, rcMonitor: RECT, rcWork: RECT, dwFlags: u32 }
+
+const GWL_STYLE: i32 = -16;
+const WS_POPUP: u32 = 0x8000_0000;
+const WS_VISIBLE: u32 = 0x1000_0000;
+const SWP_NOSIZE: u32 = 0x0001;
+const SWP_NOZORDER: u32 = 0x0004;
+const SWP_NOACTIVATE: u32 = 0x0010;
+const SWP_FRAMECHANGED: u32 = 0x0020;
+const MONITOR_DEFAULTTONEAREST: u32 = 2;
+const MONITORINFOF_PRIMARY: u32 = 1;
+
 #[repr(C)]
 struct BITMAPINFOHEADER {
     biSize: u32,
@@ -112,6 +125,24 @@ unsafe extern "system" {
     fn GetClientRect(hWnd: HWND, lpRect: *mut RECT) -> i32;
     fn SetWindowLongPtrW(hWnd: HWND, nIndex: i32, dwNewLong: isize) -> isize;
     fn GetWindowLongPtrW(hWnd: HWND, nIndex: i32) -> isize;
+    fn ScreenToClient(hWnd: HWND, lpPoint: *mut POINT) -> i32;
+    fn SetCapture(hWnd: HWND) -> HWND;
+    fn ReleaseCapture() -> i32;
+    fn GetKeyState(nVirtKey: c_int) -> i16;
+    fn EnumDisplayMonitors(hdc: HDC, lprcClip: *const RECT,
+                           lpfnEnum: extern "system" fn(*mut c_void, HDC, *mut RECT, LPARAM) -> i32, dwData: LPARAM) -> i32;
+    fn GetMonitorInfoW(hMonitor: *mut c_void, lpmi: *mut MONITORINFO) -> i32;
+    fn MonitorFromWindow(hwnd: HWND, dwFlags: u32) -> *mut c_void;
+    fn SetWindowPos(hWnd: HWND, hWndInsertAfter: HWND, X: i32, Y: i32, cx: i32, cy: i32, uFlags: u32) -> i32;
+    fn GetWindowRect(hWnd: HWND, lpRect: *mut RECT) -> i32;
+}
+
+#[link(name = "shell32")]
+unsafe extern "system" {
+    fn DragAcceptFiles(hWnd: HWND, fAccept: i32);
+    fn DragQueryFileW(hDrop: *mut c_void, iFile: u32, lpszFile: *mut u16, cch: u32) -> u32;
+    fn DragQueryPoint(hDrop: *mut c_void, ppt: *mut POINT) -> i32;
+    fn DragFinish(hDrop: *mut c_void);
 }
 
 #[link(name = "gdi32")]
@@ -140,15 +171,17 @@ pub enum WindowCommand {
     Clear(u8,u8,u8,u8), // r,g,b,a
     Present,
     DrawText(i32,i32,String), // x,y,text (very simple stub)
+    /// Push a copy of the canvas, e.g. before a dialog covers it
+    Save,
+    /// Put back the last saved copy, if the canvas was not resized since
+    Restore,
     Close,
 }
 
 type Sender = mpsc::Sender<WindowCommand>;
 
 static REGISTRY: OnceLock<Mutex<HashMap<u64, Sender>>> = OnceLock::new();
-static NEXT_ID: AtomicU64 = AtomicU64::new(1);
 static EVENTS: OnceLock<Mutex<Vec<(u64, (i32,i32))>>> = OnceLock::new();
-static HANDLERS: OnceLock<Mutex<HashMap<u64, String>>> = OnceLock::new();
 static HWND_MAP: OnceLock<Mutex<HashMap<usize, u64>>> = OnceLock::new();
 static WIDGETS: OnceLock<Mutex<HashMap<u64, Vec<Widget>>>> = OnceLock::new();
 static TEXTS: OnceLock<Mutex<HashMap<u64, Vec<(i32,i32,String)>>>> = OnceLock::new();
@@ -162,10 +195,6 @@ fn events_registry() -> &'static Mutex<Vec<(u64, (i32,i32))>> {
     EVENTS.get_or_init(|| Mutex::new(Vec::new()))
 }
 
-fn handlers_registry() -> &'static Mutex<HashMap<u64, String>> {
-    HANDLERS.get_or_init(|| Mutex::new(HashMap::new()))
-}
-
 fn hwnd_map() -> &'static Mutex<HashMap<usize, u64>> {
     HWND_MAP.get_or_init(|| Mutex::new(HashMap::new()))
 }
@@ -184,16 +213,6 @@ pub fn drain_events() -> Vec<(u64, (i32,i32))> {
     Vec::new()
 }
 
-pub fn register_handler(win_id: u64, handler: &str) {
-    let reg = handlers_registry();
-    if let Ok(mut g) = reg.lock() { g.insert(win_id, handler.to_string()); }
-}
-
-pub fn get_handler(win_id: u64) -> Option<String> {
-    let reg = handlers_registry();
-    if let Ok(g) = reg.lock() { g.get(&win_id).cloned() } else { None }
-}
-
 #[derive(Debug, Clone)]
 pub struct Widget {
     pub id: u64,
@@ -222,6 +241,8 @@ struct CanvasState {
     w: i32,
     h: i32,
     bg: [u8;4],
+    /// Copies pushed by `WindowCommand::Save`
+    saved: Vec<Vec<u8>>,
 }
 
 
@@ -240,11 +261,13 @@ pub fn take_texts(win_id: u64) -> Vec<(i32,i32,String)> {
 
 /// Register a rectangular widget on a window. Returns widget id.
 pub fn register_widget(win_id: u64, x: i32, y: i32, w: i32, h: i32, handler: &str) -> u64 {
-    let wid = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let wid = crate::gui::next_id();
     let widget = Widget { id: wid, x, y, w, h, handler: handler.to_string() };
+    crate::gui::set_handler(wid, handler);
     if let Ok(mut reg) = widgets_registry().lock() {
         reg.entry(win_id).or_insert_with(Vec::new).push(widget);
     }
+    crate::gui::add_focusable(win_id, wid);
     wid
 }
 
@@ -263,6 +286,157 @@ pub fn register_widget_auto(win_id: u64, _label: &str, handler: &str) -> u64 {
     register_widget(win_id, x, y, w, h, handler)
 }
 
+/// Apply mouse input to the scroll panels of a window; every panel whose
+/// offset changes gets an event calling its handler with (0, offset), and
+/// every list is redrawn. True when some panel took the input
+fn scroll_input(win_id: u64, mut f: impl FnMut(&mut crate::gui::ScrollPanel) -> Option<bool>) -> bool {
+    let mut taken = false;
+    let mut moved = Vec::new();
+    let mut ops = Vec::new();
+    if let Ok(mut panels) = crate::gui::panels().lock() {
+        for (id, entry) in panels.iter_mut().filter(|(_, e)| e.window == win_id) {
+            let Some(changed) = f(&mut entry.panel) else { continue };
+            taken = true;
+            match &entry.list {
+                Some(list) if changed => ops.extend(list.draw(&entry.panel)),
+                None if changed => moved.push((*id, entry.panel.offset)),
+                _ => {}
+            }
+        }
+    }
+    for (id, offset) in moved { push_event(id, 0, offset); }
+    let _ = draw_ops(win_id, &ops);
+    taken
+}
+
+/// Hover and selection of the lists of a window; a changed selection gets
+/// an event calling the handler with (index, 0). True when a list row took
+/// the input
+fn list_input(win_id: u64, px: i32, py: i32, click: bool) -> bool {
+    let mut taken = false;
+    let mut selected = Vec::new();
+    let mut ops = Vec::new();
+    if let Ok(mut panels) = crate::gui::panels().lock() {
+        for (id, entry) in panels.iter_mut().filter(|(_, e)| e.window == win_id) {
+            let crate::gui::PanelEntry { panel, list: Some(list), .. } = entry else { continue };
+            let mut changed = list.hover_at(panel, px, py);
+            if click && let Some(row) = list.row_at(panel, px, py) {
+                taken = true;
+                if list.select(panel, Some(row)) {
+                    changed = true;
+                    selected.push((*id, row));
+                }
+            }
+            if changed { ops.extend(list.draw(panel)); }
+        }
+    }
+    for (id, row) in selected { push_event(id, row as i32, 0); }
+    let _ = draw_ops(win_id, &ops);
+    taken
+}
+
+/// Give input to the innermost dialog of a window, redrawing it when `f`
+/// says it changed. False when the window has no dialog open
+fn modal_input(win_id: u64, f: impl FnOnce(&mut crate::gui::Dialog) -> bool) -> bool {
+    let mut ops = Vec::new();
+    {
+        let Ok(mut modals) = crate::gui::modals().lock() else { return false };
+        let Some(dialog) = modals.get_mut(&win_id).and_then(|s| s.last_mut()) else { return false };
+        // a closed dialog is about to be taken down by gui_dialog
+        if dialog.result.is_none() && f(dialog) && dialog.result.is_none() { ops = dialog.draw(); }
+    }
+    let _ = draw_ops(win_id, &ops);
+    true
+}
+
+/// Area of a widget or list, for the focus outline
+fn focus_rect(win_id: u64, id: u64) -> Option<(i32, i32, i32, i32)> {
+    if let Some(w) = widgets_registry().lock().ok()?.get(&win_id).and_then(|l| l.iter().find(|w| w.id == id)) {
+        return Some((w.x, w.y, w.w, w.h));
+    }
+    let panels = crate::gui::panels().lock().ok()?;
+    panels.get(&id).map(|e| (e.panel.x, e.panel.y, e.panel.w, e.panel.h))
+}
+
+/// Move the focus outline of a window from `old` to `new`: a 2 px frame just
+/// outside the widget, erased with the theme background
+pub fn show_focus(win_id: u64, old: Option<u64>, new: Option<u64>) -> Result<(), String> {
+    let bg = *theme_bg().lock().map_err(|_| "theme lock poisoned".to_string())?;
+    let mut ops = Vec::new();
+    for (id, color) in [(old, [bg[0], bg[1], bg[2]]), (new, [0, 120, 215])] {
+        let Some((x, y, w, h)) = id.and_then(|id| focus_rect(win_id, id)) else { continue };
+        let (x, y, w, h) = (x - 3, y - 3, w + 6, h + 6);
+        ops.extend([
+            crate::gui::DrawOp::Rect { x, y, w, h: 2, color },
+            crate::gui::DrawOp::Rect { x, y: y + h - 2, w, h: 2, color },
+            crate::gui::DrawOp::Rect { x, y, w: 2, h, color },
+            crate::gui::DrawOp::Rect { x: x + w - 2, y, w: 2, h, color },
+        ]);
+    }
+    draw_ops(win_id, &ops)
+}
+
+/// Keyboard navigation: Tab and Shift+Tab move the focus, Enter and Space
+/// press the focused widget, Up and Down move the selection of a focused
+/// list. True when the key was used
+fn key_input(win_id: u64, vk: usize) -> bool {
+    const VK_TAB: usize = 0x09;
+    const VK_RETURN: usize = 0x0D;
+    const VK_SPACE: usize = 0x20;
+    const VK_UP: usize = 0x26;
+    const VK_DOWN: usize = 0x28;
+    const VK_SHIFT: c_int = 0x10;
+    let current = crate::gui::focus().lock().ok().and_then(|f| f.get(&win_id).and_then(|f| f.current));
+    match vk {
+        VK_TAB => {
+            let back = unsafe { GetKeyState(VK_SHIFT) } < 0;
+            let new = crate::gui::focus().lock().ok().and_then(|mut f| f.get_mut(&win_id).and_then(|f| f.advance(back)));
+            if new.is_none() { return false; }
+            let _ = show_focus(win_id, current, new);
+            true
+        }
+        VK_RETURN | VK_SPACE => {
+            let Some(id) = current else { return false };
+            let widget = widgets_registry().lock().ok().and_then(|r| r.get(&win_id).and_then(|l| l.iter().find(|w| w.id == id).cloned()));
+            let Some(widget) = widget else { return false };
+            // the same event a click in the middle of the widget gives
+            push_event(widget.id, widget.x + widget.w / 2, widget.y + widget.h / 2);
+            true
+        }
+        VK_UP | VK_DOWN => {
+            let Some(id) = current else { return false };
+            let mut ops = Vec::new();
+            let mut selected = None;
+            if let Ok(mut panels) = crate::gui::panels().lock()
+                && let Some(crate::gui::PanelEntry { panel, list: Some(list), .. }) = panels.get_mut(&id) {
+                    let row = match (list.selected, vk == VK_UP) {
+                        (None, _) => 0,
+                        (Some(r), true) => r.saturating_sub(1),
+                        (Some(r), false) => (r + 1).min(list.items.len().saturating_sub(1)),
+                    };
+                    if list.select(panel, Some(row)) { selected = list.selected; }
+                    ops = list.draw(panel);
+            }
+            if let Some(row) = selected { push_event(id, row as i32, 0); }
+            let _ = draw_ops(win_id, &ops);
+            !ops.is_empty()
+        }
+        _ => false,
+    }
+}
+
+/// Send widget drawing to a window's canvas
+pub fn draw_ops(win_id: u64, ops: &[crate::gui::DrawOp]) -> Result<(), String> {
+    if ops.is_empty() { return Ok(()); }
+    for op in ops {
+        match op {
+            crate::gui::DrawOp::Rect { x, y, w, h, color: [r, g, b] } => canvas_draw_rect(win_id, *x, *y, *w, *h, *r, *g, *b, 255)?,
+            crate::gui::DrawOp::Text { x, y, text } => canvas_draw_text(win_id, *x, *y, text)?,
+        }
+    }
+    canvas_present(win_id)
+}
+
 fn find_widget_hit(win_id: u64, px: i32, py: i32) -> Option<Widget> {
     if let Ok(reg) = widgets_registry().lock() {
         if let Some(list) = reg.get(&win_id) {
@@ -280,7 +454,7 @@ fn find_widget_hit(win_id: u64, px: i32, py: i32) -> Option<Widget> {
 /// on WM_PAINT uses SetDIBitsToDevice to draw the provided RGBA32 buffer (top-down).
 pub fn create_window(title: &str, w: i32, h: i32) -> u64 {
     let (tx, rx) = mpsc::channel::<WindowCommand>();
-    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let id = crate::gui::next_id();
     registry().lock().unwrap().insert(id, tx.clone());
     let title = title.to_string();
     std::thread::spawn(move || {
@@ -319,8 +493,65 @@ pub fn create_window(title: &str, w: i32, h: i32) -> u64 {
                 unsafe { PostQuitMessage(0); }
                 return 0;
             }
+            let win_id_of = |hwnd: HWND| hwnd_map().lock().ok().and_then(|map| map.get(&(hwnd as usize)).copied());
+            // while a dialog is open it gets the clicks and keys, and the
+            // rest of the window gets no input at all
+            if let Some(win_id) = win_id_of(hWnd) {
+                let handled = match msg {
+                    0x0201 /* WM_LBUTTONDOWN */ => {
+                        let (lx, ly) = ((l_param & 0xFFFF) as i16 as i32, ((l_param >> 16) & 0xFFFF) as i16 as i32);
+                        modal_input(win_id, |d| d.click(lx, ly))
+                    }
+                    0x0100 /* WM_KEYDOWN */ if w_param == 0x0D || w_param == 0x1B /* VK_RETURN, VK_ESCAPE */ => {
+                        modal_input(win_id, |d| { d.key(w_param == 0x0D); true })
+                    }
+                    0x0100 | 0x0200 | 0x0202 | 0x020A => modal_input(win_id, |_| false),
+                    _ => false,
+                };
+                if handled { return 0; }
+                if msg == 0x0100 /* WM_KEYDOWN */ && key_input(win_id, w_param) { return 0; }
+            }
+            if msg == 0x0233 /* WM_DROPFILES */ {
+                let (paths, x, y) = read_drop(w_param as *mut c_void);
+                if let Some(window) = win_id_of(hWnd) {
+                    crate::gui::push_drop(crate::gui::FileDrop { window, paths, x, y });
+                }
+                return 0;
+            }
+            if msg == 0x020A /* WM_MOUSEWHEEL */ {
+                // the position comes in screen coordinates
+                let mut pt = POINT { x: (l_param & 0xFFFF) as i16 as i32, y: ((l_param >> 16) & 0xFFFF) as i16 as i32 };
+                unsafe { ScreenToClient(hWnd, &mut pt as *mut _); }
+                let delta = ((w_param >> 16) & 0xFFFF) as i16 as i32;
+                if let Some(win_id) = win_id_of(hWnd)
+                    && scroll_input(win_id, |p| p.contains(pt.x, pt.y).then(|| p.wheel(delta))) {
+                        return 0;
+                }
+            } else if msg == 0x0200 /* WM_MOUSEMOVE */ {
+                let ly = ((l_param >> 16) & 0xFFFF) as i16 as i32;
+                let lx = (l_param & 0xFFFF) as i16 as i32;
+                if let Some(win_id) = win_id_of(hWnd) {
+                    scroll_input(win_id, |p| p.dragging().then(|| p.drag_to(ly)));
+                    list_input(win_id, lx, ly, false);
+                }
+            } else if msg == 0x0202 /* WM_LBUTTONUP */ {
+                if let Some(win_id) = win_id_of(hWnd)
+                    && scroll_input(win_id, |p| p.dragging().then(|| { p.release(); false })) {
+                        unsafe { ReleaseCapture(); }
+                }
+            }
             // handle mouse click
             if msg == 0x0201 /* WM_LBUTTONDOWN */ {
+                let (lx, ly) = ((l_param & 0xFFFF) as i16 as i32, ((l_param >> 16) & 0xFFFF) as i16 as i32);
+                if let Some(win_id) = win_id_of(hWnd) {
+                    if scroll_input(win_id, |p| { let old = p.offset; p.press(lx, ly).then(|| p.offset != old) }) {
+                        // keep getting WM_MOUSEMOVE while the thumb is dragged outside the window
+                        unsafe { SetCapture(hWnd); }
+                        return 0;
+                    }
+                    if list_input(win_id, lx, ly, true) { return 0; }
+                }
+
                 // extract x,y from l_param
                 let lx = (l_param & 0xFFFF) as i16 as i32;
                 let ly = ((l_param >> 16) & 0xFFFF) as i16 as i32;
@@ -332,18 +563,8 @@ pub fn create_window(title: &str, w: i32, h: i32) -> u64 {
                 if let Some(win_id) = win_id_opt {
                     // find widget hit
                     if let Some(widget) = find_widget_hit(win_id, lx, ly) {
-                        // push event by handler name
-                        let handler = widget.handler.clone();
-                        let reg = events_registry();
-                        if let Ok(mut g) = reg.lock() {
-                            // reuse events vector for (win_id, (x,y)) but we'll push in handlers form by encoding handler name into HANDLERS map? simpler: store handler mapping in EVENTS as u64->ignored, but to avoid changing many parts, push as before and handlers_lookup will be used.
-                            // We'll push as a special negative id mapping by storing win_id as widget id in first field and use handlers registry to map widget id to name.
-                            g.push((widget.id, (lx, ly)));
-                        }
-                        // also save handler name for widget id
-                        if let Ok(mut wmap) = handlers_registry().lock() {
-                            wmap.insert(widget.id, handler);
-                        }
+                        // the widget's handler was registered with it
+                        push_event(widget.id, lx, ly);
                     } else {
                         // no widget hit: push window-level event
                         push_event(win_id, lx, ly);
@@ -385,7 +606,7 @@ pub fn create_window(title: &str, w: i32, h: i32) -> u64 {
             let bufsize = (w as usize).saturating_mul(h as usize).saturating_mul(4);
             let mut buf = vec![0u8; bufsize];
             for i in (0..buf.len()).step_by(4) { buf[i+0]=bg[0]; buf[i+1]=bg[1]; buf[i+2]=bg[2]; buf[i+3]=bg[3]; }
-            let canvas = CanvasState { buf, w, h, bg };
+            let canvas = CanvasState { buf, w, h, bg, saved: Vec::new() };
             let buffer_holder: Box<Mutex<CanvasState>> = Box::new(Mutex::new(canvas));
             let bh_ptr = Box::into_raw(buffer_holder) as isize;
             unsafe { SetWindowLongPtrW(hwnd, GWLP_USERDATA, bh_ptr); }
@@ -446,6 +667,8 @@ pub fn create_window(title: &str, w: i32, h: i32) -> u64 {
                             // just request repaint (buffer already stored)
                             unsafe { InvalidateRect(hwnd_local as HWND, null(), 1); }
                         }
+                        WindowCommand::Save => save_or_restore(hwnd_local, true),
+                        WindowCommand::Restore => save_or_restore(hwnd_local, false),
                         WindowCommand::DrawText(x,y,txt) => {
                             // very small placeholder: draw a simple colored rectangle behind where text would be
                             unsafe {
@@ -670,6 +893,145 @@ pub fn canvas_draw_text(id: u64, x: i32, y: i32, text: &str) -> Result<(), Strin
     } else { Err("window id not found".to_string()) }
 }
 
+/// `WindowCommand::Save` / `Restore` on the window thread
+fn save_or_restore(hwnd: usize, save: bool) {
+    unsafe {
+        let bh_ptr = GetWindowLongPtrW(hwnd as HWND, GWLP_USERDATA) as *mut Mutex<CanvasState>;
+        if !bh_ptr.is_null() {
+            if let Ok(mut guard) = (*bh_ptr).lock() {
+                if save {
+                    let copy = guard.buf.clone();
+                    guard.saved.push(copy);
+                } else if let Some(copy) = guard.saved.pop() {
+                    if copy.len() == guard.buf.len() { guard.buf = copy; }
+                }
+            }
+        }
+        InvalidateRect(hwnd as HWND, null(), 1);
+    }
+}
+
+fn send(id: u64, cmd: WindowCommand) -> Result<(), String> {
+    let guard = registry().lock().map_err(|_| "registry lock poisoned".to_string())?;
+    let tx = guard.get(&id).ok_or_else(|| "window id not found".to_string())?;
+    tx.send(cmd).map_err(|e| e.to_string())
+}
+
+fn hwnd_of(id: u64) -> Result<HWND, String> {
+    let map = hwnd_map().lock().map_err(|_| "window map lock poisoned".to_string())?;
+    map.iter().find(|(_, win)| **win == id).map(|(hwnd, _)| *hwnd as HWND).ok_or_else(|| "window id not found".to_string())
+}
+
+fn monitor_info(monitor: *mut c_void) -> Option<crate::gui::Monitor> {
+    let mut mi = MONITORINFO { cbSize: std::mem::size_of::<MONITORINFO>() as u32, rcMonitor: RECT { left: 0, top: 0, right: 0, bottom: 0 }, rcWork: RECT { left: 0, top: 0, right: 0, bottom: 0 }, dwFlags: 0 };
+    if unsafe { GetMonitorInfoW(monitor, &mut mi as *mut _) } == 0 { return None; }
+    let rect = |r: RECT| (r.left, r.top, r.right - r.left, r.bottom - r.top);
+    Some(crate::gui::Monitor { bounds: rect(mi.rcMonitor), work: rect(mi.rcWork), primary: mi.dwFlags & MONITORINFOF_PRIMARY != 0 })
+}
+
+/// Every monitor, in the system's order; coordinates are on the virtual
+/// screen, where the primary monitor starts at (0, 0)
+pub fn monitors() -> Vec<crate::gui::Monitor> {
+    extern "system" fn each(monitor: *mut c_void, _hdc: HDC, _rect: *mut RECT, data: LPARAM) -> i32 {
+        let out = unsafe { &mut *(data as *mut Vec<crate::gui::Monitor>) };
+        out.extend(monitor_info(monitor));
+        1
+    }
+    let mut out: Vec<crate::gui::Monitor> = Vec::new();
+    unsafe { EnumDisplayMonitors(null_mut(), null(), each, &mut out as *mut _ as LPARAM); }
+    out
+}
+
+/// Move a window's top-left corner to (x, y) on the virtual screen
+pub fn move_window(id: u64, x: i32, y: i32) -> Result<(), String> {
+    let hwnd = hwnd_of(id)?;
+    unsafe { SetWindowPos(hwnd, null_mut(), x, y, 0, 0, SWP_NOSIZE | SWP_NOZORDER | SWP_NOACTIVATE); }
+    Ok(())
+}
+
+static WINDOWED: OnceLock<Mutex<HashMap<u64, (isize, RECT)>>> = OnceLock::new();
+
+/// Cover the monitor the window is on with a borderless window, or go back
+/// to the style and place it had before
+pub fn set_fullscreen(id: u64, on: bool) -> Result<(), String> {
+    let hwnd = hwnd_of(id)?;
+    let mut windowed = WINDOWED.get_or_init(Default::default).lock().map_err(|_| "fullscreen lock poisoned".to_string())?;
+    unsafe {
+        if on {
+            if windowed.contains_key(&id) { return Ok(()); }
+            let mut rect = RECT { left: 0, top: 0, right: 0, bottom: 0 };
+            GetWindowRect(hwnd, &mut rect as *mut _);
+            let style = GetWindowLongPtrW(hwnd, GWL_STYLE);
+            let (x, y, w, h) = monitor_info(MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST)).ok_or("no monitor for the window")?.bounds;
+            windowed.insert(id, (style, rect));
+            SetWindowLongPtrW(hwnd, GWL_STYLE, (WS_POPUP | WS_VISIBLE) as isize);
+            SetWindowPos(hwnd, null_mut(), x, y, w, h, SWP_NOZORDER | SWP_FRAMECHANGED);
+        } else if let Some((style, r)) = windowed.remove(&id) {
+            SetWindowLongPtrW(hwnd, GWL_STYLE, style);
+            SetWindowPos(hwnd, null_mut(), r.left, r.top, r.right - r.left, r.bottom - r.top, SWP_NOZORDER | SWP_FRAMECHANGED);
+        }
+    }
+    Ok(())
+}
+
+/// Keep a copy of the canvas to go back to with `canvas_restore`
+pub fn canvas_save(id: u64) -> Result<(), String> {
+    send(id, WindowCommand::Save)
+}
+
+pub fn canvas_restore(id: u64) -> Result<(), String> {
+    send(id, WindowCommand::Restore)
+}
+
+/// Width and height of a window's canvas
+pub fn canvas_size(id: u64) -> Option<(i32, i32)> {
+    let hwnd = hwnd_of(id).ok()?;
+    unsafe {
+        let bh_ptr = GetWindowLongPtrW(hwnd as HWND, GWLP_USERDATA) as *mut Mutex<CanvasState>;
+        if bh_ptr.is_null() { return None; }
+        let guard = (*bh_ptr).lock().ok()?;
+        Some((guard.w, guard.h))
+    }
+}
+
+/// Width, height and RGBA pixels of a window's canvas, as drawn so far
+pub fn canvas_pixels(id: u64) -> Option<(i32, i32, Vec<u8>)> {
+    let hwnd = hwnd_of(id).ok()?;
+    unsafe {
+        let bh_ptr = GetWindowLongPtrW(hwnd as HWND, GWLP_USERDATA) as *mut Mutex<CanvasState>;
+        if bh_ptr.is_null() { return None; }
+        let guard = (*bh_ptr).lock().ok()?;
+        Some((guard.w, guard.h, guard.buf.clone()))
+    }
+}
+
+/// Let files be dropped on a window; they arrive as `WM_DROPFILES`
+pub fn accept_files(id: u64) -> Result<(), String> {
+    unsafe { DragAcceptFiles(hwnd_of(id)?, 1); }
+    Ok(())
+}
+
+/// Paths and drop point of a `WM_DROPFILES` handle, which is released
+fn read_drop(hdrop: *mut c_void) -> (Vec<String>, i32, i32) {
+    unsafe {
+        let count = DragQueryFileW(hdrop, u32::MAX, null_mut(), 0);
+        let paths = (0..count).map(|i| {
+            let len = DragQueryFileW(hdrop, i, null_mut(), 0);
+            let mut buf = vec![0u16; len as usize + 1];
+            let got = DragQueryFileW(hdrop, i, buf.as_mut_ptr(), len + 1);
+            String::from_utf16_lossy(&buf[..got as usize])
+        }).collect();
+        let mut pt = POINT { x: 0, y: 0 };
+        DragQueryPoint(hdrop, &mut pt as *mut _);
+        DragFinish(hdrop);
+        (paths, pt.x, pt.y)
+    }
+}
+
+pub fn window_exists(id: u64) -> bool {
+    registry().lock().map(|g| g.contains_key(&id)).unwrap_or(false)
+}
+
 pub fn close_window(id: u64) {
     if let Ok(mut guard) = registry().lock() {
         if let Some(tx) = guard.get(&id) {
@@ -684,3 +1046,79 @@ pub fn has_windows() -> bool {
         !g.is_empty()
     } else { false }
 }
+
+// --- registry ---
+
+type HKEY = *mut c_void;
+
+const REG_SZ: u32 = 1;
+const REG_DWORD: u32 = 4;
+const REG_QWORD: u32 = 11;
+// REG_EXPAND_SZ values are expanded and returned as REG_SZ
+const RRF_RT_REG_SZ: u32 = 0x0000_0002;
+const RRF_RT_REG_DWORD: u32 = 0x0000_0010;
+const RRF_RT_REG_QWORD: u32 = 0x0000_0040;
+
+#[link(name = "advapi32")]
+unsafe extern "system" {
+    fn RegGetValueW(hkey: HKEY, lpSubKey: *const u16, lpValue: *const u16, dwFlags: u32,
+                    pdwType: *mut u32, pvData: *mut c_void, pcbData: *mut u32) -> i32;
+    fn RegSetKeyValueW(hKey: HKEY, lpSubKey: *const u16, lpValueName: *const u16, dwType: u32,
+                       lpData: *const c_void, cbData: u32) -> i32;
+}
+
+/// A registry value as scripts see it: strings, DWORDs and QWORDs
+pub enum RegValue {
+    Str(String),
+    Int(i64),
+}
+
+fn hive(name: &str) -> Result<HKEY, String> {
+    let key: u32 = match name.to_ascii_uppercase().as_str() {
+        "HKCR" | "HKEY_CLASSES_ROOT" => 0x8000_0000,
+        "HKCU" | "HKEY_CURRENT_USER" => 0x8000_0001,
+        "HKLM" | "HKEY_LOCAL_MACHINE" => 0x8000_0002,
+        "HKU" | "HKEY_USERS" => 0x8000_0003,
+        _ => return Err(format!("unknown registry hive {} (expected HKCU, HKLM, HKCR or HKU)", name)),
+    };
+    // predefined keys are sign-extended 32-bit handles
+    Ok(key as i32 as isize as HKEY)
+}
+
+pub fn reg_read(hive_name: &str, path: &str, name: &str) -> Result<RegValue, String> {
+    let key = hive(hive_name)?;
+    let (path_w, name_w) = (to_wide(path), to_wide(name));
+    let flags = RRF_RT_REG_SZ | RRF_RT_REG_DWORD | RRF_RT_REG_QWORD;
+    let fail = |rc: i32| format!("{}\\{}\\{}: registry error {}", hive_name, path, name, rc);
+    let (mut kind, mut size) = (0u32, 0u32);
+    let rc = unsafe { RegGetValueW(key, path_w.as_ptr(), name_w.as_ptr(), flags, &mut kind, null_mut(), &mut size) };
+    if rc != 0 { return Err(fail(rc)); }
+    let mut buf = vec![0u8; size as usize];
+    let rc = unsafe { RegGetValueW(key, path_w.as_ptr(), name_w.as_ptr(), flags, &mut kind, buf.as_mut_ptr() as *mut c_void, &mut size) };
+    if rc != 0 { return Err(fail(rc)); }
+    buf.truncate(size as usize);
+    Ok(match kind {
+        REG_DWORD if buf.len() >= 4 => RegValue::Int(u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as i64),
+        REG_QWORD if buf.len() >= 8 => RegValue::Int(i64::from_le_bytes(buf[..8].try_into().unwrap())),
+        _ => {
+            let wide: Vec<u16> = buf.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).take_while(|&c| c != 0).collect();
+            RegValue::Str(String::from_utf16_lossy(&wide))
+        }
+    })
+}
+
+/// Create the key if needed; ints that fit in 32 bits are stored as DWORDs
+pub fn reg_write(hive_name: &str, path: &str, name: &str, value: &RegValue) -> Result<(), String> {
+    let key = hive(hive_name)?;
+    let (kind, data): (u32, Vec<u8>) = match value {
+        RegValue::Str(s) => (REG_SZ, to_wide(s).iter().flat_map(|c| c.to_le_bytes()).collect()),
+        RegValue::Int(n) => match u32::try_from(*n) {
+            Ok(d) => (REG_DWORD, d.to_le_bytes().to_vec()),
+            Err(_) => (REG_QWORD, n.to_le_bytes().to_vec()),
+        },
+    };
+    let (path_w, name_w) = (to_wide(path), to_wide(name));
+    let rc = unsafe { RegSetKeyValueW(key, path_w.as_ptr(), name_w.as_ptr(), kind, data.as_ptr() as *const c_void, data.len() as u32) };
+    if rc != 0 { return Err(format!("{}\\{}\\{}: registry error {}", hive_name, path, name, rc)); }
+    Ok(())
+}
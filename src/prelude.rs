@@ -0,0 +1,17 @@
+//! The supported API for programs that embed userd:
+//! `use userd::prelude::*;`
+//!
+//! Everything re-exported here follows semantic versioning: while the
+//! version is 0.x, removing or changing any of it bumps the minor version.
+//! The modules of the crate itself are implementation details for the
+//! `userd` tools and may change in any release; the hidden ones are left out
+//! of the docs.
+pub use crate::builtins::{from_args, Builtin, BuiltinFn, FromArgs, Registry, VmError, VARIADIC};
+pub use crate::error::Error;
+pub use crate::group::VmGroup;
+pub use crate::host::HostApi;
+pub use crate::parser::{parse, Parser};
+pub use crate::usage::Usage;
+pub use crate::vm::{
+    Capability, CancelToken, DebugAction, DisplayLimits, OutputBuffer, Pause, Policy, RunState, Value, VM,
+};
@@ -1,8 +1,6 @@
-use std::os::raw::c_void;
-
 #[cfg(target_os = "windows")]
 mod platform_rng {
-    use super::*;
+    use std::os::raw::c_void;
     #[link(name = "bcrypt")]
     unsafe extern "system" {
         // NTSTATUS BCryptGenRandom(BCRYPT_ALG_HANDLE hAlgorithm, PUCHAR pbBuffer, ULONG cbBuffer, ULONG dwFlags);
@@ -17,7 +15,6 @@ mod platform_rng {
 
 #[cfg(not(target_os = "windows"))]
 mod platform_rng {
-    use super::*;
     pub fn fill_bytes(buf: &mut [u8]) -> Result<(), String> {
         use std::io::Read;
         use std::fs::File;
@@ -31,6 +28,11 @@ mod platform_rng {
     }
 }
 
+/// Fill `buf` from the operating system's secure generator
+pub fn secure_bytes(buf: &mut [u8]) -> Result<(), String> {
+    platform_rng::fill_bytes(buf)
+}
+
 /// Returns a secure random u64 in range [0, max)
 pub fn secure_random_u64(max: u64) -> Result<u64, String> {
     if max == 0 { return Err("secure_random: max must be > 0".to_string()); }
@@ -47,3 +49,84 @@ pub fn secure_random_u64(max: u64) -> Result<u64, String> {
         // otherwise retry
     }
 }
+
+/// Sample of the normal distribution with the given mean and standard
+/// deviation, from the secure generator
+pub fn normal(mean: f64, stddev: f64) -> Result<f64, String> {
+    normal_with(mean, stddev, secure_random_u64)
+}
+
+/// `normal` drawing uniform numbers from `below` (a `secure_random_u64`-like
+/// source), so seeded generators can drive it too. Box–Muller transform
+pub fn normal_with(mean: f64, stddev: f64, mut below: impl FnMut(u64) -> Result<u64, String>) -> Result<f64, String> {
+    if !(stddev >= 0.0 && stddev.is_finite()) { return Err(format!("stddev must be a finite number >= 0, got {}", stddev)); }
+    const SCALE: f64 = (1u64 << 53) as f64;
+    // u1 in (0, 1] keeps ln(u1) finite
+    let u1 = (below(1 << 53)? + 1) as f64 / SCALE;
+    let u2 = below(1 << 53)? as f64 / SCALE;
+    Ok(mean + stddev * (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos())
+}
+
+/// Seeded SplitMix64 generator for `VM::deterministic`: the same seed always
+/// gives the same sequence. Not suitable for secrets.
+#[derive(Debug, Clone)]
+pub struct SeededRng(u64);
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self { Self(seed) }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Value in [0, max), with the same rejection sampling as `secure_random_u64`
+    pub fn below(&mut self, max: u64) -> Result<u64, String> {
+        if max == 0 { return Err("secure_random: max must be > 0".to_string()); }
+        let limit = u64::MAX - (u64::MAX % max);
+        loop {
+            let v = self.next_u64();
+            if v < limit { return Ok(v % max); }
+        }
+    }
+}
+
+/// xoshiro256** for `rand_seed` / `rand_next`: fast and reproducible from a
+/// seed, for games and tests. Not suitable for secrets; `secure_random` is.
+#[derive(Debug, Clone)]
+pub struct Xoshiro256([u64; 4]);
+
+impl Xoshiro256 {
+    /// State expanded from `seed` with SplitMix64, as the authors recommend,
+    /// so that small or similar seeds still give unrelated sequences
+    pub fn new(seed: u64) -> Self {
+        let mut sm = SeededRng::new(seed);
+        Self([sm.next_u64(), sm.next_u64(), sm.next_u64(), sm.next_u64()])
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.0;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    /// Value in [0, max), without modulo bias
+    pub fn below(&mut self, max: u64) -> Result<u64, String> {
+        if max == 0 { return Err("rand_below: max must be > 0".to_string()); }
+        let limit = u64::MAX - (u64::MAX % max);
+        loop {
+            let v = self.next_u64();
+            if v < limit { return Ok(v % max); }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Software canvas for windows that have no platform window behind them, and
+//! the BMP files `canvas_save` writes and `assert_canvas_matches` compares
+//! against. Only rectangles and clears are rasterized; text needs a font and
+//! is left to the platform layers.
+//!
+//! Pixels are RGBA, row by row from the top. Files are uncompressed 32-bit
+//! BMPs; 24-bit ones are read too, so a golden image can come from any editor.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Raster {
+    pub w: i32,
+    pub h: i32,
+    pub pixels: Vec<u8>,
+}
+
+/// Where two rasters differ by more than the tolerance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub count: usize,
+    /// The first differing pixel, scanning rows from the top
+    pub first: (i32, i32),
+}
+
+impl Raster {
+    /// A canvas filled with opaque white, like a fresh window
+    pub fn new(w: i32, h: i32) -> Self {
+        let (w, h) = (w.max(0), h.max(0));
+        Raster { w, h, pixels: vec![255; w as usize * h as usize * 4] }
+    }
+
+    pub fn clear(&mut self, rgba: [u8; 4]) {
+        for px in self.pixels.chunks_exact_mut(4) { px.copy_from_slice(&rgba); }
+    }
+
+    /// Fill a rectangle, clipped to the canvas
+    pub fn fill_rect(&mut self, x: i32, y: i32, w: i32, h: i32, rgba: [u8; 4]) {
+        for py in y.max(0)..(y.saturating_add(h)).min(self.h) {
+            for px in x.max(0)..(x.saturating_add(w)).min(self.w) {
+                let i = (py as usize * self.w as usize + px as usize) * 4;
+                self.pixels[i..i + 4].copy_from_slice(&rgba);
+            }
+        }
+    }
+
+    /// Pixels whose channels differ by more than `tolerance`; `Err` when the
+    /// sizes differ
+    pub fn compare(&self, other: &Raster, tolerance: u8) -> Result<Option<Mismatch>, String> {
+        if (self.w, self.h) != (other.w, other.h) {
+            return Err(format!("size {}x{} differs from {}x{}", self.w, self.h, other.w, other.h));
+        }
+        let mut found: Option<Mismatch> = None;
+        for (n, (a, b)) in self.pixels.chunks_exact(4).zip(other.pixels.chunks_exact(4)).enumerate() {
+            if a.iter().zip(b).all(|(a, b)| a.abs_diff(*b) <= tolerance) { continue; }
+            let at = ((n % self.w as usize) as i32, (n / self.w as usize) as i32);
+            found.get_or_insert(Mismatch { count: 0, first: at }).count += 1;
+        }
+        Ok(found)
+    }
+
+    /// A bottom-up 32-bit BMP
+    pub fn to_bmp(&self) -> Vec<u8> {
+        let size = self.pixels.len() as u32;
+        let mut out = Vec::with_capacity(54 + self.pixels.len());
+        out.extend_from_slice(b"BM");
+        for v in [54 + size, 0, 54, 40] { out.extend_from_slice(&v.to_le_bytes()); }
+        out.extend_from_slice(&self.w.to_le_bytes());
+        out.extend_from_slice(&self.h.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&32u16.to_le_bytes());
+        for v in [0u32, size, 2835, 2835, 0, 0] { out.extend_from_slice(&v.to_le_bytes()); }
+        for row in self.pixels.chunks_exact(self.w as usize * 4).rev() {
+            for px in row.chunks_exact(4) { out.extend_from_slice(&[px[2], px[1], px[0], px[3]]); }
+        }
+        out
+    }
+
+    /// An uncompressed 24- or 32-bit BMP, either row order
+    pub fn from_bmp(data: &[u8]) -> Result<Self, String> {
+        let u16_at = |i: usize| data.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+        let u32_at = |i: usize| data.get(i..i + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        if !data.starts_with(b"BM") { return Err("not a BMP file".to_string()); }
+        let (Some(offset), Some(w), Some(h), Some(bits), Some(compression)) =
+            (u32_at(10), u32_at(18), u32_at(22), u16_at(28), u32_at(30)) else { return Err("truncated BMP header".to_string()) };
+        let (w, h) = (w as i32, h as i32);
+        if compression != 0 && !(compression == 3 && bits == 32) { return Err("compressed BMPs are not supported".to_string()); }
+        if bits != 24 && bits != 32 { return Err(format!("{}-bit BMPs are not supported, only 24 and 32", bits)); }
+        if w <= 0 || h == 0 { return Err("BMP has no pixels".to_string()); }
+        let (rows, bottom_up) = (h.unsigned_abs() as usize, h > 0);
+        let bpp = bits as usize / 8;
+        let stride = (w as usize * bpp).div_ceil(4) * 4;
+        let mut raster = Raster::new(w, rows as i32);
+        for y in 0..rows {
+            let src_row = if bottom_up { rows - 1 - y } else { y };
+            let start = offset as usize + src_row * stride;
+            let row = data.get(start..start + w as usize * bpp).ok_or("truncated BMP pixel data")?;
+            for (x, px) in row.chunks_exact(bpp).enumerate() {
+                let a = if bpp == 4 { px[3] } else { 255 };
+                let i = (y * w as usize + x) * 4;
+                raster.pixels[i..i + 4].copy_from_slice(&[px[2], px[1], px[0], a]);
+            }
+        }
+        Ok(raster)
+    }
+}
@@ -0,0 +1,202 @@
+//! Time-travel recording: a trace hook that keeps the program state before
+//! every statement, so a run can be stepped through backwards afterwards
+//! (`userd run --record=trace.bin`, `userd replay trace.bin`, REPL `:record`).
+//!
+//! Globals are stored as VM snapshots; a step whose globals did not change
+//! shares the previous snapshot. Locals are kept as display text.
+//! Trace files: `USRDT` magic, version (u16), payload length (u64), payload,
+//! FNV-1a checksum, like snapshots.
+//!
+//! A recording keeps at most `MAX_STEPS` steps and `MAX_STATE_BYTES` of
+//! snapshots; past either it stops, and `Trace::stopped` says why.
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::artifact::{fnv1a, Reader, Writer};
+use crate::vm::VM;
+
+const MAGIC: &[u8] = b"USRDT";
+/// 2: the reason recording stopped early follows the steps
+const VERSION: u16 = 2;
+
+const MAX_STEPS: usize = 100_000;
+const MAX_STATE_BYTES: usize = 256 << 20;
+
+/// Program state before one statement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    /// 0 for the state after the program finished
+    pub line: usize,
+    pub depth: usize,
+    /// Parameters of the innermost call, as displayed by the debugger
+    pub locals: Vec<(String, String)>,
+    /// Index into `Trace::states`
+    state: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    pub steps: Vec<Step>,
+    states: Vec<Vec<u8>>,
+    /// Total size of `states`
+    state_bytes: usize,
+    /// Why recording stopped before the run ended; later steps are missing
+    pub stopped: Option<String>,
+}
+
+impl Trace {
+    fn push(&mut self, line: usize, vm: &VM, max_steps: usize, max_state_bytes: usize) {
+        if self.stopped.is_some() { return; }
+        if self.steps.len() >= max_steps {
+            self.stopped = Some(format!("recording stopped after {} steps", max_steps));
+            return;
+        }
+        let snapshot = vm.snapshot();
+        if self.states.last() != Some(&snapshot) {
+            if self.state_bytes + snapshot.len() > max_state_bytes {
+                self.stopped = Some(format!("recording stopped at step {}: snapshots reached {} bytes", self.steps.len(), max_state_bytes));
+                return;
+            }
+            self.state_bytes += snapshot.len();
+            self.states.push(snapshot);
+        }
+        let locals = vm.locals(0).iter().map(|(n, v)| (n.clone(), crate::json::inspect(v))).collect();
+        self.steps.push(Step { line, depth: vm.call_depth(), locals, state: self.states.len() - 1 });
+    }
+
+    /// Load the globals as they were at step `i` into `vm`
+    pub fn restore(&self, i: usize, vm: &mut VM) -> Result<(), String> {
+        let step = self.steps.get(i).ok_or_else(|| format!("no step {}", i))?;
+        vm.restore(&self.states[step.state])
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut p = Writer::default();
+        p.u32(self.states.len() as u32);
+        for s in &self.states {
+            p.u64(s.len() as u64);
+            p.buf.extend_from_slice(s);
+        }
+        p.u32(self.steps.len() as u32);
+        for s in &self.steps {
+            p.u32(s.line as u32);
+            p.u32(s.depth as u32);
+            p.u32(s.state as u32);
+            p.u32(s.locals.len() as u32);
+            for (name, v) in &s.locals { p.str(name); p.str(v); }
+        }
+        p.opt_str(&self.stopped);
+
+        let mut w = Writer::default();
+        w.buf.extend_from_slice(MAGIC);
+        w.buf.extend_from_slice(&VERSION.to_le_bytes());
+        w.u64(p.buf.len() as u64);
+        w.buf.extend_from_slice(&p.buf);
+        w.u64(fnv1a(&p.buf));
+        w.buf
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        if !bytes.starts_with(MAGIC) {
+            return Err("not a userd trace".to_string());
+        }
+        let mut r = Reader::new(bytes);
+        r.take(MAGIC.len())?;
+        let version = u16::from_le_bytes(r.take(2)?.try_into().unwrap());
+        if !(1..=VERSION).contains(&version) {
+            return Err(format!("unsupported trace version {} (expected 1..={})", version, VERSION));
+        }
+        let len = r.u64()? as usize;
+        let payload = r.take(len)?;
+        if r.u64()? != fnv1a(payload) {
+            return Err("trace checksum mismatch".to_string());
+        }
+        let mut r = Reader::new(payload);
+        let mut trace = Trace::default();
+        for _ in 0..r.u32()? {
+            let n = r.u64()? as usize;
+            trace.states.push(r.take(n)?.to_vec());
+            trace.state_bytes += n;
+        }
+        for _ in 0..r.u32()? {
+            let (line, depth, state) = (r.u32()? as usize, r.u32()? as usize, r.u32()? as usize);
+            if state >= trace.states.len() { return Err(format!("bad state {} in trace", state)); }
+            let locals = (0..r.u32()?).map(|_| Ok((r.str()?, r.str()?))).collect::<Result<_, String>>()?;
+            trace.steps.push(Step { line, depth, locals, state });
+        }
+        if version >= 2 { trace.stopped = r.opt_str()?; }
+        Ok(trace)
+    }
+}
+
+/// Records into a `Trace` while installed as the VM's trace hook
+pub struct Recorder {
+    trace: Rc<RefCell<Trace>>,
+    max_steps: usize,
+    max_state_bytes: usize,
+}
+
+impl Recorder {
+    /// Start recording `vm`; replaces its trace hook
+    pub fn start(vm: &mut VM) -> Self {
+        Self::start_limited(vm, MAX_STEPS, MAX_STATE_BYTES)
+    }
+
+    /// `start` with other limits than `MAX_STEPS` and `MAX_STATE_BYTES`
+    pub(crate) fn start_limited(vm: &mut VM, max_steps: usize, max_state_bytes: usize) -> Self {
+        let trace = Rc::new(RefCell::new(Trace::default()));
+        let t = trace.clone();
+        vm.set_trace_hook(move |stmt, vm| {
+            if let Some(line) = stmt.line() { t.borrow_mut().push(line, vm, max_steps, max_state_bytes); }
+        });
+        Recorder { trace, max_steps, max_state_bytes }
+    }
+
+    /// Stop recording and add the final state, unless recording already stopped
+    pub fn finish(self, vm: &mut VM) -> Trace {
+        vm.clear_trace_hook();
+        self.trace.borrow_mut().push(0, vm, self.max_steps, self.max_state_bytes);
+        self.trace.take()
+    }
+}
+
+/// Step through a trace on stdin: `n` next, `b` back, `g N` go to step N,
+/// `p name` prints a global at the current step, `q` quits
+pub fn replay(trace: &Trace) {
+    if trace.steps.is_empty() { println!("empty trace"); return; }
+    if let Some(why) = &trace.stopped { println!("{}; the steps after it are not in the trace", why); }
+    let mut vm = VM::new();
+    let mut i = 0;
+    loop {
+        let step = &trace.steps[i];
+        let at = if step.line == 0 { "end".to_string() } else { format!("line {}", step.line) };
+        let locals: Vec<String> = step.locals.iter().map(|(n, v)| format!("{} = {}", n, v)).collect();
+        println!("[{}/{}] {} depth {} {}", i, trace.steps.len() - 1, at, step.depth, locals.join(", "));
+        print!("(replay) ");
+        let _ = io::stdout().flush();
+        let mut cmd = String::new();
+        if io::stdin().read_line(&mut cmd).unwrap_or(0) == 0 { return; }
+        match cmd.trim() {
+            "n" | "" => i = (i + 1).min(trace.steps.len() - 1),
+            "b" => i = i.saturating_sub(1),
+            "q" => return,
+            cmd => {
+                if let Some(n) = cmd.strip_prefix("g ") {
+                    match n.trim().parse::<usize>() {
+                        Ok(n) if n < trace.steps.len() => i = n,
+                        _ => println!("no step {}", n.trim()),
+                    }
+                } else if let Some(name) = cmd.strip_prefix("p ") {
+                    match trace.restore(i, &mut vm).map(|_| vm.get_global(name.trim())) {
+                        Ok(Some(v)) => println!("  {}", crate::json::inspect(&v)),
+                        Ok(None) => println!("  undefined: {}", name.trim()),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                } else {
+                    println!("commands: n(ext), b(ack), g <step>, p <global>, q(uit)");
+                }
+            }
+        }
+    }
+}
@@ -1,30 +1,463 @@
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Read, Write};
 use crate::parser::Parser;
-use crate::vm::VM;
+use crate::token::Span;
+use crate::recorder::Recorder;
+use crate::vm::{DebugAction, DisplayLimits, Pause, Value, VM};
 
-/// REPL: собирает ввод до `;`, затем парсит и исполняет программу
-pub fn start_repl() {
+/// REPL commands, offered by Tab at the start of a line, and what `:help` says about them
+const COMMANDS: [(&str, &str); 13] = [
+    (":vars", "list the session's globals with their types"),
+    (":load ", "file.usrd or .usrdc: run it in this session; other files: restore a :save"),
+    (":save ", "file: write the session's globals to a file"),
+    (":save-session ", "file: write the globals and the inputs so far, to resume later"),
+    (":restore-session ", "file: replace this session with one from :save-session"),
+    (":reset", "start over with a fresh VM"),
+    (":help ", "[name]: this list, or the docs of a builtin"),
+    (":paste", "read lines as they are until :end or Ctrl-D, then run them at once"),
+    (":full", "switch truncation of large values off and on"),
+    (":time ", "on|off: after each input, print how long it ran and how many steps it took"),
+    (":record", "start recording the following input"),
+    (":replay", "stop recording and step through it"),
+    (":debug ", "file line...: run a file, pausing at the lines"),
+];
+
+/// REPL: собирает ввод, пока он не станет законченной программой (см.
+/// `input_complete`), затем парсит и исполняет её. С `color` ввод
+/// подсвечивается, а ошибки печатаются красным (если вывод — терминал)
+pub fn start_repl(color: bool) {
+    let color = color && io::stdout().is_terminal();
     println!("userd REPL — введите 'exit' для выхода");
     let mut buffer = String::new();
     let mut vm = VM::new();
-    loop {
-        print!("> ");
-        let _ = io::stdout().flush();
-        let mut line = String::new();
-        if io::stdin().read_line(&mut line).is_err() { break; }
+    let mut full = false;
+    let mut timing = false;
+    // inputs that ran, kept by `:save-session`
+    let mut inputs: Vec<String> = Vec::new();
+    let mut recorder: Option<Recorder> = None;
+    // Ctrl-C stops the running input, not the REPL
+    let interrupt = vm.cancel_token();
+    vm.set_pretty_objects(true);
+    crate::platform::interrupt::cancel_on_ctrl_c(interrupt.clone());
+    // `...` while a class, function or call spans several lines
+    while let Some(line) = read_input(&vm, if buffer.is_empty() { "> " } else { "... " }, color) {
         let trimmed = line.trim_end();
-        if trimmed == "exit" { break; }
-        buffer.push_str(trimmed);
-        // if there's a semicolon, attempt to parse-execute everything up to last semicolon
-        if buffer.contains(';') {
-            // naive: parse whole buffer
+        if !buffer.is_empty() {
+            buffer.push('\n');
+        } else if trimmed == "exit" {
+            break;
+        }
+        // `:save file` / `:load file` keep the session's globals across restarts;
+        // `:load` also runs a `.usrd` or `.usrdc` file into the session
+        if let Some(path) = trimmed.strip_prefix(":save ") {
+            match std::fs::write(path.trim(), vm.snapshot()) {
+                Ok(_) => println!("saved to {}", path.trim()),
+                Err(e) => print_error(color, &e),
+            }
+            continue;
+        }
+        if let Some(path) = trimmed.strip_prefix(":load ") {
+            interrupt.reset();
+            match load(&mut vm, path.trim()) {
+                Ok(_) => println!("loaded {}", path.trim()),
+                Err(_) if vm.exit_code().is_some() => break,
+                Err(e) => print_error(color, &e),
+            }
+            continue;
+        }
+        if let Some(path) = trimmed.strip_prefix(":save-session ") {
+            let session = crate::session::Session::capture(&vm, inputs.clone());
+            match std::fs::write(path.trim(), session.encode()) {
+                Ok(_) => println!("saved session ({} inputs) to {}", inputs.len(), path.trim()),
+                Err(e) => print_error(color, &e),
+            }
+            continue;
+        }
+        if let Some(path) = trimmed.strip_prefix(":restore-session ") {
+            let restored = std::fs::read(path.trim()).map_err(|e| e.to_string())
+                .and_then(|b| crate::session::Session::decode(&b))
+                .and_then(|s| { s.restore_into(&mut vm)?; Ok(s.inputs) });
+            match restored {
+                Ok(saved) => {
+                    println!("restored session ({} inputs) from {}", saved.len(), path.trim());
+                    inputs = saved;
+                }
+                Err(e) => print_error(color, &e),
+            }
+            continue;
+        }
+        // `:reset` forgets everything but the Ctrl-C handler
+        if trimmed == ":reset" {
+            vm = VM::new();
+            vm.set_cancel_token(interrupt.clone());
+            vm.set_pretty_objects(true);
+            full = false;
+            timing = false;
+            inputs.clear();
+            recorder = None;
+            println!("session reset");
+            continue;
+        }
+        if trimmed == ":help" || trimmed.starts_with(":help ") {
+            match trimmed[":help".len()..].trim() {
+                "" => for (cmd, text) in COMMANDS { println!("{:<9} {}", cmd.trim_end(), text); },
+                name => match vm.help(name) {
+                    Ok(text) => println!("{}", text),
+                    Err(e) => print_error(color, &e),
+                },
+            }
+            continue;
+        }
+        // `:full` switches truncation of large values off and back on
+        if trimmed == ":full" {
+            full = !full;
+            vm.set_display_limits(if full { None } else { Some(DisplayLimits::default()) });
+            println!("{}", if full { "values are printed in full" } else { "large values are truncated" });
+            continue;
+        }
+        // `:time on` prints the wall-clock time and step count of each input
+        if trimmed == ":time" || trimmed.starts_with(":time ") {
+            match trimmed[":time".len()..].trim() {
+                "on" => timing = true,
+                "off" => timing = false,
+                _ => { println!("usage: :time on|off"); continue; }
+            }
+            println!("timing {}", if timing { "on" } else { "off" });
+            continue;
+        }
+        // `:record` starts recording the following input, `:replay` stops and steps through it
+        if trimmed == ":record" {
+            recorder = Some(Recorder::start(&mut vm));
+            println!("recording");
+            continue;
+        }
+        if trimmed == ":replay" {
+            match recorder.take() {
+                Some(r) => crate::recorder::replay(&r.finish(&mut vm)),
+                None => println!("nothing recorded; use :record first"),
+            }
+            continue;
+        }
+        // `:vars` lists the session's globals
+        if trimmed == ":vars" {
+            for (name, _) in vm.globals_iter() {
+                if let Some(d) = vm.describe(name) { println!("{}", d); }
+            }
+            continue;
+        }
+        // `:debug file line...` runs a file, pausing at the given lines
+        if let Some(rest) = trimmed.strip_prefix(":debug ") {
+            debug_file(&mut vm, rest);
+            continue;
+        }
+        // `:paste` takes the lines as they come, so a pasted class doesn't run
+        // before its end and pasted tabs don't complete anything
+        let paste = buffer.is_empty() && trimmed == ":paste";
+        if paste {
+            println!("paste mode: end with :end or Ctrl-D");
+            buffer = read_paste(&mut io::stdin().lock());
+        } else {
+            buffer.push_str(trimmed);
+        }
+        if paste || input_complete(&buffer) {
             let mut parser = Parser::new(&buffer);
             let prog = parser.parse_program();
-            match vm.execute_program(prog) {
-                Ok(_) => {},
-                Err(e) => println!("Error: {}", e),
+            // input with skipped tokens would run without them; point at them instead
+            if !parser.errors().is_empty() {
+                for (e, span) in parser.errors().iter().zip(parser.error_spans()) {
+                    print_error(color, e);
+                    println!("{}", caret(&buffer, span, color));
+                }
+                buffer.clear();
+                continue;
+            }
+            // forget a Ctrl-C pressed at the prompt
+            interrupt.reset();
+            let started = std::time::Instant::now();
+            let result = vm.execute_program(prog);
+            let elapsed = started.elapsed();
+            match result {
+                Ok(_) => inputs.push(buffer.clone()),
+                Err(_) if vm.exit_code().is_some() => break,
+                Err(e) => print_error(color, &e),
+            }
+            if timing {
+                let text = format!("time: {:.3} ms, {} steps", elapsed.as_secs_f64() * 1000.0, vm.steps());
+                if color { println!("{}{}{}", GRAY, text, RESET); } else { println!("{}", text); }
             }
             buffer.clear();
         }
     }
 }
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const MAGENTA: &str = "\x1b[35m";
+const CYAN: &str = "\x1b[36m";
+const GRAY: &str = "\x1b[90m";
+const RESET: &str = "\x1b[0m";
+
+fn print_error(color: bool, e: &dyn std::fmt::Display) {
+    if color { println!("{}Error:{} {}", RED, RESET, e); } else { println!("Error: {}", e); }
+}
+
+/// The source line of `span` with `^` under the token
+fn caret(src: &str, span: &Span, color: bool) -> String {
+    let line = src.lines().nth(span.line.saturating_sub(1)).unwrap_or("");
+    let marks = "^".repeat((span.end - span.start).max(1));
+    let marks = if color { format!("{}{}{}", RED, marks, RESET) } else { marks };
+    format!("  {}\n  {}{}", line, " ".repeat(span.col.saturating_sub(1)), marks)
+}
+
+/// `line` with ANSI colors: keywords, numbers, strings (closed or not) and
+/// comments. Only colors are added, so the text stays as wide as it was
+pub fn highlight(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let color = if c == '/' && chars.get(i + 1) == Some(&'/') {
+            i = chars.len();
+            GRAY
+        } else if c == '"' {
+            i += 1;
+            while i < chars.len() && chars[i] != '"' { i += 1; }
+            i = (i + 1).min(chars.len());
+            GREEN
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') { i += 1; }
+            CYAN
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') { i += 1; }
+            let word: String = chars[start..i].iter().collect();
+            if matches!(word.as_str(), "rtd" | "class" | "data" | "macro" | "self") {
+                MAGENTA
+            } else {
+                out.push_str(&word);
+                continue;
+            }
+        } else {
+            out.push(c);
+            i += 1;
+            continue;
+        };
+        out.push_str(color);
+        out.extend(&chars[start..i]);
+        out.push_str(RESET);
+    }
+    out
+}
+
+/// `:load path`: source files and artifacts run in the session, anything
+/// else is a snapshot written by `:save`
+fn load(vm: &mut VM, path: &str) -> Result<(), String> {
+    if path.ends_with(".usrd") || path.ends_with(".usrdc") {
+        return vm.eval_file(path).map(|_| ()).map_err(|e| e.to_string());
+    }
+    std::fs::read(path).map_err(|e| e.to_string()).and_then(|b| vm.restore(&b))
+}
+
+/// `src` can run: every `{`, `(` and `[` is closed, no string is left open,
+/// and it ends with `;` or `}`. Unbalanced closers count as complete, so the
+/// parser gets to report them
+pub fn input_complete(src: &str) -> bool {
+    let mut depth = 0i64;
+    let mut in_str = false;
+    // last character outside of comments and whitespace
+    let mut last = ' ';
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_str {
+            if c == '"' { in_str = false; }
+            continue;
+        }
+        match c {
+            '"' => in_str = true,
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth -= 1,
+            // a comment runs to the end of its line
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() { if c == '\n' { break; } }
+                continue;
+            }
+            _ => {}
+        }
+        if !c.is_whitespace() { last = c; }
+    }
+    !in_str && (depth < 0 || depth == 0 && matches!(last, ';' | '}'))
+}
+
+/// One line of input. On a terminal Tab completes the word before the
+/// cursor, and with `color` the line is redrawn highlighted after every
+/// key; elsewhere the line is read as is. `None` at end of input
+fn read_input(vm: &VM, prompt: &str, color: bool) -> Option<String> {
+    print!("{}", prompt);
+    let _ = io::stdout().flush();
+    let Some(_raw) = crate::platform::terminal::RawMode::enable() else {
+        let mut line = String::new();
+        return match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line),
+        };
+    };
+    let mut line = String::new();
+    // bytes of a character not yet complete
+    let mut pending = Vec::new();
+    let mut stdin = io::stdin().lock();
+    let mut byte = [0u8];
+    loop {
+        if stdin.read(&mut byte).unwrap_or(0) == 0 { return if line.is_empty() { None } else { Some(line) }; }
+        match byte[0] {
+            b'\r' | b'\n' => { println!(); return Some(line); }
+            // Ctrl-D on an empty line ends the session
+            4 if line.is_empty() => { println!(); return None; }
+            8 | 127 => if line.pop().is_some() { print!("\u{8} \u{8}"); },
+            b'\t' => {
+                let (start, options) = completions(vm, &line);
+                let word = line[start..].to_string();
+                let common = common_prefix(&options);
+                if common.len() > word.len() {
+                    print!("{}", &common[word.len()..]);
+                    line.push_str(&common[word.len()..]);
+                } else if options.len() > 1 {
+                    println!();
+                    println!("{}", options.join("  "));
+                    print!("{}{}", prompt, line);
+                }
+            }
+            // escape sequences (arrow keys and the like) are skipped
+            27 => {
+                let mut seq = [0u8; 2];
+                let _ = stdin.read(&mut seq);
+            }
+            b if b < 32 => {}
+            b => {
+                pending.push(b);
+                if let Ok(s) = std::str::from_utf8(&pending) {
+                    print!("{}", s);
+                    line.push_str(s);
+                    pending.clear();
+                } else if pending.len() >= 4 {
+                    pending.clear();
+                }
+            }
+        }
+        if color { print!("\r{}{}\x1b[K", prompt, highlight(&line)); }
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Lines for `:paste` up to `:end` or the end of input, read without the
+/// line editor
+pub fn read_paste(input: &mut impl io::BufRead) -> String {
+    let mut text = String::new();
+    let mut line = String::new();
+    while matches!(input.read_line(&mut line), Ok(n) if n > 0) {
+        if line.trim_end() == ":end" { break; }
+        text.push_str(&line);
+        line.clear();
+    }
+    text
+}
+
+/// Where the word before the end of `line` starts, and the sorted
+/// completions for it: REPL commands at the start of a line, fields and
+/// methods after `name.` (following fields for `a.b.`), otherwise globals,
+/// builtins and the intrinsic functions
+pub fn completions(vm: &VM, line: &str) -> (usize, Vec<String>) {
+    if line.starts_with(':') && !line.contains(' ') {
+        return (0, COMMANDS.iter().filter(|(c, _)| c.starts_with(line)).map(|(c, _)| c.to_string()).collect());
+    }
+    let start = line.rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.')).map_or(0, |i| i + 1);
+    let word = &line[start..];
+    let (start, prefix, mut names) = match word.rsplit_once('.') {
+        Some((path, prefix)) => (start + path.len() + 1, prefix, members(vm, path)),
+        None => {
+            let mut names: Vec<String> = vm.globals_iter().map(|(n, _)| n.clone()).collect();
+            names.extend(vm.builtin_names().into_iter().map(String::from));
+            (start, word, names)
+        }
+    };
+    names.retain(|n| n.starts_with(prefix) && !n.starts_with("__"));
+    names.sort();
+    names.dedup();
+    (start, names)
+}
+
+/// Fields and methods of the object at `path` (`a` or `a.b`)
+fn members(vm: &VM, path: &str) -> Vec<String> {
+    let mut parts = path.split('.');
+    let Some(mut value) = parts.next().and_then(|root| vm.get_global(root)) else { return Vec::new() };
+    for field in parts {
+        let Value::Object(o) = &value else { return Vec::new() };
+        let next = o.borrow().fields.get(field).cloned();
+        match next { Some(v) => value = v, None => return Vec::new() }
+    }
+    let Value::Object(o) = &value else { return Vec::new() };
+    let o = o.borrow();
+    let mut names: Vec<String> = o.fields.iter().map(|(n, _)| n.clone()).collect();
+    names.extend(o.methods.keys().cloned());
+    if let Some(class) = &o.class { names.extend(class.methods.keys().cloned()); }
+    names
+}
+
+fn common_prefix(options: &[String]) -> String {
+    let Some(first) = options.first() else { return String::new() };
+    let mut len = first.len();
+    for o in &options[1..] {
+        len = first.char_indices().zip(o.chars()).take_while(|((_, a), b)| a == b).last().map_or(0, |((i, a), _)| i + a.len_utf8()).min(len);
+    }
+    first[..len].to_string()
+}
+
+/// Run a file under the debugger. At each pause: `s` steps, `c` continues,
+/// `l` lists locals, `bt` shows every frame, `p name` prints a global.
+fn debug_file(vm: &mut VM, args: &str) {
+    let mut parts = args.split_whitespace();
+    let Some(path) = parts.next() else { println!("usage: :debug <file> <line>..."); return };
+    let src = match std::fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => { println!("Error: {}", e); return; }
+    };
+    vm.clear_breakpoints();
+    for line in parts {
+        match line.parse() {
+            Ok(n) => vm.set_breakpoint(path, n),
+            Err(_) => { println!("Error: bad line number {}", line); return; }
+        }
+    }
+    vm.set_file(path);
+    vm.set_debug_handler(debug_prompt);
+    if let Err(e) = vm.execute_program(Parser::new(&src).parse_program()) {
+        println!("Error: {}", e);
+    }
+}
+
+fn debug_prompt(pause: &Pause, vm: &VM) -> DebugAction {
+    let show = |(name, v): &(String, crate::vm::Value)| format!("{} = {}", name, crate::json::inspect(v));
+    println!("paused at {}:{}", pause.file, pause.line);
+    loop {
+        print!("(debug) ");
+        let _ = io::stdout().flush();
+        let mut cmd = String::new();
+        // end of input: let the program finish
+        if io::stdin().read_line(&mut cmd).unwrap_or(0) == 0 { return DebugAction::Continue; }
+        match cmd.trim() {
+            "s" | "step" => return DebugAction::Step,
+            "c" | "continue" => return DebugAction::Continue,
+            "l" | "locals" => for l in vm.locals(0) { println!("  {}", show(&l)); },
+            "bt" => for depth in 0..vm.call_depth() {
+                let locals: Vec<String> = vm.locals(depth).iter().map(show).collect();
+                println!("  #{} ({})", depth, locals.join(", "));
+            },
+            cmd => match cmd.strip_prefix("p ") {
+                Some(name) => match vm.get_global(name.trim()) {
+                    Some(v) => println!("  {}", crate::json::inspect(&v)),
+                    None => println!("  undefined: {}", name.trim()),
+                },
+                None => println!("commands: s(tep), c(ontinue), l(ocals), bt, p <global>"),
+            },
+        }
+    }
+}
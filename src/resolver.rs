@@ -0,0 +1,58 @@
+//! Slot resolution: rewrites references to a function's parameters into
+//! `Expr::Local` slot indexes, so calls index a Vec instead of hashing names.
+//! Anything that is not a parameter stays an `Ident` and is looked up in globals.
+use crate::ast::{Expr, Stmt};
+
+/// Resolve `body` against `params`; slot `i` is `params[i]`.
+/// Nested function and class declarations are left alone — they are
+/// resolved against their own params when declared.
+pub fn resolve_function(params: &[String], body: &mut [Stmt]) {
+    for stmt in body.iter_mut() {
+        resolve_stmt(params, stmt);
+    }
+}
+
+fn resolve_stmt(params: &[String], stmt: &mut Stmt) {
+    match stmt {
+        Stmt::VarDecl { value, .. } => resolve_expr(params, value),
+        Stmt::ExprStmt { expr: e, .. } => resolve_expr(params, e),
+        Stmt::MemberAssign { receiver, value, .. } => {
+            resolve_expr(params, receiver);
+            resolve_expr(params, value);
+        }
+        Stmt::Block(stmts) => resolve_function(params, stmts),
+        Stmt::FunctionDecl { .. } | Stmt::ClassDecl { .. } => {}
+    }
+}
+
+fn resolve_expr(params: &[String], expr: &mut Expr) {
+    match expr {
+        Expr::Ident(name) => {
+            if let Some(slot) = params.iter().position(|p| p == name) {
+                *expr = Expr::Local { slot, name: std::mem::take(name) };
+            }
+        }
+        Expr::BinaryOp { left, right, .. } => {
+            resolve_expr(params, left);
+            resolve_expr(params, right);
+        }
+        Expr::Call { func, args, .. } => {
+            resolve_expr(params, func);
+            for a in args.iter_mut() { resolve_expr(params, a); }
+        }
+        Expr::MemberCall { receiver, args, .. } => {
+            resolve_expr(params, receiver);
+            for a in args.iter_mut() { resolve_expr(params, a); }
+        }
+        Expr::MemberAccess { receiver, .. } => resolve_expr(params, receiver),
+        // a parameter named like an intrinsic shadows it: make it an ordinary call again
+        Expr::Intrinsic { op, args, line } => {
+            for a in args.iter_mut() { resolve_expr(params, a); }
+            if let Some(slot) = params.iter().position(|p| p == op.name()) {
+                let func = Box::new(Expr::Local { slot, name: op.name().to_string() });
+                *expr = Expr::Call { func, args: std::mem::take(args), line: *line };
+            }
+        }
+        Expr::Int(_) | Expr::Float(_) | Expr::Str(_) | Expr::Local { .. } => {}
+    }
+}
@@ -0,0 +1,72 @@
+//! Interactive sessions that can be resumed later: a VM snapshot (see
+//! `snapshot`) together with the inputs that led to it. The REPL writes them
+//! with `:save-session` and reads them with `:restore-session`; the web
+//! editor keeps one per run, so a run can continue from an earlier one and a
+//! user can download their session and upload it again.
+//!
+//! Layout: `USRDSESS` magic, format version (u16), payload length (u64),
+//! payload, FNV-1a checksum of the payload. The payload is the inputs (u32
+//! count, then length-prefixed strings), then the snapshot (u64 length and
+//! its bytes).
+use crate::artifact::{fnv1a, Reader, Writer};
+use crate::vm::VM;
+
+const MAGIC: &[u8] = b"USRDSESS";
+const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Session {
+    /// Source of each input that ran, oldest first
+    pub inputs: Vec<String>,
+    /// `VM::snapshot` after the last of them
+    pub snapshot: Vec<u8>,
+}
+
+impl Session {
+    /// The globals of `vm` and the inputs that made them
+    pub fn capture(vm: &VM, inputs: Vec<String>) -> Self {
+        Self { inputs, snapshot: vm.snapshot() }
+    }
+
+    /// Put the saved globals back into `vm`, replacing its own
+    pub fn restore_into(&self, vm: &mut VM) -> Result<(), String> {
+        vm.restore(&self.snapshot)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut p = Writer::default();
+        p.strs(&self.inputs);
+        p.u64(self.snapshot.len() as u64);
+        p.buf.extend_from_slice(&self.snapshot);
+
+        let mut w = Writer::default();
+        w.buf.extend_from_slice(MAGIC);
+        w.buf.extend_from_slice(&VERSION.to_le_bytes());
+        w.u64(p.buf.len() as u64);
+        w.buf.extend_from_slice(&p.buf);
+        w.u64(fnv1a(&p.buf));
+        w.buf
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let rest = bytes.strip_prefix(MAGIC).ok_or("not a userd session")?;
+        let mut r = Reader::new(rest);
+        let version = u16::from_le_bytes(r.take(2)?.try_into().unwrap());
+        if version != VERSION {
+            return Err(format!("unsupported session version {}", version));
+        }
+        let len = r.u64()? as usize;
+        let payload = r.take(len)?;
+        if r.u64()? != fnv1a(payload) {
+            return Err("session checksum mismatch".to_string());
+        }
+        let mut p = Reader::new(payload);
+        let inputs = p.strs()?;
+        let len = p.u64()? as usize;
+        let snapshot = p.take(len)?.to_vec();
+        // checked now, so a broken snapshot is refused when the file is read
+        // (or uploaded) rather than when a run starts from it
+        crate::snapshot::decode(&snapshot)?;
+        Ok(Self { inputs, snapshot })
+    }
+}
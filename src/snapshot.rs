@@ -0,0 +1,303 @@
+//! VM snapshots (`VM::snapshot` / `VM::restore`): all globals in a binary form,
+//! so a REPL or web editor session can be saved and resumed later.
+//!
+//! Layout: `USRDS` magic, format version (u16), payload length (u64), payload,
+//! FNV-1a checksum of the payload. The payload is the number of globals, then
+//! a name and a value for each, in declaration order. Objects, lists and
+//! classes are written once and referred to by id afterwards, so shared
+//! references and cycles survive.
+//! Functions keep their resolved AST, encoded as in artifacts. Native methods
+//! of data classes are regenerated from the class header on restore.
+//! Builtins registered by the host are not part of a snapshot, and host
+//! objects (`Value::Host`) are written as their `describe` text.
+//! `encode_value` uses the same format for a single value, so it can be
+//! copied into another VM (`VmGroup` messages).
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::artifact::{fnv1a, Reader, Writer};
+use crate::vm::{data_class_methods, ClassObject, Fields, FunctionObject, Object, Value};
+
+const MAGIC: &[u8] = b"USRDS";
+/// 2: statements carry their source line
+const VERSION: u16 = 2;
+
+pub fn encode(globals: &Fields) -> Vec<u8> {
+    let mut e = Encoder { w: Writer::default(), seen: HashMap::new() };
+    e.fields(globals);
+    let payload = e.w.buf;
+
+    let mut w = Writer::default();
+    w.buf.extend_from_slice(MAGIC);
+    w.buf.extend_from_slice(&VERSION.to_le_bytes());
+    w.u64(payload.len() as u64);
+    w.buf.extend_from_slice(&payload);
+    w.u64(fnv1a(&payload));
+    w.buf
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Fields, String> {
+    if !bytes.starts_with(MAGIC) {
+        return Err("not a userd snapshot".to_string());
+    }
+    let mut r = Reader::new(bytes);
+    r.take(MAGIC.len())?;
+    let version = u16::from_le_bytes(r.take(2)?.try_into().unwrap());
+    if version != VERSION {
+        return Err(format!("unsupported snapshot version {} (expected {})", version, VERSION));
+    }
+    let len = r.u64()? as usize;
+    let payload = r.take(len)?;
+    if r.u64()? != fnv1a(payload) {
+        return Err("snapshot checksum mismatch".to_string());
+    }
+    let mut d = Decoder { r: Reader::new(payload), heap: Vec::new() };
+    let globals = d.fields()?;
+    if d.r.pos != payload.len() {
+        return Err("trailing data in snapshot".to_string());
+    }
+    Ok(globals)
+}
+
+/// A snapshot holding only `v`, under an empty name
+pub fn encode_value(v: &Value) -> Vec<u8> {
+    let mut fields = Fields::new();
+    fields.insert(String::new(), v.clone());
+    encode(&fields)
+}
+
+/// A deep copy of the value written by `encode_value`, sharing nothing with the original
+pub fn decode_value(bytes: &[u8]) -> Result<Value, String> {
+    let fields = decode(bytes)?;
+    match (fields.len(), fields.get("")) {
+        (1, Some(v)) => Ok(v.clone()),
+        _ => Err("snapshot does not hold a single value".to_string()),
+    }
+}
+
+/// Shared values already written, by address
+struct Encoder {
+    w: Writer,
+    seen: HashMap<*const (), u32>,
+}
+
+impl Encoder {
+    /// Write a reference; returns true when the referent must follow (first visit)
+    fn reference(&mut self, ptr: *const ()) -> bool {
+        if let Some(id) = self.seen.get(&ptr) {
+            self.w.u8(0);
+            self.w.u32(*id);
+            return false;
+        }
+        let id = self.seen.len() as u32;
+        self.seen.insert(ptr, id);
+        self.w.u8(1);
+        true
+    }
+
+    fn value(&mut self, v: &Value) {
+        match v {
+            Value::Int(n) => { self.w.u8(0); self.w.u64(*n as u64); }
+            Value::Float(f) => { self.w.u8(1); self.w.u64(f.to_bits()); }
+            Value::Str(s) => { self.w.u8(2); self.w.str(s); }
+            Value::Function(f) => { self.w.u8(3); self.function(f); }
+            Value::Overloaded(set) => {
+                self.w.u8(4);
+                self.w.u32(set.len() as u32);
+                for f in set.iter() { self.function(f); }
+            }
+            Value::Class(c) => { self.w.u8(5); self.class(c); }
+            Value::Object(o) => {
+                self.w.u8(6);
+                if !self.reference(Rc::as_ptr(o) as *const ()) { return; }
+                let o = o.borrow();
+                self.w.str(&o.class_name);
+                match &o.class {
+                    Some(c) => { self.w.u8(1); self.class(c); }
+                    None => self.w.u8(0),
+                }
+                self.fields(&o.fields);
+                self.methods(&o.methods);
+            }
+            Value::List(items) => {
+                self.w.u8(7);
+                if !self.reference(Rc::as_ptr(items) as *const ()) { return; }
+                let items = items.borrow();
+                self.w.u32(items.len() as u32);
+                for item in items.iter() { self.value(item); }
+            }
+            // the Rust object cannot be saved; it comes back as its description
+            Value::Host(h) => { self.w.u8(2); self.w.str(&h.try_borrow().map_or_else(|_| "<host>".to_string(), |h| h.describe())); }
+        }
+    }
+
+    fn class(&mut self, c: &Rc<ClassObject>) {
+        if !self.reference(Rc::as_ptr(c) as *const ()) { return; }
+        self.w.str(&c.name);
+        self.w.strs(&c.fields);
+        self.w.u8(c.is_data as u8);
+        self.methods(&c.methods);
+    }
+
+    /// In map order, so restored objects print their fields in the same order
+    fn fields(&mut self, fields: &Fields) {
+        self.w.u32(fields.len() as u32);
+        for (name, v) in fields {
+            self.w.str(name);
+            self.value(v);
+        }
+    }
+
+    fn methods(&mut self, methods: &HashMap<String, FunctionObject>) {
+        let mut names: Vec<&String> = methods.keys().collect();
+        names.sort();
+        self.w.u32(names.len() as u32);
+        for name in names {
+            self.w.str(name);
+            self.function(&methods[name]);
+        }
+    }
+
+    fn function(&mut self, f: &FunctionObject) {
+        // native methods only come from data classes and are rebuilt by name
+        self.w.u8(f.native.is_some() as u8);
+        if f.native.is_some() { return; }
+        self.w.strs(&f.params);
+        self.w.u32(f.param_types.len() as u32);
+        for t in f.param_types.iter() { self.w.opt_str(t); }
+        self.w.opt_str(&f.ret_type);
+        self.w.stmts(&f.body);
+    }
+}
+
+/// Heap entries in the order they were first written
+enum Shared {
+    Object(Rc<RefCell<Object>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Class(Rc<ClassObject>),
+}
+
+struct Decoder<'a> {
+    r: Reader<'a>,
+    heap: Vec<Option<Shared>>,
+}
+
+impl Decoder<'_> {
+    /// Read a reference: `Ok(Err(id))` when a new referent follows, `Ok(Ok(entry))` otherwise
+    fn reference(&mut self) -> Result<Result<&Shared, usize>, String> {
+        let is_new = self.r.u8()? == 1;
+        if is_new {
+            self.heap.push(None);
+            return Ok(Err(self.heap.len() - 1));
+        }
+        let id = self.r.u32()? as usize;
+        match self.heap.get(id) {
+            Some(Some(entry)) => Ok(Ok(entry)),
+            _ => Err(format!("bad reference {} in snapshot", id)),
+        }
+    }
+
+    /// Nesting is counted by the reader, together with that of function bodies
+    fn value(&mut self) -> Result<Value, String> {
+        self.r.enter()?;
+        let value = self.value_tagged();
+        self.r.leave();
+        value
+    }
+
+    fn value_tagged(&mut self) -> Result<Value, String> {
+        Ok(match self.r.u8()? {
+            0 => Value::Int(self.r.u64()? as i64),
+            1 => Value::Float(f64::from_bits(self.r.u64()?)),
+            2 => Value::Str(self.r.str()?),
+            3 => Value::Function(self.function(None)?),
+            4 => {
+                let set = (0..self.r.u32()?).map(|_| self.function(None)).collect::<Result<Vec<_>, _>>()?;
+                Value::Overloaded(set.into())
+            }
+            5 => Value::Class(self.class()?),
+            6 => match self.reference()? {
+                Ok(Shared::Object(o)) => Value::Object(o.clone()),
+                Ok(_) => return Err("snapshot reference is not an object".to_string()),
+                Err(id) => {
+                    let class_name = self.r.str()?;
+                    // registered before the fields are read, so cycles resolve to it
+                    let obj = Rc::new(RefCell::new(Object::plain(&class_name, Fields::new())));
+                    self.heap[id] = Some(Shared::Object(obj.clone()));
+                    let class = if self.r.u8()? == 1 { Some(self.class()?) } else { None };
+                    let fields = self.fields()?;
+                    let methods = self.methods(None)?;
+                    let mut o = obj.borrow_mut();
+                    o.class = class;
+                    o.fields = fields;
+                    o.methods = methods;
+                    drop(o);
+                    Value::Object(obj)
+                }
+            },
+            7 => match self.reference()? {
+                Ok(Shared::List(items)) => Value::List(items.clone()),
+                Ok(_) => return Err("snapshot reference is not a list".to_string()),
+                Err(id) => {
+                    let list = Rc::new(RefCell::new(Vec::new()));
+                    self.heap[id] = Some(Shared::List(list.clone()));
+                    for _ in 0..self.r.u32()? {
+                        let item = self.value()?;
+                        list.borrow_mut().push(item);
+                    }
+                    Value::List(list)
+                }
+            },
+            t => return Err(format!("bad value tag {} in snapshot", t)),
+        })
+    }
+
+    fn class(&mut self) -> Result<Rc<ClassObject>, String> {
+        let id = match self.reference()? {
+            Ok(Shared::Class(c)) => return Ok(c.clone()),
+            Ok(_) => return Err("snapshot reference is not a class".to_string()),
+            Err(id) => id,
+        };
+        let name = self.r.str()?;
+        let fields = self.r.strs()?;
+        let is_data = self.r.u8()? != 0;
+        let native: HashMap<&str, FunctionObject> = if is_data { data_class_methods(&name, &fields).into_iter().collect() } else { HashMap::new() };
+        let methods = self.methods(Some(&native))?;
+        let class = Rc::new(ClassObject { name, fields, is_data, methods });
+        self.heap[id] = Some(Shared::Class(class.clone()));
+        Ok(class)
+    }
+
+    fn fields(&mut self) -> Result<Fields, String> {
+        let mut fields = Fields::new();
+        for _ in 0..self.r.u32()? {
+            let name = self.r.str()?;
+            fields.insert(name, self.value()?);
+        }
+        Ok(fields)
+    }
+
+    fn methods(&mut self, native: Option<&HashMap<&str, FunctionObject>>) -> Result<HashMap<String, FunctionObject>, String> {
+        let mut methods = HashMap::new();
+        for _ in 0..self.r.u32()? {
+            let name = self.r.str()?;
+            let f = self.function(native.and_then(|n| n.get(name.as_str())))?;
+            methods.insert(name, f);
+        }
+        Ok(methods)
+    }
+
+    /// `native` is the regenerated method to use if the snapshot marks this one native
+    fn function(&mut self, native: Option<&FunctionObject>) -> Result<FunctionObject, String> {
+        if self.r.u8()? == 1 {
+            return native.cloned().ok_or_else(|| "snapshot has a native method outside a data class".to_string());
+        }
+        let params = self.r.strs()?;
+        let param_types: Vec<Option<String>> = (0..self.r.u32()?).map(|_| self.r.opt_str()).collect::<Result<_, _>>()?;
+        let ret_type = self.r.opt_str()?;
+        let body = self.r.stmts()?;
+        // the body was resolved when first declared
+        Ok(FunctionObject { params: params.into(), body: body.into(), param_types: param_types.into(), ret_type, native: None })
+    }
+}
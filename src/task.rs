@@ -0,0 +1,59 @@
+//! Cooperative tasks: functions that take turns on the VM's own thread.
+//! `task_spawn(fn)` queues one, `run_tasks()` runs the queue round-robin
+//! until every task has finished, and `gui_poll`/`gui_run` give each task one
+//! turn per pass of the event loop.
+//!
+//! Like `VM::run_steps`, a turn ends between statements: a task that called
+//! `task_yield()` pauses after the statement of its own body that made the
+//! call, with the calls it made finished. Its parameters are kept for the
+//! next turn; everything else a function declares is global anyway.
+use std::collections::VecDeque;
+
+use crate::vm::{FunctionObject, Value};
+
+pub struct Task {
+    pub id: u64,
+    /// Function name, for error messages
+    pub name: String,
+    pub(crate) func: FunctionObject,
+    pub(crate) frame: Vec<Option<Value>>,
+    /// Index of the next body statement
+    pub(crate) next: usize,
+}
+
+/// The tasks of one VM
+#[derive(Default)]
+pub struct Tasks {
+    next_id: u64,
+    queue: VecDeque<Task>,
+    /// Task in its turn; `task_yield` outside of a task does nothing
+    pub(crate) current: Option<u64>,
+    pub(crate) yield_requested: bool,
+}
+
+impl Tasks {
+    pub fn spawn(&mut self, name: String, func: FunctionObject) -> u64 {
+        self.next_id += 1;
+        let frame = vec![None; func.params.len()];
+        self.queue.push_back(Task { id: self.next_id, name, func, frame, next: 0 });
+        self.next_id
+    }
+
+    pub fn len(&self) -> usize { self.queue.len() }
+
+    pub fn is_empty(&self) -> bool { self.queue.is_empty() }
+
+    /// Tasks are waiting and none is in its turn, which the waiting ones
+    /// would have to wait for
+    pub fn runnable(&self) -> bool { self.current.is_none() && !self.queue.is_empty() }
+
+    /// Take the task whose turn it is
+    pub(crate) fn next(&mut self) -> Option<Task> {
+        self.queue.pop_front()
+    }
+
+    /// Put a paused task at the back of the queue
+    pub(crate) fn requeue(&mut self, task: Task) {
+        self.queue.push_back(task);
+    }
+}
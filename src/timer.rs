@@ -0,0 +1,62 @@
+//! Timers: `set_timeout` and `set_interval` callbacks, dispatched by the GUI
+//! loop (`gui_run`, `gui_poll`) next to click events instead of a script
+//! looping on `sleep_ms`. Times are `perf_now_us` readings, so a frozen clock
+//! (`VM::freeze_time`) makes them fire in a fixed order.
+
+#[derive(Debug, Clone)]
+pub struct Timer {
+    pub id: u64,
+    /// Clock reading at which the handler runs next
+    pub due_us: u64,
+    /// Period of an interval; `None` for a one-shot timeout
+    pub every_us: Option<u64>,
+    /// Function called with no arguments
+    pub handler: String,
+}
+
+/// The timers of one VM
+#[derive(Debug, Default)]
+pub struct Timers {
+    next_id: u64,
+    active: Vec<Timer>,
+}
+
+impl Timers {
+    pub fn add(&mut self, due_us: u64, every_us: Option<u64>, handler: String) -> u64 {
+        self.next_id += 1;
+        self.active.push(Timer { id: self.next_id, due_us, every_us, handler });
+        self.next_id
+    }
+
+    /// Stop a timer; false if it is not pending
+    pub fn cancel(&mut self, id: u64) -> bool {
+        let before = self.active.len();
+        self.active.retain(|t| t.id != id);
+        self.active.len() < before
+    }
+
+    pub fn len(&self) -> usize { self.active.len() }
+
+    pub fn is_empty(&self) -> bool { self.active.is_empty() }
+
+    /// (id, handler) of every timer due at `now_us`, earliest first. Timeouts
+    /// are removed; an interval that fell several periods behind fires once
+    /// and moves to its next boundary after `now_us`
+    pub fn due(&mut self, now_us: u64) -> Vec<(u64, String)> {
+        let mut due: Vec<(u64, u64, String)> = Vec::new();
+        self.active.retain_mut(|t| {
+            if t.due_us > now_us { return true; }
+            due.push((t.due_us, t.id, t.handler.clone()));
+            match t.every_us {
+                Some(every) => {
+                    let every = every.max(1);
+                    t.due_us += (now_us - t.due_us) / every * every + every;
+                    true
+                }
+                None => false,
+            }
+        });
+        due.sort();
+        due.into_iter().map(|(_, id, handler)| (id, handler)).collect()
+    }
+}
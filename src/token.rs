@@ -2,6 +2,9 @@
 pub enum Token {
     Ident(String),
     Int(i64),
+    /// Integer literal too large for an int, as written; only valid after `-`
+    /// as the smallest int
+    BigInt(String),
     Float(f64),
     Str(String),
     Plus,
@@ -16,6 +19,7 @@ pub enum Token {
     Assign,     // =
     Semicolon,  // ;
     Dot,
+    Arrow,      // ->
     Eof,
     Illegal(char),
     // Keywords
@@ -23,8 +27,60 @@ pub enum Token {
     Class, // class keyword
 }
 
+/// Source-like text, for error messages
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Ident(s) => write!(f, "{}", s),
+            Token::Int(n) => write!(f, "{}", n),
+            Token::BigInt(s) => f.write_str(s),
+            Token::Float(x) => write!(f, "{}", x),
+            Token::Str(s) => write!(f, "\"{}\"", s),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Asterisk => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::LBrace => write!(f, "{{"),
+            Token::RBrace => write!(f, "}}"),
+            Token::Comma => write!(f, ","),
+            Token::Assign => write!(f, "="),
+            Token::Semicolon => write!(f, ";"),
+            Token::Dot => write!(f, "."),
+            Token::Arrow => write!(f, "->"),
+            Token::Eof => write!(f, "end of input"),
+            Token::Illegal(c) => write!(f, "{}", c),
+            Token::Rtd => write!(f, "rtd"),
+            Token::Class => write!(f, "class"),
+        }
+    }
+}
+
+/// Where a token is in the source: 1-based line and column, and its range
+/// of char offsets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
 impl Token {
     pub fn is_eof(&self) -> bool {
         matches!(self, Token::Eof)
     }
+
+    /// `{"kind":"Ident","text":"x","line":1,"col":5,"start":4,"end":5}`;
+    /// offsets count chars, `end` is exclusive
+    pub fn to_json(&self, span: &Span) -> String {
+        let debug = format!("{:?}", self);
+        let kind = debug.split('(').next().unwrap_or("");
+        let text = if self.is_eof() { String::new() } else { self.to_string() };
+        format!(
+            "{{\"kind\":\"{}\",\"text\":\"{}\",\"line\":{},\"col\":{},\"start\":{},\"end\":{}}}",
+            kind, crate::json::escape(&text), span.line, span.col, span.start, span.end,
+        )
+    }
 }
@@ -0,0 +1,251 @@
+//! `userd transpile`: turn a script into a Rust program that links this crate
+//! as its runtime.
+//!
+//! Top-level functions that are declared once and called by name become native
+//! Rust functions over `Value`; top-level statements become straight-line Rust.
+//! Function and class declarations are still registered in the VM (embedded as
+//! artifacts), so classes, methods, builtins, callbacks and functions passed as
+//! values keep working through the interpreter.
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::ast::{BinOp, Expr, Stmt};
+
+/// Generate the Rust source of a standalone program for `src`
+pub fn transpile(src: &str, script_name: &str) -> String {
+    let mut parser = crate::parser::Parser::new(src);
+    Transpiler::new(&parser.parse_program()).program(script_name)
+}
+
+struct Transpiler<'a> {
+    prog: &'a [Stmt],
+    /// Functions compiled to native Rust: name -> param count
+    native: HashMap<String, usize>,
+    decls: Vec<Vec<u8>>,
+    tmp: usize,
+}
+
+impl<'a> Transpiler<'a> {
+    fn new(prog: &'a [Stmt]) -> Self {
+        let mut t = Self { prog, native: HashMap::new(), decls: Vec::new(), tmp: 0 };
+        t.native = t.native_candidates();
+        t
+    }
+
+    /// A top-level `rtd` can be called natively when its name is bound exactly
+    /// once in the whole program and its body declares nothing itself
+    fn native_candidates(&self) -> HashMap<String, usize> {
+        let mut bound: HashMap<&str, usize> = HashMap::new();
+        fn count<'s>(stmts: &'s [Stmt], bound: &mut HashMap<&'s str, usize>) {
+            for s in stmts {
+                match s {
+                    Stmt::VarDecl { name, .. } => *bound.entry(name).or_default() += 1,
+                    Stmt::FunctionDecl { name, body, .. } | Stmt::ClassDecl { name, body, .. } => {
+                        *bound.entry(name).or_default() += 1;
+                        count(body, bound);
+                    }
+                    Stmt::Block(b) => count(b, bound),
+                    _ => {}
+                }
+            }
+        }
+        count(self.prog, &mut bound);
+        self.prog.iter().filter_map(|s| match s {
+            Stmt::FunctionDecl { name, params, body, .. }
+                if bound[name.as_str()] == 1 && !crate::vm::is_builtin(name) && !declares(body) =>
+                Some((name.clone(), params.len())),
+            _ => None,
+        }).collect()
+    }
+
+    fn program(mut self, script_name: &str) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "// Generated by `userd transpile` from {}; do not edit.", script_name);
+        out.push_str("#![allow(unused_imports, unused_mut, unused_variables, unused_assignments, non_snake_case)]\n");
+        out.push_str("use userd::ast::{BinOp, Intrinsic};\nuse userd::vm::{binary_op, get_field, set_field, Value, VM};\n\n");
+
+        let mut funcs: Vec<&Stmt> = self.prog.iter().filter(|s| matches!(s, Stmt::FunctionDecl { name, .. } if self.native.contains_key(name))).collect();
+        funcs.sort_by_key(|s| match s { Stmt::FunctionDecl { name, .. } => name.clone(), _ => String::new() });
+        for f in funcs {
+            if let Stmt::FunctionDecl { name, params, param_types, ret_type, body } = f {
+                out.push_str(&self.function(name, params, param_types, ret_type, body));
+            }
+        }
+
+        let mut run = String::new();
+        for s in self.prog {
+            self.top_stmt(s, &mut run);
+        }
+        out.push_str("fn run(vm: &mut VM) -> Result<(), String> {\n");
+        out.push_str(&run);
+        out.push_str("    Ok(())\n}\n\n");
+        for (i, bytes) in self.decls.iter().enumerate() {
+            let _ = writeln!(out, "const DECL_{}: &[u8] = &{:?};", i, bytes);
+        }
+        out.push_str("\nfn main() {\n    let mut vm = VM::new();\n    if let Err(e) = run(&mut vm) {\n        eprintln!(\"Execution error: {}\", e);\n        std::process::exit(1);\n    }\n}\n");
+        out
+    }
+
+    fn function(&mut self, name: &str, params: &[String], types: &[Option<String>], ret: &Option<String>, body: &[Stmt]) -> String {
+        let mut out = String::new();
+        let args: Vec<String> = params.iter().map(|p| format!("l_{}: Value", p)).collect();
+        let _ = writeln!(out, "fn u_{}(vm: &mut VM, {}) -> Result<Value, String> {{", name, args.join(", "));
+        for (p, t) in params.iter().zip(types) {
+            if let Some(t) = t {
+                let _ = writeln!(out, "    if !vm.value_has_type(&l_{p}, {t:?}) {{ return Err(format!(\"param {p} expects {t}, got {{}}\", l_{p}.type_name())); }}");
+            }
+        }
+        out.push_str("    let mut last = Value::Int(0);\n");
+        for s in body {
+            self.stmt(s, params, true, &mut out);
+        }
+        if let Some(t) = ret {
+            let _ = writeln!(out, "    if !vm.value_has_type(&last, {t:?}) {{ return Err(format!(\"expected return of {t}, got {{}}\", last.type_name())); }}");
+        }
+        out.push_str("    Ok(last)\n}\n\n");
+        out
+    }
+
+    fn top_stmt(&mut self, s: &Stmt, out: &mut String) {
+        match s {
+            Stmt::FunctionDecl { .. } | Stmt::ClassDecl { .. } => {
+                // registered in the VM so it can be used as a value, a class or a callback
+                let _ = writeln!(out, "    vm.execute_program(userd::artifact::load(DECL_{})?.program)?;", self.decls.len());
+                self.decls.push(crate::artifact::encode(&[], std::slice::from_ref(s)));
+            }
+            Stmt::Block(b) => for s in b { self.top_stmt(s, out); },
+            other => self.stmt(other, &[], false, out),
+        }
+    }
+
+    /// A statement inside `run` or a native function; `last` tracks the value of
+    /// expression statements in functions
+    fn stmt(&mut self, s: &Stmt, locals: &[String], in_fn: bool, out: &mut String) {
+        let ind = "    ";
+        match s {
+            Stmt::VarDecl { name, value, .. } => {
+                let v = self.expr(value, locals);
+                let _ = writeln!(out, "{ind}{{ let v = {v}; vm.set_global({name:?}, v); }}");
+            }
+            Stmt::ExprStmt { expr: e, .. } => {
+                let v = self.expr(e, locals);
+                if in_fn {
+                    let _ = writeln!(out, "{ind}last = {{ let v = {v}; vm.print_value(&v)?; v }};");
+                } else {
+                    let _ = writeln!(out, "{ind}{{ let v = {v}; vm.print_value(&v)?; }}");
+                }
+            }
+            Stmt::MemberAssign { receiver, name, value, .. } => {
+                let r = self.expr(receiver, locals);
+                let v = self.expr(value, locals);
+                let _ = writeln!(out, "{ind}{{ let r = {r}; let v = {v}; set_field(&r, {name:?}, v)?; }}");
+            }
+            Stmt::Block(b) => for s in b { self.stmt(s, locals, in_fn, out); },
+            // excluded by native_candidates / handled by top_stmt
+            Stmt::FunctionDecl { .. } | Stmt::ClassDecl { .. } => {}
+        }
+    }
+
+    fn expr(&mut self, e: &Expr, locals: &[String]) -> String {
+        match e {
+            Expr::Int(n) => format!("Value::Int({}i64)", n),
+            Expr::Float(f) => format!("Value::Float(f64::from_bits({:#x}))", f.to_bits()),
+            Expr::Str(s) => format!("Value::Str({:?}.to_string())", s),
+            Expr::Ident(name) | Expr::Local { name, .. } => {
+                if locals.contains(name) { format!("l_{}.clone()", name) } else { format!("vm.global({:?})?", name) }
+            }
+            Expr::BinaryOp { left, op, right } => {
+                let op = match op { BinOp::Add => "Add", BinOp::Sub => "Sub", BinOp::Mul => "Mul", BinOp::Div => "Div" };
+                format!("binary_op({}, {}, &BinOp::{})?", self.expr(left, locals), self.expr(right, locals), op)
+            }
+            Expr::MemberAccess { receiver, field } => {
+                format!("{{ let r = {}; get_field(&r, {:?})? }}", self.expr(receiver, locals), field)
+            }
+            Expr::Intrinsic { op, args, .. } => {
+                let (binds, names) = self.args(args, locals);
+                format!("{{ {}vm.call_intrinsic(Intrinsic::{:?}, &[{}])? }}", binds, op, names)
+            }
+            Expr::MemberCall { receiver, method, args, .. } => {
+                let r = self.expr(receiver, locals);
+                let (binds, names) = self.args(args, locals);
+                format!("{{ let r = {}; {}vm.call_member(&r, {:?}, &[{}])? }}", r, binds, method, names)
+            }
+            Expr::Call { func, args, .. } => {
+                let name = match &**func {
+                    Expr::Ident(n) | Expr::Local { name: n, .. } => n.clone(),
+                    _ => return "return Err(\"call of non-identifier not supported\".to_string())".to_string(),
+                };
+                if name == "help" && !locals.contains(&name) {
+                    return match args.as_slice() {
+                        [Expr::Str(t)] => format!("Value::Str(vm.help({:?})?)", t),
+                        [Expr::Ident(t)] if !locals.contains(t) => format!("Value::Str(vm.help({:?})?)", t),
+                        [a @ (Expr::Ident(t) | Expr::Local { name: t, .. })] => {
+                            format!("Value::Str(userd::vm::signature_text({:?}, &{}))", t, self.expr(a, locals))
+                        }
+                        _ => "return Err(\"help requires one name\".to_string())".to_string(),
+                    };
+                }
+                let (binds, names) = self.args(args, locals);
+                if locals.contains(&name) {
+                    format!("{{ {}vm.call_value({:?}, l_{}.clone(), vec![{}])? }}", binds, name, name, names)
+                } else if self.native.get(&name) == Some(&args.len()) {
+                    format!("{{ {}u_{}(vm, {})? }}", binds, name, names)
+                } else {
+                    format!("{{ {}vm.call({:?}, vec![{}])? }}", binds, name, names)
+                }
+            }
+        }
+    }
+
+    /// Evaluate args into temporaries first, so `vm` is not borrowed twice in one call
+    fn args(&mut self, args: &[Expr], locals: &[String]) -> (String, String) {
+        let mut binds = String::new();
+        let mut names = Vec::new();
+        for a in args {
+            self.tmp += 1;
+            let t = format!("a{}", self.tmp);
+            let v = self.expr(a, locals);
+            let _ = write!(binds, "let {} = {}; ", t, v);
+            names.push(t);
+        }
+        self.tmp -= args.len();
+        (binds, names.join(", "))
+    }
+}
+
+/// Does a body declare functions or classes (which bind globals at run time)?
+fn declares(body: &[Stmt]) -> bool {
+    body.iter().any(|s| match s {
+        Stmt::FunctionDecl { .. } | Stmt::ClassDecl { .. } => true,
+        Stmt::Block(b) => declares(b),
+        _ => false,
+    })
+}
+
+/// `--build`: wrap the generated source in a cargo project next to `out_rs`
+/// and build it in release mode; returns the path of the executable
+pub fn build(out_rs: &std::path::Path, code: &str) -> Result<std::path::PathBuf, String> {
+    let stem = out_rs.file_stem().and_then(|s| s.to_str()).unwrap_or("program");
+    let crate_name: String = stem.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect();
+    let crate_name = if crate_name.starts_with(|c: char| c.is_ascii_alphabetic()) { crate_name } else { format!("u_{}", crate_name) };
+    let dir = out_rs.with_file_name(format!("{}_build", stem));
+    std::fs::create_dir_all(dir.join("src")).map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+    let manifest = format!(
+        "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2024\"\n\n[dependencies]\nuserd = {{ path = {:?} }}\n\n[profile.release]\nopt-level = 3\n",
+        crate_name, env!("CARGO_MANIFEST_DIR"));
+    std::fs::write(dir.join("Cargo.toml"), manifest).map_err(|e| e.to_string())?;
+    std::fs::write(dir.join("src").join("main.rs"), code).map_err(|e| e.to_string())?;
+    let status = std::process::Command::new("cargo")
+        .args(["build", "--release", "--manifest-path"])
+        .arg(dir.join("Cargo.toml"))
+        .status()
+        .map_err(|e| format!("failed to run cargo: {}", e))?;
+    if !status.success() {
+        return Err(format!("cargo build failed in {}", dir.display()));
+    }
+    let exe = format!("{}{}", crate_name, std::env::consts::EXE_SUFFIX);
+    let built = dir.join("target").join("release").join(&exe);
+    let dest = out_rs.with_file_name(&exe);
+    std::fs::copy(&built, &dest).map_err(|e| format!("failed to copy {}: {}", built.display(), e))?;
+    Ok(dest)
+}
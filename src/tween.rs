@@ -0,0 +1,140 @@
+//! Tweens: a number moving from `from` to `to` over a fixed time along an
+//! easing curve. The VM keeps the running ones; every pump (`frame_wait`,
+//! `gui_poll`, `gui_run` or an explicit `tween_update`) computes each value
+//! from the clock and hands it to the script's handler, so a frame that runs
+//! late jumps ahead instead of slowing the animation down.
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    InQuad,
+    OutQuad,
+    InOutQuad,
+    InCubic,
+    OutCubic,
+    InOutCubic,
+    InSine,
+    OutSine,
+    InOutSine,
+    OutBack,
+    OutBounce,
+}
+
+impl Easing {
+    pub const NAMES: [&'static str; 12] = [
+        "linear", "in_quad", "out_quad", "in_out_quad", "in_cubic", "out_cubic", "in_out_cubic",
+        "in_sine", "out_sine", "in_out_sine", "out_back", "out_bounce",
+    ];
+
+    pub fn parse(name: &str) -> Result<Self, String> {
+        use Easing::*;
+        Ok(match name {
+            "linear" => Linear,
+            "in_quad" => InQuad,
+            "out_quad" => OutQuad,
+            "in_out_quad" => InOutQuad,
+            "in_cubic" => InCubic,
+            "out_cubic" => OutCubic,
+            "in_out_cubic" => InOutCubic,
+            "in_sine" => InSine,
+            "out_sine" => OutSine,
+            "in_out_sine" => InOutSine,
+            "out_back" => OutBack,
+            "out_bounce" => OutBounce,
+            _ => return Err(format!("unknown easing {:?}; expected one of {}", name, Self::NAMES.join(", "))),
+        })
+    }
+
+    /// Progress along the curve for time `t` in 0..=1; 0 and 1 map to
+    /// themselves, `OutBack` overshoots past 1 on the way
+    pub fn apply(self, t: f64) -> f64 {
+        use Easing::*;
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Linear => t,
+            InQuad => t * t,
+            OutQuad => 1.0 - (1.0 - t) * (1.0 - t),
+            InOutQuad => if t < 0.5 { 2.0 * t * t } else { 1.0 - (-2.0 * t + 2.0).powi(2) / 2.0 },
+            InCubic => t * t * t,
+            OutCubic => 1.0 - (1.0 - t).powi(3),
+            InOutCubic => if t < 0.5 { 4.0 * t * t * t } else { 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0 },
+            InSine => 1.0 - (t * PI / 2.0).cos(),
+            OutSine => (t * PI / 2.0).sin(),
+            InOutSine => -((PI * t).cos() - 1.0) / 2.0,
+            OutBack => {
+                let (c1, c3) = (1.70158, 2.70158);
+                1.0 + c3 * (t - 1.0).powi(3) + c1 * (t - 1.0).powi(2)
+            }
+            OutBounce => {
+                let (n, d) = (7.5625, 2.75);
+                if t < 1.0 / d { n * t * t }
+                else if t < 2.0 / d { let t = t - 1.5 / d; n * t * t + 0.75 }
+                else if t < 2.5 / d { let t = t - 2.25 / d; n * t * t + 0.9375 }
+                else { let t = t - 2.625 / d; n * t * t + 0.984375 }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tween {
+    pub id: u64,
+    pub from: f64,
+    pub to: f64,
+    /// Clock reading at creation, in `perf_now_us` time
+    pub start_us: u64,
+    pub duration_us: u64,
+    pub easing: Easing,
+    /// Function called with each new value
+    pub handler: String,
+}
+
+impl Tween {
+    /// Value at clock reading `now_us` and whether the tween has finished;
+    /// the last value is exactly `to`
+    pub fn value_at(&self, now_us: u64) -> (f64, bool) {
+        let elapsed = now_us.saturating_sub(self.start_us);
+        if elapsed >= self.duration_us { return (self.to, true); }
+        let t = elapsed as f64 / self.duration_us as f64;
+        (self.from + (self.to - self.from) * self.easing.apply(t), false)
+    }
+}
+
+/// The tweens of one VM
+#[derive(Debug, Default)]
+pub struct Tweens {
+    next_id: u64,
+    active: Vec<Tween>,
+}
+
+impl Tweens {
+    pub fn add(&mut self, from: f64, to: f64, start_us: u64, duration_us: u64, easing: Easing, handler: String) -> u64 {
+        self.next_id += 1;
+        self.active.push(Tween { id: self.next_id, from, to, start_us, duration_us, easing, handler });
+        self.next_id
+    }
+
+    /// Stop a tween without a final update; false if it is not running
+    pub fn cancel(&mut self, id: u64) -> bool {
+        let before = self.active.len();
+        self.active.retain(|t| t.id != id);
+        self.active.len() < before
+    }
+
+    pub fn len(&self) -> usize { self.active.len() }
+
+    pub fn is_empty(&self) -> bool { self.active.is_empty() }
+
+    /// (handler, value) for every tween at `now_us`, in creation order;
+    /// finished tweens are removed
+    pub fn advance(&mut self, now_us: u64) -> Vec<(String, f64)> {
+        let mut updates = Vec::with_capacity(self.active.len());
+        self.active.retain(|t| {
+            let (value, done) = t.value_at(now_us);
+            updates.push((t.handler.clone(), value));
+            !done
+        });
+        updates
+    }
+}
@@ -0,0 +1,337 @@
+//! Opt-in static type checking (`userd check --types`).
+//! The checker is gradual: anything it cannot infer is `Unknown` and is accepted
+//! everywhere, so untyped code keeps passing while obvious mismatches between a
+//! declared type (`int-x = "a";`) and the value are reported before running.
+//! Function annotations (`rtd add(int-a, int-b) -> int`) type the parameters
+//! inside the body, the arguments at call sites and the value of the last statement.
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::{BinOp, Expr, Intrinsic, Stmt};
+use crate::builtins::Registry;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Str,
+    Function,
+    /// the class value itself, e.g. `Point`
+    Class(String),
+    /// an instance of a class, e.g. the result of `Point(1, 2)`
+    Instance(String),
+    Unknown,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Str => write!(f, "str"),
+            Type::Function => write!(f, "function"),
+            Type::Class(n) => write!(f, "class {}", n),
+            Type::Instance(n) => write!(f, "{}", n),
+            Type::Unknown => write!(f, "?"),
+        }
+    }
+}
+
+impl Type {
+    /// `Unknown` is compatible with everything; an int is accepted where a float is expected
+    fn accepts(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Unknown, _) | (_, Type::Unknown) => true,
+            (Type::Float, Type::Int) => true,
+            _ => self == other,
+        }
+    }
+}
+
+/// Return types of builtins that always produce the same kind of value
+fn builtin_type(name: &str) -> Option<Type> {
+    Some(match name {
+        "get" | "channel_recv" | "json_stringify" | "path_join" | "path_basename" | "path_ext" | "env_get" | "csv_write"
+        | "xml_stringify" | "ini_read" | "ws_recv" | "temp_file" | "temp_dir"
+        | "file_sha256" | "file_crc32" | "b64_encode" | "b64_decode" | "apply_patch"
+        | "sha256" | "sha1" | "crc32" | "hmac_sha256" | "encrypt" | "decrypt"
+        | "casefold" | "gzip_decompress_text" | "repr" => Type::Str,
+        "to_int" | "secure_random" | "gui_window" | "spawn" | "detach" | "shared_delete" | "channel_create" | "channel_send"
+        | "channel_subscribe" | "channel_close" | "mkdir" | "remove_dir" | "env_set" | "env_unset" | "yield" | "group_send"
+        | "fsm_on" | "fsm_on_transition" | "ini_write" | "reg_write"
+        | "ws_connect" | "ws_send" | "ws_close" | "write_file_atomic"
+        | "compare_locale" | "assert" | "assert_eq" | "assert_canvas_matches" | "random_range" | "rand_seed" | "rand_next" | "rand_below" | "time_now" | "perf_now_us" | "frame_wait"
+        | "canvas_draw_text_wrapped" | "canvas_draw_text_aligned"
+        | "gui_scroll_panel" | "scroll_set_content" | "scroll_offset" | "scroll_to"
+        | "gui_list" | "list_selected" | "list_select" | "list_set_items" | "gui_focus" | "gui_focused" | "gui_move" | "gui_fullscreen"
+        | "rgb" | "rgba" | "hsl" | "hex_color" | "lighten" | "darken" | "mix" | "tween" | "tween_cancel" | "tween_update" | "set_timeout" | "set_interval" | "clear_timer" | "task_spawn" | "task_yield" | "run_tasks"
+        | "override_builtin" | "restore_builtin" | "spawn_with" | "shared_create" | "shared_set" | "shared_cas" => Type::Int,
+        "to_float" | "random_float" | "random_normal" | "ease" => Type::Float,
+        "channel_try_recv" | "channel_recv_timeout" | "group_recv" => Type::Instance("Result".to_string()),
+        "exec" => Type::Instance("Process".to_string()),
+        "fsm_create" => Type::Instance("Fsm".to_string()),
+        "xml_parse" => Type::Instance("Node".to_string()),
+        "gui_dialog" => Type::Instance("DialogResult".to_string()),
+        _ => return None,
+    })
+}
+
+/// A type error with the line of the statement it was found in
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub line: Option<usize>,
+    /// Stable code for tools, e.g. `T0001` for a declaration mismatch
+    pub code: &'static str,
+    pub message: String,
+    /// A fix to try, when there is an obvious one
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The builtin that turns `actual` into `expected`, if there is one
+fn conversion(expected: &Type, actual: &Type) -> Option<String> {
+    match (expected, actual) {
+        (Type::Int, Type::Str | Type::Float) => Some("convert the value with to_int(...)".to_string()),
+        (Type::Float, Type::Str) => Some("convert the value with to_float(...)".to_string()),
+        _ => None,
+    }
+}
+
+/// "1 argument", "2 arguments"
+fn arguments(n: usize) -> String {
+    format!("{} argument{}", n, if n == 1 { "" } else { "s" })
+}
+
+/// Params, their types and the return type of a function
+struct Signature {
+    params: Vec<String>,
+    types: Vec<Type>,
+    ret: Type,
+}
+
+/// Walks a program and collects type errors
+pub struct TypeChecker {
+    globals: HashMap<String, Type>,
+    /// Signatures of functions declared once
+    functions: HashMap<String, Signature>,
+    /// Standard builtins, for their arity
+    builtins: Registry,
+    /// Params of the function being checked, in slot order
+    locals: Vec<(String, Type)>,
+    classes: Vec<String>,
+    /// Line of the statement being checked
+    line: Option<usize>,
+    errors: Vec<TypeError>,
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self { Self::new() }
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        Self { globals: HashMap::new(), functions: HashMap::new(), builtins: Registry::standard(), locals: Vec::new(), classes: Vec::new(), line: None, errors: Vec::new() }
+    }
+
+    /// Check a whole program and return the errors found, in source order
+    pub fn check_program(mut self, prog: &[Stmt]) -> Vec<TypeError> {
+        // classes may be used before they are declared inside function bodies
+        for stmt in prog {
+            if let Stmt::ClassDecl { name, .. } = stmt { self.classes.push(name.clone()); }
+        }
+        for stmt in prog { self.check_stmt(stmt); }
+        self.errors
+    }
+
+    fn error(&mut self, code: &'static str, message: String, suggestion: Option<String>) {
+        self.errors.push(TypeError { line: self.line, code, message, suggestion });
+    }
+
+    /// Resolve a declared type name; unknown names are treated as `Unknown`
+    fn declared(&self, type_name: &str) -> Type {
+        match type_name {
+            "int" => Type::Int,
+            "float" => Type::Float,
+            "str" => Type::Str,
+            n if self.classes.iter().any(|c| c == n) => Type::Instance(n.to_string()),
+            _ => Type::Unknown,
+        }
+    }
+
+    fn annotation(&self, t: Option<&str>) -> Type {
+        t.map_or(Type::Unknown, |t| self.declared(t))
+    }
+
+    /// Check a body with its params in scope; the last expression statement is the return value
+    fn check_function(&mut self, name: &str, params: &[String], ptypes: Vec<Type>, ret: Type, body: &[Stmt]) {
+        let scope = params.iter().cloned().zip(ptypes).collect();
+        let outer = std::mem::replace(&mut self.locals, scope);
+        let mut last = Type::Unknown;
+        for s in body {
+            last = match s { Stmt::ExprStmt { expr: e, .. } => self.infer(e), _ => { self.check_stmt(s); Type::Unknown } };
+        }
+        if !ret.accepts(&last) {
+            self.error("T0002", format!("{}({}): returns {} but declared {}", name, params.join(", "), last, ret), None);
+        }
+        self.locals = outer;
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        if stmt.line().is_some() { self.line = stmt.line(); }
+        match stmt {
+            Stmt::VarDecl { type_name, name, value, .. } => {
+                let actual = self.infer(value);
+                let expected = self.declared(type_name);
+                if !expected.accepts(&actual) {
+                    let message = format!("{}-{}: declared {} but value is {}", type_name, name, expected, actual);
+                    self.error("T0001", message, conversion(&expected, &actual));
+                }
+                let t = if expected == Type::Unknown { actual } else { expected };
+                self.globals.insert(name.clone(), t);
+            }
+            Stmt::ExprStmt { expr: e, .. } => { self.infer(e); }
+            Stmt::FunctionDecl { name, params, param_types, ret_type, body } => {
+                let ptypes: Vec<Type> = param_types.iter().map(|t| self.annotation(t.as_deref())).collect();
+                let ret = self.annotation(ret_type.as_deref());
+                // overloads are not tracked: calls to a redeclared name are unchecked
+                if self.globals.insert(name.clone(), Type::Function).is_some() {
+                    self.functions.remove(name);
+                } else {
+                    self.functions.insert(name.clone(), Signature { params: params.clone(), types: ptypes.clone(), ret: ret.clone() });
+                }
+                self.check_function(name, params, ptypes, ret, body);
+            }
+            Stmt::ClassDecl { name, body, .. } => {
+                if !self.classes.contains(name) { self.classes.push(name.clone()); }
+                self.globals.insert(name.clone(), Type::Class(name.clone()));
+                for s in body {
+                    // methods: `self` is an instance of the class unless annotated otherwise
+                    if let Stmt::FunctionDecl { name: mname, params, param_types, ret_type, body: mb } = s {
+                        let ptypes = params.iter().zip(param_types).map(|(p, t)| match t {
+                            None if p == "self" => Type::Instance(name.clone()),
+                            t => self.annotation(t.as_deref()),
+                        }).collect();
+                        let ret = self.annotation(ret_type.as_deref());
+                        self.check_function(&format!("{}.{}", name, mname), params, ptypes, ret, mb);
+                    } else {
+                        self.check_stmt(s);
+                    }
+                }
+            }
+            Stmt::MemberAssign { receiver, value, .. } => {
+                self.infer(receiver);
+                self.infer(value);
+            }
+            Stmt::Block(stmts) => for s in stmts { self.check_stmt(s); },
+        }
+    }
+
+    /// Infer the type of an expression, recording errors for operations that always fail
+    fn infer(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Int(_) => Type::Int,
+            Expr::Float(_) => Type::Float,
+            Expr::Str(_) => Type::Str,
+            Expr::Ident(name) => match self.locals.iter().find(|(p, _)| p == name) {
+                Some((_, t)) => t.clone(),
+                None => self.globals.get(name).cloned().unwrap_or(Type::Unknown),
+            },
+            Expr::Local { slot, .. } => self.locals.get(*slot).map_or(Type::Unknown, |(_, t)| t.clone()),
+            Expr::BinaryOp { left, op, right } => {
+                let l = self.infer(left);
+                let r = self.infer(right);
+                match (&l, &r) {
+                    (Type::Unknown, _) | (_, Type::Unknown) => Type::Unknown,
+                    (Type::Int, Type::Int) => Type::Int,
+                    (Type::Int | Type::Float, Type::Int | Type::Float) => Type::Float,
+                    _ => {
+                        let sym = match op { BinOp::Add => "+", BinOp::Sub => "-", BinOp::Mul => "*", BinOp::Div => "/" };
+                        self.error("T0003", format!("unsupported operands: {} {} {}", l, sym, r), None);
+                        Type::Unknown
+                    }
+                }
+            }
+            Expr::Call { func, args, .. } => {
+                let arg_types: Vec<Type> = args.iter().map(|a| self.infer(a)).collect();
+                match &**func {
+                    // a parameter holding a function
+                    Expr::Ident(name) if self.locals.iter().any(|(p, _)| p == name) => Type::Unknown,
+                    // builtins shadow user definitions
+                    Expr::Ident(name) if self.builtins.contains(name) => {
+                        let arity = self.builtins.get(name).map_or(0, |b| b.arity);
+                        if arity != args.len() {
+                            let message = format!("{}: expects {}, got {}", name, arguments(arity), args.len());
+                            self.error("T0006", message, Some(format!("{} takes exactly {}", name, arguments(arity))));
+                        }
+                        builtin_type(name).unwrap_or(Type::Unknown)
+                    }
+                    Expr::Ident(name) => match self.globals.get(name) {
+                        Some(Type::Class(c)) => Type::Instance(c.clone()),
+                        Some(Type::Function) => match self.functions.get(name) {
+                            Some(sig) if sig.params.len() != args.len() => {
+                                let (ret, params) = (sig.ret.clone(), sig.params.join(", "));
+                                let message = format!("{}: expects {}, got {}", name, arguments(sig.params.len()), args.len());
+                                self.error("T0006", message, Some(format!("call it as {}({})", name, params)));
+                                ret
+                            }
+                            Some(Signature { types: ptypes, ret, .. }) => {
+                                let ret = ret.clone();
+                                let bad: Vec<(String, Option<String>)> = ptypes.iter().zip(&arg_types).enumerate()
+                                    .filter(|(_, (p, a))| !p.accepts(a))
+                                    .map(|(i, (p, a))| (format!("{}: argument {} expects {}, got {}", name, i + 1, p, a), conversion(p, a)))
+                                    .collect();
+                                for (message, suggestion) in bad { self.error("T0004", message, suggestion); }
+                                ret
+                            }
+                            None => Type::Unknown,
+                        },
+                        Some(Type::Unknown) | None => builtin_type(name).unwrap_or(Type::Unknown),
+                        Some(t) => {
+                            self.error("T0005", format!("{} is {} and cannot be called", name, t), None);
+                            Type::Unknown
+                        }
+                    },
+                    _ => Type::Unknown,
+                }
+            }
+            Expr::MemberCall { receiver, args, .. } => {
+                self.infer(receiver);
+                for a in args { self.infer(a); }
+                Type::Unknown
+            }
+            Expr::MemberAccess { receiver, .. } => {
+                self.infer(receiver);
+                Type::Unknown
+            }
+            Expr::Intrinsic { op, args, .. } => {
+                let arg_types: Vec<Type> = args.iter().map(|a| self.infer(a)).collect();
+                match op {
+                    Intrinsic::Len | Intrinsic::Push => Type::Int,
+                    Intrinsic::Sqrt => Type::Float,
+                    // ints stay ints, anything involving a float is a float
+                    Intrinsic::Abs | Intrinsic::Min | Intrinsic::Max => {
+                        if arg_types.iter().all(|t| *t == Type::Int) { Type::Int }
+                        else if arg_types.iter().all(|t| matches!(t, Type::Int | Type::Float)) { Type::Float }
+                        else { Type::Unknown }
+                    }
+                    Intrinsic::List | Intrinsic::At => Type::Unknown,
+                }
+            }
+        }
+    }
+}
+
+/// Convenience wrapper: check `prog` with a fresh checker
+pub fn check_program(prog: &[Stmt]) -> Vec<String> {
+    type_errors(prog).iter().map(ToString::to_string).collect()
+}
+
+/// Like `check_program`, keeping lines and codes for diagnostics
+pub fn type_errors(prog: &[Stmt]) -> Vec<TypeError> {
+    TypeChecker::new().check_program(prog)
+}
@@ -0,0 +1,89 @@
+//! Usage statistics for embedders: which builtins a program called and which
+//! language features it contains, handed to a host callback after each run
+//! (`VM::set_usage_hook`). Nothing is collected without a hook, and nothing
+//! leaves the process unless the host sends it somewhere itself.
+//!
+//! Builtins are counted as they are called, intrinsics (`len`, `push`, ...)
+//! included. Features are counted in the program text before it runs; macros
+//! are already expanded by then, so they show up as what they expand to.
+use std::collections::BTreeMap;
+
+use crate::ast::{walk_expr, walk_stmt, Expr, Program, Stmt, Visitor};
+
+/// Feature names used in `Usage::features`
+pub const FEATURES: [&str; 12] = [
+    "function", "typed_param", "return_type", "class", "data_class", "method",
+    "member_call", "field_access", "field_assign", "float_literal", "string_literal", "arithmetic",
+];
+
+/// Host callback given the `Usage` of each run
+pub type UsageHook = Box<dyn FnMut(&Usage)>;
+
+/// One run's feature vector
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Calls per builtin name
+    pub builtins: BTreeMap<String, u64>,
+    /// Occurrences per feature in `FEATURES`; features not used are absent
+    pub features: BTreeMap<&'static str, u64>,
+}
+
+impl Usage {
+    pub(crate) fn called(&mut self, name: &str) {
+        match self.builtins.get_mut(name) {
+            Some(n) => *n += 1,
+            None => { self.builtins.insert(name.to_string(), 1); }
+        }
+    }
+
+    fn feature(&mut self, name: &'static str, n: u64) {
+        if n > 0 { *self.features.entry(name).or_default() += n; }
+    }
+
+    /// Count the features of `prog`
+    pub fn add_program(&mut self, prog: &Program) {
+        let mut counter = FeatureCounter { usage: self, in_class: false };
+        for s in prog { counter.visit_stmt(s); }
+    }
+}
+
+/// Walks a program adding its features to `usage`
+struct FeatureCounter<'a> {
+    usage: &'a mut Usage,
+    /// Directly in a class body, where functions are methods
+    in_class: bool,
+}
+
+impl Visitor for FeatureCounter<'_> {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        let outer = self.in_class;
+        match stmt {
+            Stmt::FunctionDecl { param_types, ret_type, .. } => {
+                self.usage.feature(if self.in_class { "method" } else { "function" }, 1);
+                self.usage.feature("typed_param", param_types.iter().filter(|t| t.is_some()).count() as u64);
+                self.usage.feature("return_type", ret_type.is_some() as u64);
+                self.in_class = false;
+            }
+            Stmt::ClassDecl { is_data, .. } => {
+                self.usage.feature(if *is_data { "data_class" } else { "class" }, 1);
+                self.in_class = true;
+            }
+            Stmt::MemberAssign { .. } => self.usage.feature("field_assign", 1),
+            _ => {}
+        }
+        walk_stmt(self, stmt);
+        self.in_class = outer;
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Float(_) => self.usage.feature("float_literal", 1),
+            Expr::Str(_) => self.usage.feature("string_literal", 1),
+            Expr::BinaryOp { .. } => self.usage.feature("arithmetic", 1),
+            Expr::MemberCall { .. } => self.usage.feature("member_call", 1),
+            Expr::MemberAccess { .. } => self.usage.feature("field_access", 1),
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+}
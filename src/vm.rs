@@ -1,12 +1,12 @@
-use crate::ast::{Expr, Stmt, BinOp};
-use std::collections::HashMap;
+use crate::ast::{Expr, Stmt, BinOp, Intrinsic, Program};
+use crate::builtins::{Builtin, BuiltinFn, Registry, VmError};
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::io::{self, Write};
-use std::thread;
-use std::time::Duration;
-use std::sync::{Mutex, OnceLock, mpsc};
-use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone)]
 pub enum Value {
@@ -14,759 +14,1786 @@ pub enum Value {
     Float(f64),
     Str(String),
     Function(FunctionObject),
-    Class(ClassObject),
+    /// Several `rtd` definitions of one name with different parameter counts
+    Overloaded(Rc<[FunctionObject]>),
+    Class(Rc<ClassObject>),
     Object(Rc<RefCell<Object>>),
+    /// Produced by host builtins; shared like objects
+    List(Rc<RefCell<Vec<Value>>>),
+    /// Opaque Rust object from the embedder; `obj.m(args)` and `obj.field`
+    /// go to its `HostApi`. Shared like objects
+    Host(Rc<RefCell<Box<dyn crate::host::HostApi>>>),
 }
 
-#[derive(Debug, Clone)]
+impl From<i64> for Value {
+    fn from(n: i64) -> Self { Value::Int(n) }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Self { Value::Float(f) }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self { Value::Str(s) }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self { Value::Str(s.to_string()) }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(items: Vec<Value>) -> Self { Value::List(Rc::new(RefCell::new(items))) }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = VmError;
+    fn try_from(v: Value) -> Result<Self, VmError> {
+        match v {
+            Value::Int(n) => Ok(n),
+            other => Err(format!("expected int, got {}", other.type_name())),
+        }
+    }
+}
+
+/// Ints are accepted where a float is expected, as in annotations
+impl TryFrom<Value> for f64 {
+    type Error = VmError;
+    fn try_from(v: Value) -> Result<Self, VmError> {
+        match v {
+            Value::Float(f) => Ok(f),
+            Value::Int(n) => Ok(n as f64),
+            other => Err(format!("expected float, got {}", other.type_name())),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = VmError;
+    fn try_from(v: Value) -> Result<Self, VmError> {
+        match v {
+            Value::Str(s) => Ok(s),
+            other => Err(format!("expected str, got {}", other.type_name())),
+        }
+    }
+}
+
+/// Copies the items; the list itself stays shared with the program
+impl TryFrom<Value> for Vec<Value> {
+    type Error = VmError;
+    fn try_from(v: Value) -> Result<Self, VmError> {
+        match v {
+            Value::List(items) => Ok(items.borrow().clone()),
+            other => Err(format!("expected list, got {}", other.type_name())),
+        }
+    }
+}
+
+/// A user function. Params and body are shared, so cloning a function value
+/// (e.g. on every call lookup) never copies the AST.
+#[derive(Clone)]
 pub struct FunctionObject {
-    pub params: Vec<String>,
-    pub body: Vec<Stmt>,
+    pub params: Rc<[String]>,
+    pub body: Rc<[Stmt]>,
+    /// Declared parameter types, aligned with `params` (`None` = unannotated)
+    pub param_types: Rc<[Option<String>]>,
+    pub ret_type: Option<String>,
+    /// Rust implementation for methods the VM generates itself (data classes)
+    pub native: Option<NativeMethod>,
+}
+
+impl Value {
+    /// Type name used in annotations and type errors; objects report their class
+    pub fn type_name(&self) -> String {
+        match self {
+            Value::Int(_) => "int".to_string(),
+            Value::Float(_) => "float".to_string(),
+            Value::Str(_) => "str".to_string(),
+            Value::Function(_) | Value::Overloaded(_) => "function".to_string(),
+            Value::Class(_) => "class".to_string(),
+            Value::Object(o) => o.borrow().class_name.clone(),
+            Value::List(_) => "list".to_string(),
+            Value::Host(h) => h.try_borrow().map_or_else(|_| "host".to_string(), |h| h.type_name().to_string()),
+        }
+    }
+
+    /// The definition a call with `arity` arguments runs: the function itself
+    /// or the member of an overload set with that many parameters
+    pub fn overload(&self, arity: usize) -> Option<&FunctionObject> {
+        match self {
+            Value::Function(f) if f.params.len() == arity => Some(f),
+            Value::Overloaded(set) => set.iter().find(|f| f.params.len() == arity),
+            _ => None,
+        }
+    }
+}
+
+/// Native method: receives the instance and the evaluated arguments
+pub type NativeMethod = Rc<dyn Fn(&mut VM, &Rc<RefCell<Object>>, &[Value]) -> Result<Value, String>>;
+
+impl FunctionObject {
+    /// Build a function, resolving parameter references in `body` to frame slots
+    pub fn new(params: Vec<String>, mut body: Vec<Stmt>) -> Self {
+        crate::resolver::resolve_function(&params, &mut body);
+        let param_types = vec![None; params.len()].into();
+        Self { params: params.into(), body: body.into(), param_types, ret_type: None, native: None }
+    }
+
+    /// Attach parameter and return annotations, checked on every call
+    pub fn with_types(mut self, param_types: Vec<Option<String>>, ret_type: Option<String>) -> Self {
+        self.param_types = param_types.into();
+        self.ret_type = ret_type;
+        self
+    }
+
+    /// `name(int-a, b) -> int`, as shown by `help()`
+    pub fn signature(&self, name: &str) -> String {
+        let params: Vec<String> = self.params.iter().zip(self.param_types.iter()).map(|(p, t)| match t {
+            Some(t) => format!("{}-{}", t, p),
+            None => p.clone(),
+        }).collect();
+        match &self.ret_type {
+            Some(r) => format!("{}({}) -> {}", name, params.join(", "), r),
+            None => format!("{}({})", name, params.join(", ")),
+        }
+    }
+
+    fn native(params: &[&str], f: NativeMethod) -> Self {
+        let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+        let param_types = vec![None; params.len()].into();
+        Self { params: params.into(), body: Vec::new().into(), param_types, ret_type: None, native: Some(f) }
+    }
+}
+
+impl std::fmt::Debug for FunctionObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FunctionObject")
+            .field("params", &self.params)
+            .field("body", &self.body)
+            .field("param_types", &self.param_types)
+            .field("ret_type", &self.ret_type)
+            .field("native", &self.native.is_some())
+            .finish()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ClassObject {
     pub name: String,
+    /// Header fields of `class Name(f1, f2)`
+    pub fields: Vec<String>,
+    /// `data class`: the native methods can be regenerated from `fields`
+    pub is_data: bool,
     pub methods: HashMap<String, FunctionObject>,
 }
 
+/// Object fields and globals, kept in insertion (declaration) order
+pub type Fields = crate::utils::OrderedMap<String, Value>;
+
 #[derive(Debug)]
 pub struct Object {
     pub class_name: String,
-    pub fields: HashMap<String, Value>,
+    /// Class the object was constructed from; `None` for objects built by builtins
+    pub class: Option<Rc<ClassObject>>,
+    pub fields: Fields,
+    /// Per-instance methods, looked up before the class ones
     pub methods: HashMap<String, FunctionObject>,
 }
 
+impl Object {
+    /// Object without a class, e.g. the `Result` objects returned by builtins
+    pub fn plain(class_name: &str, fields: Fields) -> Self {
+        Self { class_name: class_name.to_string(), class: None, fields, methods: HashMap::new() }
+    }
+
+    pub fn instance_of(class: &Rc<ClassObject>) -> Self {
+        Self { class_name: class.name.clone(), class: Some(class.clone()), fields: Fields::new(), methods: HashMap::new() }
+    }
+
+    /// Names of the methods callable on this object, sorted
+    pub fn method_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.methods.keys().cloned().collect();
+        if let Some(c) = &self.class {
+            names.extend(c.methods.keys().filter(|m| !self.methods.contains_key(*m)).cloned());
+        }
+        names.sort();
+        names
+    }
+
+    /// Instance override first, then the class method table
+    pub fn find_method(&self, name: &str) -> Option<FunctionObject> {
+        self.methods.get(name)
+            .or_else(|| self.class.as_ref().and_then(|c| c.methods.get(name)))
+            .cloned()
+    }
+}
+
 pub struct VM {
-    globals: HashMap<String, Value>,
-    frames: Vec<HashMap<String, Value>>, // call stack locals
+    globals: Fields,
+    frames: Vec<Frame>, // call stack locals
+    /// Parameter names of each frame, for `locals`
+    frame_params: Vec<Rc<[String]>>,
+    limits: Limits,
+    policy: Policy,
+    /// Inline caches for member calls, indexed by call site: the class last seen
+    /// there, the method name and what it resolved to. Programs run on one VM
+    /// number their sites independently, so entries are checked by name too.
+    method_cache: Vec<Option<(Rc<ClassObject>, String, FunctionObject)>>,
+    builtins: Registry,
+    /// Builtins as they were before their first `wrap_builtin`
+    originals: HashMap<String, Builtin>,
+    /// Set when the host registers a builtin or the program defines a global
+    /// named like an intrinsic; such calls then go to that definition
+    intrinsics_overridden: bool,
+    /// Where printed values, prompts and console fallbacks go; stdout by default
+    out: Box<dyn Write>,
+    /// Fakes set by `deterministic`, `freeze_time` and `seed_random`
+    replay: Replay,
+    /// Generator behind `rand_next`; seeded by `rand_seed`, or on first use
+    prng: Option<crate::rand::Xoshiro256>,
+    /// Boundary `frame_wait` last waited for, in `perf_now_us` time
+    next_frame_us: Option<u64>,
+    /// Animations advanced by `frame_wait`, `gui_poll` and `tween_update`
+    tweens: crate::tween::Tweens,
+    /// `set_timeout` and `set_interval` callbacks, run by `gui_poll` and `gui_run`
+    timers: crate::timer::Timers,
+    /// Cooperative tasks from `task_spawn`
+    tasks: crate::task::Tasks,
+    /// GUI events being recorded or replayed
+    gui_events: crate::gui::EventTape,
+    trace_hook: Option<TraceHook>,
+    /// Set by `set_usage_hook`: what the current run used, and who gets it
+    usage: Option<(crate::usage::Usage, crate::usage::UsageHook)>,
+    debug: Debugger,
+    /// `None` prints values in full
+    display: Option<DisplayLimits>,
+    /// Set by `set_pretty_objects`
+    pretty_objects: bool,
+    /// Set by `enable_profiling`
+    profiler: Option<Profiler>,
+    /// Set by `enable_call_trace`
+    call_trace: Option<CallTrace>,
+    /// Line of the statement the last error came from
+    error_line: Option<usize>,
+    /// Set by the `exit` builtin
+    exit_code: Option<i32>,
+    /// Command-line arguments for `argv()`
+    args: Vec<String>,
+    cancel: CancelToken,
+    /// Program loaded by `start`, run in slices by `run_steps`
+    pending: Option<Pending>,
+    /// Set by the `yield` builtin; ends the current `run_steps` slice
+    yield_requested: bool,
+    /// Set when the VM is added to a `VmGroup`
+    group: Option<crate::group::GroupLink>,
+    /// Line of the statement running, for assertion failures
+    line: Option<usize>,
+    /// Set by `set_test_mode`: failed assertions are collected, not raised
+    test_mode: bool,
+    failures: Vec<AssertFailure>,
 }
 
-static CH_SENDERS: OnceLock<Mutex<HashMap<u64, mpsc::Sender<String>>>> = OnceLock::new();
-static CH_RECEIVERS: OnceLock<Mutex<HashMap<u64, mpsc::Receiver<String>>>> = OnceLock::new();
-static CH_NEXT_ID: AtomicU64 = AtomicU64::new(1);
-static CH_BCAST: OnceLock<Mutex<HashMap<u64, Vec<(u64, mpsc::Sender<String>)>>>> = OnceLock::new();
-static SUB_TO_CHANNEL: OnceLock<Mutex<HashMap<u64, u64>>> = OnceLock::new();
+/// A program between `run_steps` slices
+struct Pending {
+    prog: Program,
+    /// Index of the next top-level statement
+    next: usize,
+    last: Option<Value>,
+}
 
-fn ch_senders() -> &'static Mutex<HashMap<u64, mpsc::Sender<String>>> {
-    CH_SENDERS.get_or_init(|| Mutex::new(HashMap::new()))
+/// What a `run_steps` slice ended with
+#[derive(Debug, Clone)]
+pub enum RunState {
+    /// Statements are left; call `run_steps` again to continue
+    Paused,
+    /// The program ended; the value of its last statement
+    Finished(Option<Value>),
+}
+
+/// One finished call recorded by `enable_call_trace`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallSpan {
+    pub name: String,
+    /// Since `enable_call_trace`
+    pub start: Duration,
+    pub duration: Duration,
+    /// User calls in progress when it started
+    pub depth: usize,
+}
+
+struct CallTrace {
+    origin: Instant,
+    spans: Vec<CallSpan>,
+}
+
+/// Calls of one function (or builtin, or `Class.method`) while profiling
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileEntry {
+    pub name: String,
+    pub calls: u64,
+    /// Time spent in the calls, including their callees
+    pub total: Duration,
+}
+
+#[derive(Default)]
+struct Profiler {
+    entries: HashMap<String, ProfileEntry>,
+    /// Calls of each name currently running, to time recursion once
+    active: HashMap<String, usize>,
+}
+
+/// How much of a large value is printed; see `VM::set_display_limits`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayLimits {
+    /// List items shown before a `... (N more)` marker
+    pub max_items: usize,
+    /// Lists nested deeper than this print as `[...]`
+    pub max_depth: usize,
+}
+
+impl Default for DisplayLimits {
+    fn default() -> Self { Self { max_items: 100, max_depth: 8 } }
 }
 
-fn ch_receivers() -> &'static Mutex<HashMap<u64, mpsc::Receiver<String>>> {
-    CH_RECEIVERS.get_or_init(|| Mutex::new(HashMap::new()))
+/// Called before each statement runs, with the VM as it is at that point
+pub type TraceHook = Rc<dyn Fn(&Stmt, &VM)>;
+
+/// Where a program stopped under the debugger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pause {
+    pub file: String,
+    pub line: usize,
+    /// false when the pause comes from stepping
+    pub breakpoint: bool,
+}
+
+/// How to go on after a pause
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugAction {
+    /// Stop again at the next statement, entering calls
+    Step,
+    /// Run until the next breakpoint
+    Continue,
+}
+
+/// Inspects the paused VM and decides how to continue
+pub type DebugHandler = Box<dyn FnMut(&Pause, &VM) -> DebugAction>;
+
+/// Breakpoints per file and the handler called when one is hit
+#[derive(Default)]
+struct Debugger {
+    /// Name of the source being run, see `VM::set_file`
+    file: String,
+    breakpoints: HashMap<String, HashSet<usize>>,
+    handler: Option<DebugHandler>,
+    stepping: bool,
 }
 
-fn ch_bcast() -> &'static Mutex<HashMap<u64, Vec<(u64, mpsc::Sender<String>)>>> {
-    CH_BCAST.get_or_init(|| Mutex::new(HashMap::new()))
+/// Sources of nondeterminism and their fakes; `None` uses the real source
+#[derive(Default)]
+struct Replay {
+    /// Set by `seed_random`: `secure_random` and friends draw from it
+    rng: Option<crate::rand::SeededRng>,
+    /// Set by `freeze_time`: logical time in microseconds, advanced by
+    /// `sleep_ms` and `frame_wait` instead of sleeping
+    clock_us: Option<u64>,
+    /// Unix time in milliseconds that `time_now` reports at logical time 0
+    epoch_ms: u64,
+    /// Lines returned by `get`, in order, in deterministic mode
+    stdin: Option<std::collections::VecDeque<String>>,
 }
 
-fn sub_to_channel() -> &'static Mutex<HashMap<u64, u64>> {
-    SUB_TO_CHANNEL.get_or_init(|| Mutex::new(HashMap::new()))
+/// In-memory output sink for `VM::set_output`; clones share one buffer, so a
+/// copy kept by the host sees everything the program printed
+#[derive(Clone, Default)]
+pub struct OutputBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl OutputBuffer {
+    pub fn new() -> Self { Self::default() }
+
+    /// Everything written so far, as (lossy) UTF-8
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+/// Groups of builtins a `Policy` can switch off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability { FileIo, Process, Gui, Network, Stdin }
+
+/// Which capabilities a program may use; everything is allowed by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    pub file_io: bool,
+    /// starting threads or processes (`spawn`)
+    pub process: bool,
+    pub gui: bool,
+    pub network: bool,
+    pub stdin: bool,
+}
+
+impl Policy {
+    pub fn allow_all() -> Self { Self { file_io: true, process: true, gui: true, network: true, stdin: true } }
+
+    /// For untrusted code: no IO outside the program's own output
+    pub fn sandboxed() -> Self { Self { file_io: false, process: false, gui: false, network: false, stdin: false } }
+
+    pub fn allows(&self, cap: Capability) -> bool {
+        match cap {
+            Capability::FileIo => self.file_io,
+            Capability::Process => self.process,
+            Capability::Gui => self.gui,
+            Capability::Network => self.network,
+            Capability::Stdin => self.stdin,
+        }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self { Self::allow_all() }
+}
+
+/// Stops a running program from another thread (a watchdog, a Ctrl-C handler,
+/// a GUI host); get one with `VM::cancel_token`. The run fails with
+/// `CANCELLED` at its next statement or `sleep_ms` slice. A cancel while
+/// nothing runs stops the next run, unless `reset` first.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) { self.0.store(true, Ordering::SeqCst); }
+
+    pub fn is_cancelled(&self) -> bool { self.0.load(Ordering::Relaxed) }
+
+    pub fn reset(&self) { self.0.store(false, Ordering::SeqCst); }
+}
+
+/// Error of a run stopped through a `CancelToken`
+pub const CANCELLED: &str = "execution cancelled";
+
+/// Start of the error raised by a failing `assert` or `assert_eq`
+pub const ASSERTION_FAILED: &str = "assertion failed";
+
+/// An assertion that failed while the VM was in test mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssertFailure {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Execution budget set by `VM::with_limits`, counted per `execute_program`
+#[derive(Default)]
+struct Limits {
+    max_steps: Option<u64>,
+    max_time: Option<Duration>,
+    steps: u64,
+    deadline: Option<Instant>,
+    /// Deepest nesting of user calls; `None` leaves it to the native stack
+    max_depth: Option<usize>,
+}
+
+/// Call depth a `with_limits` VM allows: runaway recursion in untrusted code
+/// fails with an error instead of overflowing the native stack
+const LIMITED_CALL_DEPTH: usize = 100;
+
+/// Locals of one call, indexed by the slots assigned in `resolver`; `None` = unbound
+type Frame = Vec<Option<Value>>;
+
+impl Default for VM {
+    fn default() -> Self { Self::new() }
 }
 
 impl VM {
-    pub fn new() -> Self { Self { globals: HashMap::new(), frames: Vec::new() } }
+    pub fn new() -> Self {
+        Self { globals: Fields::new(), frames: Vec::new(), frame_params: Vec::new(), limits: Limits::default(), policy: Policy::default(), method_cache: Vec::new(), builtins: Registry::standard(), originals: HashMap::new(), intrinsics_overridden: false, out: Box::new(io::stdout()), replay: Replay::default(), prng: None, next_frame_us: None, tweens: Default::default(), timers: Default::default(), tasks: Default::default(), gui_events: Default::default(), trace_hook: None, usage: None, debug: Debugger::default(), display: Some(DisplayLimits::default()), pretty_objects: false, profiler: None, call_trace: None, error_line: None, exit_code: None, args: Vec::new(), cancel: CancelToken::default(), pending: None, yield_requested: false, group: None, line: None, test_mode: false, failures: Vec::new() }
+    }
 
-    fn push_frame(&mut self) { self.frames.push(HashMap::new()); }
-    fn pop_frame(&mut self) { self.frames.pop(); }
+    /// VM whose runs are reproducible: `seed_random(seed)` and
+    /// `freeze_time(0)`, and `get` reads the lines given to `feed_stdin` (an
+    /// error once they run out)
+    pub fn deterministic(seed: u64) -> Self {
+        let mut vm = Self::new();
+        vm.seed_random(seed);
+        vm.freeze_time(0);
+        vm.replay.stdin = Some(Default::default());
+        vm
+    }
 
-    fn set_local(&mut self, name: String, val: Value) {
-        if let Some(frame) = self.frames.last_mut() { frame.insert(name, val); }
-        else { self.globals.insert(name, val); }
+    /// Stop the clocks at `unix_ms`: `time_now` starts there and
+    /// `perf_now_us` at 0, and both move only when `sleep_ms` or `frame_wait`
+    /// advance them, without really waiting
+    pub fn freeze_time(&mut self, unix_ms: u64) {
+        self.replay.clock_us = Some(0);
+        self.replay.epoch_ms = unix_ms;
     }
 
-    fn get_var(&self, name: &str) -> Option<Value> {
-        for frame in self.frames.iter().rev() {
-            if let Some(v) = frame.get(name) { return Some(v.clone()); }
+    /// Draw `secure_random`, `random_float` and the other random builtins
+    /// from a PRNG seeded with `seed` instead of the operating system
+    pub fn seed_random(&mut self, seed: u64) {
+        self.replay.rng = Some(crate::rand::SeededRng::new(seed));
+    }
+
+    /// Queue scripted input for `get`; only used in deterministic mode
+    pub fn feed_stdin<I: IntoIterator<Item = S>, S: Into<String>>(&mut self, lines: I) {
+        if let Some(s) = &mut self.replay.stdin { s.extend(lines.into_iter().map(Into::into)); }
+    }
+
+    /// Milliseconds slept so far while time is frozen
+    pub fn logical_time_ms(&self) -> Option<u64> {
+        self.replay.clock_us.map(|us| us / 1000)
+    }
+
+    /// `time_now`: Unix time in milliseconds, or the frozen clock
+    pub(crate) fn time_now_ms(&self) -> u64 {
+        match self.replay.clock_us {
+            Some(us) => self.replay.epoch_ms + us / 1000,
+            None => std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64),
+        }
+    }
+
+    /// Random value in [0, max) for `secure_random`
+    pub(crate) fn random_below(&mut self, max: u64) -> Result<u64, String> {
+        match &mut self.replay.rng {
+            Some(rng) => rng.below(max),
+            None => crate::rand::secure_random_u64(max),
+        }
+    }
+
+    pub(crate) fn seed_prng(&mut self, seed: u64) {
+        self.prng = Some(crate::rand::Xoshiro256::new(seed));
+    }
+
+    /// The `rand_next` generator; unless `rand_seed` was called, seeded from
+    /// `random_below`, so deterministic mode reproduces it too
+    pub(crate) fn prng(&mut self) -> Result<&mut crate::rand::Xoshiro256, String> {
+        if self.prng.is_none() {
+            let seed = self.random_below(u64::MAX)?;
+            self.seed_prng(seed);
+        }
+        Ok(self.prng.as_mut().expect("seeded above"))
+    }
+
+    /// `sleep_ms`: real sleep, or a tick of the logical clock
+    /// Sleeps in short slices so a cancel does not wait for the whole pause
+    pub(crate) fn sleep_ms(&mut self, ms: u64) -> Result<(), VmError> {
+        if let Some(us) = &mut self.replay.clock_us {
+            *us += ms.saturating_mul(1000);
+            return Ok(());
+        }
+        let end = Instant::now() + Duration::from_millis(ms);
+        loop {
+            self.check_cancelled()?;
+            let left = end.saturating_duration_since(Instant::now());
+            if left.is_zero() { return Ok(()); }
+            std::thread::sleep(left.min(Duration::from_millis(10)));
+        }
+    }
+
+    /// `perf_now_us`: the high-resolution clock, or the logical one
+    pub(crate) fn perf_now_us(&self) -> u64 {
+        match self.replay.clock_us {
+            Some(us) => us,
+            None => crate::platform::clock::now_us(),
+        }
+    }
+
+    /// `frame_wait(fps)`: wait until the next frame boundary. Boundaries are
+    /// a fixed period apart from the first call, so time spent drawing does
+    /// not add up the way repeated `sleep_ms` does. A loop that falls more
+    /// than a frame behind skips the missed frames instead of rushing through
+    /// them; the result is how many were skipped
+    pub(crate) fn frame_wait(&mut self, fps: u64) -> Result<u64, VmError> {
+        let period = 1_000_000 / fps.max(1);
+        let now = self.perf_now_us();
+        let (next, missed) = match self.next_frame_us {
+            Some(next) => {
+                // the first boundary not already passed
+                let k = now.saturating_sub(next).div_ceil(period).max(1);
+                (next + k * period, k - 1)
+            }
+            None => (now + period, 0),
+        };
+        self.next_frame_us = Some(next);
+        if let Some(us) = &mut self.replay.clock_us {
+            *us = (*us).max(next);
+            return Ok(missed);
+        }
+        // sleep in slices, then spin out the last stretch that sleep would overshoot
+        loop {
+            self.check_cancelled()?;
+            let left = next.saturating_sub(crate::platform::clock::now_us());
+            if left == 0 { return Ok(missed); }
+            if left > 2000 {
+                std::thread::sleep(Duration::from_micros((left - 1500).min(10_000)));
+            } else {
+                std::thread::yield_now();
+            }
+        }
+    }
+
+    pub(crate) fn tweens(&mut self) -> &mut crate::tween::Tweens {
+        &mut self.tweens
+    }
+
+    pub(crate) fn timers(&mut self) -> &mut crate::timer::Timers {
+        &mut self.timers
+    }
+
+    pub(crate) fn tasks(&mut self) -> &mut crate::task::Tasks {
+        &mut self.tasks
+    }
+
+    /// One turn of `task`: its body statements until it yields or ends;
+    /// true when it has finished
+    pub(crate) fn task_turn(&mut self, task: &mut crate::task::Task) -> Result<bool, String> {
+        self.push_frame(std::mem::take(&mut task.frame), &task.func.params)?;
+        self.tasks.current = Some(task.id);
+        self.tasks.yield_requested = false;
+        let body = task.func.body.clone();
+        let res = loop {
+            let Some(stmt) = body.get(task.next) else { break Ok(true) };
+            if let Err(e) = self.execute_stmt(stmt) { break Err(e) }
+            task.next += 1;
+            if std::mem::take(&mut self.tasks.yield_requested) { break Ok(task.next >= body.len()) }
+        };
+        self.tasks.current = None;
+        task.frame = self.frames.pop().unwrap_or_default();
+        self.frame_params.pop();
+        res
+    }
+
+    /// Recording and replay of the GUI events this VM's handlers receive
+    pub fn gui_events(&mut self) -> &mut crate::gui::EventTape {
+        &mut self.gui_events
+    }
+
+    /// One trimmed line of input for `get`
+    pub(crate) fn read_line(&mut self) -> Result<String, String> {
+        if let Some(s) = &mut self.replay.stdin {
+            return s.pop_front().ok_or_else(|| "get: scripted input exhausted".to_string());
+        }
+        let mut line = String::new();
+        io::stdin().read_line(&mut line).map_err(|e| e.to_string())?;
+        Ok(line.trim().to_string())
+    }
+
+    /// Add a host function callable from scripts as `name(...)` with exactly
+    /// `arity` arguments; replaces a builtin of the same name
+    pub fn register_builtin(&mut self, name: &str, arity: usize, f: impl Fn(&mut VM, &[Value]) -> Result<Value, VmError> + 'static) {
+        self.intrinsics_overridden |= Intrinsic::from_name(name).is_some();
+        self.builtins.register(name, arity, None, f);
+    }
+
+    /// Replace builtin `name` with `f`, which gets the implementation it
+    /// replaces as its last argument, e.g. to record calls or stub out IO in
+    /// tests. Arity, capability and help text stay those of the builtin
+    pub fn wrap_builtin(&mut self, name: &str, f: impl Fn(&mut VM, &[Value], &BuiltinFn) -> Result<Value, VmError> + 'static) -> Result<(), VmError> {
+        let Some(prev) = self.builtins.get(name).cloned() else {
+            return Err(format!("{} is not a builtin", name));
+        };
+        if Intrinsic::from_name(name).is_some() {
+            return Err(format!("{} is part of the language and cannot be overridden", name));
+        }
+        self.originals.entry(name.to_string()).or_insert_with(|| prev.clone());
+        let inner = prev.func.clone();
+        self.builtins.register(name, prev.arity, prev.capability, move |vm, args| f(vm, args, &inner)).doc = prev.doc;
+        Ok(())
+    }
+
+    /// Undo every `wrap_builtin` of `name`; false if it was not wrapped
+    pub fn restore_builtin(&mut self, name: &str) -> bool {
+        let Some(b) = self.originals.remove(name) else { return false };
+        let func = b.func.clone();
+        self.builtins.register(name, b.arity, b.capability, move |vm, args| func(vm, args)).doc = b.doc;
+        true
+    }
+
+    /// Call builtin `name` as it was before any `wrap_builtin`, with the usual checks
+    pub fn call_original(&mut self, name: &str, args: &[Value]) -> Result<Value, VmError> {
+        let Some(b) = self.originals.get(name).cloned() else { return self.call_builtin(name, args) };
+        if let Some(cap) = b.capability && !self.policy.allows(cap) {
+            return Err(format!("{} is not allowed by the sandbox policy ({:?})", name, cap));
+        }
+        if !b.accepts(args.len()) {
+            return Err(format!("{} requires {} argument{}", name, b.arity, if b.arity == 1 { "" } else { "s" }));
+        }
+        (b.func)(self, args)
+    }
+
+    /// Send everything the program prints to `out` instead of stdout.
+    /// Errors are not printed by the VM; they come back from `execute_program`.
+    pub fn set_output(&mut self, out: Box<dyn Write>) { self.out = out; }
+
+    /// Write one line to the output sink
+    pub(crate) fn emit(&mut self, text: &str) -> Result<(), String> {
+        writeln!(self.out, "{}", text).map_err(|e| format!("output error: {}", e))
+    }
+
+    /// Write `text` without a newline and flush, e.g. an input prompt
+    pub(crate) fn prompt(&mut self, text: &str) {
+        let _ = write!(self.out, "{}", text);
+        let _ = self.out.flush();
+    }
+
+    /// Call `hook` before every statement, including those in function bodies;
+    /// a base for tracers, coverage tools and profilers. Replaces an earlier hook.
+    pub fn set_trace_hook(&mut self, hook: impl Fn(&Stmt, &VM) + 'static) {
+        self.trace_hook = Some(Rc::new(hook));
+    }
+
+    pub fn clear_trace_hook(&mut self) { self.trace_hook = None; }
+
+    /// Number of user function calls in progress; 0 at the top level
+    pub fn call_depth(&self) -> usize { self.frames.len() }
+
+    /// Bound parameters of a call in progress, `depth` 0 being the innermost;
+    /// empty at the top level, where everything is a global
+    pub fn locals(&self, depth: usize) -> Vec<(String, Value)> {
+        let Some(i) = self.frames.len().checked_sub(depth + 1) else { return Vec::new() };
+        self.frame_params[i].iter().zip(&self.frames[i])
+            .filter_map(|(name, v)| Some((name.clone(), v.clone()?)))
+            .collect()
+    }
+
+    /// Name of the source about to run, matched against `set_breakpoint`
+    pub fn set_file(&mut self, file: &str) { self.debug.file = file.to_string(); }
+
+    /// Pause before the statement starting at `line` of `file`
+    pub fn set_breakpoint(&mut self, file: &str, line: usize) {
+        self.debug.breakpoints.entry(file.to_string()).or_default().insert(line);
+    }
+
+    pub fn clear_breakpoints(&mut self) { self.debug.breakpoints.clear(); }
+
+    /// Called on every pause; without a handler breakpoints are ignored
+    pub fn set_debug_handler(&mut self, handler: impl FnMut(&Pause, &VM) -> DebugAction + 'static) {
+        self.debug.handler = Some(Box::new(handler));
+    }
+
+    /// Stop here if stepping or at a breakpoint, and let the handler inspect the VM
+    fn debug_stop(&mut self, line: usize) {
+        let at_breakpoint = self.debug.breakpoints.get(&self.debug.file).is_some_and(|b| b.contains(&line));
+        if !self.debug.stepping && !at_breakpoint { return; }
+        // taken out while it runs: it only gets a shared view of the VM
+        let Some(mut handler) = self.debug.handler.take() else { return };
+        let pause = Pause { file: self.debug.file.clone(), line, breakpoint: at_breakpoint };
+        self.debug.stepping = handler(&pause, self) == DebugAction::Step;
+        self.debug.handler = Some(handler);
+    }
+
+    /// Call `hook` after every program run (`execute_program`, `eval`, or a
+    /// `start`ed program finishing or failing) with the builtins it called and
+    /// the language features it contains. Off until set; nothing is sent anywhere.
+    pub fn set_usage_hook(&mut self, hook: impl FnMut(&crate::usage::Usage) + 'static) {
+        self.usage = Some((crate::usage::Usage::default(), Box::new(hook)));
+    }
+
+    pub fn clear_usage_hook(&mut self) {
+        self.usage = None;
+    }
+
+    /// Hand the finished run's usage to the hook and start counting afresh
+    fn report_usage(&mut self) {
+        if let Some((usage, hook)) = &mut self.usage {
+            hook(usage);
+            *usage = crate::usage::Usage::default();
         }
+    }
+
+    /// Start recording call counts and time per function; see `profile_report`
+    pub fn enable_profiling(&mut self) { self.profiler = Some(Profiler::default()); }
+
+    /// What was recorded since `enable_profiling`, slowest first
+    pub fn profile_report(&self) -> Vec<ProfileEntry> {
+        let Some(p) = &self.profiler else { return Vec::new() };
+        let mut entries: Vec<ProfileEntry> = p.entries.values().cloned().collect();
+        entries.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.name.cmp(&b.name)));
+        entries
+    }
+
+    /// Start recording a span for every call; see `call_spans`
+    pub fn enable_call_trace(&mut self) {
+        self.call_trace = Some(CallTrace { origin: Instant::now(), spans: Vec::new() });
+    }
+
+    /// Source line of the innermost statement that failed in the last
+    /// `execute_program`, for diagnostics; `None` after a successful run
+    pub fn error_line(&self) -> Option<usize> {
+        self.error_line
+    }
+
+    /// Arguments the script was started with, returned by `argv()`
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// `help(name)`: a builtin's signature and summary, or the signature of a
+    /// global function or class; builtins shadow globals, as in calls
+    pub fn help(&self, name: &str) -> Result<String, VmError> {
+        if let Some(b) = self.builtins.get(name) { return Ok(b.help(name)); }
+        Ok(signature_text(name, &self.global(name)?))
+    }
+
+    /// Number of arguments builtin `name` takes, if there is one
+    pub fn builtin_arity(&self, name: &str) -> Option<usize> {
+        self.builtins.get(name).map(|b| b.arity)
+    }
+
+    /// Names of the registered builtins, sorted
+    pub fn builtin_names(&self) -> Vec<&str> {
+        self.builtins.names()
+    }
+
+    /// Code passed to `exit` if the last `execute_program` stopped that way
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// In test mode a failing `assert` / `assert_eq` is recorded and returns
+    /// 0 so the program goes on; `take_failures` collects the records
+    pub fn set_test_mode(&mut self, on: bool) {
+        self.test_mode = on;
+    }
+
+    /// Assertions failed in test mode since the last call, in order
+    pub fn take_failures(&mut self) -> Vec<AssertFailure> {
+        std::mem::take(&mut self.failures)
+    }
+
+    /// Raise or, in test mode, record a failed assertion
+    pub(crate) fn assertion_failed(&mut self, message: String) -> Result<Value, VmError> {
+        if !self.test_mode { return Err(format!("{}: {}", ASSERTION_FAILED, message)); }
+        self.failures.push(AssertFailure { line: self.line, message });
+        Ok(Value::Int(0))
+    }
+
+    /// Record an exit request; the returned error unwinds the program
+    pub(crate) fn request_exit(&mut self, code: i32) -> VmError {
+        self.exit_code = Some(code);
+        format!("exit({})", code)
+    }
+
+    /// Calls recorded since `enable_call_trace`, in the order they finished
+    pub fn call_spans(&self) -> &[CallSpan] {
+        self.call_trace.as_ref().map_or(&[], |t| &t.spans)
+    }
+
+    /// Cut printed values to `limits`; `None` prints them in full
+    pub fn set_display_limits(&mut self, limits: Option<DisplayLimits>) { self.display = limits; }
+
+    /// Print objects without `__str__` with their fields, as `repr` does,
+    /// instead of `<object>`; the REPL turns this on
+    pub fn set_pretty_objects(&mut self, on: bool) { self.pretty_objects = on; }
+
+    /// Restrict the builtins this VM (and threads it spawns) may call
+    pub fn set_policy(&mut self, policy: Policy) { self.policy = policy; }
+
+    pub fn policy(&self) -> Policy { self.policy }
+
+    /// VM that stops a program after `max_steps` statements or `max_millis` of
+    /// wall time (0 = no limit), e.g. for untrusted code from the web editor.
+    /// Its calls also nest at most 100 deep (see `set_call_depth_limit`)
+    pub fn with_limits(max_steps: u64, max_millis: u64) -> Self {
+        let mut vm = Self::new();
+        vm.limits.max_steps = (max_steps > 0).then_some(max_steps);
+        vm.limits.max_time = (max_millis > 0).then(|| Duration::from_millis(max_millis));
+        vm.limits.max_depth = Some(LIMITED_CALL_DEPTH);
+        vm
+    }
+
+    /// Fail calls nested deeper than `max` (0 = no limit, the default of
+    /// `VM::new`, where only the native stack bounds recursion)
+    pub fn set_call_depth_limit(&mut self, max: usize) {
+        self.limits.max_depth = (max > 0).then_some(max);
+    }
+
+    /// Handle for stopping this VM's runs from another thread
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Steps (the unit of `with_limits`) the current or last run has taken
+    pub fn steps(&self) -> u64 {
+        self.limits.steps
+    }
+
+    /// Stop runs with `token` instead of this VM's own, e.g. to keep a Ctrl-C
+    /// handler when a host replaces the VM
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel = token;
+    }
+
+    /// Consume a pending cancel as an error
+    pub(crate) fn check_cancelled(&self) -> Result<(), VmError> {
+        if self.cancel.is_cancelled() {
+            self.cancel.reset();
+            return Err(CANCELLED.to_string());
+        }
+        Ok(())
+    }
+
+    /// Count one step against the budget
+    fn tick(&mut self) -> Result<(), String> {
+        self.check_cancelled()?;
+        let l = &mut self.limits;
+        l.steps += 1;
+        if let Some(max) = l.max_steps && l.steps > max {
+            return Err(format!("step limit of {} exceeded", max));
+        }
+        if let Some(d) = l.deadline && Instant::now() >= d {
+            return Err(format!("time limit of {} ms exceeded", l.max_time.unwrap_or_default().as_millis()));
+        }
+        Ok(())
+    }
+
+    fn push_frame(&mut self, frame: Frame, params: &Rc<[String]>) -> Result<(), String> {
+        if let Some(max) = self.limits.max_depth && self.frames.len() >= max {
+            return Err(format!("call depth limit of {} exceeded", max));
+        }
+        self.frames.push(frame);
+        self.frame_params.push(params.clone());
+        Ok(())
+    }
+    fn pop_frame(&mut self) { self.frames.pop(); self.frame_params.pop(); }
+
+    fn get_local(&self, slot: usize, name: &str) -> Result<Value, String> {
+        match self.frames.last().and_then(|f| f.get(slot)) {
+            Some(Some(v)) => Ok(v.clone()),
+            _ => Err(format!("undefined: {}", name)),
+        }
+    }
+
+    fn get_var(&self, name: &str) -> Option<Value> {
         self.globals.get(name).cloned()
     }
 
+    /// Global by name, or the same error an undefined identifier gives
+    pub fn global(&self, name: &str) -> Result<Value, String> {
+        self.get_var(name).ok_or_else(|| format!("undefined: {}", name))
+    }
+
+    pub fn set_global(&mut self, name: &str, v: Value) {
+        self.note_global(name);
+        self.globals.insert(name.to_string(), v);
+    }
+
     pub fn execute_program(&mut self, prog: Vec<Stmt>) -> Result<Option<Value>, String> {
+        self.begin_run();
+        if let Some((usage, _)) = &mut self.usage { usage.add_program(&prog); }
+        self.limits.deadline = self.limits.max_time.map(|t| Instant::now() + t);
+        let result = self.execute_block(&prog);
+        self.report_usage();
+        result
+    }
+
+    fn begin_run(&mut self) {
+        self.limits.steps = 0;
+        self.debug.stepping = false;
+        self.error_line = None;
+        self.exit_code = None;
+    }
+
+    /// Load a program to run in slices with `run_steps`, so a host can
+    /// interleave several VMs on one thread. Replaces a program already started.
+    pub fn start(&mut self, prog: Program) {
+        self.begin_run();
+        if let Some((usage, _)) = &mut self.usage { *usage = Default::default(); usage.add_program(&prog); }
+        self.pending = Some(Pending { prog, next: 0, last: None });
+    }
+
+    /// Continue the started program, top-level statement by top-level
+    /// statement, and pause before the next one once `n` steps (the unit of
+    /// `with_limits`) are spent or the script called `yield()`.
+    ///
+    /// A slice is not bounded by `n`: the evaluator cannot suspend inside a
+    /// statement, so the statement in progress always finishes first, with
+    /// every call it makes. A program whose work happens inside one statement
+    /// (`main();`) runs to the end in one call. To cap a slice, set
+    /// `with_limits` (the time limit applies to each slice) or cancel it
+    /// through `cancel_token`; either ends the program with an error, as
+    /// any error does.
+    pub fn run_steps(&mut self, n: u64) -> Result<RunState, String> {
+        let Some(mut p) = self.pending.take() else { return Err("run_steps: no program started".to_string()) };
+        let budget = self.limits.steps.saturating_add(n);
+        self.yield_requested = false;
+        self.limits.deadline = self.limits.max_time.map(|t| Instant::now() + t);
+        loop {
+            let Some(stmt) = p.prog.get(p.next) else {
+                self.report_usage();
+                return Ok(RunState::Finished(p.last));
+            };
+            p.last = match self.execute_stmt(stmt) {
+                Ok(v) => v,
+                Err(e) => { self.report_usage(); return Err(e); }
+            };
+            p.next += 1;
+            let yielded = std::mem::take(&mut self.yield_requested);
+            if p.next < p.prog.len() && (yielded || self.limits.steps >= budget) {
+                self.pending = Some(p);
+                return Ok(RunState::Paused);
+            }
+        }
+    }
+
+    /// A program started with `start` has statements left
+    pub fn is_running(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// The group this VM belongs to, for `group_send` and `group_recv`
+    pub(crate) fn group(&self) -> Option<&crate::group::GroupLink> {
+        self.group.as_ref()
+    }
+
+    pub(crate) fn join_group(&mut self, link: crate::group::GroupLink) {
+        self.group = Some(link);
+    }
+
+    /// Ask `run_steps` to pause after the current top-level statement
+    pub(crate) fn request_yield(&mut self) {
+        self.yield_requested = true;
+    }
+
+    /// Parse and run source text; for hosts embedding the language
+    pub fn eval(&mut self, src: &str) -> Result<Option<Value>, crate::Error> {
+        let prog = crate::parser::parse(src)?;
+        self.execute_program(prog).map_err(|message| crate::Error::Runtime { line: self.error_line, message })
+    }
+
+    /// Run a `.usrd` source file or a compiled `.usrdc` artifact
+    pub fn eval_file(&mut self, path: &str) -> Result<Option<Value>, crate::Error> {
+        let io_error = |source| crate::Error::Io { path: Some(path.to_string()), source };
+        if path.ends_with(".usrdc") {
+            let bytes = std::fs::read(path).map_err(io_error)?;
+            let art = crate::artifact::load(&bytes).map_err(crate::Error::Format)?;
+            return self.execute_program(art.program).map_err(|message| crate::Error::Runtime { line: self.error_line, message });
+        }
+        self.eval(&std::fs::read_to_string(path).map_err(io_error)?)
+    }
+
+    fn execute_block(&mut self, stmts: &[Stmt]) -> Result<Option<Value>, String> {
         let mut last = None;
-        for s in prog {
+        for s in stmts {
             last = self.execute_stmt(s)?;
         }
         Ok(last)
     }
 
+    /// Serialize all globals, including objects, lists, classes and functions;
+    /// see `snapshot` for the format
+    pub fn snapshot(&self) -> Vec<u8> {
+        crate::snapshot::encode(&self.globals)
+    }
+
+    /// Replace all globals with the ones saved by `snapshot`
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.globals = crate::snapshot::decode(bytes)?;
+        self.intrinsics_overridden |= self.globals.iter().any(|(name, _)| Intrinsic::from_name(name).is_some());
+        self.method_cache.clear();
+        Ok(())
+    }
+
+    /// All globals with their values, in declaration order
+    pub fn globals_iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.globals.iter()
+    }
+
+    /// One line about a global for inspectors: the signature of a function or
+    /// class, otherwise `name: type = value`
+    pub fn describe(&self, name: &str) -> Option<String> {
+        let v = self.globals.get(name)?;
+        Some(match v {
+            Value::Function(_) | Value::Overloaded(_) | Value::Class(_) => signature_text(name, v),
+            Value::Object(o) => format!("{}: {} = {}", name, o.borrow().class_name, crate::json::inspect(v)),
+            _ => format!("{}: {} = {}", name, v.type_name(), crate::json::inspect(v)),
+        })
+    }
+
     /// Тестовый геттер: вернуть глобальное значение по имени
     pub fn get_global(&self, name: &str) -> Option<Value> {
         self.globals.get(name).cloned()
     }
 
-    fn execute_stmt(&mut self, stmt: Stmt) -> Result<Option<Value>, String> {
+    fn execute_stmt(&mut self, stmt: &Stmt) -> Result<Option<Value>, String> {
+        self.tick()?;
+        if let Some(hook) = self.trace_hook.clone() { hook(stmt, self); }
+        if self.debug.handler.is_some() && let Some(line) = stmt.line() { self.debug_stop(line); }
+        let outer = self.line;
+        if stmt.line().is_some() { self.line = stmt.line(); }
+        let result = self.run_stmt(stmt);
+        self.line = outer;
+        // the innermost statement with a line is where the error happened
+        if result.is_err() && self.error_line.is_none() { self.error_line = stmt.line(); }
+        result
+    }
+
+    fn run_stmt(&mut self, stmt: &Stmt) -> Result<Option<Value>, String> {
         match stmt {
-            Stmt::VarDecl { type_name: _t, name, value } => {
+            Stmt::VarDecl { name, value, .. } => {
                 let v = self.eval_expr(value)?;
-                self.globals.insert(name, v);
+                self.note_global(name);
+                self.globals.insert(name.clone(), v);
                 Ok(None)
             }
-            Stmt::MemberAssign { receiver, name, value } => {
+            Stmt::MemberAssign { receiver, name, value, .. } => {
                 let recv = self.eval_expr(receiver)?;
                 let val = self.eval_expr(value)?;
-                match recv {
-                    Value::Object(o) => {
-                        o.borrow_mut().fields.insert(name, val);
-                        Ok(None)
-                    }
-                    _ => Err("member assignment on non-object".to_string()),
-                }
+                set_field(&recv, name, val)?;
+                Ok(None)
             }
-            Stmt::ExprStmt(e) => {
+            Stmt::ExprStmt { expr: e, .. } => {
                 let v = self.eval_expr(e)?;
-                match &v {
-                    Value::Int(n) => println!("{}", n),
-                    Value::Float(f) => println!("{}", f),
-                    Value::Str(s) => println!("{}", s),
-                    Value::Function(_) => println!("<function>"),
-                    Value::Class(_) => println!("<class>"),
-                    Value::Object(_) => println!("<object>"),
-                }
+                self.print_value(&v)?;
                 Ok(Some(v))
             }
-            Stmt::FunctionDecl { name, params, body } => {
-                let fo = FunctionObject { params, body };
-                self.globals.insert(name, Value::Function(fo));
+            Stmt::FunctionDecl { name, params, param_types, ret_type, body } => {
+                let fo = FunctionObject::new(params.clone(), body.clone()).with_types(param_types.clone(), ret_type.clone());
+                self.declare_function(name, fo);
                 Ok(None)
             }
-            Stmt::ClassDecl { name, body } => {
-                let mut methods = HashMap::new();
-                for s in body {
-                    if let Stmt::FunctionDecl { name: mname, params, body: mb } = s {
-                        methods.insert(mname, FunctionObject { params, body: mb });
-                    }
-                }
-                let cls = ClassObject { name: name.clone(), methods };
-                self.globals.insert(name, Value::Class(cls));
+            Stmt::ClassDecl { name, fields, body, is_data } => {
+                self.declare_class(name, fields, body, *is_data)?;
                 Ok(None)
             }
             _ => Ok(None),
         }
     }
 
-    fn eval_expr(&mut self, expr: Expr) -> Result<Value, String> {
-        match expr {
-            Expr::Int(n) => Ok(Value::Int(n)),
-            Expr::Float(f) => Ok(Value::Float(f)),
-            Expr::Str(s) => Ok(Value::Str(s)),
-            Expr::Ident(name) => {
-                if let Some(v) = self.get_var(&name) { Ok(v) }
-                else {
-                    // debug assistance: print available globals and frames to stderr
-                    eprintln!("VM: undefined identifier '{}' — globals: {:?} — frames count: {}", name, self.globals.keys().collect::<Vec<_>>(), self.frames.len());
-                    return Err(format!("undefined: {}", name));
+    /// A global named like an intrinsic replaces it in calls from then on
+    fn note_global(&mut self, name: &str) {
+        self.intrinsics_overridden |= Intrinsic::from_name(name).is_some();
+    }
+
+    /// Bind `fo` under `name`; a redefinition with another arity adds an overload
+    fn declare_function(&mut self, name: &str, fo: FunctionObject) {
+        self.note_global(name);
+        let val = match self.globals.remove(name) {
+            Some(Value::Function(prev)) if prev.params.len() != fo.params.len() => {
+                Value::Overloaded(vec![prev, fo].into())
+            }
+            Some(Value::Overloaded(set)) => {
+                let mut set: Vec<FunctionObject> = set.iter().filter(|f| f.params.len() != fo.params.len()).cloned().collect();
+                set.push(fo);
+                Value::Overloaded(set.into())
+            }
+            _ => Value::Function(fo),
+        };
+        self.globals.insert(name.to_string(), val);
+    }
+
+    fn declare_class(&mut self, name: &str, fields: &[String], body: &[Stmt], is_data: bool) -> Result<(), String> {
+        self.note_global(name);
+        let mut methods: HashMap<String, FunctionObject> = HashMap::new();
+        for s in body {
+            if let Stmt::FunctionDecl { name: mname, params, param_types, ret_type, body: mb } = s {
+                // methods are looked up by name alone, so unlike functions they have no overloads
+                if methods.get(mname).is_some_and(|prev| prev.params.len() != params.len()) {
+                    return Err(format!("class {}: method {} is declared with two parameter counts; methods cannot be overloaded", name, mname));
                 }
+                let m = FunctionObject::new(params.clone(), mb.clone()).with_types(param_types.clone(), ret_type.clone());
+                methods.insert(mname.clone(), m);
             }
+        }
+        // record header `class Point(x, y)`: generate __init__(self, x, y) unless one is declared
+        if !fields.is_empty() && !methods.contains_key("__init__") {
+            let mut params = vec!["self".to_string()];
+            params.extend(fields.iter().cloned());
+            let body = fields.iter().map(|f| Stmt::MemberAssign {
+                receiver: Expr::Ident("self".to_string()),
+                name: f.clone(),
+                value: Expr::Ident(f.clone()),
+                line: 0,
+            }).collect();
+            methods.insert("__init__".to_string(), FunctionObject::new(params, body));
+        }
+        if is_data {
+            for (mname, m) in data_class_methods(name, fields) {
+                methods.entry(mname.to_string()).or_insert(m);
+            }
+        }
+        let cls = ClassObject { name: name.to_string(), fields: fields.to_vec(), is_data, methods };
+        self.globals.insert(name.to_string(), Value::Class(Rc::new(cls)));
+        Ok(())
+    }
+
+    fn eval_expr(&mut self, expr: &Expr) -> Result<Value, String> {
+        match expr {
+            Expr::Int(n) => Ok(Value::Int(*n)),
+            Expr::Float(f) => Ok(Value::Float(*f)),
+            Expr::Str(s) => Ok(Value::Str(s.clone())),
+            Expr::Local { slot, name } => self.get_local(*slot, name),
+            Expr::Ident(name) => self.global(name),
             Expr::MemberAccess { receiver, field } => {
-                let r = self.eval_expr(*receiver)?;
-                if let Value::Object(o) = r {
-                    if let Some(v) = o.borrow().fields.get(&field) { Ok(v.clone()) }
-                    else { Err(format!("field {} not found", field)) }
-                } else { Err("member access on non-object".to_string()) }
+                let r = self.eval_expr(receiver)?;
+                get_field(&r, field)
             }
             Expr::BinaryOp { left, op, right } => {
-                let l = self.eval_expr(*left)?;
-                let r = self.eval_expr(*right)?;
-                match (l, r, op) {
-                    (Value::Int(a), Value::Int(b), BinOp::Add) => Ok(Value::Int(a + b)),
-                    (Value::Int(a), Value::Int(b), BinOp::Sub) => Ok(Value::Int(a - b)),
-                    (Value::Int(a), Value::Int(b), BinOp::Mul) => Ok(Value::Int(a * b)),
-                    (Value::Int(a), Value::Int(b), BinOp::Div) => Ok(Value::Int(a / b)),
-                    // float cases
-                    (Value::Float(a), Value::Float(b), BinOp::Add) => Ok(Value::Float(a + b)),
-                    (Value::Float(a), Value::Float(b), BinOp::Sub) => Ok(Value::Float(a - b)),
-                    (Value::Float(a), Value::Float(b), BinOp::Mul) => Ok(Value::Float(a * b)),
-                    (Value::Float(a), Value::Float(b), BinOp::Div) => Ok(Value::Float(a / b)),
-                    // mixed int/float
-                    (Value::Int(a), Value::Float(b), BinOp::Add) => Ok(Value::Float((a as f64) + b)),
-                    (Value::Float(a), Value::Int(b), BinOp::Add) => Ok(Value::Float(a + (b as f64))),
-                    (Value::Int(a), Value::Float(b), BinOp::Sub) => Ok(Value::Float((a as f64) - b)),
-                    (Value::Float(a), Value::Int(b), BinOp::Sub) => Ok(Value::Float(a - (b as f64))),
-                    (Value::Int(a), Value::Float(b), BinOp::Mul) => Ok(Value::Float((a as f64) * b)),
-                    (Value::Float(a), Value::Int(b), BinOp::Mul) => Ok(Value::Float(a * (b as f64))),
-                    (Value::Int(a), Value::Float(b), BinOp::Div) => Ok(Value::Float((a as f64) / b)),
-                    (Value::Float(a), Value::Int(b), BinOp::Div) => Ok(Value::Float(a / (b as f64))),
-                    _ => Err("type error in binary op".to_string()),
-                }
+                let l = self.eval_expr(left)?;
+                let r = self.eval_expr(right)?;
+                binary_op(l, r, op)
             }
-            Expr::Call { func, args } => {
+            Expr::Call { func, args, line } => {
                 // calling a function or a class constructor by identifier
-                match *func {
-                    Expr::Ident(fname) => {
-                        // Builtins: get(prompt) -> String, to_int(x) -> Int, apply_op(a,b,op) -> Int
-                        if fname == "get" {
-                            if args.len() != 1 { return Err("get requires one argument".to_string()); }
-                            let p = self.eval_expr(args[0].clone())?;
-                            let prompt = match p {
-                                Value::Str(s) => s,
-                                Value::Int(n) => n.to_string(),
-                                _ => return Err("get: prompt must be string or int".to_string()),
-                            };
-                            print!("{}", prompt);
-                            let _ = io::stdout().flush();
-                            let mut line = String::new();
-                            io::stdin().read_line(&mut line).map_err(|e| e.to_string())?;
-                            let s = line.trim().to_string();
-                            return Ok(Value::Str(s));
-                        }
-                        if fname == "to_int" {
-                            if args.len() != 1 { return Err("to_int requires one argument".to_string()); }
-                            let v = self.eval_expr(args[0].clone())?;
-                            match v {
-                                Value::Int(n) => return Ok(Value::Int(n)),
-                                Value::Str(s) => {
-                                    let parsed = s.trim().parse::<i64>().map_err(|_| "to_int: parse error".to_string())?;
-                                    return Ok(Value::Int(parsed));
-                                }
-                                _ => return Err("to_int: unsupported argument type".to_string()),
-                            }
-                        }
-                        if fname == "to_float" {
-                            if args.len() != 1 { return Err("to_float requires one argument".to_string()); }
-                            let v = self.eval_expr(args[0].clone())?;
-                            match v {
-                                Value::Float(n) => return Ok(Value::Float(n)),
-                                Value::Int(n) => return Ok(Value::Float(n as f64)),
-                                Value::Str(s) => {
-                                    let parsed = s.trim().parse::<f64>().map_err(|_| "to_float: parse error".to_string())?;
-                                    return Ok(Value::Float(parsed));
-                                }
-                                _ => return Err("to_float: unsupported argument type".to_string()),
+                match &**func {
+                    Expr::Ident(fname) if fname == "help" => {
+                        // help(fn_or_class) or help("name") -> signature text; needs the name, not just the value
+                        match args.as_slice() {
+                            [Expr::Ident(target) | Expr::Str(target)] => Ok(Value::Str(self.help(target)?)),
+                            [arg @ Expr::Local { name: target, .. }] => {
+                                let v = self.eval_expr(arg)?;
+                                Ok(Value::Str(signature_text(target, &v)))
                             }
+                            _ => Err("help requires one name".to_string()),
                         }
-                        if fname == "apply_op" {
-                            if args.len() != 3 { return Err("apply_op requires three arguments".to_string()); }
-                            let a = self.eval_expr(args[0].clone())?;
-                            let b = self.eval_expr(args[1].clone())?;
-                            let opv = self.eval_expr(args[2].clone())?;
-                            let ai = if let Value::Int(n) = a { n } else { return Err("apply_op: arg a must be int".to_string()) };
-                            let bi = if let Value::Int(n) = b { n } else { return Err("apply_op: arg b must be int".to_string()) };
-                            let oc = if let Value::Int(n) = opv { n } else { return Err("apply_op: op must be int".to_string()) };
-                            let res = match oc {
-                                1 => Value::Int(ai + bi),
-                                2 => Value::Int(ai - bi),
-                                3 => Value::Int(ai * bi),
-                                4 => Value::Int(ai / bi),
-                                _ => return Err("apply_op: unknown op code".to_string()),
-                            };
-                            return Ok(res);
-                        }
-                        // GUI builtins (stubs): gui_window(title, w, h) -> Object, gui_label(win, text), gui_show(win)
-                        if fname == "gui_window" {
-                            if args.len() != 3 { return Err("gui_window requires 3 arguments".to_string()); }
-                            let t = self.eval_expr(args[0].clone())?;
-                            let wv = self.eval_expr(args[1].clone())?;
-                            let hv = self.eval_expr(args[2].clone())?;
-                            let _title = match t { Value::Str(s) => s, Value::Int(n) => n.to_string(), _ => "window".to_string() };
-                            let _w = if let Value::Int(n) = wv { n as u32 } else { 400 };
-                            let _h = if let Value::Int(n) = hv { n as u32 } else { 300 };
-                            // call platform-specific window creation when available
-                            let title = _title;
-                            let wid = {
-                                #[cfg(target_os = "windows")]
-                                {
-                                    crate::platform::windows::create_window(&title, _w as i32, _h as i32) as i64
-                                }
-                                #[cfg(not(target_os = "windows"))]
-                                { 0i64 }
-                            };
-                            return Ok(Value::Int(wid));
-                        }
-                        if fname == "gui_blit_b64" {
-                            // gui_blit_b64(id, b64str, w, h)
-                            if args.len() != 4 { return Err("gui_blit_b64 requires 4 arguments".to_string()); }
-                            let idv = self.eval_expr(args[0].clone())?;
-                            let b64v = self.eval_expr(args[1].clone())?;
-                            let wv = self.eval_expr(args[2].clone())?;
-                            let hv = self.eval_expr(args[3].clone())?;
-                            let id = if let Value::Int(n) = idv { n as u64 } else { return Err("gui_blit_b64: id must be int".to_string()) };
-                            let b64s = if let Value::Str(s) = b64v { s } else { return Err("gui_blit_b64: data must be string".to_string()) };
-                            let w = if let Value::Int(n) = wv { n as i32 } else { return Err("gui_blit_b64: w must be int".to_string()) };
-                            let h = if let Value::Int(n) = hv { n as i32 } else { return Err("gui_blit_b64: h must be int".to_string()) };
-                            // decode base64 (simple implementation)
-                            fn decode_b64(s: &str) -> Result<Vec<u8>, String> {
-                                let mut out = Vec::new();
-                                let mut bits: u32 = 0;
-                                let mut count: u8 = 0;
-                                for ch in s.chars() {
-                                    let val = match ch {
-                                        'A'..='Z' => (ch as u8 - b'A') as i32,
-                                        'a'..='z' => (ch as u8 - b'a' + 26) as i32,
-                                        '0'..='9' => (ch as u8 - b'0' + 52) as i32,
-                                        '+' => 62,
-                                        '/' => 63,
-                                        '=' => { break; }
-                                        _ => { continue; }
-                                    } as u32;
-                                    bits = (bits << 6) | val;
-                                    count += 6;
-                                    while count >= 8 {
-                                        count -= 8;
-                                        let b = ((bits >> count) & 0xFF) as u8;
-                                        out.push(b);
-                                    }
-                                }
-                                Ok(out)
-                            }
-                            let bytes = decode_b64(&b64s)?;
-                            #[cfg(target_os = "windows")]
-                            {
-                                crate::platform::windows::blit_window(id, bytes, w, h).map_err(|e| e.to_string())?;
-                                return Ok(Value::Int(1));
-                            }
-                            #[cfg(not(target_os = "windows"))]
-                            {
-                                return Ok(Value::Int(0));
-                            }
-                        }
-                        if fname == "draw_rect" {
-                            // draw_rect(id, canvas_w, canvas_h, x,y,w,h, r,g,b,a)
-                            if args.len() != 10 { return Err("draw_rect requires 10 arguments".to_string()); }
-                            let idv = self.eval_expr(args[0].clone())?;
-                            let _canvas_w = if let Value::Int(n) = self.eval_expr(args[1].clone())? { n as i32 } else { return Err("draw_rect: canvas_w must be int".to_string()) };
-                            let _canvas_h = if let Value::Int(n) = self.eval_expr(args[2].clone())? { n as i32 } else { return Err("draw_rect: canvas_h must be int".to_string()) };
-                            let x = if let Value::Int(n) = self.eval_expr(args[3].clone())? { n as i32 } else { return Err("draw_rect: x must be int".to_string()) };
-                            let y = if let Value::Int(n) = self.eval_expr(args[4].clone())? { n as i32 } else { return Err("draw_rect: y must be int".to_string()) };
-                            let w = if let Value::Int(n) = self.eval_expr(args[5].clone())? { n as i32 } else { return Err("draw_rect: w must be int".to_string()) };
-                            let h = if let Value::Int(n) = self.eval_expr(args[6].clone())? { n as i32 } else { return Err("draw_rect: h must be int".to_string()) };
-                            let r = if let Value::Int(n) = self.eval_expr(args[7].clone())? { n as u8 } else { return Err("draw_rect: r must be int".to_string()) };
-                            let g = if let Value::Int(n) = self.eval_expr(args[8].clone())? { n as u8 } else { return Err("draw_rect: g must be int".to_string()) };
-                            let b = if let Value::Int(n) = self.eval_expr(args[9].clone())? { n as u8 } else { return Err("draw_rect: b must be int".to_string()) };
-                            let a = 255u8;
-                            let id = if let Value::Int(n) = idv { n as u64 } else { return Err("draw_rect: id must be int".to_string()) };
-                            #[cfg(target_os = "windows")]
-                            {
-                                crate::platform::windows::canvas_draw_rect(id, x, y, w, h, r, g, b, a).map_err(|e| e.to_string())?;
-                                return Ok(Value::Int(1));
-                            }
-                            #[cfg(not(target_os = "windows"))]
-                            {
-                                // Fallback: construct full buffer (slow) and try to blit if platform supports it; otherwise no-op
-                                let canvas_w = _canvas_w as i32;
-                                let canvas_h = _canvas_h as i32;
-                                let wsz = (canvas_w as usize).saturating_mul(canvas_h as usize).saturating_mul(4);
-                                if canvas_w <= 0 || canvas_h <= 0 { return Err("draw_rect: invalid canvas size".to_string()) }
-                                let mut buf = vec![0u8; wsz];
-                                for yy in 0..canvas_h {
-                                    for xx in 0..canvas_w {
-                                        let px = xx;
-                                        let py = yy;
-                                        if px >= x && px < x + w && py >= y && py < y + h {
-                                            let idx = ((py as usize) * (canvas_w as usize) + (px as usize)) * 4;
-                                            buf[idx+0] = r;
-                                            buf[idx+1] = g;
-                                            buf[idx+2] = b;
-                                            buf[idx+3] = a;
-                                        }
-                                    }
-                                }
-                                return Ok(Value::Int(0));
-                            }
+                    }
+                    Expr::Ident(fname) => {
+                        let mut avals = Vec::new();
+                        for a in args { avals.push(self.eval_expr(a)?); }
+                        // builtins live in their own function so user recursion does not
+                        // carry their stack frame
+                        if self.builtins.contains(fname) { return self.profiled(fname, |vm| vm.call_builtin(fname, &avals)); }
+                        let val = self.get_var(fname).ok_or_else(|| format!("undefined function/class {}", fname))?;
+                        traced(self.profiled(fname, |vm| vm.call_value(fname, val, avals)), fname, *line)
+                    }
+                    // a parameter holding a function or class
+                    Expr::Local { slot, name } => {
+                        let val = self.get_local(*slot, name)?;
+                        let mut avals = Vec::new();
+                        for a in args { avals.push(self.eval_expr(a)?); }
+                        traced(self.profiled(name, |vm| vm.call_value(name, val, avals)), name, *line)
+                    }
+                    _ => Err("call of non-identifier not supported".to_string()),
+                }
+            }
+            Expr::Intrinsic { op, args, line } => {
+                let mut avals = Vec::with_capacity(args.len());
+                for a in args { avals.push(self.eval_expr(a)?); }
+                traced(self.call_intrinsic(*op, &avals), op.name(), *line)
+            }
+            Expr::MemberCall { receiver, method, args, line, site } => {
+                let recv = self.eval_expr(receiver)?;
+                let mut avals = Vec::new();
+                for a in args { avals.push(self.eval_expr(a)?); }
+                let res = match &recv {
+                    Value::Object(o) => match self.cached_method(*site, o, method) {
+                        Some(m) if self.profiler.is_some() || self.call_trace.is_some() => {
+                            let name = format!("{}.{}", o.borrow().class_name, method);
+                            self.profiled(&name, |vm| vm.invoke_method(o, &m, &avals))
                         }
+                        Some(m) => self.invoke_method(o, &m, &avals),
+                        None => Err(format!("method {} not found", method)),
+                    },
+                    Value::Host(h) => crate::host::call_method(self, h, method, &avals),
+                    _ => Err("member call on non-object".to_string()),
+                };
+                match &recv {
+                    Value::Object(o) if res.is_err() => traced(res, &format!("{}.{}", o.borrow().class_name, method), *line),
+                    Value::Host(_) if res.is_err() => traced(res, &format!("{}.{}", recv.type_name(), method), *line),
+                    _ => res,
+                }
+            }
+        }
+    }
 
-                        if fname == "secure_random" {
-                            if args.len() != 1 { return Err("secure_random requires 1 argument".to_string()); }
-                            let maxv = self.eval_expr(args[0].clone())?;
-                            let max = if let Value::Int(n) = maxv { if n <= 0 { return Err("secure_random: max must be >0".to_string()) } else { n as u64 } } else { return Err("secure_random: max must be int".to_string()) };
-                            let r = crate::rand::secure_random_u64(max).map_err(|e| e.to_string())?;
-                            return Ok(Value::Int(r as i64));
-                        }
-                        if fname == "canvas_clear" {
-                            // canvas_clear(id, r,g,b,a)
-                            if args.len() != 5 { return Err("canvas_clear requires 5 arguments".to_string()); }
-                            let idv = self.eval_expr(args[0].clone())?;
-                            let r = if let Value::Int(n) = self.eval_expr(args[1].clone())? { n as u8 } else { return Err("canvas_clear: r must be int".to_string()) };
-                            let g = if let Value::Int(n) = self.eval_expr(args[2].clone())? { n as u8 } else { return Err("canvas_clear: g must be int".to_string()) };
-                            let b = if let Value::Int(n) = self.eval_expr(args[3].clone())? { n as u8 } else { return Err("canvas_clear: b must be int".to_string()) };
-                            let a = if let Value::Int(n) = self.eval_expr(args[4].clone())? { n as u8 } else { return Err("canvas_clear: a must be int".to_string()) };
-                            let id = if let Value::Int(n) = idv { n as u64 } else { return Err("canvas_clear: id must be int".to_string()) };
-                            #[cfg(target_os = "windows")] { crate::platform::windows::canvas_clear(id, r,g,b,a).map_err(|e| e.to_string())?; return Ok(Value::Int(1)); }
-                            #[cfg(not(target_os = "windows"))] { return Ok(Value::Int(0)); }
-                        }
+    /// Run `call`, counting it under `name` when profiling and recording its
+    /// span when tracing calls
+    fn profiled(&mut self, name: &str, call: impl FnOnce(&mut Self) -> Result<Value, String>) -> Result<Value, String> {
+        if self.profiler.is_none() && self.call_trace.is_none() { return call(self); }
+        // recursive calls are counted, but only the outermost one adds time
+        let outermost = self.profiler.as_mut().is_some_and(|p| {
+            let active = p.active.entry(name.to_string()).or_default();
+            *active += 1;
+            *active == 1
+        });
+        let depth = self.frames.len();
+        let start = Instant::now();
+        let res = call(self);
+        let elapsed = start.elapsed();
+        if let Some(p) = &mut self.profiler {
+            if let Some(n) = p.active.get_mut(name) { *n -= 1; }
+            let e = p.entries.entry(name.to_string()).or_insert_with(|| ProfileEntry { name: name.to_string(), ..Default::default() });
+            e.calls += 1;
+            if outermost { e.total += elapsed; }
+        }
+        if let Some(t) = &mut self.call_trace {
+            t.spans.push(CallSpan { name: name.to_string(), start: start - t.origin, duration: elapsed, depth });
+        }
+        res
+    }
 
-                        if fname == "canvas_present" {
-                            if args.len() != 1 { return Err("canvas_present requires 1 argument".to_string()); }
-                            let idv = self.eval_expr(args[0].clone())?;
-                            let id = if let Value::Int(n) = idv { n as u64 } else { return Err("canvas_present: id must be int".to_string()) };
-                            #[cfg(target_os = "windows")] { crate::platform::windows::canvas_present(id).map_err(|e| e.to_string())?; return Ok(Value::Int(1)); }
-                            #[cfg(not(target_os = "windows"))] { return Ok(Value::Int(0)); }
-                        }
+    /// Run a registered builtin with evaluated args, after the policy and arity checks
+    #[inline(never)]
+    fn call_builtin(&mut self, fname: &str, args: &[Value]) -> Result<Value, VmError> {
+        let b = self.builtins.get(fname).cloned().ok_or_else(|| format!("undefined function/class {}", fname))?;
+        if let Some(cap) = b.capability && !self.policy.allows(cap) {
+            return Err(format!("{} is not allowed by the sandbox policy ({:?})", fname, cap));
+        }
+        if !b.accepts(args.len()) {
+            return Err(format!("{} requires {} argument{}", fname, b.arity, if b.arity == 1 { "" } else { "s" }));
+        }
+        if let Some((usage, _)) = &mut self.usage { usage.called(fname); }
+        (b.func)(self, args)
+    }
 
-                        if fname == "canvas_draw_text" {
-                            // canvas_draw_text(id, x, y, text)
-                            if args.len() != 4 { return Err("canvas_draw_text requires 4 arguments".to_string()); }
-                            let idv = self.eval_expr(args[0].clone())?;
-                            let x = if let Value::Int(n) = self.eval_expr(args[1].clone())? { n as i32 } else { return Err("canvas_draw_text: x must be int".to_string()) };
-                            let y = if let Value::Int(n) = self.eval_expr(args[2].clone())? { n as i32 } else { return Err("canvas_draw_text: y must be int".to_string()) };
-                            let tv = self.eval_expr(args[3].clone())?;
-                            let text = if let Value::Str(s) = tv { s } else { return Err("canvas_draw_text: text must be string".to_string()) };
-                            let id = if let Value::Int(n) = idv { n as u64 } else { return Err("canvas_draw_text: id must be int".to_string()) };
-                            #[cfg(target_os = "windows")] { crate::platform::windows::canvas_draw_text(id, x, y, &text).map_err(|e| e.to_string())?; return Ok(Value::Int(1)); }
-                            #[cfg(not(target_os = "windows"))] { return Ok(Value::Int(0)); }
-                        }
-                        if fname == "register_widget" {
-                            // register_widget(win_id, x, y, w, h, handler_name)
-                            if args.len() != 6 { return Err("register_widget requires 6 arguments".to_string()); }
-                            let idv = self.eval_expr(args[0].clone())?;
-                            let x = if let Value::Int(n) = self.eval_expr(args[1].clone())? { n as i32 } else { return Err("register_widget: x must be int".to_string()) };
-                            let y = if let Value::Int(n) = self.eval_expr(args[2].clone())? { n as i32 } else { return Err("register_widget: y must be int".to_string()) };
-                            let w = if let Value::Int(n) = self.eval_expr(args[3].clone())? { n as i32 } else { return Err("register_widget: w must be int".to_string()) };
-                            let h = if let Value::Int(n) = self.eval_expr(args[4].clone())? { n as i32 } else { return Err("register_widget: h must be int".to_string()) };
-                            let hv = self.eval_expr(args[5].clone())?;
-                            let handler = if let Value::Str(s) = hv { s } else { return Err("register_widget: handler must be string".to_string()) };
-                            let id = if let Value::Int(n) = idv { n as u64 } else { return Err("register_widget: id must be int".to_string()) };
-                            #[cfg(target_os = "windows")] { crate::platform::windows::register_widget(id, x, y, w, h, &handler); return Ok(Value::Int(1)); }
-                            #[cfg(not(target_os = "windows"))] { return Ok(Value::Int(0)); }
-                        }
-                        if fname == "gui_button" {
-                            // gui_button(win_id, label, handler_name)
-                            if args.len() != 3 { return Err("gui_button requires 3 arguments".to_string()); }
-                            let idv = self.eval_expr(args[0].clone())?;
-                            let _labelv = self.eval_expr(args[1].clone())?;
-                            let handlerv = self.eval_expr(args[2].clone())?;
-                            let id = if let Value::Int(n) = idv { n as u64 } else { return Err("gui_button: id must be int".to_string()) };
-                            let handler_name = if let Value::Str(s) = handlerv { s } else { return Err("gui_button: handler must be string".to_string()) };
-                            #[cfg(target_os = "windows")] {
-                                // register a widget using simple auto layout and handler name
-                                crate::platform::windows::register_widget_auto(id, "button", &handler_name);
-                            }
-                            return Ok(Value::Int(1));
-                        }
-                        if fname == "gui_poll" {
-                            // poll events and invoke registered handlers
-                            #[cfg(target_os = "windows")] {
-                                let evs = crate::platform::windows::drain_events();
-                                for (win_id, (x,y)) in evs {
-                                    if let Some(hname) = crate::platform::windows::get_handler(win_id) {
-                                        if let Some(Value::Function(fobj)) = self.get_var(&hname) {
-                                            // call handler with x,y
-                                            self.push_frame();
-                                            if fobj.params.len() >= 1 { self.set_local(fobj.params[0].clone(), Value::Int(x as i64)); }
-                                            if fobj.params.len() >= 2 { self.set_local(fobj.params[1].clone(), Value::Int(y as i64)); }
-                                            let _ = self.execute_program(fobj.body.clone())?;
-                                            self.pop_frame();
-                                        }
-                                    }
-                                }
-                                return Ok(Value::Int(1));
-                            }
-                            #[cfg(not(target_os = "windows"))] { return Ok(Value::Int(0)); }
-                        }
-                        if fname == "gui_run" {
-                            // run loop: keep polling events while windows exist
-                            #[cfg(target_os = "windows")] {
-                                while crate::platform::windows::has_windows() {
-                                    let evs = crate::platform::windows::drain_events();
-                                    for (win_id, (x,y)) in evs {
-                                        if let Some(hname) = crate::platform::windows::get_handler(win_id) {
-                                            if let Some(Value::Function(fobj)) = self.get_var(&hname) {
-                                                self.push_frame();
-                                                if fobj.params.len() >= 1 { self.set_local(fobj.params[0].clone(), Value::Int(x as i64)); }
-                                                if fobj.params.len() >= 2 { self.set_local(fobj.params[1].clone(), Value::Int(y as i64)); }
-                                                let _ = self.execute_program(fobj.body.clone())?;
-                                                self.pop_frame();
-                                            }
-                                        }
-                                    }
-                                    // small sleep
-                                    std::thread::sleep(std::time::Duration::from_millis(20));
-                                }
-                                return Ok(Value::Int(1));
-                            }
-                            #[cfg(not(target_os = "windows"))] { return Ok(Value::Int(0)); }
-                        }
-                        if fname == "gui_close" {
-                            if args.len() != 1 { return Err("gui_close requires 1 argument".to_string()) }
-                            let idv = self.eval_expr(args[0].clone())?;
-                            let id = if let Value::Int(n) = idv { n as u64 } else { return Err("gui_close: id must be int".to_string()) };
-                            #[cfg(target_os = "windows")] { crate::platform::windows::close_window(id); }
-                            return Ok(Value::Int(1));
-                        }
-                        if fname == "gui_label" {
-                            if args.len() != 2 { return Err("gui_label requires 2 arguments".to_string()); }
-                            let objv = self.eval_expr(args[0].clone())?;
-                            let txtv = self.eval_expr(args[1].clone())?;
-                            let text = match txtv { Value::Str(s) => s, Value::Int(n) => n.to_string(), _ => "".to_string() };
-                            if let Value::Object(o) = objv {
-                                o.borrow_mut().fields.insert("label".to_string(), Value::Str(text));
-                                return Ok(Value::Int(1));
-                            }
-                            return Err("gui_label: first arg must be a Window object".to_string());
-                        }
-                        if fname == "gui_show" {
-                            if args.len() != 1 { return Err("gui_show requires 1 argument".to_string()); }
-                            let objv = self.eval_expr(args[0].clone())?;
-                            if let Value::Object(_o) = objv {
-                                // no-op placeholder; real implementation will present the window
-                                return Ok(Value::Int(1));
-                            }
-                            return Err("gui_show: arg must be a Window object".to_string());
-                        }
-                        if fname == "gui_message" {
-                            if args.len() != 2 { return Err("gui_message requires 2 arguments".to_string()); }
-                            let t = self.eval_expr(args[0].clone())?;
-                            let m = self.eval_expr(args[1].clone())?;
-                            let title = match t { Value::Str(s) => s, Value::Int(n) => n.to_string(), _ => "".to_string() };
-                            let text = match m { Value::Str(s) => s, Value::Int(n) => n.to_string(), _ => "".to_string() };
-                            #[cfg(target_os = "windows")]
-                            {
-                                crate::platform::windows::show_message(&title, &text);
-                                return Ok(Value::Int(1));
-                            }
-                            #[cfg(not(target_os = "windows"))]
-                            {
-                                // fallback to printing on other platforms
-                                println!("{}: {}", title, text);
-                                return Ok(Value::Int(1));
-                            }
-                        }
-                        if fname == "sleep_ms" {
-                            // sleep_ms(ms)
-                            if args.len() != 1 { return Err("sleep_ms requires 1 argument".to_string()); }
-                            let v = self.eval_expr(args[0].clone())?;
-                            let ms = if let Value::Int(n) = v { n } else { return Err("sleep_ms: arg must be int".to_string()) };
-                            thread::sleep(Duration::from_millis(ms as u64));
-                            return Ok(Value::Int(1));
-                        }
-                        if fname == "spawn" {
-                            // spawn(function_name)
-                            if args.len() != 1 { return Err("spawn requires 1 argument".to_string()); }
-                            let nv = self.eval_expr(args[0].clone())?;
-                            let fname = if let Value::Str(s) = nv { s } else { return Err("spawn: arg must be string".to_string()) };
-                            // find function in current globals
-                            if let Some(Value::Function(fobj)) = self.get_var(&fname) {
-                                let fclone = fobj.clone();
-                                // spawn thread and execute function body in fresh VM instance
-                                thread::spawn(move || {
-                                    let mut vm2 = VM::new();
-                                    // run function body (no args / minimal environment)
-                                    let _ = vm2.execute_program(fclone.body.clone());
-                                });
-                                return Ok(Value::Int(1));
-                            } else {
-                                return Err("spawn: function not found".to_string());
-                            }
-                        }
-                        if fname == "channel_create" {
-                            // channel_create() -> id (creates primary channel with one receiver)
-                            let id = CH_NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                            let (tx, rx) = mpsc::channel::<String>();
-                            if let Ok(mut s) = ch_senders().lock() { s.insert(id, tx); }
-                            if let Ok(mut r) = ch_receivers().lock() { r.insert(id, rx); }
-                            return Ok(Value::Int(id as i64));
-                        }
-                        if fname == "channel_send" {
-                            // channel_send(id, text) -> 1 on success
-                            if args.len() != 2 { return Err("channel_send requires 2 arguments".to_string()); }
-                            let idv = self.eval_expr(args[0].clone())?;
-                            let tv = self.eval_expr(args[1].clone())?;
-                            let id = if let Value::Int(n) = idv { n as u64 } else { return Err("channel_send: id must be int".to_string()) };
-                            let s = if let Value::Str(st) = tv { st } else { return Err("channel_send: text must be string".to_string()) };
-                            let mut sent = false;
-                            if let Ok(map) = ch_senders().lock() {
-                                if let Some(tx) = map.get(&id) {
-                                    let _ = tx.send(s.clone());
-                                    sent = true;
-                                }
-                            }
-                            // send to broadcast subscribers if any
-                            if let Ok(bmap) = ch_bcast().lock() {
-                                if let Some(list) = bmap.get(&id) {
-                                    for (_subid, tx) in list.iter() {
-                                        let _ = tx.send(s.clone());
-                                        sent = true;
-                                    }
-                                }
-                            }
-                            if sent { return Ok(Value::Int(1)); }
-                            return Err("channel_send: channel not found".to_string());
-                        }
-                        if fname == "channel_try_recv" {
-                            // channel_try_recv(id) -> object { ok:1, msg: "..." } or { ok:0 }
-                            if args.len() != 1 { return Err("channel_try_recv requires 1 argument".to_string()); }
-                            let idv = self.eval_expr(args[0].clone())?;
-                            let id = if let Value::Int(n) = idv { n as u64 } else { return Err("channel_try_recv: id must be int".to_string()) };
-                            if let Ok(mut map) = ch_receivers().lock() {
-                                if let Some(rx) = map.get_mut(&id) {
-                                    match rx.try_recv() {
-                                        Ok(s) => {
-                                            // build Result object { ok:1, msg: s }
-                                            let mut fields = HashMap::new();
-                                            fields.insert("ok".to_string(), Value::Int(1));
-                                            fields.insert("msg".to_string(), Value::Str(s));
-                                            let obj = Rc::new(RefCell::new(Object { class_name: "Result".to_string(), fields, methods: HashMap::new() }));
-                                            return Ok(Value::Object(obj));
-                                        }
-                                        Err(mpsc::TryRecvError::Empty) => {
-                                            let mut fields = HashMap::new();
-                                            fields.insert("ok".to_string(), Value::Int(0));
-                                            let obj = Rc::new(RefCell::new(Object { class_name: "Result".to_string(), fields, methods: HashMap::new() }));
-                                            return Ok(Value::Object(obj));
-                                        }
-                                        Err(_) => return Err("channel_try_recv: receive error".to_string()),
-                                    }
-                                }
-                            }
-                            return Err("channel_try_recv: channel not found".to_string());
-                        }
-                        if fname == "channel_recv" {
-                            // channel_recv(id) -> blocks until message (returns string)
-                            if args.len() != 1 { return Err("channel_recv requires 1 argument".to_string()); }
-                            let idv = self.eval_expr(args[0].clone())?;
-                            let id = if let Value::Int(n) = idv { n as u64 } else { return Err("channel_recv: id must be int".to_string()) };
-                            if let Ok(mut map) = ch_receivers().lock() {
-                                if let Some(rx) = map.get_mut(&id) {
-                                    match rx.recv() {
-                                        Ok(s) => return Ok(Value::Str(s)),
-                                        Err(_) => return Err("channel_recv: receive error".to_string()),
-                                    }
-                                }
-                            }
-                            return Err("channel_recv: channel not found".to_string());
-                        }
-                        if fname == "channel_subscribe" {
-                            // channel_subscribe(channel_id) -> subscriber_id
-                            if args.len() != 1 { return Err("channel_subscribe requires 1 argument".to_string()); }
-                            let idv = self.eval_expr(args[0].clone())?;
-                            let chid = if let Value::Int(n) = idv { n as u64 } else { return Err("channel_subscribe: id must be int".to_string()) };
-                            // create new tx/rx pair for subscriber
-                            let sub_id = CH_NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                            let (tx, rx) = mpsc::channel::<String>();
-                            // register receiver under sub_id
-                            if let Ok(mut rmap) = ch_receivers().lock() { rmap.insert(sub_id, rx); }
-                            // register sender in bcast list
-                            if let Ok(mut bmap) = ch_bcast().lock() {
-                                bmap.entry(chid).or_insert_with(Vec::new).push((sub_id, tx));
-                            }
-                            // remember mapping
-                            if let Ok(mut m) = sub_to_channel().lock() { m.insert(sub_id, chid); }
-                            return Ok(Value::Int(sub_id as i64));
-                        }
-                        if fname == "channel_close" {
-                            // channel_close(id) - closes channel or subscriber and cleans resources
-                            if args.len() != 1 { return Err("channel_close requires 1 argument".to_string()); }
-                            let idv = self.eval_expr(args[0].clone())?;
-                            let id = if let Value::Int(n) = idv { n as u64 } else { return Err("channel_close: id must be int".to_string()) };
-                            // first, if it's a primary channel
-                            if let Ok(mut smap) = ch_senders().lock() {
-                                if smap.remove(&id).is_some() {
-                                    // remove primary receiver too
-                                    if let Ok(mut rmap) = ch_receivers().lock() { rmap.remove(&id); }
-                                    // remove and cleanup broadcast subscribers
-                                    if let Ok(mut bmap) = ch_bcast().lock() {
-                                        if let Some(list) = bmap.remove(&id) {
-                                            for (subid, _tx) in list {
-                                                if let Ok(mut rmap) = ch_receivers().lock() { rmap.remove(&subid); }
-                                                if let Ok(mut m) = sub_to_channel().lock() { m.remove(&subid); }
-                                            }
-                                        }
-                                    }
-                                    return Ok(Value::Int(1));
-                                }
-                            }
-                            // if it's a subscriber or receiver id
-                            if let Ok(mut rmap) = ch_receivers().lock() {
-                                if rmap.remove(&id).is_some() {
-                                    // if subscriber, remove its sender from bcast list
-                                    if let Ok(mut m) = sub_to_channel().lock() {
-                                        if let Some(chid) = m.remove(&id) {
-                                            if let Ok(mut bmap) = ch_bcast().lock() {
-                                                if let Some(list) = bmap.get_mut(&chid) {
-                                                    list.retain(|(sid, _)| *sid != id);
-                                                }
-                                            }
-                                        }
-                                    }
-                                    return Ok(Value::Int(1));
-                                }
-                            }
-                            return Err("channel_close: id not found".to_string());
-                        }
-                        if fname == "set_theme" {
-                            // set_theme(name)
-                            if args.len() != 1 { return Err("set_theme requires 1 argument".to_string()); }
-                            let nv = self.eval_expr(args[0].clone())?;
-                            let name = if let Value::Str(s) = nv { s } else { return Err("set_theme: arg must be string".to_string()) };
-                            #[cfg(target_os = "windows")] { crate::platform::windows::set_theme(&name); }
-                            return Ok(Value::Int(1));
-                        }
-                        let val = self.get_var(&fname).ok_or_else(|| format!("undefined function/class {}", fname))?;
-                        match val {
-                            Value::Function(fobj) => {
-                                if fobj.params.len() != args.len() { return Err("arg count mismatch".to_string()); }
-                                // evaluate args first
-                                let mut avals = Vec::new();
-                                for a in &args { avals.push(self.eval_expr(a.clone())?); }
-                                self.push_frame();
-                                for (i, p) in fobj.params.iter().enumerate() {
-                                    let aval = avals[i].clone();
-                                    self.set_local(p.clone(), aval);
-                                }
-                                let res = self.execute_program(fobj.body.clone())?;
-                                self.pop_frame();
-                                Ok(res.unwrap_or(Value::Int(0)))
-                            }
-                            Value::Class(cobj) => {
-                                // construct object: copy class methods
-                                let mut obj_methods = HashMap::new();
-                                for (k, v) in &cobj.methods { obj_methods.insert(k.clone(), v.clone()); }
-                                let obj = Rc::new(RefCell::new(Object { class_name: cobj.name.clone(), fields: HashMap::new(), methods: obj_methods }));
-                                // call __init__ if present
-                                if let Some(init) = cobj.methods.get("__init__") {
-                                    // evaluate args
-                                    let mut avals = Vec::new();
-                                    for a in &args { avals.push(self.eval_expr(a.clone())?); }
-                                    self.push_frame();
-                                    // bind params: if param == "self" bind to obj, else take from avals in order
-                                    let mut ai = 0usize;
-                                    for p in init.params.iter() {
-                                        if p == "self" {
-                                            self.set_local("self".to_string(), Value::Object(obj.clone()));
-                                        } else {
-                                            if ai < avals.len() {
-                                                self.set_local(p.clone(), avals[ai].clone());
-                                            }
-                                            ai += 1;
-                                        }
-                                    }
-                                    let _ = self.execute_program(init.body.clone())?;
-                                    self.pop_frame();
-                                }
-                                Ok(Value::Object(obj))
-                            }
-                            _ => Err("call of non-callable".to_string()),
-                        }
-                    }
-                    _ => Err("call of non-identifier not supported".to_string()),
+    /// Fast path for `Intrinsic` calls. A function or class the script
+    /// declares under the name wins, then the registry entry, which a host
+    /// may have replaced; untouched, that entry does what the fast path does
+    #[inline(never)]
+    pub fn call_intrinsic(&mut self, op: Intrinsic, args: &[Value]) -> Result<Value, VmError> {
+        if self.intrinsics_overridden {
+            if let Some(f @ (Value::Function(_) | Value::Overloaded(_) | Value::Class(_))) = self.get_var(op.name()) {
+                return self.call_value(op.name(), f, args.to_vec());
+            }
+            return self.call_builtin(op.name(), args);
+        }
+        if let Some((usage, _)) = &mut self.usage { usage.called(op.name()); }
+        intrinsic(op, args)
+    }
+
+    /// Call a builtin or global function/class by name with evaluated args
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        if let Some(op) = Intrinsic::from_name(name) { return self.call_intrinsic(op, &args); }
+        if self.builtins.contains(name) { return self.call_builtin(name, &args); }
+        let val = self.get_var(name).ok_or_else(|| format!("undefined function/class {}", name))?;
+        self.call_value(name, val, args)
+    }
+
+    /// Call a function, overload set or class constructor value
+    pub fn call_value(&mut self, fname: &str, val: Value, args: Vec<Value>) -> Result<Value, String> {
+        match val {
+            Value::Function(fobj) => {
+                if fobj.params.len() != args.len() { return Err("arg count mismatch".to_string()); }
+                self.call_function(&fobj, &args)
+            }
+            Value::Overloaded(set) => {
+                let fobj = set.iter().find(|f| f.params.len() == args.len()).cloned()
+                    .ok_or_else(|| format!("no overload of {} takes {} arguments", fname, args.len()))?;
+                self.call_function(&fobj, &args)
+            }
+            Value::Class(cobj) => {
+                // construct object: methods are dispatched through the shared class
+                let obj = Rc::new(RefCell::new(Object::instance_of(&cobj)));
+                // call __init__ if present
+                if let Some(init) = cobj.methods.get("__init__") {
+                    self.invoke_method(&obj, init, &args)?;
                 }
+                Ok(Value::Object(obj))
             }
-            Expr::MemberCall { receiver, method, args } => {
-                let recv = self.eval_expr(*receiver)?;
-                if let Value::Object(o) = recv {
-                    // find method in object
-                    let m = o.borrow().methods.get(&method).cloned().ok_or_else(|| format!("method {} not found", method))?;
-                    // evaluate args first
-                    let mut avals = Vec::new();
-                    for a in &args { avals.push(self.eval_expr(a.clone())?); }
-                    self.push_frame();
-                    // bind params: if param == "self" bind to object, else take next arg
-                    let mut ai = 0usize;
-                    for p in m.params.iter() {
-                        if p == "self" {
-                            self.set_local("self".to_string(), Value::Object(o.clone()));
-                        } else {
-                            if ai < avals.len() {
-                                self.set_local(p.clone(), avals[ai].clone());
-                            }
-                            ai += 1;
-                        }
-                    }
-                    let res = self.execute_program(m.body.clone())?;
-                    self.pop_frame();
-                    Ok(res.unwrap_or(Value::Int(0)))
-                } else { Err("member call on non-object".to_string()) }
+            _ => Err("call of non-callable".to_string()),
+        }
+    }
+
+    /// Call the global function `name` for an event (timer, tween, GUI). A
+    /// plain function gets as many of `args` as it declares; an overload set
+    /// runs the definition taking all of them. Anything else is skipped.
+    pub(crate) fn call_handler(&mut self, name: &str, args: &[Value]) -> Result<(), String> {
+        match self.get_var(name) {
+            Some(Value::Function(fobj)) => { self.call_function(&fobj, args)?; }
+            Some(set @ Value::Overloaded(_)) => { self.call_value(name, set, args.to_vec())?; }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Call a plain function with already evaluated arguments
+    pub(crate) fn call_function(&mut self, fobj: &FunctionObject, avals: &[Value]) -> Result<Value, String> {
+        let mut frame: Frame = vec![None; fobj.params.len()];
+        for (slot, aval) in frame.iter_mut().zip(avals) {
+            *slot = Some(aval.clone());
+        }
+        self.check_params(fobj, &frame)?;
+        self.push_frame(frame, &fobj.params)?;
+        let res = self.execute_block(&fobj.body);
+        self.pop_frame();
+        self.check_return(fobj, res?.unwrap_or(Value::Int(0)))
+    }
+
+    /// Call method `m` on `obj`: a `self` param binds the instance, the rest take args in order
+    fn invoke_method(&mut self, obj: &Rc<RefCell<Object>>, m: &FunctionObject, avals: &[Value]) -> Result<Value, String> {
+        if let Some(native) = &m.native {
+            return native(self, obj, avals);
+        }
+        let mut frame: Frame = vec![None; m.params.len()];
+        let mut ai = 0usize;
+        for (slot, p) in frame.iter_mut().zip(m.params.iter()) {
+            if p == "self" {
+                *slot = Some(Value::Object(obj.clone()));
+            } else {
+                *slot = avals.get(ai).cloned();
+                ai += 1;
+            }
+        }
+        self.check_params(m, &frame)?;
+        self.push_frame(frame, &m.params)?;
+        let res = self.execute_block(&m.body);
+        self.pop_frame();
+        self.check_return(m, res?.unwrap_or(Value::Int(0)))
+    }
+
+    /// Does `v` satisfy the annotation `ty`? Names that are neither builtin types
+    /// nor classes are not checked.
+    pub fn value_has_type(&self, v: &Value, ty: &str) -> bool {
+        match (ty, v) {
+            ("int", Value::Int(_)) | ("float", Value::Float(_) | Value::Int(_)) | ("str", Value::Str(_)) | ("list", Value::List(_)) => true,
+            ("int" | "float" | "str" | "list", _) => false,
+            (_, Value::Object(o)) if o.borrow().class_name == ty => true,
+            (_, Value::Host(_)) => v.type_name() == ty || !matches!(self.globals.get(ty), Some(Value::Class(_))),
+            _ => !matches!(self.globals.get(ty), Some(Value::Class(_))),
+        }
+    }
+
+    fn check_params(&self, fobj: &FunctionObject, frame: &Frame) -> Result<(), String> {
+        for ((p, ty), v) in fobj.params.iter().zip(fobj.param_types.iter()).zip(frame) {
+            if let (Some(ty), Some(v)) = (ty, v)
+                && !self.value_has_type(v, ty) {
+                    return Err(format!("param {} expects {}, got {}", p, ty, v.type_name()));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_return(&self, fobj: &FunctionObject, v: Value) -> Result<Value, String> {
+        match &fobj.ret_type {
+            Some(ty) if !self.value_has_type(&v, ty) => Err(format!("expected return of {}, got {}", ty, v.type_name())),
+            _ => Ok(v),
+        }
+    }
+
+    /// Method lookup through the inline cache of `site`. Only class methods are
+    /// cached; objects with per-instance methods always take the slow path.
+    fn cached_method(&mut self, site: usize, obj: &Rc<RefCell<Object>>, method: &str) -> Option<FunctionObject> {
+        let o = obj.borrow();
+        let Some(class) = o.class.as_ref().filter(|_| o.methods.is_empty()) else { return o.find_method(method) };
+        if let Some(Some((c, name, m))) = self.method_cache.get(site)
+            && Rc::ptr_eq(c, class) && name == method {
+                return Some(m.clone());
+        }
+        let m = class.methods.get(method)?.clone();
+        if self.method_cache.len() <= site { self.method_cache.resize(site + 1, None); }
+        self.method_cache[site] = Some((class.clone(), method.to_string(), m.clone()));
+        Some(m)
+    }
+
+    /// `recv.method(args)` with evaluated receiver and args
+    pub fn call_member(&mut self, recv: &Value, method: &str, avals: &[Value]) -> Result<Value, String> {
+        if let Value::Host(h) = recv { return crate::host::call_method(self, h, method, avals); }
+        let Value::Object(o) = recv else { return Err("member call on non-object".to_string()) };
+        let m = o.borrow().find_method(method).ok_or_else(|| format!("method {} not found", method))?;
+        self.invoke_method(o, &m, avals)
+    }
+
+    /// Print a value the way an expression statement does
+    pub fn print_value(&mut self, v: &Value) -> Result<(), String> {
+        let text = self.value_to_string(v)?;
+        self.emit(&text)
+    }
+
+    /// Look up and call a method by name, if the object has it
+    fn call_method(&mut self, obj: &Rc<RefCell<Object>>, name: &str, avals: &[Value]) -> Option<Result<Value, String>> {
+        let m = obj.borrow().find_method(name)?;
+        Some(self.invoke_method(obj, &m, avals))
+    }
+
+    /// Text shown for a value; objects with a `__str__` method are rendered through it.
+    /// Large lists are cut according to `set_display_limits`.
+    pub fn value_to_string(&mut self, v: &Value) -> Result<String, String> {
+        self.format_value(v, 0, &mut Vec::new())
+    }
+
+    /// `open` holds the lists being printed: without display limits a list
+    /// that contains itself would never end, so it shows up again as `<cycle>`
+    fn format_value(&mut self, v: &Value, depth: usize, open: &mut Vec<*const ()>) -> Result<String, String> {
+        Ok(match v {
+            Value::Int(n) => n.to_string(),
+            Value::Float(f) => f.to_string(),
+            Value::Str(s) => s.clone(),
+            Value::Function(_) | Value::Overloaded(_) => "<function>".to_string(),
+            Value::Class(_) => "<class>".to_string(),
+            Value::Host(h) => h.try_borrow().map_or_else(|_| "<host>".to_string(), |h| h.describe()),
+            Value::Object(o) => match self.call_method(o, "__str__", &[]) {
+                Some(r) => self.value_to_string(&r?)?,
+                None if self.pretty_objects => self.format_repr(v, depth, open),
+                None => "<object>".to_string(),
+            },
+            Value::List(list) => {
+                let ptr = Rc::as_ptr(list) as *const ();
+                if open.contains(&ptr) { return Ok("<cycle>".to_string()); }
+                let limits = self.display;
+                if limits.is_some_and(|l| depth >= l.max_depth) { return Ok("[...]".to_string()); }
+                let items = list.borrow().clone();
+                let shown = limits.map_or(items.len(), |l| l.max_items.min(items.len()));
+                let mut parts = Vec::new();
+                open.push(ptr);
+                for item in &items[..shown] {
+                    parts.push(match item {
+                        Value::Str(s) => format!("{:?}", s),
+                        v => self.format_value(v, depth + 1, open)?,
+                    });
+                }
+                open.pop();
+                if shown < items.len() { parts.push(format!("... ({} more)", items.len() - shown)); }
+                format!("[{}]", parts.join(", "))
+            }
+        })
+    }
+
+    /// Structure of a value for `repr`: strings quoted, objects as
+    /// `Class { field: value }`, nested values included. Cut to the display
+    /// limits, which cap fields as well as list items; a list or object that
+    /// contains itself shows up again as `<cycle>`
+    pub fn repr(&self, v: &Value) -> String {
+        self.format_repr(v, 0, &mut Vec::new())
+    }
+
+    /// `open` holds the lists and objects being printed, to stop on cycles
+    fn format_repr(&self, v: &Value, depth: usize, open: &mut Vec<*const ()>) -> String {
+        let limits = self.display;
+        let too_deep = limits.is_some_and(|l| depth >= l.max_depth);
+        let shown = |len: usize| limits.map_or(len, |l| l.max_items.min(len));
+        match v {
+            Value::Int(n) => n.to_string(),
+            Value::Float(f) => f.to_string(),
+            Value::Str(s) => format!("{:?}", s),
+            Value::Function(_) | Value::Overloaded(_) => "<function>".to_string(),
+            Value::Class(c) => format!("<class {}>", c.name),
+            Value::Host(h) => h.try_borrow().map_or_else(|_| "<host>".to_string(), |h| h.describe()),
+            Value::List(items) => {
+                let ptr = Rc::as_ptr(items) as *const ();
+                if open.contains(&ptr) { return "<cycle>".to_string(); }
+                if too_deep { return "[...]".to_string(); }
+                open.push(ptr);
+                let items = items.borrow();
+                let n = shown(items.len());
+                let mut parts: Vec<String> = items[..n].iter().map(|item| self.format_repr(item, depth + 1, open)).collect();
+                if n < items.len() { parts.push(format!("... ({} more)", items.len() - n)); }
+                open.pop();
+                format!("[{}]", parts.join(", "))
+            }
+            Value::Object(o) => {
+                let ptr = Rc::as_ptr(o) as *const ();
+                if open.contains(&ptr) { return "<cycle>".to_string(); }
+                let o = o.borrow();
+                if too_deep { return format!("{} {{...}}", o.class_name); }
+                if o.fields.is_empty() { return format!("{} {{}}", o.class_name); }
+                open.push(ptr);
+                let n = shown(o.fields.len());
+                let mut parts: Vec<String> = o.fields.iter().take(n)
+                    .map(|(k, val)| format!("{}: {}", k, self.format_repr(val, depth + 1, open)))
+                    .collect();
+                if n < o.fields.len() { parts.push(format!("... ({} more)", o.fields.len() - n)); }
+                open.pop();
+                format!("{} {{ {} }}", o.class_name, parts.join(", "))
             }
         }
     }
+
+    /// Equality used by generated `__eq__`: numbers and strings by value, objects by identity
+    /// unless they define `__eq__` themselves
+    pub(crate) fn values_equal(&mut self, a: &Value, b: &Value) -> Result<bool, String> {
+        Ok(match (a, b) {
+            (Value::Int(x), Value::Int(y)) => x == y,
+            (Value::Float(x), Value::Float(y)) => x == y,
+            (Value::Int(x), Value::Float(y)) | (Value::Float(y), Value::Int(x)) => (*x as f64) == *y,
+            (Value::Str(x), Value::Str(y)) => x == y,
+            (Value::List(x), Value::List(y)) => {
+                if Rc::ptr_eq(x, y) { return Ok(true); }
+                let (x, y) = (x.borrow().clone(), y.borrow().clone());
+                if x.len() != y.len() { return Ok(false); }
+                for (a, b) in x.iter().zip(&y) {
+                    if !self.values_equal(a, b)? { return Ok(false); }
+                }
+                true
+            }
+            (Value::Host(x), Value::Host(y)) => Rc::ptr_eq(x, y),
+            (Value::Object(x), Value::Object(y)) => {
+                if Rc::ptr_eq(x, y) { return Ok(true); }
+                match self.call_method(x, "__eq__", std::slice::from_ref(b)) {
+                    Some(r) => matches!(r?, Value::Int(n) if n != 0),
+                    None => false,
+                }
+            }
+            _ => false,
+        })
+    }
+}
+
+/// Is `name` a standard builtin, an intrinsic or `help`? Builtins registered on a VM are not included.
+pub fn is_builtin(name: &str) -> bool {
+    // the standard registry never changes, so its names are collected once
+    static STANDARD: OnceLock<HashSet<String>> = OnceLock::new();
+    let standard = STANDARD.get_or_init(|| Registry::standard().names().into_iter().map(String::from).collect());
+    name == "help" || standard.contains(name)
+}
+
+/// Evaluate an intrinsic: lists (`list`, `len`, `push`, `at`) and math
+/// (`abs`, `min`, `max`, `sqrt`). `len` and `at` also work on strings.
+pub fn intrinsic(op: Intrinsic, args: &[Value]) -> Result<Value, VmError> {
+    let name = op.name();
+    let arity = match op {
+        Intrinsic::List => args.len(),
+        Intrinsic::Len | Intrinsic::Abs | Intrinsic::Sqrt => 1,
+        Intrinsic::Push | Intrinsic::At | Intrinsic::Min | Intrinsic::Max => 2,
+    };
+    if args.len() != arity {
+        return Err(format!("{} requires {} argument{}", name, arity, if arity == 1 { "" } else { "s" }));
+    }
+    let num = |v: &Value| match v {
+        Value::Int(n) => Ok(*n as f64),
+        Value::Float(f) => Ok(*f),
+        other => Err(format!("{}: expected a number, got {}", name, other.type_name())),
+    };
+    match (op, args) {
+        (Intrinsic::List, _) => Ok(Value::from(args.to_vec())),
+        (Intrinsic::Len, [Value::List(items)]) => Ok(Value::Int(items.borrow().len() as i64)),
+        (Intrinsic::Len, [Value::Str(s)]) => Ok(Value::Int(s.chars().count() as i64)),
+        (Intrinsic::Push, [Value::List(items), v]) => {
+            items.borrow_mut().push(v.clone());
+            Ok(Value::Int(items.borrow().len() as i64))
+        }
+        (Intrinsic::At, [Value::List(items), Value::Int(i)]) => {
+            let items = items.borrow();
+            usize::try_from(*i).ok().and_then(|i| items.get(i)).cloned()
+                .ok_or_else(|| format!("at: index {} out of range (len {})", i, items.len()))
+        }
+        (Intrinsic::At, [Value::Str(s), Value::Int(i)]) => {
+            usize::try_from(*i).ok().and_then(|i| s.chars().nth(i)).map(|c| Value::Str(c.to_string()))
+                .ok_or_else(|| format!("at: index {} out of range (len {})", i, s.chars().count()))
+        }
+        (Intrinsic::Abs, [Value::Int(n)]) => n.checked_abs().map(Value::Int).ok_or_else(|| format!("abs: {} has no int absolute value", n)),
+        (Intrinsic::Min | Intrinsic::Max, [Value::Int(a), Value::Int(b)]) => {
+            Ok(Value::Int(if op == Intrinsic::Min { *a.min(b) } else { *a.max(b) }))
+        }
+        (Intrinsic::Abs, [v]) => Ok(Value::Float(num(v)?.abs())),
+        (Intrinsic::Min, [a, b]) => Ok(Value::Float(num(a)?.min(num(b)?))),
+        (Intrinsic::Max, [a, b]) => Ok(Value::Float(num(a)?.max(num(b)?))),
+        (Intrinsic::Sqrt, [v]) => Ok(Value::Float(num(v)?.sqrt())),
+        (Intrinsic::Len | Intrinsic::Push | Intrinsic::At, _) => {
+            let types: Vec<String> = args.iter().map(|a| a.type_name()).collect();
+            Err(format!("{}: unsupported arguments ({})", name, types.join(", ")))
+        }
+        _ => unreachable!("arity checked above"),
+    }
+}
+
+pub fn get_field(recv: &Value, field: &str) -> Result<Value, String> {
+    if let Value::Host(h) = recv { return crate::host::field(h, field); }
+    let Value::Object(o) = recv else { return Err("member access on non-object".to_string()) };
+    o.borrow().fields.get(field).cloned().ok_or_else(|| format!("field {} not found", field))
+}
+
+pub fn set_field(recv: &Value, field: &str, val: Value) -> Result<(), String> {
+    let Value::Object(o) = recv else { return Err("member assignment on non-object".to_string()) };
+    o.borrow_mut().fields.insert(field.to_string(), val);
+    Ok(())
+}
+
+/// Text returned by `help(name)`: the signature(s) of a function or the methods of a class
+pub fn signature_text(name: &str, v: &Value) -> String {
+    match v {
+        Value::Function(f) => f.signature(name),
+        Value::Overloaded(set) => set.iter().map(|f| f.signature(name)).collect::<Vec<_>>().join("\n"),
+        Value::Class(c) => {
+            let mut names: Vec<&String> = c.methods.keys().collect();
+            names.sort();
+            let mut lines = vec![format!("class {}", c.name)];
+            lines.extend(names.into_iter().map(|m| format!("  {}", c.methods[m].signature(m))));
+            lines.join("\n")
+        }
+        other => other.type_name(),
+    }
+}
+
+/// Arithmetic on two evaluated operands; mixed int/float promotes to float
+pub fn binary_op(l: Value, r: Value, op: &BinOp) -> Result<Value, String> {
+    match (l, r, op) {
+        (Value::Int(a), Value::Int(b), BinOp::Add) => Ok(Value::Int(a + b)),
+        (Value::Int(a), Value::Int(b), BinOp::Sub) => Ok(Value::Int(a - b)),
+        (Value::Int(a), Value::Int(b), BinOp::Mul) => Ok(Value::Int(a * b)),
+        (Value::Int(a), Value::Int(b), BinOp::Div) => Ok(Value::Int(a / b)),
+        // float cases
+        (Value::Float(a), Value::Float(b), BinOp::Add) => Ok(Value::Float(a + b)),
+        (Value::Float(a), Value::Float(b), BinOp::Sub) => Ok(Value::Float(a - b)),
+        (Value::Float(a), Value::Float(b), BinOp::Mul) => Ok(Value::Float(a * b)),
+        (Value::Float(a), Value::Float(b), BinOp::Div) => Ok(Value::Float(a / b)),
+        // mixed int/float
+        (Value::Int(a), Value::Float(b), BinOp::Add) => Ok(Value::Float((a as f64) + b)),
+        (Value::Float(a), Value::Int(b), BinOp::Add) => Ok(Value::Float(a + (b as f64))),
+        (Value::Int(a), Value::Float(b), BinOp::Sub) => Ok(Value::Float((a as f64) - b)),
+        (Value::Float(a), Value::Int(b), BinOp::Sub) => Ok(Value::Float(a - (b as f64))),
+        (Value::Int(a), Value::Float(b), BinOp::Mul) => Ok(Value::Float((a as f64) * b)),
+        (Value::Float(a), Value::Int(b), BinOp::Mul) => Ok(Value::Float(a * (b as f64))),
+        (Value::Int(a), Value::Float(b), BinOp::Div) => Ok(Value::Float((a as f64) / b)),
+        (Value::Float(a), Value::Int(b), BinOp::Div) => Ok(Value::Float(a / (b as f64))),
+        _ => Err("type error in binary op".to_string()),
+    }
+}
+
+/// Add one stack trace line to an error leaving a call; the innermost call comes first
+fn traced(res: Result<Value, String>, name: &str, line: usize) -> Result<Value, String> {
+    res.map_err(|e| format!("{}\n  in {}, called at line {}", e, name, line))
+}
+
+/// Methods generated for `data class Name(fields...)`
+pub(crate) fn data_class_methods(class_name: &str, fields: &[String]) -> Vec<(&'static str, FunctionObject)> {
+    let fields: Rc<[String]> = fields.to_vec().into();
+    let class_name = class_name.to_string();
+
+    let eq_fields = fields.clone();
+    let eq = FunctionObject::native(&["self", "other"], Rc::new(move |vm, obj, args| {
+        let other = match args.first() { Some(Value::Object(o)) => o.clone(), _ => return Ok(Value::Int(0)) };
+        if obj.borrow().class_name != other.borrow().class_name { return Ok(Value::Int(0)); }
+        for f in eq_fields.iter() {
+            let a = obj.borrow().fields.get(f).cloned();
+            let b = other.borrow().fields.get(f).cloned();
+            let same = match (a, b) {
+                (Some(a), Some(b)) => vm.values_equal(&a, &b)?,
+                (None, None) => true,
+                _ => false,
+            };
+            if !same { return Ok(Value::Int(0)); }
+        }
+        Ok(Value::Int(1))
+    }));
+
+    let str_fields = fields.clone();
+    let to_str = FunctionObject::native(&["self"], Rc::new(move |vm, obj, _args| {
+        let mut parts = Vec::new();
+        for f in str_fields.iter() {
+            let v = obj.borrow().fields.get(f).cloned();
+            let text = match v {
+                Some(Value::Str(s)) => format!("{:?}", s),
+                Some(v) => vm.value_to_string(&v)?,
+                None => "<unset>".to_string(),
+            };
+            parts.push(format!("{}={}", f, text));
+        }
+        Ok(Value::Str(format!("{}({})", class_name, parts.join(", "))))
+    }));
+
+    let copy_fields = fields;
+    let copy_with = FunctionObject::native(&["self", "field", "value"], Rc::new(move |_vm, obj, args| {
+        let (field, value) = match args {
+            [Value::Str(f), v] => (f.clone(), v.clone()),
+            _ => return Err("copy_with requires (field_name, value)".to_string()),
+        };
+        if !copy_fields.contains(&field) { return Err(format!("copy_with: unknown field {}", field)); }
+        let src = obj.borrow();
+        let mut fields = src.fields.clone();
+        fields.insert(field, value);
+        let copy = Object { class_name: src.class_name.clone(), class: src.class.clone(), fields, methods: src.methods.clone() };
+        Ok(Value::Object(Rc::new(RefCell::new(copy))))
+    }));
+
+    vec![("__eq__", eq), ("__str__", to_str), ("copy_with", copy_with)]
 }
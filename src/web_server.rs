@@ -2,16 +2,105 @@ use std::net::{TcpListener, TcpStream};
 use std::io::{Read, Write};
 use std::thread;
 use std::fs;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock};
 
-fn handle_client(mut stream: TcpStream) {
+use crate::json::escape;
+use crate::session::Session;
+use crate::vm::Value;
+
+/// Budget for one `/run` request
+const RUN_MAX_STEPS: u64 = 1_000_000;
+const RUN_MAX_MILLIS: u64 = 2_000;
+/// Responses up to this size are sent uncompressed
+const GZIP_MIN_BODY: usize = 1024;
+
+/// Program state after recent runs, for `/api/inspect`, `/run?session=ID`
+/// and `/api/session`; the oldest is dropped once there are `MAX_SESSIONS`.
+/// Ids are random: every client shares the table, and an id is all it takes
+/// to read a session
+type Sessions = VecDeque<(String, Session)>;
+
+static SESSIONS: OnceLock<Mutex<Sessions>> = OnceLock::new();
+const MAX_SESSIONS: usize = 32;
+/// Largest session kept (snapshot plus inputs); bigger ones get no id
+const MAX_SESSION_BYTES: usize = 1 << 20;
+
+fn sessions() -> &'static Mutex<Sessions> {
+    SESSIONS.get_or_init(|| Mutex::new(VecDeque::new()))
+}
+
+fn save_session(session: Session) -> Result<String, String> {
+    let size = session.snapshot.len() + session.inputs.iter().map(String::len).sum::<usize>();
+    if size > MAX_SESSION_BYTES {
+        return Err(format!("session too large to keep ({} bytes, limit {})", size, MAX_SESSION_BYTES));
+    }
+    let mut bytes = [0u8; 16];
+    crate::rand::secure_bytes(&mut bytes)?;
+    let id: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    if let Ok(mut s) = sessions().lock() {
+        if s.len() >= MAX_SESSIONS { s.pop_front(); }
+        s.push_back((id.clone(), session));
+    }
+    Ok(id)
+}
+
+fn find_session(id: &str) -> Option<Session> {
+    sessions().lock().ok()?.iter().find(|(i, _)| i == id).map(|(_, s)| s.clone())
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ServerConfig {
+    /// Sorted object keys and a fixed float format in `/run` responses,
+    /// so they can be diffed or checked byte for byte
+    pub canonical_json: bool,
+}
+
+fn handle_client(mut stream: TcpStream, config: ServerConfig) {
     let mut buf = Vec::new();
-    if let Err(_) = stream.read_to_end(&mut buf) { return; }
+    if stream.read_to_end(&mut buf).is_err() { return; }
     let req = String::from_utf8_lossy(&buf);
     let mut lines = req.lines();
     let first = lines.next().unwrap_or("");
     let mut parts = first.split_whitespace();
     let method = parts.next().unwrap_or("");
     let path = parts.next().unwrap_or("");
+    let gzip = lines.take_while(|l| !l.is_empty()).filter_map(|l| l.split_once(':'))
+        .any(|(name, value)| name.trim().eq_ignore_ascii_case("accept-encoding") && value.contains("gzip"));
+
+    // `/api/inspect?session=ID&path=obj.field`: one level of a value from a finished run
+    if method == "GET" && let Some(query) = path.strip_prefix("/api/inspect?") {
+        let session = query_param(query, "session").unwrap_or_default();
+        let json = inspect(&session, &query_param(query, "path").unwrap_or_default());
+        respond(&mut stream, "application/json", json.as_bytes(), gzip);
+        return;
+    }
+
+    // `/api/session?id=ID`: a saved session as a file for the user to keep
+    if method == "GET" && let Some(query) = path.strip_prefix("/api/session?") {
+        match query_param(query, "id").and_then(|id| find_session(&id)) {
+            Some(session) => respond(&mut stream, "application/octet-stream", &session.encode(), gzip),
+            None => { let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"); }
+        }
+        return;
+    }
+    // `POST /api/session` with such a file: saved again under a new id that
+    // `/run?session=ID` can continue from
+    if method == "POST" && path == "/api/session" {
+        let body = buf.windows(4).position(|w| w == b"\r\n\r\n").map_or(&buf[..0], |i| &buf[i + 4..]);
+        let json = upload_session(body);
+        respond(&mut stream, "application/json", json.as_bytes(), gzip);
+        return;
+    }
+
+    // `/api/builtins`: names, signatures and summaries for completion in the editor
+    if method == "GET" && path == "/api/builtins" {
+        let json = builtins_json();
+        respond(&mut stream, "application/json", json.as_bytes(), gzip);
+        return;
+    }
 
     if method == "GET" {
         let file = match path {
@@ -32,9 +121,7 @@ fn handle_client(mut stream: TcpStream) {
         match fs::read_to_string(file) {
             Ok(body) => {
                 let content_type = if file.ends_with(".js") { "application/javascript" } else if file.ends_with(".css") { "text/css" } else { "text/html" };
-                let header = format!("HTTP/1.1 200 OK\r\nContent-Type: {}; charset=utf-8\r\nContent-Length: {}\r\n\r\n", content_type, body.len());
-                let _ = stream.write_all(header.as_bytes());
-                let _ = stream.write_all(body.as_bytes());
+                respond(&mut stream, &format!("{}; charset=utf-8", content_type), body.as_bytes(), gzip);
             }
             Err(_) => {
                 let resp = "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n";
@@ -44,40 +131,21 @@ fn handle_client(mut stream: TcpStream) {
         return;
     }
 
-    if method == "POST" && path == "/run" {
+    // `/debug?lines=3,7` runs like `/run` but also reports every stop at those lines;
+    // `?session=ID` on either starts from the globals a saved session left
+    let (route, query) = path.split_once('?').unwrap_or((path, ""));
+    if method == "POST" && (route == "/run" || route == "/debug") {
         // find blank line separating headers and body
         let reqs = req.as_ref();
         if let Some(idx) = reqs.find("\r\n\r\n") {
-            let body = &reqs[idx+4..];
             // body is raw code
-            let code = body.to_string();
-            // execute code using parser + vm
-            let mut parser = crate::parser::Parser::new(&code);
-            let prog = parser.parse_program();
-            let mut vm = crate::vm::VM::new();
-            match vm.execute_program(prog) {
-                Ok(opt) => {
-                    let json = match opt {
-                        Some(v) => {
-                            let mut s = String::from("{\"ok\":true,\"result\":");
-                            s.push_str(&serialize_value(&v));
-                            s.push('}');
-                            s
-                        }
-                        None => "{\"ok\":true,\"result\":null}".to_string(),
-                    };
-                    let header = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n", json.len());
-                    let _ = stream.write_all(header.as_bytes());
-                    let _ = stream.write_all(json.as_bytes());
-                }
-                Err(e) => {
-                    let esc = e.replace('"', "\\\"");
-                    let json = format!("{{\"ok\":false,\"error\":\"{}\"}}", esc);
-                    let header = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n", json.len());
-                    let _ = stream.write_all(header.as_bytes());
-                    let _ = stream.write_all(json.as_bytes());
-                }
-            }
+            let code = &reqs[idx+4..];
+            let breakpoints = (route == "/debug").then(|| {
+                query_param(query, "lines").unwrap_or_default().split(',').filter_map(|l| l.trim().parse().ok()).collect()
+            });
+            let from = query_param(query, "session");
+            let json = run_code(code, config, breakpoints, from.as_deref());
+            respond(&mut stream, "application/json", json.as_bytes(), gzip);
         } else {
             let resp = "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n";
             let _ = stream.write_all(resp.as_bytes());
@@ -90,29 +158,232 @@ fn handle_client(mut stream: TcpStream) {
     let _ = stream.write_all(resp.as_bytes());
 }
 
-fn serialize_value(v: &crate::vm::Value) -> String {
+/// A 200 response, gzipped when the client accepts it and the body is big enough to gain
+fn respond(stream: &mut TcpStream, content_type: &str, body: &[u8], gzip: bool) {
+    let compressed;
+    let (body, encoding) = if gzip && body.len() > GZIP_MIN_BODY {
+        compressed = crate::gzip::compress(body);
+        (&compressed[..], "Content-Encoding: gzip\r\n")
+    } else { (body, "") };
+    let header = format!("HTTP/1.1 200 OK\r\nContent-Type: {}\r\n{}Content-Length: {}\r\n\r\n", content_type, encoding, body.len());
+    let _ = stream.write_all(header.as_bytes());
+    let _ = stream.write_all(body);
+}
+
+/// The standard builtins as `[{"name","signature","summary","arity"}]`, sorted by name;
+/// `arity` is null for those taking any number of arguments
+pub(crate) fn builtins_json() -> String {
+    let registry = crate::builtins::Registry::standard();
+    let items: Vec<String> = registry.names().into_iter().filter_map(|name| {
+        let b = registry.get(name)?;
+        let (signature, summary) = b.doc.map_or(("", ""), |d| (d.signature, d.summary));
+        // any number of arguments is null
+        let arity = if b.arity == crate::builtins::VARIADIC { "null".to_string() } else { b.arity.to_string() };
+        Some(format!("{{\"name\":\"{}\",\"signature\":\"{}\",\"summary\":\"{}\",\"arity\":{}}}", escape(name), escape(signature), escape(summary), arity))
+    }).collect();
+    format!("[{}]", items.join(","))
+}
+
+/// Run editor code and describe the outcome as JSON. With `breakpoints`, the
+/// response also lists each pause with its line, call depth and locals.
+/// With `from`, the run starts from the globals of that saved session.
+pub(crate) fn run_code(code: &str, config: ServerConfig, breakpoints: Option<Vec<usize>>, from: Option<&str>) -> String {
+    let prog = crate::parser::Parser::new(code).parse_program();
+    // editor code is untrusted: stop runaway programs instead of holding the thread
+    let mut vm = crate::vm::VM::with_limits(RUN_MAX_STEPS, RUN_MAX_MILLIS);
+    vm.set_policy(crate::vm::Policy::sandboxed());
+    let mut inputs = Vec::new();
+    if let Some(id) = from {
+        let Some(session) = find_session(id) else { return "{\"ok\":false,\"error\":\"unknown session\"}".to_string() };
+        if let Err(e) = session.restore_into(&mut vm) {
+            return format!("{{\"ok\":false,\"error\":\"{}\"}}", escape(&e));
+        }
+        inputs = session.inputs;
+    }
+    // return what the program printed alongside the result
+    let output = crate::vm::OutputBuffer::new();
+    vm.set_output(Box::new(output.clone()));
+    let pauses = Rc::new(RefCell::new(Vec::new()));
+    if let Some(lines) = &breakpoints {
+        for line in lines { vm.set_breakpoint("", *line); }
+        let log = pauses.clone();
+        vm.set_debug_handler(move |pause, vm| {
+            let locals: Vec<String> = vm.locals(0).iter()
+                .map(|(name, v)| format!("\"{}\":{}", escape(name), serialize_value(v, config.canonical_json)))
+                .collect();
+            log.borrow_mut().push(format!("{{\"line\":{},\"depth\":{},\"locals\":{{{}}}}}", pause.line, vm.call_depth(), locals.join(",")));
+            crate::vm::DebugAction::Continue
+        });
+    }
+    // the time limit is only checked between statements; a watchdog also
+    // cancels runs stuck in a sleep
+    let (finished, done) = std::sync::mpsc::channel::<()>();
+    let token = vm.cancel_token();
+    thread::spawn(move || {
+        if let Err(std::sync::mpsc::RecvTimeoutError::Timeout) = done.recv_timeout(std::time::Duration::from_millis(RUN_MAX_MILLIS)) {
+            token.cancel();
+        }
+    });
+    let res = vm.execute_program(prog);
+    drop(finished);
+    let printed = escape(&output.text());
+    inputs.push(code.to_string());
+    let mut extra = match save_session(Session::capture(&vm, inputs)) {
+        Ok(id) => format!(",\"session\":\"{}\"", id),
+        Err(e) => format!(",\"session\":null,\"session_error\":\"{}\"", escape(&e)),
+    };
+    if breakpoints.is_some() { extra.push_str(&format!(",\"pauses\":[{}]", pauses.borrow().join(","))); }
+    match res {
+        Ok(opt) => {
+            let result = match opt {
+                Some(v) => serialize_value(&v, config.canonical_json),
+                None => "null".to_string(),
+            };
+            format!("{{\"ok\":true,\"result\":{},\"output\":\"{}\"{}}}", result, printed, extra)
+        }
+        Err(e) => format!("{{\"ok\":false,\"error\":\"{}\",\"output\":\"{}\"{}}}", escape(&e), printed, extra),
+    }
+}
+
+/// Value of `key` in a query string, with `%XX` escapes and `+` decoded
+pub(crate) fn query_param(query: &str, key: &str) -> Option<String> {
+    query.split('&').find_map(|kv| {
+        let (k, v) = kv.split_once('=').unwrap_or((kv, ""));
+        (url_decode(k) == key).then(|| url_decode(v))
+    })
+}
+
+/// Undo URL encoding; a `%` not followed by two hex digits is kept as it is
+fn url_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let hex = |i: usize| bytes.get(i).and_then(|b| (*b as char).to_digit(16));
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], hex(i + 1), hex(i + 2)) {
+            (b'%', Some(hi), Some(lo)) => { out.push((hi * 16 + lo) as u8); i += 2; }
+            (b'+', ..) => out.push(b' '),
+            (b, ..) => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Keep an uploaded session file; its new id and inputs as JSON
+pub(crate) fn upload_session(bytes: &[u8]) -> String {
+    match Session::decode(bytes) {
+        Ok(session) => {
+            let inputs: Vec<String> = session.inputs.iter().map(|i| format!("\"{}\"", escape(i))).collect();
+            match save_session(session) {
+                Ok(id) => format!("{{\"ok\":true,\"session\":\"{}\",\"inputs\":[{}]}}", id, inputs.join(",")),
+                Err(e) => format!("{{\"ok\":false,\"error\":\"{}\"}}", escape(&e)),
+            }
+        }
+        Err(e) => format!("{{\"ok\":false,\"error\":\"{}\"}}", escape(&e)),
+    }
+}
+
+/// The value at `path` (dot separated fields and list indexes; empty = all
+/// globals) in a saved session, with its children summarized so the editor
+/// can expand them on demand
+pub(crate) fn inspect(session: &str, path: &str) -> String {
+    let Some(saved) = find_session(session) else { return "{\"ok\":false,\"error\":\"unknown session\"}".to_string() };
+    let mut vm = crate::vm::VM::new();
+    if let Err(e) = saved.restore_into(&mut vm) {
+        return format!("{{\"ok\":false,\"error\":\"{}\"}}", escape(&e));
+    }
+    let mut segments = path.split('.').filter(|s| !s.is_empty());
+    let mut cur = match segments.next() {
+        None => {
+            let globals: Vec<String> = vm.globals_iter()
+                .map(|(name, v)| format!("\"{}\":{}", escape(name), summary(v)))
+                .collect();
+            return format!("{{\"ok\":true,\"value\":{{\"type\":\"globals\",\"fields\":{{{}}}}}}}", globals.join(","));
+        }
+        Some(name) => match vm.get_global(name) {
+            Some(v) => v,
+            None => return format!("{{\"ok\":false,\"error\":\"undefined: {}\"}}", escape(name)),
+        },
+    };
+    for seg in segments {
+        let next = match &cur {
+            Value::Object(o) => o.borrow().fields.get(seg).cloned(),
+            Value::List(items) => seg.parse::<usize>().ok().and_then(|i| items.borrow().get(i).cloned()),
+            _ => None,
+        };
+        cur = match next {
+            Some(v) => v,
+            None => return format!("{{\"ok\":false,\"error\":\"no {} in {}\"}}", escape(seg), escape(path)),
+        };
+    }
+    let value = match &cur {
+        Value::Object(o) => {
+            let o = o.borrow();
+            let fields: Vec<String> = o.fields.iter().map(|(k, v)| format!("\"{}\":{}", escape(k), summary(v))).collect();
+            format!("{{\"type\":\"object\",\"class\":\"{}\",\"fields\":{{{}}}}}", escape(&o.class_name), fields.join(","))
+        }
+        Value::List(items) => {
+            let items: Vec<String> = items.borrow().iter().map(summary).collect();
+            format!("{{\"type\":\"list\",\"items\":[{}]}}", items.join(","))
+        }
+        v => summary(v),
+    };
+    format!("{{\"ok\":true,\"value\":{}}}", value)
+}
+
+/// A value without its children: objects and lists only say what they hold
+fn summary(v: &Value) -> String {
+    match v {
+        Value::Object(o) => {
+            let o = o.borrow();
+            format!("{{\"type\":\"object\",\"class\":\"{}\",\"size\":{},\"expandable\":true}}", escape(&o.class_name), o.fields.len())
+        }
+        Value::List(items) => format!("{{\"type\":\"list\",\"size\":{},\"expandable\":true}}", items.borrow().len()),
+        Value::Function(_) | Value::Overloaded(_) => "{\"type\":\"function\"}".to_string(),
+        Value::Class(c) => format!("{{\"type\":\"class\",\"name\":\"{}\"}}", escape(&c.name)),
+        v => serialize_value(v, false),
+    }
+}
+
+fn serialize_value(v: &crate::vm::Value, canonical: bool) -> String {
+    serialize_nested(v, canonical, &mut Vec::new())
+}
+
+/// `open` holds the objects being written; one that contains itself shows
+/// up again as `{"type":"cycle"}` instead of recursing until the stack overflows
+fn serialize_nested(v: &crate::vm::Value, canonical: bool, open: &mut Vec<*const ()>) -> String {
     match v {
         crate::vm::Value::Int(n) => format!("{{\"type\":\"int\",\"value\":{}}}", n),
-        crate::vm::Value::Str(s) => format!("{{\"type\":\"str\",\"value\":\"{}\"}}", s.replace('"', "\\\"")),
+        crate::vm::Value::Float(f) => format!("{{\"type\":\"float\",\"value\":{}}}", crate::json::float(*f, canonical)),
+        crate::vm::Value::Str(s) => format!("{{\"type\":\"str\",\"value\":\"{}\"}}", escape(s)),
         crate::vm::Value::Object(o) => {
+            let ptr = Rc::as_ptr(o) as *const ();
+            if open.contains(&ptr) { return "{\"type\":\"cycle\"}".to_string(); }
             // show fields only
             let b = o.borrow();
             let mut fields = Vec::new();
-            for (k, val) in &b.fields {
-                fields.push(format!("\"{}\":{}", k, serialize_value(val)));
+            open.push(ptr);
+            for (k, val) in crate::json::field_order(&b.fields, canonical) {
+                fields.push(format!("\"{}\":{}", escape(k), serialize_nested(val, canonical, open)));
             }
+            open.pop();
             format!("{{\"type\":\"object\",\"class\":\"{}\",\"fields\":{{{}}}}}", b.class_name, fields.join(","))
         }
-        _ => format!("{{\"type\":\"other\"}}"),
+        _ => "{\"type\":\"other\"}".to_string(),
     }
 }
 
 pub fn run_server(addr: &str) -> std::io::Result<()> {
+    run_server_with(addr, ServerConfig::default())
+}
+
+pub fn run_server_with(addr: &str, config: ServerConfig) -> std::io::Result<()> {
     let listener = TcpListener::bind(addr)?;
     println!("Editor server running at http://{}", addr);
     for stream in listener.incoming() {
         match stream {
-            Ok(s) => { thread::spawn(|| handle_client(s)); }
+            Ok(s) => { thread::spawn(move || handle_client(s, config)); }
             Err(e) => eprintln!("connection failed: {}", e),
         }
     }